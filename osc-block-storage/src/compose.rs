@@ -0,0 +1,384 @@
+use crate::{BlockDevice, BlockDeviceError};
+use alloc::vec::Vec;
+use core::{cmp, fmt};
+
+/// Why devices couldn't be put together into one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ComposeError {
+    NoDevices,
+    /// The devices don't all have the same block size.
+    MixedBlockSizes,
+    /// One of the devices doesn't know its block count, which is needed to tell where
+    /// the next starts, or how big the whole can be.
+    UnknownBlockCount,
+    /// Stripes have to have at least one block.
+    EmptyStripe,
+    /// A run of blocks is mapped onto a device that isn't there.
+    MissingDevice,
+    /// Runs of blocks overlap, so some blocks are mapped twice.
+    OverlappingSegments,
+}
+
+impl fmt::Display for ComposeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoDevices => write!(f, "there are no devices"),
+            Self::MixedBlockSizes => write!(f, "the devices don't all have the same block size"),
+            Self::UnknownBlockCount => write!(f, "a device doesn't know its block count"),
+            Self::EmptyStripe => write!(f, "stripes must have at least one block"),
+            Self::MissingDevice => write!(f, "blocks are mapped onto a device that isn't there"),
+            Self::OverlappingSegments => write!(f, "blocks are mapped more than once"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ComposeError {}
+
+/// Presents devices one after the other as one device, e.g. the pieces of an image
+/// split into `image.001`, `image.002` and so on.
+pub struct ConcatDevice<D> {
+    devices: Vec<D>,
+    /// The block each device starts at, followed by the block count of the whole.
+    starts: Vec<u64>,
+}
+
+impl<D> ConcatDevice<D>
+where
+    D: BlockDevice,
+{
+    /// Joins `devices` in the order given, which fails if there are none, they don't
+    /// all have the same block size, or any of them doesn't know its block count.
+    pub fn new(devices: Vec<D>) -> Result<Self, ComposeError> {
+        check_same_block_size(&devices)?;
+
+        let mut starts = Vec::with_capacity(devices.len() + 1);
+        let mut start = 0;
+
+        for device in &devices {
+            starts.push(start);
+            start += device
+                .block_count()
+                .ok_or(ComposeError::UnknownBlockCount)?;
+        }
+
+        starts.push(start);
+
+        Ok(Self { devices, starts })
+    }
+
+    pub fn into_inner(self) -> Vec<D> {
+        self.devices
+    }
+
+    /// Goes through the devices covering `block_count` blocks from `start_block`, with
+    /// each device, the block within it to start at, how many blocks of it to cover, and
+    /// how far into the whole transfer they are, until `transfer` gives back fewer
+    /// blocks than it was asked for.
+    fn transfer<F>(
+        &mut self,
+        start_block: u64,
+        block_count: u64,
+        mut transfer: F,
+    ) -> Result<u64, BlockDeviceError>
+    where
+        F: FnMut(&mut D, u64, u64, u64) -> Result<u64, BlockDeviceError>,
+    {
+        let end_block = cmp::min(start_block.saturating_add(block_count), self.block_total());
+        let mut block = start_block;
+
+        while block < end_block {
+            let index = self.starts.partition_point(|&start| start <= block) - 1;
+            let part_end = cmp::min(end_block, self.starts[index + 1]);
+            let requested = part_end - block;
+
+            let transferred = transfer(
+                &mut self.devices[index],
+                block - self.starts[index],
+                requested,
+                block - start_block,
+            )?;
+
+            block += transferred;
+
+            if transferred < requested {
+                break;
+            }
+        }
+
+        Ok(block.saturating_sub(start_block))
+    }
+
+    fn block_total(&self) -> u64 {
+        self.starts[self.devices.len()]
+    }
+}
+
+impl<D> BlockDevice for ConcatDevice<D>
+where
+    D: BlockDevice,
+{
+    fn block_size(&self) -> u16 {
+        self.devices[0].block_size()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.block_size());
+        let block_count = (destination.len() / block_size) as u64;
+
+        self.transfer(start_block, block_count, |device, block, count, offset| {
+            let offset = offset as usize * block_size;
+            let len = count as usize * block_size;
+            device.read_blocks(block, &mut destination[offset..(offset + len)])
+        })
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        Some(self.block_total())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.devices.iter().any(|device| device.is_read_only())
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.block_size());
+        let block_count = (source.len() / block_size) as u64;
+
+        self.transfer(start_block, block_count, |device, block, count, offset| {
+            let offset = offset as usize * block_size;
+            let len = count as usize * block_size;
+            device.write_blocks(block, &source[offset..(offset + len)])
+        })
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        let _ = self.transfer(start_block, block_count, |device, block, count, _| {
+            device.prefetch(block, count);
+            Ok(count)
+        });
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        self.transfer(start_block, block_count, |device, block, count, _| {
+            device.discard(block, count).map(|_| count)
+        })
+        .map(|_| ())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.devices
+            .iter_mut()
+            .try_for_each(|device| device.flush())
+    }
+}
+
+/// Spreads blocks across devices in turn, a stripe of them on each, as RAID 0 does.
+/// The device is as big as the smallest of them allows, in whole stripes.
+pub struct StripedDevice<D> {
+    devices: Vec<D>,
+    stripe_blocks: u64,
+    block_count: u64,
+}
+
+impl<D> StripedDevice<D>
+where
+    D: BlockDevice,
+{
+    /// Stripes `devices` in the order given, `stripe_blocks` blocks at a time, which
+    /// fails if there are no devices, they don't all have the same block size, any of
+    /// them doesn't know its block count, or `stripe_blocks` is zero.
+    pub fn new(devices: Vec<D>, stripe_blocks: u64) -> Result<Self, ComposeError> {
+        check_same_block_size(&devices)?;
+
+        if stripe_blocks == 0 {
+            return Err(ComposeError::EmptyStripe);
+        }
+
+        let smallest = devices
+            .iter()
+            .map(|device| device.block_count().ok_or(ComposeError::UnknownBlockCount))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .min()
+            .unwrap_or(0);
+
+        let block_count = smallest / stripe_blocks * stripe_blocks * devices.len() as u64;
+
+        Ok(Self {
+            devices,
+            stripe_blocks,
+            block_count,
+        })
+    }
+
+    pub fn into_inner(self) -> Vec<D> {
+        self.devices
+    }
+
+    /// Goes through the stripes covering `block_count` blocks from `start_block`, with
+    /// the device each is on, the block within it to start at, how many blocks to cover,
+    /// and how far into the whole transfer they are, until `transfer` gives back fewer
+    /// blocks than it was asked for.
+    fn transfer<F>(
+        &mut self,
+        start_block: u64,
+        block_count: u64,
+        mut transfer: F,
+    ) -> Result<u64, BlockDeviceError>
+    where
+        F: FnMut(&mut D, u64, u64, u64) -> Result<u64, BlockDeviceError>,
+    {
+        let end_block = cmp::min(start_block.saturating_add(block_count), self.block_count);
+        let device_count = self.devices.len() as u64;
+        let mut block = start_block;
+
+        while block < end_block {
+            let stripe = block / self.stripe_blocks;
+            let within = block % self.stripe_blocks;
+            let requested = cmp::min(end_block - block, self.stripe_blocks - within);
+
+            let transferred = transfer(
+                &mut self.devices[(stripe % device_count) as usize],
+                stripe / device_count * self.stripe_blocks + within,
+                requested,
+                block - start_block,
+            )?;
+
+            block += transferred;
+
+            if transferred < requested {
+                break;
+            }
+        }
+
+        Ok(block.saturating_sub(start_block))
+    }
+}
+
+impl<D> BlockDevice for StripedDevice<D>
+where
+    D: BlockDevice,
+{
+    fn block_size(&self) -> u16 {
+        self.devices[0].block_size()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.block_size());
+        let block_count = (destination.len() / block_size) as u64;
+
+        self.transfer(start_block, block_count, |device, block, count, offset| {
+            let offset = offset as usize * block_size;
+            let len = count as usize * block_size;
+            device.read_blocks(block, &mut destination[offset..(offset + len)])
+        })
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        Some(self.block_count)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.devices.iter().any(|device| device.is_read_only())
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.block_size());
+        let block_count = (source.len() / block_size) as u64;
+
+        self.transfer(start_block, block_count, |device, block, count, offset| {
+            let offset = offset as usize * block_size;
+            let len = count as usize * block_size;
+            device.write_blocks(block, &source[offset..(offset + len)])
+        })
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        let _ = self.transfer(start_block, block_count, |device, block, count, _| {
+            device.prefetch(block, count);
+            Ok(count)
+        });
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        self.transfer(start_block, block_count, |device, block, count, _| {
+            device.discard(block, count).map(|_| count)
+        })
+        .map(|_| ())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.devices
+            .iter_mut()
+            .try_for_each(|device| device.flush())
+    }
+}
+
+/// Fails if there are no `devices`, or they don't all have the same block size.
+pub(crate) fn check_same_block_size<D: BlockDevice>(devices: &[D]) -> Result<(), ComposeError> {
+    let block_size = devices.first().ok_or(ComposeError::NoDevices)?.block_size();
+
+    if devices
+        .iter()
+        .all(|device| device.block_size() == block_size)
+    {
+        Ok(())
+    } else {
+        Err(ComposeError::MixedBlockSizes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::tests::MemoryDevice;
+    use alloc::vec;
+
+    mod concat {
+        use super::*;
+
+        crate::block_device_conformance_tests!(ConcatDevice::new(vec![
+            MemoryDevice::new(40),
+            MemoryDevice::new(1),
+            MemoryDevice::new(23),
+        ])
+        .unwrap());
+    }
+
+    mod striped {
+        use super::*;
+
+        // NOTE: the devices' blocks past the last whole stripe of the smallest aren't
+        // part of it
+        crate::block_device_conformance_tests!(StripedDevice::new(
+            vec![
+                MemoryDevice::new(35),
+                MemoryDevice::new(33),
+                MemoryDevice::new(40)
+            ],
+            4
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn composing_nothing_fails() {
+        assert_eq!(
+            ConcatDevice::<MemoryDevice>::new(vec![]).err(),
+            Some(ComposeError::NoDevices)
+        );
+        assert_eq!(
+            StripedDevice::new(vec![MemoryDevice::new(8)], 0).err(),
+            Some(ComposeError::EmptyStripe)
+        );
+    }
+}
@@ -0,0 +1,459 @@
+use crate::{BlockDevice, BlockDeviceError};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// What destinations are filled with beforehand, to tell which bytes a read changed.
+const FILL: u8 = 0xA5;
+
+/// How many bytes the huge read asks for, which is rounded down to whole blocks.
+const HUGE_READ_BYTES: usize = 8 << 20;
+
+/// How far past the end of the device the furthest read starts, in blocks.
+const FAR_PAST_END: u64 = 1 << 32;
+
+/// How a device broke the `BlockDevice` contract, and in which check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConformanceFailure {
+    /// A transfer that should have worked failed.
+    Failed {
+        check: &'static str,
+        error: BlockDeviceError,
+    },
+    /// A transfer handled more or fewer blocks than it should have.
+    WrongBlockCount {
+        check: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+    /// A read changed bytes of its destination beyond the blocks it said it read.
+    WroteBeyondBlocks { check: &'static str },
+    /// Reading the same blocks gave back something different, or something other than
+    /// what was written to them.
+    WrongData { check: &'static str },
+    /// `block_count` doesn't say where reads come to an end.
+    WrongBlockCountReported { reported: u64, actual: u64 },
+    /// A device that says it's read-only didn't refuse a write with
+    /// `BlockDeviceError::ReadOnly`.
+    AcceptedWrite,
+}
+
+impl fmt::Display for ConformanceFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed { check, error } => write!(f, "{}: {}", check, error),
+            Self::WrongBlockCount {
+                check,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: transferred {} blocks rather than {}",
+                check, actual, expected
+            ),
+            Self::WroteBeyondBlocks { check } => write!(
+                f,
+                "{}: changed the destination beyond the blocks it read",
+                check
+            ),
+            Self::WrongData { check } => write!(f, "{}: read back the wrong data", check),
+            Self::WrongBlockCountReported { reported, actual } => write!(
+                f,
+                "block_count: reported {} blocks, but reads end after {}",
+                reported, actual
+            ),
+            Self::AcceptedWrite => write!(f, "a read-only device accepted a write"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConformanceFailure {}
+
+type CheckResult = Result<(), ConformanceFailure>;
+
+/// Runs every check on `device`, stopping at the first failure. A writable device is
+/// written to, so it should be a scratch copy, though what's overwritten is put back.
+pub fn check_conformance<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    check_empty_read(device)?;
+    check_partial_block_read(device)?;
+    check_read_at_end(device)?;
+    check_read_past_end(device)?;
+    check_read_across_end(device)?;
+    check_huge_read(device)?;
+    check_block_count(device)?;
+    check_writes(device)
+}
+
+/// An empty destination reads nothing.
+pub fn check_empty_read<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    expect_read(device, "empty read", 0, 0, 0)
+}
+
+/// A destination that isn't a whole number of blocks has as many blocks read into it
+/// as fit, and the rest left alone, down to one too short to hold any.
+pub fn check_partial_block_read<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    let block_size = usize::from(device.block_size());
+    let end = device_end(device)?;
+
+    expect_read(device, "partial block read", 0, block_size - 1, 0)?;
+    expect_read(
+        device,
+        "partial block read",
+        0,
+        2 * block_size + 7,
+        end.min(2),
+    )
+}
+
+/// A read starting at the end of the device reads nothing.
+pub fn check_read_at_end<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    let block_size = usize::from(device.block_size());
+    let end = device_end(device)?;
+
+    expect_read(device, "read at end", end, block_size, 0)
+}
+
+/// Reads starting past the end of the device, just past it or far past it, read
+/// nothing rather than failing.
+pub fn check_read_past_end<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    let block_size = usize::from(device.block_size());
+    let end = device_end(device)?;
+
+    expect_read(device, "read past end", end + 1, block_size, 0)?;
+    expect_read(device, "read past end", end + FAR_PAST_END, block_size, 0)
+}
+
+/// A read running over the end of the device reads the blocks before it, and leaves
+/// the rest of the destination alone.
+pub fn check_read_across_end<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    let block_size = usize::from(device.block_size());
+    let end = device_end(device)?;
+    let start = end.saturating_sub(2);
+
+    expect_read(
+        device,
+        "read across end",
+        start,
+        4 * block_size,
+        end - start,
+    )
+}
+
+/// A read of megabytes at once gives back what reading the same blocks one at a time
+/// does.
+pub fn check_huge_read<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    const CHECK: &str = "huge read";
+
+    let block_size = usize::from(device.block_size());
+    let end = device_end(device)?;
+    let blocks = (HUGE_READ_BYTES / block_size) as u64;
+    let expected = end.min(blocks);
+
+    let mut huge = vec![FILL; blocks as usize * block_size];
+    read(device, CHECK, 0, &mut huge, expected)?;
+
+    let mut block = vec![0u8; block_size];
+
+    for (index, read_block) in huge.chunks(block_size).take(expected as usize).enumerate() {
+        read(device, CHECK, index as u64, &mut block, 1)?;
+
+        if block != read_block {
+            return Err(ConformanceFailure::WrongData { check: CHECK });
+        }
+    }
+
+    Ok(())
+}
+
+/// `block_count`, if the device knows it, is where reads come to an end.
+pub fn check_block_count<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    let reported = match device.block_count() {
+        Some(reported) => reported,
+        None => return Ok(()),
+    };
+
+    let actual = probe_end(device)?;
+
+    if reported != actual {
+        return Err(ConformanceFailure::WrongBlockCountReported { reported, actual });
+    }
+
+    Ok(())
+}
+
+/// A read-only device refuses writes with `BlockDeviceError::ReadOnly`. A writable one
+/// writes nothing for an empty source or one starting at or past the end, cuts writes
+/// running over the end short, and reads back what was written. The blocks written to
+/// are put back as they were.
+pub fn check_writes<D: BlockDevice + ?Sized>(device: &mut D) -> CheckResult {
+    const CHECK: &str = "write";
+
+    let block_size = usize::from(device.block_size());
+    let end = device_end(device)?;
+
+    if device.is_read_only() {
+        return match device.write_blocks(0, &vec![0u8; block_size]) {
+            Err(BlockDeviceError::ReadOnly) => Ok(()),
+            _ => Err(ConformanceFailure::AcceptedWrite),
+        };
+    }
+
+    let start = end.saturating_sub(1);
+    let written = end - start;
+
+    let mut original = vec![0u8; written as usize * block_size];
+    read(device, CHECK, start, &mut original, written)?;
+
+    let pattern = (0..2 * block_size)
+        .map(|index| (index % 251) as u8)
+        .collect::<Vec<_>>();
+
+    let results = [
+        (start, &pattern[..0], 0),
+        (end, &pattern[..block_size], 0),
+        (end + 1, &pattern[..block_size], 0),
+        (start, &pattern[..], written),
+    ];
+
+    for (block, source, expected) in results.iter().copied() {
+        let actual =
+            device
+                .write_blocks(block, source)
+                .map_err(|error| ConformanceFailure::Failed {
+                    check: CHECK,
+                    error,
+                })?;
+
+        if actual != expected {
+            return Err(ConformanceFailure::WrongBlockCount {
+                check: CHECK,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    let mut read_back = vec![0u8; original.len()];
+    read(device, CHECK, start, &mut read_back, written)?;
+
+    device
+        .write_blocks(start, &original)
+        .and_then(|_| device.flush())
+        .map_err(|error| ConformanceFailure::Failed {
+            check: CHECK,
+            error,
+        })?;
+
+    if read_back[..] != pattern[..read_back.len()] {
+        return Err(ConformanceFailure::WrongData { check: CHECK });
+    }
+
+    Ok(())
+}
+
+/// Reads `len` bytes' worth of blocks from `start` into a destination filled with
+/// `FILL`, expecting `expected` blocks to be read and nothing past them to change.
+fn expect_read<D: BlockDevice + ?Sized>(
+    device: &mut D,
+    check: &'static str,
+    start: u64,
+    len: usize,
+    expected: u64,
+) -> CheckResult {
+    let block_size = usize::from(device.block_size());
+    let mut destination = vec![FILL; len];
+
+    read(device, check, start, &mut destination, expected)?;
+
+    let read_len = expected as usize * block_size;
+
+    if destination[read_len..].iter().any(|&b| b != FILL) {
+        return Err(ConformanceFailure::WroteBeyondBlocks { check });
+    }
+
+    Ok(())
+}
+
+fn read<D: BlockDevice + ?Sized>(
+    device: &mut D,
+    check: &'static str,
+    start: u64,
+    destination: &mut [u8],
+    expected: u64,
+) -> CheckResult {
+    let actual = device
+        .read_blocks(start, destination)
+        .map_err(|error| ConformanceFailure::Failed { check, error })?;
+
+    if actual != expected {
+        return Err(ConformanceFailure::WrongBlockCount {
+            check,
+            expected,
+            actual,
+        });
+    }
+
+    Ok(())
+}
+
+/// The number of blocks the device has, as it says, or as found by reading.
+fn device_end<D: BlockDevice + ?Sized>(device: &mut D) -> Result<u64, ConformanceFailure> {
+    match device.block_count() {
+        Some(block_count) => Ok(block_count),
+        None => probe_end(device),
+    }
+}
+
+/// Finds the first block that can't be read, by doubling how far it reads until a
+/// read comes back empty, then halving the distance between that and the last that
+/// didn't.
+fn probe_end<D: BlockDevice + ?Sized>(device: &mut D) -> Result<u64, ConformanceFailure> {
+    const CHECK: &str = "finding the end";
+
+    let mut block = vec![0u8; usize::from(device.block_size())];
+    let mut can_read = |device: &mut D, index: u64| {
+        device
+            .read_blocks(index, &mut block)
+            .map(|read| read > 0)
+            .map_err(|error| ConformanceFailure::Failed {
+                check: CHECK,
+                error,
+            })
+    };
+
+    if !can_read(device, 0)? {
+        return Ok(0);
+    }
+
+    let (mut readable, mut unreadable) = (0, 1);
+
+    while can_read(device, unreadable)? {
+        readable = unreadable;
+        unreadable *= 2;
+    }
+
+    while unreadable - readable > 1 {
+        let middle = readable + (unreadable - readable) / 2;
+
+        if can_read(device, middle)? {
+            readable = middle;
+        } else {
+            unreadable = middle;
+        }
+    }
+
+    Ok(unreadable)
+}
+
+/// Adds a test for each of the conformance checks to the module it's used in, which
+/// run on the device made by the expression it's given, made again for each test, e.g.
+/// `block_device_conformance_tests!(FileBlockDevice::new(File::open("test.img").unwrap(), 0).unwrap())`.
+/// Writable devices are written to, so should be scratch copies.
+#[macro_export]
+macro_rules! block_device_conformance_tests {
+    ($make_device:expr) => {
+        $crate::block_device_conformance_tests!(
+            $make_device;
+            empty_read => check_empty_read,
+            partial_block_read => check_partial_block_read,
+            read_at_end => check_read_at_end,
+            read_past_end => check_read_past_end,
+            read_across_end => check_read_across_end,
+            huge_read => check_huge_read,
+            block_count => check_block_count,
+            writes => check_writes,
+        );
+    };
+    ($make_device:expr; $($test:ident => $check:ident,)*) => {
+        $(
+            #[test]
+            fn $test() {
+                let mut device = $make_device;
+
+                if let Err(failure) = $crate::conformance::$check(&mut device) {
+                    panic!("{}", failure);
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{BlockDevice, BlockDeviceError};
+    use alloc::vec::Vec;
+    use core::cmp;
+    use core::ops::Range;
+
+    /// A device held in memory, to run the checks on, and to build the devices made
+    /// from others out of.
+    pub(crate) struct MemoryDevice {
+        bytes: Vec<u8>,
+    }
+
+    impl MemoryDevice {
+        const BLOCK_SIZE: usize = 512;
+
+        /// A device of `block_count` blocks, each filled with a byte from its index, so
+        /// that blocks that are mixed up read back wrong.
+        pub(crate) fn new(block_count: u64) -> Self {
+            let bytes = (0..block_count)
+                .flat_map(|block| core::iter::repeat_n((block % 251) as u8, Self::BLOCK_SIZE))
+                .collect();
+
+            Self { bytes }
+        }
+
+        /// The bytes of as many whole blocks of `len` bytes from `start_block` as the
+        /// device has.
+        fn range(&self, start_block: u64, len: usize) -> Range<usize> {
+            let block_count = (self.bytes.len() / Self::BLOCK_SIZE) as u64;
+            let start = cmp::min(start_block, block_count);
+            let blocks = cmp::min(block_count - start, (len / Self::BLOCK_SIZE) as u64);
+
+            let start = start as usize * Self::BLOCK_SIZE;
+            start..(start + blocks as usize * Self::BLOCK_SIZE)
+        }
+    }
+
+    impl BlockDevice for MemoryDevice {
+        fn block_size(&self) -> u16 {
+            Self::BLOCK_SIZE as u16
+        }
+
+        fn read_blocks(
+            &mut self,
+            start_block: u64,
+            destination: &mut [u8],
+        ) -> Result<u64, BlockDeviceError> {
+            let range = self.range(start_block, destination.len());
+            let len = range.len();
+
+            destination[..len].copy_from_slice(&self.bytes[range]);
+            Ok((len / Self::BLOCK_SIZE) as u64)
+        }
+
+        fn block_count(&self) -> Option<u64> {
+            Some((self.bytes.len() / Self::BLOCK_SIZE) as u64)
+        }
+
+        fn is_read_only(&self) -> bool {
+            false
+        }
+
+        fn write_blocks(
+            &mut self,
+            start_block: u64,
+            source: &[u8],
+        ) -> Result<u64, BlockDeviceError> {
+            let range = self.range(start_block, source.len());
+            let len = range.len();
+
+            self.bytes[range].copy_from_slice(&source[..len]);
+            Ok((len / Self::BLOCK_SIZE) as u64)
+        }
+    }
+
+    crate::block_device_conformance_tests!(MemoryDevice::new(64));
+}
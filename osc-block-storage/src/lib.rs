@@ -1,8 +1,95 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+use core::fmt;
+
+pub mod compose;
+pub mod conformance;
+pub mod linear;
+#[cfg(feature = "std")]
+pub mod nbd;
+#[cfg(feature = "object-store")]
+pub mod object;
+pub mod remap;
+pub mod retry;
+#[cfg(feature = "std")]
+pub mod split;
+#[cfg(feature = "aes-xts")]
+pub mod xts;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockDeviceError {
+    /// The backend failed to transfer the requested blocks.
+    Io,
+    /// A write was attempted on a device that doesn't accept them.
+    ReadOnly,
+    /// The device has gone away, e.g. a USB stick that was pulled out, and nothing
+    /// more can be done with it until it's back.
+    Gone,
+}
+
+impl fmt::Display for BlockDeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io => write!(f, "the device reported an I/O error"),
+            Self::ReadOnly => write!(f, "the device is read-only"),
+            Self::Gone => write!(f, "the device has gone away"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BlockDeviceError {}
+
 pub trait BlockDevice {
     fn block_size(&self) -> u16;
-    fn read_blocks(&mut self, start_block: u64, destination: &mut [u8]) -> u64;
+
+    /// Reads whole blocks into `destination`, as many as it has room for, returning the
+    /// number of blocks read, which is only less than that when the end of the device
+    /// is reached. Reads starting at or past the end read nothing rather than failing.
+    /// What's past the blocks read is left alone, including any part of a block at the
+    /// end of `destination`, so an empty one, or one shorter than a block, reads
+    /// nothing. `conformance` checks devices keep to this.
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError>;
+
+    /// The size of the device in blocks, if it's known without probing for the end.
+    fn block_count(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether the backend can't accept writes (e.g. it's served over HTTP, or is a
+    /// read-only slice). Devices are assumed to be read-only unless they say otherwise.
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    /// Writes whole blocks from `source`, returning the number of blocks written,
+    /// which is only less than supplied when the end of the device is reached. As with
+    /// reads, any part of a block at the end of `source` is ignored.
+    fn write_blocks(&mut self, _start_block: u64, _source: &[u8]) -> Result<u64, BlockDeviceError> {
+        Err(BlockDeviceError::ReadOnly)
+    }
+
+    /// A hint that the given blocks are about to be read, so that backends with high
+    /// latency can start fetching them. Doing nothing is always correct.
+    fn prefetch(&mut self, _start_block: u64, _block_count: u64) {}
+
+    /// A hint that the given blocks no longer hold anything that matters, so that flash
+    /// storage can erase them ahead of time (a TRIM). What they read back as afterwards
+    /// is unspecified. Doing nothing is always correct.
+    fn discard(&mut self, _start_block: u64, _block_count: u64) -> Result<(), BlockDeviceError> {
+        Ok(())
+    }
+
+    /// Makes sure everything written so far has reached stable storage.
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        Ok(())
+    }
 }
 
 #[cfg(feature = "std")]
@@ -11,19 +98,33 @@ pub mod virt {
     use std::{
         cmp,
         fs::File,
-        io::{Read, Seek, SeekFrom},
+        io::{Read, Seek, SeekFrom, Write},
     };
 
     pub struct FileBlockDevice {
         file: File,
         offset: u64,
         len: u64,
+        writable: bool,
     }
 
     impl FileBlockDevice {
-        pub fn new(mut file: File, offset: u64) -> Self {
-            let len = file.seek(SeekFrom::End(0)).unwrap();
-            Self { file, offset, len }
+        /// Fails if the file's length can't be found, e.g. because it's a pipe.
+        pub fn new(mut file: File, offset: u64) -> std::io::Result<Self> {
+            let len = file.seek(SeekFrom::End(0))?;
+
+            Ok(Self {
+                file,
+                offset,
+                len,
+                writable: false,
+            })
+        }
+
+        /// Allows writes, which needs the file to have been opened for writing.
+        pub fn writable(mut self, writable: bool) -> Self {
+            self.writable = writable;
+            self
         }
     }
 
@@ -32,21 +133,19 @@ pub mod virt {
             512
         }
 
-        fn read_blocks(&mut self, start_block: u64, dest: &mut [u8]) -> u64 {
+        fn read_blocks(
+            &mut self,
+            start_block: u64,
+            dest: &mut [u8],
+        ) -> Result<u64, BlockDeviceError> {
             let block_size = self.block_size() as u64;
 
-            if dest.is_empty() {
-                panic!("The destination must be at least one block in size");
-            }
-
-            if dest.len() % (block_size as usize) > 0 {
-                panic!("The destination must be a multiple of the block size");
-            }
-
             let offset = self.offset + (start_block * block_size);
-            self.file.seek(SeekFrom::Start(offset)).unwrap();
+            self.file
+                .seek(SeekFrom::Start(offset))
+                .map_err(device_error)?;
 
-            let available_bytes = self.len - offset;
+            let available_bytes = self.len.saturating_sub(offset);
             let available_blocks = available_bytes / block_size;
 
             let dest_blocks = dest.len() as u64 / block_size;
@@ -56,9 +155,109 @@ pub mod virt {
 
             let dest = &mut dest[0..(read_bytes as usize)];
 
-            self.file.read_exact(dest).unwrap();
+            self.file.read_exact(dest).map_err(device_error)?;
+
+            Ok(read_blocks)
+        }
+
+        fn block_count(&self) -> Option<u64> {
+            Some(self.len.saturating_sub(self.offset) / self.block_size() as u64)
+        }
+
+        fn is_read_only(&self) -> bool {
+            !self.writable
+        }
+
+        fn write_blocks(
+            &mut self,
+            start_block: u64,
+            source: &[u8],
+        ) -> Result<u64, BlockDeviceError> {
+            let block_size = self.block_size() as u64;
+
+            if !self.writable {
+                return Err(BlockDeviceError::ReadOnly);
+            }
+
+            let offset = self.offset + (start_block * block_size);
+            self.file
+                .seek(SeekFrom::Start(offset))
+                .map_err(device_error)?;
+
+            // NOTE: images don't grow, writes past the end are cut short like reads
+            let available_blocks = self.len.saturating_sub(offset) / block_size;
+            let write_blocks = cmp::min(available_blocks, source.len() as u64 / block_size);
+            let write_bytes = write_blocks * block_size;
+
+            self.file
+                .write_all(&source[0..(write_bytes as usize)])
+                .map_err(device_error)?;
+
+            Ok(write_blocks)
+        }
+
+        fn flush(&mut self) -> Result<(), BlockDeviceError> {
+            self.file.sync_all().map_err(device_error)
+        }
+    }
+
+    fn device_error(err: std::io::Error) -> BlockDeviceError {
+        // NOTE: ENXIO and ENODEV, which are what's reported once a disk has been
+        // removed, rather than EIO
+        match err.raw_os_error() {
+            Some(6) | Some(19) if cfg!(unix) => BlockDeviceError::Gone,
+            _ => BlockDeviceError::Io,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::fs::{self, OpenOptions};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// A file of `len` bytes in the temporary directory, that's gone once it's
+        /// closed, on systems that let open files be removed.
+        fn scratch_file(len: u64) -> File {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let path = std::env::temp_dir().join(format!(
+                "osc-block-storage-{}-{}.img",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::SeqCst)
+            ));
+
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .unwrap();
+
+            file.set_len(len).unwrap();
+            let _ = fs::remove_file(&path);
+            file
+        }
+
+        mod writable {
+            use super::*;
+
+            // NOTE: the offset leaves part of a block at the end, which isn't part of
+            // the device
+            crate::block_device_conformance_tests!(FileBlockDevice::new(
+                scratch_file(64 * 512 + 100),
+                1024
+            )
+            .unwrap()
+            .writable(true));
+        }
+
+        mod read_only {
+            use super::*;
 
-            read_blocks
+            crate::block_device_conformance_tests!(
+                FileBlockDevice::new(scratch_file(64 * 512), 0).unwrap()
+            );
         }
     }
 }
@@ -0,0 +1,365 @@
+use crate::compose::{check_same_block_size, ComposeError};
+use crate::{BlockDevice, BlockDeviceError};
+use alloc::vec::Vec;
+use core::{cmp, fmt};
+
+/// A run of the device's blocks that's mapped onto blocks of one of the devices it's
+/// made from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LinearSegment {
+    /// The first block of the run.
+    pub start: u64,
+    /// How many blocks the run has.
+    pub length: u64,
+    /// Which of the devices the run is mapped onto, or `None` if it reads as zeros.
+    pub device: Option<usize>,
+    /// The block of that device the run starts at.
+    pub offset: u64,
+}
+
+/// Maps runs of blocks onto blocks of other devices, as device-mapper's linear target
+/// does, e.g. for an image whose pieces are laid out oddly in one or more files.
+/// Blocks no run covers read as zeros and can't be written.
+pub struct LinearDevice<D> {
+    devices: Vec<D>,
+    /// In order of where they start.
+    segments: Vec<LinearSegment>,
+}
+
+impl<D> LinearDevice<D>
+where
+    D: BlockDevice,
+{
+    /// Maps `segments` onto `devices`, in the order they're given, which fails if there
+    /// are no devices, they don't all have the same block size, a segment refers to a
+    /// device that isn't there, or segments overlap.
+    pub fn new(
+        devices: Vec<D>,
+        segments: impl IntoIterator<Item = LinearSegment>,
+    ) -> Result<Self, ComposeError> {
+        check_same_block_size(&devices)?;
+
+        let mut segments = segments
+            .into_iter()
+            .filter(|segment| segment.length > 0)
+            .collect::<Vec<_>>();
+        segments.sort_unstable_by_key(|segment| segment.start);
+
+        let devices_there = segments.iter().all(|segment| match segment.device {
+            Some(device) => device < devices.len(),
+            None => true,
+        });
+
+        if !devices_there {
+            return Err(ComposeError::MissingDevice);
+        }
+
+        if segments.windows(2).any(|pair| overlap(&pair[0], &pair[1])) {
+            return Err(ComposeError::OverlappingSegments);
+        }
+
+        Ok(Self { devices, segments })
+    }
+
+    pub fn into_inner(self) -> Vec<D> {
+        self.devices
+    }
+
+    /// Goes through what covers `block_count` blocks from `start_block`, with the
+    /// segment covering each run of them, if any, the block of its device the run
+    /// starts at, how many blocks it has, and how far into the whole transfer it is,
+    /// until `transfer` gives back fewer blocks than it was asked for.
+    fn transfer<F>(
+        &mut self,
+        start_block: u64,
+        block_count: u64,
+        mut transfer: F,
+    ) -> Result<u64, BlockDeviceError>
+    where
+        F: FnMut(Option<&mut D>, u64, u64, u64) -> Result<u64, BlockDeviceError>,
+    {
+        let end_block = cmp::min(start_block.saturating_add(block_count), self.block_total());
+        let (devices, segments) = (&mut self.devices, &self.segments);
+        let mut block = start_block;
+
+        while block < end_block {
+            let index = segments.partition_point(|segment| segment.start + segment.length <= block);
+
+            let (device, device_block, run_end) = match segments.get(index) {
+                Some(segment) if segment.start <= block => (
+                    segment.device.map(|device| &mut devices[device]),
+                    segment.offset + (block - segment.start),
+                    segment.start + segment.length,
+                ),
+                Some(segment) => (None, 0, segment.start),
+                None => (None, 0, end_block),
+            };
+
+            let requested = cmp::min(end_block, run_end) - block;
+            let transferred = transfer(device, device_block, requested, block - start_block)?;
+
+            block += transferred;
+
+            if transferred < requested {
+                break;
+            }
+        }
+
+        Ok(block.saturating_sub(start_block))
+    }
+
+    fn block_total(&self) -> u64 {
+        self.segments
+            .last()
+            .map_or(0, |segment| segment.start + segment.length)
+    }
+}
+
+impl<D> BlockDevice for LinearDevice<D>
+where
+    D: BlockDevice,
+{
+    fn block_size(&self) -> u16 {
+        self.devices[0].block_size()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.block_size());
+        let block_count = (destination.len() / block_size) as u64;
+
+        self.transfer(start_block, block_count, |device, block, count, offset| {
+            let offset = offset as usize * block_size;
+            let destination = &mut destination[offset..(offset + count as usize * block_size)];
+
+            match device {
+                Some(device) => device.read_blocks(block, destination),
+                None => {
+                    destination.iter_mut().for_each(|byte| *byte = 0);
+                    Ok(count)
+                }
+            }
+        })
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        Some(self.block_total())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.devices.iter().any(|device| device.is_read_only())
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.block_size());
+        let block_count = (source.len() / block_size) as u64;
+
+        self.transfer(start_block, block_count, |device, block, count, offset| {
+            let offset = offset as usize * block_size;
+            let source = &source[offset..(offset + count as usize * block_size)];
+
+            match device {
+                Some(device) => device.write_blocks(block, source),
+                // NOTE: there's nowhere for the data to go
+                None => Err(BlockDeviceError::Io),
+            }
+        })
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        let _ = self.transfer(start_block, block_count, |device, block, count, _| {
+            if let Some(device) = device {
+                device.prefetch(block, count);
+            }
+
+            Ok(count)
+        });
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        self.transfer(start_block, block_count, |device, block, count, _| {
+            if let Some(device) = device {
+                device.discard(block, count)?;
+            }
+
+            Ok(count)
+        })
+        .map(|_| ())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.devices
+            .iter_mut()
+            .try_for_each(|device| device.flush())
+    }
+}
+
+/// A mapping table in the form device-mapper takes, one run of blocks per line:
+/// `START LENGTH linear DEVICE OFFSET` maps the run onto `DEVICE` from `OFFSET`, and
+/// `START LENGTH zero` leaves it reading as zeros. Blank lines and lines starting with
+/// `#` are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinearTable<'t> {
+    pub segments: Vec<LinearSegment>,
+    /// The devices the segments are mapped onto, named as they are in the table, in
+    /// the order they're first named.
+    pub devices: Vec<&'t str>,
+}
+
+/// A line of a mapping table that couldn't be made sense of, or maps blocks that an
+/// earlier line already has.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LinearTableError {
+    /// Counting from 1.
+    pub line: usize,
+}
+
+impl fmt::Display for LinearTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} of the mapping table is invalid", self.line)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LinearTableError {}
+
+impl<'t> LinearTable<'t> {
+    pub fn parse(table: &'t str) -> Result<Self, LinearTableError> {
+        let mut parsed = Self {
+            segments: Vec::new(),
+            devices: Vec::new(),
+        };
+
+        for (index, line) in table.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = LinearTableError { line: index + 1 };
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let number = |field: &str| field.parse::<u64>().map_err(|_| error);
+
+            let segment = match fields[..] {
+                [start, length, "linear", device, offset] => {
+                    let device = match parsed.devices.iter().position(|&name| name == device) {
+                        Some(index) => index,
+                        None => {
+                            parsed.devices.push(device);
+                            parsed.devices.len() - 1
+                        }
+                    };
+
+                    LinearSegment {
+                        start: number(start)?,
+                        length: number(length)?,
+                        device: Some(device),
+                        offset: number(offset)?,
+                    }
+                }
+                [start, length, "zero"] => LinearSegment {
+                    start: number(start)?,
+                    length: number(length)?,
+                    device: None,
+                    offset: 0,
+                },
+                _ => return Err(error),
+            };
+
+            if parsed.segments.iter().any(|other| overlap(other, &segment)) {
+                return Err(error);
+            }
+
+            parsed.segments.push(segment);
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(feature = "std")]
+impl LinearDevice<crate::virt::FileBlockDevice> {
+    /// Opens the mapping table at `path`, whose devices are files named relative to the
+    /// directory it's in, as a device of 512-byte blocks.
+    pub fn open_table(path: impl AsRef<std::path::Path>, writable: bool) -> std::io::Result<Self> {
+        use std::io;
+
+        let path = path.as_ref();
+        let table = std::fs::read_to_string(path)?;
+        let table = LinearTable::parse(&table)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        if table.devices.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the mapping table maps nothing onto any file",
+            ));
+        }
+
+        let directory = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+        let devices = table
+            .devices
+            .iter()
+            .map(|name| {
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(writable)
+                    .open(directory.join(name))?;
+                Ok(crate::virt::FileBlockDevice::new(file, 0)?.writable(writable))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Self::new(devices, table.segments)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+fn overlap(a: &LinearSegment, b: &LinearSegment) -> bool {
+    a.length > 0 && b.length > 0 && a.start < b.start + b.length && b.start < a.start + a.length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::tests::MemoryDevice;
+    use alloc::vec;
+
+    fn segment(start: u64, length: u64, device: Option<usize>, offset: u64) -> LinearSegment {
+        LinearSegment {
+            start,
+            length,
+            device,
+            offset,
+        }
+    }
+
+    // NOTE: out of order, with a hole that reads as zeros, and the end mapped onto
+    // the end of one of the devices so that writes over it are cut short
+    crate::block_device_conformance_tests!(LinearDevice::new(
+        vec![MemoryDevice::new(32), MemoryDevice::new(16)],
+        vec![
+            segment(20, 12, Some(0), 4),
+            segment(0, 16, Some(1), 0),
+            segment(16, 4, None, 0),
+            segment(32, 8, Some(0), 24),
+        ],
+    )
+    .unwrap());
+
+    #[test]
+    fn invalid_segments_fail() {
+        let missing =
+            LinearDevice::new(vec![MemoryDevice::new(8)], vec![segment(0, 8, Some(1), 0)]);
+        assert_eq!(missing.err(), Some(ComposeError::MissingDevice));
+
+        let overlapping = LinearDevice::new(
+            vec![MemoryDevice::new(8)],
+            vec![segment(0, 4, Some(0), 0), segment(2, 4, Some(0), 4)],
+        );
+        assert_eq!(overlapping.err(), Some(ComposeError::OverlappingSegments));
+    }
+}
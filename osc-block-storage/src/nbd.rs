@@ -0,0 +1,458 @@
+use crate::{BlockDevice, BlockDeviceError};
+use std::io::{self, Read, Write};
+
+const NBDMAGIC: u64 = 0x4e42_444d_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054;
+const OPTION_REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) | 1;
+const REP_ERR_INVALID: u32 = (1 << 31) | 3;
+
+const INFO_EXPORT: u16 = 0;
+const INFO_BLOCK_SIZE: u16 = 3;
+
+const TRANSMISSION_HAS_FLAGS: u16 = 1 << 0;
+const TRANSMISSION_READ_ONLY: u16 = 1 << 1;
+const TRANSMISSION_SEND_FLUSH: u16 = 1 << 2;
+const TRANSMISSION_SEND_FUA: u16 = 1 << 3;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+const CMD_FLAG_FUA: u16 = 1 << 0;
+
+const EPERM: u32 = 1;
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+const ENOSPC: u32 = 28;
+
+/// The most a single request may transfer, as advertised to clients.
+const MAX_REQUEST_SIZE: u32 = 32 << 20;
+const MAX_OPTION_SIZE: u32 = 4096;
+
+/// Exports a device over the NBD protocol (fixed newstyle handshake, simple replies),
+/// so that the kernel's NBD driver or qemu can use it as a disk. The device is exported
+/// read-only unless it accepts writes.
+pub struct NbdServer<D> {
+    device: D,
+    export_name: String,
+}
+
+impl<D> NbdServer<D>
+where
+    D: BlockDevice,
+{
+    pub fn new(device: D) -> Self {
+        Self {
+            device,
+            export_name: String::new(),
+        }
+    }
+
+    /// The name the export is listed under. Clients asking for any name get the device.
+    pub fn export_name(mut self, export_name: impl Into<String>) -> Self {
+        self.export_name = export_name.into();
+        self
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Serves one client on `stream`, until it disconnects.
+    pub fn serve<S: Read + Write>(&mut self, mut stream: S) -> io::Result<()> {
+        let size = self.size()?;
+
+        if self.negotiate(&mut stream, size)? {
+            self.transmit(&mut stream, size)?;
+        }
+
+        Ok(())
+    }
+
+    /// The size of the device in bytes. Devices that don't know their size are probed
+    /// for the last block that can be read.
+    fn size(&mut self) -> io::Result<u64> {
+        let block_size = u64::from(self.device.block_size());
+
+        if let Some(block_count) = self.device.block_count() {
+            return Ok(block_count * block_size);
+        }
+
+        let mut block = vec![0u8; block_size as usize];
+        let mut readable = |device: &mut D, index: u64| -> io::Result<bool> {
+            Ok(device.read_blocks(index, &mut block).map_err(io_error)? == 1)
+        };
+
+        if !readable(&mut self.device, 0)? {
+            return Ok(0);
+        }
+
+        // Double until past the end, then narrow down on it
+        let mut low = 0;
+        let mut high = 1;
+
+        while readable(&mut self.device, high)? {
+            low = high;
+            high *= 2;
+        }
+
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+
+            if readable(&mut self.device, middle)? {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        Ok(high * block_size)
+    }
+
+    fn transmission_flags(&self) -> u16 {
+        if self.device.is_read_only() {
+            TRANSMISSION_HAS_FLAGS | TRANSMISSION_READ_ONLY
+        } else {
+            TRANSMISSION_HAS_FLAGS | TRANSMISSION_SEND_FLUSH | TRANSMISSION_SEND_FUA
+        }
+    }
+
+    /// Goes through the handshake and option haggling, giving whether the client went
+    /// on to the transmission phase.
+    fn negotiate<S: Read + Write>(&mut self, stream: &mut S, size: u64) -> io::Result<bool> {
+        let mut greeting = Vec::with_capacity(18);
+        greeting.extend_from_slice(&NBDMAGIC.to_be_bytes());
+        greeting.extend_from_slice(&IHAVEOPT.to_be_bytes());
+        greeting.extend_from_slice(&(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes());
+        stream.write_all(&greeting)?;
+        stream.flush()?;
+
+        let client_flags = read_u32(stream)? as u16;
+
+        if client_flags & FLAG_FIXED_NEWSTYLE == 0 {
+            return Err(invalid_data("the client doesn't speak fixed newstyle"));
+        }
+
+        loop {
+            if read_u64(stream)? != IHAVEOPT {
+                return Err(invalid_data("expected an option"));
+            }
+
+            let option = read_u32(stream)?;
+            let length = read_u32(stream)?;
+
+            if length > MAX_OPTION_SIZE {
+                return Err(invalid_data("option too long"));
+            }
+
+            let mut data = vec![0u8; length as usize];
+            stream.read_exact(&mut data)?;
+
+            match option {
+                OPT_EXPORT_NAME => {
+                    let mut reply = Vec::with_capacity(10 + 124);
+                    reply.extend_from_slice(&size.to_be_bytes());
+                    reply.extend_from_slice(&self.transmission_flags().to_be_bytes());
+
+                    if client_flags & FLAG_NO_ZEROES == 0 {
+                        reply.resize(reply.len() + 124, 0);
+                    }
+
+                    stream.write_all(&reply)?;
+                    stream.flush()?;
+                    return Ok(true);
+                }
+                OPT_ABORT => {
+                    write_option_reply(stream, option, REP_ACK, &[])?;
+                    return Ok(false);
+                }
+                OPT_LIST => {
+                    let name = self.export_name.as_bytes();
+                    let mut server = Vec::with_capacity(4 + name.len());
+                    server.extend_from_slice(&(name.len() as u32).to_be_bytes());
+                    server.extend_from_slice(name);
+
+                    write_option_reply(stream, option, REP_SERVER, &server)?;
+                    write_option_reply(stream, option, REP_ACK, &[])?;
+                }
+                OPT_INFO | OPT_GO => {
+                    if !is_valid_info_request(&data) {
+                        write_option_reply(stream, option, REP_ERR_INVALID, &[])?;
+                        continue;
+                    }
+
+                    let mut export = Vec::with_capacity(12);
+                    export.extend_from_slice(&INFO_EXPORT.to_be_bytes());
+                    export.extend_from_slice(&size.to_be_bytes());
+                    export.extend_from_slice(&self.transmission_flags().to_be_bytes());
+                    write_option_reply(stream, option, REP_INFO, &export)?;
+
+                    let block_size = u32::from(self.device.block_size());
+                    let mut block_sizes = Vec::with_capacity(14);
+                    block_sizes.extend_from_slice(&INFO_BLOCK_SIZE.to_be_bytes());
+                    block_sizes.extend_from_slice(&1u32.to_be_bytes());
+                    block_sizes.extend_from_slice(&block_size.to_be_bytes());
+                    block_sizes.extend_from_slice(&MAX_REQUEST_SIZE.to_be_bytes());
+                    write_option_reply(stream, option, REP_INFO, &block_sizes)?;
+
+                    write_option_reply(stream, option, REP_ACK, &[])?;
+
+                    if option == OPT_GO {
+                        return Ok(true);
+                    }
+                }
+                _ => write_option_reply(stream, option, REP_ERR_UNSUP, &[])?,
+            }
+        }
+    }
+
+    fn transmit<S: Read + Write>(&mut self, stream: &mut S, size: u64) -> io::Result<()> {
+        loop {
+            let mut request = [0u8; 28];
+
+            match stream.read_exact(&mut request) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err),
+            }
+
+            let magic = be_u64(&request[0..4]) as u32;
+            let flags = be_u64(&request[4..6]) as u16;
+            let command = be_u64(&request[6..8]) as u16;
+            let handle = &request[8..16];
+            let offset = be_u64(&request[16..24]);
+            let length = be_u64(&request[24..28]) as u32;
+
+            if magic != REQUEST_MAGIC {
+                return Err(invalid_data("expected a request"));
+            }
+
+            let in_bounds = length <= MAX_REQUEST_SIZE
+                && offset
+                    .checked_add(u64::from(length))
+                    .is_some_and(|end| end <= size);
+
+            match command {
+                CMD_READ => {
+                    let mut data = vec![0u8; length as usize];
+
+                    let error = if !in_bounds {
+                        EINVAL
+                    } else {
+                        error_code(self.read_bytes(offset, &mut data))
+                    };
+
+                    let data = if error == 0 { &data[..] } else { &[] };
+                    write_reply(stream, error, handle, data)?;
+                }
+                CMD_WRITE => {
+                    // NOTE: the data has to be taken off the stream even if the write is
+                    // refused
+                    if length > MAX_REQUEST_SIZE {
+                        return Err(invalid_data("write too long"));
+                    }
+
+                    let mut data = vec![0u8; length as usize];
+                    stream.read_exact(&mut data)?;
+
+                    let error = if self.device.is_read_only() {
+                        EPERM
+                    } else if !in_bounds {
+                        ENOSPC
+                    } else {
+                        let mut result = self.write_bytes(offset, &data);
+
+                        if result.is_ok() && flags & CMD_FLAG_FUA != 0 {
+                            result = self.device.flush();
+                        }
+
+                        error_code(result)
+                    };
+
+                    write_reply(stream, error, handle, &[])?;
+                }
+                CMD_FLUSH => {
+                    let error = error_code(self.device.flush());
+                    write_reply(stream, error, handle, &[])?;
+                }
+                CMD_DISC => return Ok(()),
+                _ => write_reply(stream, EINVAL, handle, &[])?,
+            }
+        }
+    }
+
+    /// Reads bytes from anywhere on the device, reading whole blocks around them.
+    fn read_bytes(&mut self, offset: u64, destination: &mut [u8]) -> Result<(), BlockDeviceError> {
+        if destination.is_empty() {
+            return Ok(());
+        }
+
+        let block_size = u64::from(self.device.block_size());
+        let first_block = offset / block_size;
+        let end_block = (offset + destination.len() as u64).div_ceil(block_size);
+        let skip = (offset % block_size) as usize;
+
+        if skip == 0 && (destination.len() as u64).is_multiple_of(block_size) {
+            return self.read_blocks_exact(first_block, destination);
+        }
+
+        let mut blocks = vec![0u8; ((end_block - first_block) * block_size) as usize];
+        self.read_blocks_exact(first_block, &mut blocks)?;
+        destination.copy_from_slice(&blocks[skip..(skip + destination.len())]);
+
+        Ok(())
+    }
+
+    /// Writes bytes anywhere on the device, reading back the blocks at either end if the
+    /// bytes only cover part of them.
+    fn write_bytes(&mut self, offset: u64, source: &[u8]) -> Result<(), BlockDeviceError> {
+        if source.is_empty() {
+            return Ok(());
+        }
+
+        let block_size = u64::from(self.device.block_size());
+        let first_block = offset / block_size;
+        let end_block = (offset + source.len() as u64).div_ceil(block_size);
+        let skip = (offset % block_size) as usize;
+
+        if skip == 0 && (source.len() as u64).is_multiple_of(block_size) {
+            return self.write_blocks_exact(first_block, source);
+        }
+
+        let mut blocks = vec![0u8; ((end_block - first_block) * block_size) as usize];
+        let last_block_start = blocks.len() - block_size as usize;
+
+        self.read_blocks_exact(first_block, &mut blocks[..block_size as usize])?;
+
+        if end_block - first_block > 1 {
+            self.read_blocks_exact(end_block - 1, &mut blocks[last_block_start..])?;
+        }
+
+        blocks[skip..(skip + source.len())].copy_from_slice(source);
+        self.write_blocks_exact(first_block, &blocks)
+    }
+
+    fn read_blocks_exact(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<(), BlockDeviceError> {
+        let block_count = destination.len() as u64 / u64::from(self.device.block_size());
+
+        if self.device.read_blocks(start_block, destination)? < block_count {
+            return Err(BlockDeviceError::Io);
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks_exact(
+        &mut self,
+        start_block: u64,
+        source: &[u8],
+    ) -> Result<(), BlockDeviceError> {
+        let block_count = source.len() as u64 / u64::from(self.device.block_size());
+
+        if self.device.write_blocks(start_block, source)? < block_count {
+            return Err(BlockDeviceError::Io);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the data of an NBD_OPT_INFO or NBD_OPT_GO holds together.
+fn is_valid_info_request(data: &[u8]) -> bool {
+    if data.len() < 6 {
+        return false;
+    }
+
+    let name_length = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+
+    match data.get((4 + name_length)..(6 + name_length)) {
+        Some(count) => {
+            let request_count = u16::from_be_bytes([count[0], count[1]]) as usize;
+            data.len() == 6 + name_length + request_count * 2
+        }
+        None => false,
+    }
+}
+
+fn write_option_reply<W: Write>(
+    writer: &mut W,
+    option: u32,
+    reply: u32,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut message = Vec::with_capacity(20 + data.len());
+    message.extend_from_slice(&OPTION_REPLY_MAGIC.to_be_bytes());
+    message.extend_from_slice(&option.to_be_bytes());
+    message.extend_from_slice(&reply.to_be_bytes());
+    message.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    message.extend_from_slice(data);
+
+    writer.write_all(&message)?;
+    writer.flush()
+}
+
+fn write_reply<W: Write>(writer: &mut W, error: u32, handle: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(16 + data.len());
+    message.extend_from_slice(&SIMPLE_REPLY_MAGIC.to_be_bytes());
+    message.extend_from_slice(&error.to_be_bytes());
+    message.extend_from_slice(handle);
+    message.extend_from_slice(data);
+
+    writer.write_all(&message)?;
+    writer.flush()
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Reads a big-endian number of up to eight bytes.
+fn be_u64(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | u64::from(byte))
+}
+
+fn error_code(result: Result<(), BlockDeviceError>) -> u32 {
+    match result {
+        Ok(()) => 0,
+        Err(BlockDeviceError::ReadOnly) => EPERM,
+        Err(BlockDeviceError::Io) | Err(BlockDeviceError::Gone) => EIO,
+    }
+}
+
+fn io_error(err: BlockDeviceError) -> io::Error {
+    io::Error::other(err)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,237 @@
+use crate::{BlockDevice, BlockDeviceError};
+use object_store::{path::Path, ObjectStore};
+use std::cmp;
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
+
+const BLOCK_SIZE: u16 = 512;
+
+/// How much of an object is fetched at a time, and how much of what's been fetched is
+/// kept around.
+#[derive(Debug, Copy, Clone)]
+pub struct ObjectCacheOptions {
+    /// Objects are fetched in chunks of this many bytes, aligned to it, with runs of
+    /// neighbouring chunks fetched in one request.
+    pub chunk_size: u32,
+    /// How many chunks are kept, least recently used going first.
+    pub chunk_count: usize,
+}
+
+impl Default for ObjectCacheOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 256 * 1024,
+            chunk_count: 64,
+        }
+    }
+}
+
+/// Reads an object held in an object store (S3, GCS, Azure, or anything else the
+/// `object_store` crate can reach) with ranged GETs, so that an image can be listed and
+/// partially extracted without downloading all of it.
+///
+/// Requests are made on a runtime of the device's own, so it mustn't be used from within
+/// an async context.
+pub struct ObjectStoreBlockDevice {
+    store: Arc<dyn ObjectStore>,
+    location: Path,
+    size: u64,
+    runtime: Runtime,
+    options: ObjectCacheOptions,
+    /// Cached chunks by index, the most recently used last.
+    chunks: Vec<(u64, Vec<u8>)>,
+}
+
+impl ObjectStoreBlockDevice {
+    /// Opens the object at `location`, finding out how large it is with a HEAD request.
+    pub fn new(store: Arc<dyn ObjectStore>, location: Path) -> Result<Self, BlockDeviceError> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|_| BlockDeviceError::Io)?;
+
+        let size = runtime
+            .block_on(store.head(&location))
+            .map_err(|_| BlockDeviceError::Io)?
+            .size;
+
+        Ok(Self {
+            store,
+            location,
+            size,
+            runtime,
+            options: ObjectCacheOptions::default(),
+            chunks: Vec::new(),
+        })
+    }
+
+    pub fn cache_options(mut self, options: ObjectCacheOptions) -> Self {
+        self.options = options;
+        self.chunks.clear();
+        self
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn chunk_size(&self) -> u64 {
+        u64::from(cmp::max(self.options.chunk_size, u32::from(BLOCK_SIZE)))
+    }
+
+    fn cached(&mut self, chunk: u64) -> Option<&[u8]> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|(cached, _)| *cached == chunk)?;
+        let entry = self.chunks.remove(index);
+        self.chunks.push(entry);
+        self.chunks.last().map(|(_, data)| &data[..])
+    }
+
+    fn is_cached(&self, chunk: u64) -> bool {
+        self.chunks.iter().any(|(cached, _)| *cached == chunk)
+    }
+
+    fn cache(&mut self, chunk: u64, data: Vec<u8>) {
+        if self.options.chunk_count == 0 {
+            return;
+        }
+
+        if self.chunks.len() >= self.options.chunk_count {
+            self.chunks.remove(0);
+        }
+
+        self.chunks.push((chunk, data));
+    }
+
+    /// Fetches chunks `first` up to `end` in one request, split back into chunks.
+    fn fetch(&mut self, first: u64, end: u64) -> Result<Vec<(u64, Vec<u8>)>, BlockDeviceError> {
+        let chunk_size = self.chunk_size();
+        let range = (first * chunk_size)..cmp::min(self.size, end * chunk_size);
+
+        let data: Vec<u8> = self
+            .runtime
+            .block_on(self.store.get_range(&self.location, range.clone()))
+            .map_err(|_| BlockDeviceError::Io)?
+            .into();
+
+        if data.len() as u64 != range.end - range.start {
+            return Err(BlockDeviceError::Io);
+        }
+
+        Ok(data
+            .chunks(chunk_size as usize)
+            .enumerate()
+            .map(|(index, chunk)| (first + index as u64, chunk.to_vec()))
+            .collect())
+    }
+
+    /// The chunk after `chunk` that is cached, or `end` if none before it is.
+    fn end_of_missing_run(&self, chunk: u64, end: u64) -> u64 {
+        (chunk + 1..end)
+            .find(|&next| self.is_cached(next))
+            .unwrap_or(end)
+    }
+}
+
+impl BlockDevice for ObjectStoreBlockDevice {
+    fn block_size(&self) -> u16 {
+        BLOCK_SIZE
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let block_size = u64::from(BLOCK_SIZE);
+        let available_blocks = (self.size / block_size).saturating_sub(start_block);
+        let read_blocks = cmp::min(available_blocks, destination.len() as u64 / block_size);
+
+        let start = start_block * block_size;
+        let end = start + read_blocks * block_size;
+        let chunk_size = self.chunk_size();
+        let end_chunk = end.div_ceil(chunk_size);
+
+        let mut position = start;
+
+        while position < end {
+            let chunk = position / chunk_size;
+
+            // NOTE: fetched chunks are copied from directly rather than from the cache,
+            // as a long read can push its own start out of it
+            let fetched = match self.cached(chunk) {
+                Some(data) => vec![(chunk, data.to_vec())],
+                None => {
+                    let run_end = self.end_of_missing_run(chunk, end_chunk);
+                    self.fetch(chunk, run_end)?
+                }
+            };
+
+            for (chunk, data) in fetched {
+                let chunk_start = chunk * chunk_size;
+                let copy_start = cmp::max(position, chunk_start);
+                let copy_end = cmp::min(end, chunk_start + data.len() as u64);
+
+                destination[((copy_start - start) as usize)..((copy_end - start) as usize)]
+                    .copy_from_slice(
+                        &data[((copy_start - chunk_start) as usize)
+                            ..((copy_end - chunk_start) as usize)],
+                    );
+
+                position = copy_end;
+
+                if !self.is_cached(chunk) {
+                    self.cache(chunk, data);
+                }
+            }
+        }
+
+        Ok(read_blocks)
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        Some(self.size / u64::from(BLOCK_SIZE))
+    }
+
+    /// Fetches what isn't cached yet of as much of the range as the cache can hold, in
+    /// as few requests as possible.
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        let chunk_size = self.chunk_size();
+        let start = cmp::min(self.size, start_block * u64::from(BLOCK_SIZE));
+        let end = cmp::min(
+            self.size,
+            (start_block + block_count) * u64::from(BLOCK_SIZE),
+        );
+
+        let first_chunk = start / chunk_size;
+        let end_chunk = cmp::min(
+            end.div_ceil(chunk_size),
+            first_chunk + self.options.chunk_count as u64,
+        );
+
+        let mut chunk = first_chunk;
+
+        while chunk < end_chunk {
+            if self.is_cached(chunk) {
+                chunk += 1;
+                continue;
+            }
+
+            let run_end = self.end_of_missing_run(chunk, end_chunk);
+
+            // NOTE: a prefetch failing is fine, the read that follows will fail too
+            match self.fetch(chunk, run_end) {
+                Ok(fetched) => {
+                    for (chunk, data) in fetched {
+                        self.cache(chunk, data);
+                    }
+                }
+                Err(_) => return,
+            }
+
+            chunk = run_end;
+        }
+    }
+}
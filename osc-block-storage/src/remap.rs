@@ -0,0 +1,257 @@
+use crate::{BlockDevice, BlockDeviceError};
+use alloc::vec::Vec;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BadBlockAction {
+    /// Read the block's data from another block of the inner device instead, e.g.
+    /// from a spare area the data was salvaged to.
+    RemapTo(u64),
+    /// Substitute a zero-filled block, reporting it through the warning callback.
+    ZeroFill,
+}
+
+/// Overlays a list of known-bad blocks on a partially failing device so it can still be
+/// imaged and parsed as far as possible.
+pub struct BadBlockRemapDevice<D, W> {
+    inner: D,
+    bad_blocks: Vec<(u64, BadBlockAction)>,
+    zero_fill_on_error: bool,
+    warn: W,
+}
+
+impl<D> BadBlockRemapDevice<D, fn(u64)>
+where
+    D: BlockDevice,
+{
+    pub fn new(inner: D, bad_blocks: impl IntoIterator<Item = (u64, BadBlockAction)>) -> Self {
+        Self::with_warning(inner, bad_blocks, |_| {})
+    }
+}
+
+impl<D, W> BadBlockRemapDevice<D, W>
+where
+    D: BlockDevice,
+    W: FnMut(u64),
+{
+    /// Like `new`, but `warn` is called with the index of every block that gets
+    /// zero-filled.
+    pub fn with_warning(
+        inner: D,
+        bad_blocks: impl IntoIterator<Item = (u64, BadBlockAction)>,
+        warn: W,
+    ) -> Self {
+        let mut bad_blocks: Vec<_> = bad_blocks.into_iter().collect();
+        bad_blocks.sort_unstable_by_key(|(block, _)| *block);
+        bad_blocks.dedup_by_key(|(block, _)| *block);
+
+        Self {
+            inner,
+            bad_blocks,
+            zero_fill_on_error: false,
+            warn,
+        }
+    }
+
+    /// Treats any block the inner device fails to read as a bad block to be
+    /// zero-filled, rather than failing the whole read.
+    pub fn zero_fill_on_error(mut self, zero_fill_on_error: bool) -> Self {
+        self.zero_fill_on_error = zero_fill_on_error;
+        self
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn next_bad_block(&self, from_block: u64) -> Option<(u64, BadBlockAction)> {
+        let index = self
+            .bad_blocks
+            .partition_point(|(block, _)| *block < from_block);
+        self.bad_blocks.get(index).copied()
+    }
+
+    fn zero_fill(&mut self, block: u64, destination: &mut [u8]) {
+        destination.iter_mut().for_each(|byte| *byte = 0);
+        (self.warn)(block);
+    }
+
+    fn read_good_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        match self.inner.read_blocks(start_block, destination) {
+            Err(_) if self.zero_fill_on_error => {}
+            result => return result,
+        }
+
+        // Something in the range is unreadable, so go block by block to salvage the rest
+        let block_size = usize::from(self.inner.block_size());
+
+        for (index, block_destination) in destination.chunks_exact_mut(block_size).enumerate() {
+            let block = start_block + index as u64;
+
+            match self.inner.read_blocks(block, block_destination) {
+                Ok(0) => return Ok(index as u64),
+                Ok(_) => {}
+                Err(_) => self.zero_fill(block, block_destination),
+            }
+        }
+
+        Ok((destination.len() / block_size) as u64)
+    }
+}
+
+impl<D, W> BlockDevice for BadBlockRemapDevice<D, W>
+where
+    D: BlockDevice,
+    W: FnMut(u64),
+{
+    fn block_size(&self) -> u16 {
+        self.inner.block_size()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.inner.block_size());
+        let block_count = (destination.len() / block_size) as u64;
+        let end_block = start_block + block_count;
+
+        let mut block = start_block;
+
+        while block < end_block {
+            let offset = ((block - start_block) as usize) * block_size;
+
+            match self.next_bad_block(block) {
+                Some((bad_block, action)) if bad_block == block => {
+                    let block_destination = &mut destination[offset..(offset + block_size)];
+
+                    match action {
+                        BadBlockAction::RemapTo(replacement) => {
+                            if self.inner.read_blocks(replacement, block_destination)? == 0 {
+                                self.zero_fill(block, block_destination);
+                            }
+                        }
+                        BadBlockAction::ZeroFill => self.zero_fill(block, block_destination),
+                    }
+
+                    block += 1;
+                }
+                next_bad_block => {
+                    let run_end = match next_bad_block {
+                        Some((bad_block, _)) if bad_block < end_block => bad_block,
+                        Some(_) | None => end_block,
+                    };
+
+                    let run_len = run_end - block;
+                    let run_destination =
+                        &mut destination[offset..(offset + (run_len as usize) * block_size)];
+
+                    let blocks_read = self.read_good_blocks(block, run_destination)?;
+                    block += blocks_read;
+
+                    if blocks_read < run_len {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(block - start_block)
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        self.inner.block_count()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.inner.block_size());
+        let block_count = (source.len() / block_size) as u64;
+        let end_block = start_block + block_count;
+
+        let mut block = start_block;
+
+        while block < end_block {
+            let offset = ((block - start_block) as usize) * block_size;
+
+            match self.next_bad_block(block) {
+                Some((bad_block, action)) if bad_block == block => {
+                    let block_source = &source[offset..(offset + block_size)];
+
+                    match action {
+                        BadBlockAction::RemapTo(replacement) => {
+                            if self.inner.write_blocks(replacement, block_source)? == 0 {
+                                break;
+                            }
+                        }
+                        // NOTE: there's nowhere for the data to go
+                        BadBlockAction::ZeroFill => return Err(BlockDeviceError::Io),
+                    }
+
+                    block += 1;
+                }
+                next_bad_block => {
+                    let run_end = match next_bad_block {
+                        Some((bad_block, _)) if bad_block < end_block => bad_block,
+                        Some(_) | None => end_block,
+                    };
+
+                    let run_len = run_end - block;
+                    let run_source = &source[offset..(offset + (run_len as usize) * block_size)];
+
+                    let blocks_written = self.inner.write_blocks(block, run_source)?;
+                    block += blocks_written;
+
+                    if blocks_written < run_len {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(block - start_block)
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        self.inner.prefetch(start_block, block_count);
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        let end_block = start_block + block_count;
+        let mut block = start_block;
+
+        while block < end_block {
+            match self.next_bad_block(block) {
+                Some((bad_block, action)) if bad_block == block => {
+                    if let BadBlockAction::RemapTo(replacement) = action {
+                        self.inner.discard(replacement, 1)?;
+                    }
+
+                    block += 1;
+                }
+                next_bad_block => {
+                    let run_end = match next_bad_block {
+                        Some((bad_block, _)) if bad_block < end_block => bad_block,
+                        Some(_) | None => end_block,
+                    };
+
+                    self.inner.discard(block, run_end - block)?;
+                    block = run_end;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.inner.flush()
+    }
+}
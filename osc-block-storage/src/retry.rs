@@ -0,0 +1,130 @@
+use crate::{BlockDevice, BlockDeviceError};
+use core::time::Duration;
+
+#[derive(Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    /// How many times a read is attempted in total before its error is returned.
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Wraps a device whose reads fail intermittently (e.g. a marginal SD card), retrying
+/// failed reads with exponential backoff before giving up. A device that's gone is
+/// given up on straight away.
+pub struct RetryBlockDevice<D, S> {
+    inner: D,
+    policy: RetryPolicy,
+    sleep: S,
+}
+
+impl<D, S> RetryBlockDevice<D, S>
+where
+    D: BlockDevice,
+    S: FnMut(Duration),
+{
+    /// Creates a wrapper that waits between attempts using `sleep`, for environments
+    /// without `std::thread::sleep`.
+    pub fn with_sleep(inner: D, policy: RetryPolicy, sleep: S) -> Self {
+        Self {
+            inner,
+            policy,
+            sleep,
+        }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+#[cfg(feature = "std")]
+impl<D> RetryBlockDevice<D, fn(Duration)>
+where
+    D: BlockDevice,
+{
+    pub fn new(inner: D, policy: RetryPolicy) -> Self {
+        Self::with_sleep(inner, policy, std::thread::sleep)
+    }
+}
+
+impl<D, S> BlockDevice for RetryBlockDevice<D, S>
+where
+    D: BlockDevice,
+    S: FnMut(Duration),
+{
+    fn block_size(&self) -> u16 {
+        self.inner.block_size()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 1;
+
+        loop {
+            match self.inner.read_blocks(start_block, destination) {
+                Ok(blocks_read) => return Ok(blocks_read),
+                Err(BlockDeviceError::Gone) => return Err(BlockDeviceError::Gone),
+                Err(err) if attempt >= self.policy.attempts => return Err(err),
+                Err(_) => {
+                    (self.sleep)(backoff);
+                    backoff = core::cmp::min(backoff * 2, self.policy.max_backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        self.inner.block_count()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 1;
+
+        loop {
+            match self.inner.write_blocks(start_block, source) {
+                Ok(blocks_written) => return Ok(blocks_written),
+                Err(BlockDeviceError::ReadOnly) => return Err(BlockDeviceError::ReadOnly),
+                Err(BlockDeviceError::Gone) => return Err(BlockDeviceError::Gone),
+                Err(err) if attempt >= self.policy.attempts => return Err(err),
+                Err(_) => {
+                    (self.sleep)(backoff);
+                    backoff = core::cmp::min(backoff * 2, self.policy.max_backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        self.inner.prefetch(start_block, block_count);
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        self.inner.discard(start_block, block_count)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.inner.flush()
+    }
+}
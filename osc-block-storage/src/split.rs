@@ -0,0 +1,116 @@
+use crate::compose::ConcatDevice;
+use crate::virt::FileBlockDevice;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The block size of the devices the pieces are opened as.
+const PIECE_BLOCK_SIZE: u64 = 512;
+
+/// An image split into numbered pieces of the same size, e.g. `img.000`, `img.001` and
+/// so on, as is done to keep backups within FAT32's file size limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitImage {
+    /// The pieces in order.
+    pub paths: Vec<PathBuf>,
+    /// The size of every piece but the last, which can be smaller.
+    pub piece_size: u64,
+}
+
+impl SplitImage {
+    /// Finds the pieces of the image `path` is a piece of, e.g. `img.003`, or the name
+    /// of, e.g. `img`, for which the pieces are numbered with three digits. The pieces
+    /// start at 0 or 1, whichever there is, and go on for as long as the numbers follow
+    /// on from each other.
+    pub fn discover(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        let (base, digits) = match piece_digits(path) {
+            Some(digits) => (path.with_extension(""), digits),
+            None => (path.to_owned(), 3),
+        };
+
+        let piece_path = |number: u64| {
+            let mut name = OsString::from(base.as_os_str());
+            name.push(format!(".{:0width$}", number, width = digits));
+            PathBuf::from(name)
+        };
+
+        let first = if piece_path(0).is_file() { 0 } else { 1 };
+        let paths = (first..)
+            .map(piece_path)
+            .take_while(|path| path.is_file())
+            .collect::<Vec<_>>();
+
+        if paths.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no pieces of the split image were found",
+            ));
+        }
+
+        let sizes = paths
+            .iter()
+            .map(|path| fs::metadata(path).map(|metadata| metadata.len()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let piece_size = sizes[0];
+        let (last, others) = sizes.split_last().unwrap();
+
+        // NOTE: pieces are joined a block at a time, so a piece that ends partway into a
+        // block would leave a gap
+        if others.iter().any(|&size| size != piece_size)
+            || *last > piece_size
+            || (paths.len() > 1 && !piece_size.is_multiple_of(PIECE_BLOCK_SIZE))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the pieces of the split image aren't all the same whole number of blocks",
+            ));
+        }
+
+        Ok(Self { paths, piece_size })
+    }
+
+    /// Opens the pieces as one device, starting `offset` bytes in, which has to be a
+    /// whole number of blocks. The pieces have to be writable if `writable` is set.
+    pub fn open(&self, offset: u64, writable: bool) -> io::Result<ConcatDevice<FileBlockDevice>> {
+        if !offset.is_multiple_of(PIECE_BLOCK_SIZE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "split images can only be opened at whole blocks",
+            ));
+        }
+
+        // NOTE: the pieces before the one the offset is in are left out altogether
+        let last = self.paths.len() as u64 - 1;
+        let skipped = match self.piece_size {
+            0 => 0,
+            piece_size => (offset / piece_size).min(last),
+        };
+
+        let mut offset = offset - skipped * self.piece_size;
+        let mut devices = Vec::new();
+
+        for path in self.paths.iter().skip(skipped as usize) {
+            let file = OpenOptions::new().read(true).write(writable).open(path)?;
+            devices.push(FileBlockDevice::new(file, offset)?.writable(writable));
+            offset = 0;
+        }
+
+        ConcatDevice::new(devices).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// How many digits `path`'s extension has, if it's a number, as the pieces of a split
+/// image have.
+fn piece_digits(path: &Path) -> Option<usize> {
+    let extension = path.extension()?.to_str()?;
+
+    if !extension.is_empty() && extension.bytes().all(|b| b.is_ascii_digit()) {
+        Some(extension.len())
+    } else {
+        None
+    }
+}
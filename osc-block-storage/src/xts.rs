@@ -0,0 +1,204 @@
+use crate::{BlockDevice, BlockDeviceError};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// The AES keys for the data and for the tweaks, the two halves of an XTS key, which
+/// are boxed as they're big with their expanded round keys.
+enum Keys {
+    Aes128(Box<(Aes128, Aes128)>),
+    Aes256(Box<(Aes256, Aes256)>),
+}
+
+impl Keys {
+    fn new(key: &[u8]) -> Option<Self> {
+        let (data, tweak) = key.split_at(key.len() / 2);
+
+        match key.len() {
+            32 => Some(Self::Aes128(Box::new((
+                Aes128::new(GenericArray::from_slice(data)),
+                Aes128::new(GenericArray::from_slice(tweak)),
+            )))),
+            64 => Some(Self::Aes256(Box::new((
+                Aes256::new(GenericArray::from_slice(data)),
+                Aes256::new(GenericArray::from_slice(tweak)),
+            )))),
+            _ => None,
+        }
+    }
+
+    fn encrypt_tweak(&self, tweak: &mut [u8]) {
+        let tweak = GenericArray::from_mut_slice(tweak);
+
+        match self {
+            Self::Aes128(keys) => keys.1.encrypt_block(tweak),
+            Self::Aes256(keys) => keys.1.encrypt_block(tweak),
+        }
+    }
+
+    fn encrypt(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+
+        match self {
+            Self::Aes128(keys) => keys.0.encrypt_block(block),
+            Self::Aes256(keys) => keys.0.encrypt_block(block),
+        }
+    }
+
+    fn decrypt(&self, block: &mut [u8]) {
+        let block = GenericArray::from_mut_slice(block);
+
+        match self {
+            Self::Aes128(keys) => keys.0.decrypt_block(block),
+            Self::Aes256(keys) => keys.0.decrypt_block(block),
+        }
+    }
+}
+
+/// Decrypts what's read from a device encrypted with AES-XTS, and encrypts what's
+/// written to it, each block being a data unit whose tweak is its number, as with
+/// dm-crypt's `aes-xts-plain64` on sectors of the device's block size.
+pub struct XtsBlockDevice<D> {
+    inner: D,
+    keys: Keys,
+    tweak_offset: u64,
+    /// Holds what's written while it's encrypted, so the source isn't changed.
+    buffer: Vec<u8>,
+}
+
+impl<D> XtsBlockDevice<D>
+where
+    D: BlockDevice,
+{
+    /// Wraps `inner` with the XTS key `key`, which is 32 bytes for AES-128 or 64 for
+    /// AES-256, the first half for the data and the second for the tweaks, or gives back
+    /// `None` if it's any other length.
+    pub fn new(inner: D, key: &[u8]) -> Option<Self> {
+        Some(Self {
+            inner,
+            keys: Keys::new(key)?,
+            tweak_offset: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Numbers the device's first block `tweak_offset` rather than 0, as with
+    /// dm-crypt's `iv_offset`, e.g. for a partition encrypted as part of a whole disk.
+    pub fn tweak_offset(mut self, tweak_offset: u64) -> Self {
+        self.tweak_offset = tweak_offset;
+        self
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Encrypts or decrypts whole blocks, the first of which is `start_block`.
+    fn transform(&self, start_block: u64, data: &mut [u8], encrypt: bool) {
+        let block_size = usize::from(self.inner.block_size());
+
+        for (index, unit) in data.chunks_exact_mut(block_size).enumerate() {
+            let number = self
+                .tweak_offset
+                .wrapping_add(start_block)
+                .wrapping_add(index as u64);
+
+            let mut tweak = [0u8; 16];
+            tweak[..8].copy_from_slice(&number.to_le_bytes());
+            self.keys.encrypt_tweak(&mut tweak);
+
+            for block in unit.chunks_exact_mut(16) {
+                xor(block, &tweak);
+
+                if encrypt {
+                    self.keys.encrypt(block);
+                } else {
+                    self.keys.decrypt(block);
+                }
+
+                xor(block, &tweak);
+                multiply_by_alpha(&mut tweak);
+            }
+        }
+    }
+}
+
+impl<D> BlockDevice for XtsBlockDevice<D>
+where
+    D: BlockDevice,
+{
+    fn block_size(&self) -> u16 {
+        self.inner.block_size()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let blocks_read = self.inner.read_blocks(start_block, destination)?;
+        let read_len = blocks_read as usize * usize::from(self.block_size());
+
+        self.transform(start_block, &mut destination[..read_len], false);
+        Ok(blocks_read)
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        self.inner.block_count()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.block_size());
+        let len = source.len() / block_size * block_size;
+
+        let mut buffer = core::mem::take(&mut self.buffer);
+        buffer.clear();
+        buffer.extend_from_slice(&source[..len]);
+
+        self.transform(start_block, &mut buffer, true);
+        let result = self.inner.write_blocks(start_block, &buffer);
+
+        self.buffer = buffer;
+        result
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        self.inner.prefetch(start_block, block_count);
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        self.inner.discard(start_block, block_count)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.inner.flush()
+    }
+}
+
+fn xor(block: &mut [u8], tweak: &[u8; 16]) {
+    for (byte, tweak) in block.iter_mut().zip(tweak) {
+        *byte ^= tweak;
+    }
+}
+
+/// Moves the tweak on to the next AES block's, multiplying it by the primitive element
+/// of GF(2^128), in the little-endian order XTS keeps it in.
+fn multiply_by_alpha(tweak: &mut [u8; 16]) {
+    let mut carry = 0;
+
+    for byte in tweak.iter_mut() {
+        let next_carry = *byte >> 7;
+        *byte = (*byte << 1) | carry;
+        carry = next_carry;
+    }
+
+    if carry != 0 {
+        tweak[0] ^= 0x87;
+    }
+}
@@ -0,0 +1,118 @@
+use osc_block_storage::virt::*;
+use osc_fat::*;
+use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::process;
+use std::str::FromStr;
+
+mod server;
+mod wire;
+
+use server::{AttributeOptions, Server};
+
+const USAGE: &str = "\
+usage: osc-fat-9p [--offset BYTES] [--uid N] [--gid N] [--umask NNN] [--time-zone ZONE]
+                  IMAGE ADDRESS
+
+serves the contents of IMAGE, read-only, over 9P2000.L at ADDRESS, which is either
+HOST:PORT to listen on TCP or the path of a Unix socket to create, for example:
+
+  osc-fat-9p disk.img 127.0.0.1:5640
+  mount -t 9p -o trans=tcp,port=5640,version=9p2000.L 127.0.0.1 /mnt
+
+timestamps are taken to be in ZONE: utc (the default), local or an offset like +01:00";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut offset = 0;
+    let mut attributes = AttributeOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--offset" => offset = parse_value(&arg, args.next()),
+            "--uid" => attributes.uid = Some(parse_value(&arg, args.next())),
+            "--gid" => attributes.gid = Some(parse_value(&arg, args.next())),
+            "--umask" => {
+                let value = args.next().unwrap_or_default();
+
+                attributes.umask = match u16::from_str_radix(&value, 8) {
+                    Ok(umask) => umask & 0o777,
+                    Err(_) => usage(format!("invalid value '{}' for --umask", value)),
+                }
+            }
+            "--time-zone" => attributes.time_zone = parse_value(&arg, args.next()),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => usage(format!("unknown option '{}'", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let (image, address) = match positional.as_slice() {
+        [image, address] => (image, address),
+        _ => usage("expected IMAGE and ADDRESS".into()),
+    };
+
+    let file = File::open(image).unwrap_or_else(|err| fail(image, err));
+    let device = FileBlockDevice::new(file, offset).unwrap_or_else(|err| fail(image, err));
+    let options = MountOptions {
+        read_only: true,
+        time_zone: attributes.time_zone,
+        ..MountOptions::default()
+    };
+    let fs = FATFileSystem::open_with_options(Box::new(device), options)
+        .unwrap_or_else(|err| fail(image, err));
+
+    let mut server = Server::new(&fs, attributes);
+
+    // NOTE: the filesystem can only be used from one thread, so connections are
+    // served one after another rather than side by side
+    let result = if address.contains('/') {
+        UnixListener::bind(address).and_then(|listener| accept(&mut server, listener.incoming()))
+    } else {
+        TcpListener::bind(address).and_then(|listener| accept(&mut server, listener.incoming()))
+    };
+
+    if let Err(err) = result {
+        fail(address, err);
+    }
+}
+
+fn accept<S, I>(server: &mut Server, incoming: I) -> io::Result<()>
+where
+    S: Read + Write,
+    I: Iterator<Item = io::Result<S>>,
+{
+    for stream in incoming {
+        if let Err(err) = stream.and_then(|stream| server.serve(stream)) {
+            eprintln!("osc-fat-9p: connection failed: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_value<T: FromStr>(name: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+
+    value
+        .parse()
+        .unwrap_or_else(|_| usage(format!("invalid value '{}' for {}", value, name)))
+}
+
+fn usage(message: String) -> ! {
+    eprintln!("osc-fat-9p: {}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn fail(context: &str, err: impl Display) -> ! {
+    eprintln!("osc-fat-9p: {}: {}", context, err);
+    process::exit(1);
+}
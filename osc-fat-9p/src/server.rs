@@ -0,0 +1,632 @@
+use crate::wire::{self, Decoder, Encoder, Malformed, Qid, QID_TYPE_DIR, QID_TYPE_FILE};
+use libc::{
+    EBADF, ECANCELED, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ENODEV, ENOENT, ENOSPC, ENOTDIR,
+    ENOTEMPTY, EOPNOTSUPP, EPROTO, EROFS, O_ACCMODE, O_RDONLY, O_TRUNC,
+};
+use osc_fat::{Attributes, FATFileSystem, FatDateTime, FatFile, Metadata, TimeZonePolicy};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TSYMLINK: u8 = 16;
+const TMKNOD: u8 = 18;
+const TRENAME: u8 = 20;
+const TREADLINK: u8 = 22;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TXATTRWALK: u8 = 30;
+const TXATTRCREATE: u8 = 32;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TLOCK: u8 = 52;
+const TGETLOCK: u8 = 54;
+const TLINK: u8 = 70;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TAUTH: u8 = 102;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+const VERSION: &str = "9P2000.L";
+const MAX_MESSAGE_SIZE: u32 = 1 << 20;
+/// The size of an Rread or Rreaddir, less the data it carries.
+const IO_HEADER_SIZE: u32 = 11;
+const NO_UID: u32 = !0;
+
+const GETATTR_BASIC: u64 = 0x7ff;
+const GETATTR_BTIME: u64 = 0x800;
+
+const LOCK_SUCCESS: u8 = 0;
+const LOCK_TYPE_UNLOCK: u8 = 2;
+
+const V9FS_MAGIC: u32 = 0x0102_1997;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
+/// How DOS attributes and timestamps are presented as Unix ownership, permissions and
+/// times.
+#[derive(Debug, Clone)]
+pub struct AttributeOptions {
+    /// The owner of everything, or the user the client attached as if `None`.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub umask: u16,
+    pub time_zone: TimeZonePolicy,
+}
+
+impl Default for AttributeOptions {
+    fn default() -> Self {
+        Self {
+            uid: None,
+            gid: None,
+            umask: 0o022,
+            time_zone: TimeZonePolicy::Utc,
+        }
+    }
+}
+
+/// Serves the contents of a filesystem, read-only, to 9P2000.L clients.
+pub struct Server<'a> {
+    fs: &'a FATFileSystem,
+    attributes: AttributeOptions,
+    /// Qid paths are handed out as paths are first seen, so they stay the same for as
+    /// long as the server runs, across connections.
+    qid_paths: HashMap<String, u64>,
+}
+
+struct Fid<'a> {
+    path: String,
+    metadata: Metadata,
+    open: Option<Open<'a>>,
+}
+
+enum Open<'a> {
+    File(Box<FatFile<'a>>),
+    /// A snapshot of the directory taken when it was opened, so that readdir offsets
+    /// stay meaningful.
+    Directory(Vec<DirectoryEntry>),
+}
+
+struct DirectoryEntry {
+    qid: Qid,
+    kind: u8,
+    name: String,
+}
+
+struct Session<'a> {
+    message_size: u32,
+    uid: u32,
+    fids: HashMap<u32, Fid<'a>>,
+}
+
+type Reply = Result<(u8, Encoder), u32>;
+
+impl From<Malformed> for u32 {
+    fn from(_: Malformed) -> Self {
+        EPROTO as u32
+    }
+}
+
+impl<'a> Server<'a> {
+    pub fn new(fs: &'a FATFileSystem, attributes: AttributeOptions) -> Self {
+        Self {
+            fs,
+            attributes,
+            qid_paths: HashMap::new(),
+        }
+    }
+
+    /// Answers requests on `stream` until the client disconnects. Requests are answered
+    /// in the order they arrive, so there's never anything in flight to flush.
+    pub fn serve<S: Read + Write>(&mut self, mut stream: S) -> io::Result<()> {
+        let mut session = Session {
+            message_size: MAX_MESSAGE_SIZE,
+            uid: 0,
+            fids: HashMap::new(),
+        };
+
+        while let Some((kind, tag, body)) = wire::read_message(&mut stream, session.message_size)? {
+            let mut decoder = Decoder::new(&body);
+
+            match self.handle(&mut session, kind, &mut decoder) {
+                Ok((reply_kind, reply)) => {
+                    wire::write_message(&mut stream, reply_kind, tag, &reply.into_bytes())?
+                }
+                Err(errno) => {
+                    let mut reply = Encoder::new();
+                    reply.u32(errno);
+                    wire::write_message(&mut stream, RLERROR, tag, &reply.into_bytes())?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, session: &mut Session<'a>, kind: u8, body: &mut Decoder) -> Reply {
+        match kind {
+            TVERSION => self.version(session, body),
+            TATTACH => self.attach(session, body),
+            TWALK => self.walk(session, body),
+            TGETATTR => self.getattr(session, body),
+            TLOPEN => self.lopen(session, body),
+            TREAD => self.read(session, body),
+            TREADDIR => self.readdir(session, body),
+            TSTATFS => self.statfs(session, body),
+            TCLUNK => {
+                let fid = body.u32()?;
+                session.fids.remove(&fid).ok_or(EBADF as u32)?;
+                Ok((TCLUNK + 1, Encoder::new()))
+            }
+            TFLUSH => Ok((TFLUSH + 1, Encoder::new())),
+            TFSYNC => {
+                session.fid(body.u32()?)?;
+                Ok((TFSYNC + 1, Encoder::new()))
+            }
+            TLOCK => {
+                session.fid(body.u32()?)?;
+                let mut reply = Encoder::new();
+                reply.u8(LOCK_SUCCESS);
+                Ok((TLOCK + 1, reply))
+            }
+            TGETLOCK => {
+                session.fid(body.u32()?)?;
+                body.u8()?;
+                let (start, length, process) = (body.u64()?, body.u64()?, body.u32()?);
+                let client = body.string()?;
+                let mut reply = Encoder::new();
+                reply
+                    .u8(LOCK_TYPE_UNLOCK)
+                    .u64(start)
+                    .u64(length)
+                    .u32(process)
+                    .string(&client);
+                Ok((TGETLOCK + 1, reply))
+            }
+            TREADLINK => Err(EINVAL as u32),
+            TAUTH | TXATTRWALK => Err(EOPNOTSUPP as u32),
+            TLCREATE | TSYMLINK | TMKNOD | TRENAME | TSETATTR | TXATTRCREATE | TLINK | TMKDIR
+            | TRENAMEAT | TUNLINKAT | TWRITE | TREMOVE => {
+                // NOTE: Tremove clunks the fid even when the removal fails
+                if kind == TREMOVE {
+                    session.fids.remove(&body.u32()?);
+                }
+
+                Err(EROFS as u32)
+            }
+            _ => Err(EOPNOTSUPP as u32),
+        }
+    }
+
+    fn version(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        let message_size = body.u32()?;
+        let version = body.string()?;
+
+        session.message_size = message_size.min(MAX_MESSAGE_SIZE);
+        session.fids.clear();
+
+        let mut reply = Encoder::new();
+        reply
+            .u32(session.message_size)
+            .string(if version.starts_with(VERSION) {
+                VERSION
+            } else {
+                "unknown"
+            });
+
+        Ok((TVERSION + 1, reply))
+    }
+
+    fn attach(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        let fid = body.u32()?;
+        let _auth_fid = body.u32()?;
+        let _user_name = body.string()?;
+        let _tree_name = body.string()?;
+        let uid = body.u32()?;
+
+        if session.fids.contains_key(&fid) {
+            return Err(EBADF as u32);
+        }
+
+        if uid != NO_UID {
+            session.uid = uid;
+        }
+
+        let metadata = self.fs.lookup("").map_err(errno)?;
+        let qid = self.qid("", &metadata);
+
+        session.fids.insert(
+            fid,
+            Fid {
+                path: String::new(),
+                metadata,
+                open: None,
+            },
+        );
+
+        let mut reply = Encoder::new();
+        reply.qid(qid);
+        Ok((TATTACH + 1, reply))
+    }
+
+    fn walk(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        let fid = body.u32()?;
+        let new_fid = body.u32()?;
+        let count = body.u16()?;
+
+        let (mut path, mut metadata) = {
+            let fid = session.fid(fid)?;
+            (fid.path.clone(), fid.metadata.clone())
+        };
+
+        if new_fid != fid && session.fids.contains_key(&new_fid) {
+            return Err(EBADF as u32);
+        }
+
+        let mut qids = Vec::new();
+
+        for index in 0..count {
+            let name = body.string()?;
+
+            match self.walk_one(&path, &metadata, &name) {
+                Ok((next_path, next_metadata)) => {
+                    qids.push(self.qid(&next_path, &next_metadata));
+                    path = next_path;
+                    metadata = next_metadata;
+                }
+                Err(errno) if index == 0 => return Err(errno),
+                Err(_) => break,
+            }
+        }
+
+        // NOTE: the new fid only comes into being if every name could be walked
+        if qids.len() == count as usize {
+            session.fids.insert(
+                new_fid,
+                Fid {
+                    path,
+                    metadata,
+                    open: None,
+                },
+            );
+        }
+
+        let mut reply = Encoder::new();
+        reply.u16(qids.len() as u16);
+
+        for qid in qids {
+            reply.qid(qid);
+        }
+
+        Ok((TWALK + 1, reply))
+    }
+
+    fn walk_one(
+        &self,
+        path: &str,
+        metadata: &Metadata,
+        name: &str,
+    ) -> Result<(String, Metadata), u32> {
+        if !metadata.is_directory() {
+            return Err(ENOTDIR as u32);
+        }
+
+        match name {
+            "." => Ok((path.into(), metadata.clone())),
+            ".." => {
+                let parent = parent_path(path);
+                let metadata = self.fs.lookup(parent).map_err(errno)?;
+                Ok((parent.into(), metadata))
+            }
+            _ if name.is_empty() || name.contains('/') => Err(ENOENT as u32),
+            _ => {
+                let metadata = self.fs.lookup(&join(path, name)).map_err(errno)?;
+
+                // NOTE: names match case-insensitively, so the path is rebuilt with the
+                // name as it's stored, giving every spelling of it the same qid
+                Ok((join(path, &metadata.name), metadata))
+            }
+        }
+    }
+
+    fn getattr(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        let fid = session.fid(body.u32()?)?;
+        let _request_mask = body.u64()?;
+        let metadata = &fid.metadata;
+
+        let qid = self.qid(&fid.path.clone(), metadata);
+        let uid = self.attributes.uid.unwrap_or(session.uid);
+        let gid = self.attributes.gid.unwrap_or(0);
+
+        let (file_type, links) = if metadata.is_directory() {
+            (libc::S_IFDIR, 2)
+        } else {
+            (libc::S_IFREG, 1)
+        };
+
+        let size = u64::from(metadata.size);
+        let (modified_seconds, modified_nanoseconds) = self.unix_time(metadata.modified);
+        let (created_seconds, created_nanoseconds) = self.unix_time(metadata.created);
+
+        let mut reply = Encoder::new();
+        reply
+            .u64(GETATTR_BASIC | GETATTR_BTIME)
+            .qid(qid)
+            .u32(file_type | u32::from(self.perm(metadata.attributes)))
+            .u32(uid)
+            .u32(gid)
+            .u64(links)
+            .u64(0)
+            .u64(size)
+            .u64(self.fs.cluster_size_bytes() as u64)
+            .u64(size.div_ceil(512));
+
+        // FAT doesn't track changes separately from modifications, and keeps only the
+        // date of the last access, so those times are all the modification time
+        for _ in 0..3 {
+            reply.u64(modified_seconds).u64(modified_nanoseconds);
+        }
+
+        reply
+            .u64(created_seconds)
+            .u64(created_nanoseconds)
+            .u64(0)
+            .u64(0);
+
+        Ok((TGETATTR + 1, reply))
+    }
+
+    fn lopen(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        let fid_number = body.u32()?;
+        let flags = body.u32()? as i32;
+
+        let (path, metadata) = {
+            let fid = session.fid(fid_number)?;
+
+            if fid.open.is_some() {
+                return Err(EBADF as u32);
+            }
+
+            (fid.path.clone(), fid.metadata.clone())
+        };
+
+        if flags & O_ACCMODE != O_RDONLY || flags & O_TRUNC != 0 {
+            return Err(EROFS as u32);
+        }
+
+        let open = if metadata.is_directory() {
+            Open::Directory(self.snapshot_directory(&path, &metadata)?)
+        } else {
+            Open::File(Box::new(self.fs.open_file(&path).map_err(errno)?))
+        };
+
+        let qid = self.qid(&path, &metadata);
+
+        if let Some(fid) = session.fids.get_mut(&fid_number) {
+            fid.open = Some(open);
+        }
+
+        let mut reply = Encoder::new();
+        reply
+            .qid(qid)
+            .u32(session.message_size.saturating_sub(IO_HEADER_SIZE));
+        Ok((TLOPEN + 1, reply))
+    }
+
+    fn snapshot_directory(
+        &mut self,
+        path: &str,
+        metadata: &Metadata,
+    ) -> Result<Vec<DirectoryEntry>, u32> {
+        let parent = parent_path(path);
+        let parent_metadata = self.fs.lookup(parent).map_err(errno)?;
+
+        let mut entries = vec![
+            DirectoryEntry {
+                qid: self.qid(path, metadata),
+                kind: DT_DIR,
+                name: ".".into(),
+            },
+            DirectoryEntry {
+                qid: self.qid(parent, &parent_metadata),
+                kind: DT_DIR,
+                name: "..".into(),
+            },
+        ];
+
+        for item in self
+            .fs
+            .open_dir(path)
+            .map_err(errno)?
+            .list()
+            .map_err(errno)?
+        {
+            let item_path = join(path, &item.name);
+
+            entries.push(DirectoryEntry {
+                qid: self.qid(&item_path, &item),
+                kind: if item.is_directory() { DT_DIR } else { DT_REG },
+                name: item.name,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn read(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        let fid_number = body.u32()?;
+        let offset = body.u64()?;
+        let count = body
+            .u32()?
+            .min(session.message_size.saturating_sub(IO_HEADER_SIZE));
+
+        let file = match session.fid_mut(fid_number)?.open {
+            Some(Open::File(ref mut file)) => file,
+            Some(Open::Directory(_)) => return Err(EISDIR as u32),
+            None => return Err(EBADF as u32),
+        };
+
+        let mut data = vec![0u8; count as usize];
+        let read = file.read_at(offset, &mut data).map_err(errno)?;
+
+        let mut reply = Encoder::new();
+        reply.u32(read as u32).bytes(&data[..read]);
+        Ok((TREAD + 1, reply))
+    }
+
+    fn readdir(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        let fid_number = body.u32()?;
+        let offset = body.u64()?;
+        let count = body
+            .u32()?
+            .min(session.message_size.saturating_sub(IO_HEADER_SIZE)) as usize;
+
+        let entries = match session.fid(fid_number)?.open {
+            Some(Open::Directory(ref entries)) => entries,
+            Some(Open::File(_)) => return Err(ENOTDIR as u32),
+            None => return Err(EBADF as u32),
+        };
+
+        // The offset of an entry is the index of the one after it, which is where to
+        // carry on from
+        let mut data = Encoder::new();
+        let mut used = 0;
+
+        for (index, entry) in entries.iter().enumerate().skip(offset as usize) {
+            let size = Qid::SIZE + 8 + 1 + 2 + entry.name.len();
+
+            if used + size > count {
+                break;
+            }
+
+            data.qid(entry.qid)
+                .u64(index as u64 + 1)
+                .u8(entry.kind)
+                .string(&entry.name);
+            used += size;
+        }
+
+        let mut reply = Encoder::new();
+        reply.u32(used as u32).bytes(&data.into_bytes());
+        Ok((TREADDIR + 1, reply))
+    }
+
+    fn statfs(&mut self, session: &mut Session<'a>, body: &mut Decoder) -> Reply {
+        session.fid(body.u32()?)?;
+
+        // TODO: report the size of the filesystem and how much of it is free
+        let mut reply = Encoder::new();
+        reply
+            .u32(V9FS_MAGIC)
+            .u32(self.fs.cluster_size_bytes() as u32)
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u64(0)
+            .u32(255);
+        Ok((TSTATFS + 1, reply))
+    }
+
+    fn qid(&mut self, path: &str, metadata: &Metadata) -> Qid {
+        let next = self.qid_paths.len() as u64;
+        let qid_path = *self.qid_paths.entry(path.into()).or_insert(next);
+
+        Qid {
+            kind: if metadata.is_directory() {
+                QID_TYPE_DIR
+            } else {
+                QID_TYPE_FILE
+            },
+            version: 0,
+            path: qid_path,
+        }
+    }
+
+    fn perm(&self, attributes: Attributes) -> u16 {
+        let mut perm = 0o777 & !self.attributes.umask;
+
+        if attributes.is_read_only() {
+            perm &= !0o222;
+        }
+
+        perm
+    }
+
+    /// Unset or nonsensical timestamps, and ones before the Unix epoch, come out as the
+    /// epoch.
+    fn unix_time(&self, timestamp: FatDateTime) -> (u64, u64) {
+        match timestamp.to_unix_seconds_in(self.attributes.time_zone) {
+            Some(seconds) if seconds > 0 => {
+                (seconds as u64, u64::from(timestamp.millisecond) * 1_000_000)
+            }
+            _ => (0, 0),
+        }
+    }
+}
+
+impl<'a> Session<'a> {
+    fn fid(&self, fid: u32) -> Result<&Fid<'a>, u32> {
+        self.fids.get(&fid).ok_or(EBADF as u32)
+    }
+
+    fn fid_mut(&mut self, fid: u32) -> Result<&mut Fid<'a>, u32> {
+        self.fids.get_mut(&fid).ok_or(EBADF as u32)
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.into()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+fn parent_path(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(index) => &path[..index],
+        None => "",
+    }
+}
+
+fn errno(err: osc_fat::Error) -> u32 {
+    let errno = match err {
+        osc_fat::Error::Cancelled => ECANCELED,
+        osc_fat::Error::WriteProtected | osc_fat::Error::NotOpenForWriting => EROFS,
+        osc_fat::Error::NotFound => ENOENT,
+        osc_fat::Error::NotADirectory => ENOTDIR,
+        osc_fat::Error::IsADirectory => EISDIR,
+        osc_fat::Error::RootDirectory
+        | osc_fat::Error::InvalidName
+        | osc_fat::Error::InvalidGeometry
+        | osc_fat::Error::InvalidIndex
+        | osc_fat::Error::OutOfRange
+        | osc_fat::Error::BufferTooSmall
+        | osc_fat::Error::MoveIntoItself => EINVAL,
+        osc_fat::Error::AlreadyExists => EEXIST,
+        osc_fat::Error::DirectoryNotEmpty => ENOTEMPTY,
+        osc_fat::Error::NoSpace => ENOSPC,
+        osc_fat::Error::FileTooLarge => EFBIG,
+        osc_fat::Error::DeviceGone => ENODEV,
+        osc_fat::Error::Device(_)
+        | osc_fat::Error::Output
+        | osc_fat::Error::BadCluster
+        | osc_fat::Error::Corrupt
+        | osc_fat::Error::LimitExceeded(_)
+        | osc_fat::Error::Host(_) => EIO,
+    };
+
+    errno as u32
+}
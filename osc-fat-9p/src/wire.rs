@@ -0,0 +1,169 @@
+use std::io::{self, Read, Write};
+
+pub const HEADER_SIZE: usize = 7;
+
+pub const QID_TYPE_DIR: u8 = 0x80;
+pub const QID_TYPE_FILE: u8 = 0x00;
+
+/// Identifies a file to the client; two qids with the same path are the same file.
+#[derive(Debug, Copy, Clone)]
+pub struct Qid {
+    pub kind: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+impl Qid {
+    pub const SIZE: usize = 13;
+}
+
+/// The body of a message didn't match what its type says it should hold.
+#[derive(Debug)]
+pub struct Malformed;
+
+/// Takes the fields of a message body apart, in order.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Malformed> {
+        if self.data.len() < count {
+            return Err(Malformed);
+        }
+
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Malformed> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Malformed> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Malformed> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Malformed> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn string(&mut self) -> Result<String, Malformed> {
+        let length = self.u16()? as usize;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Malformed)
+    }
+}
+
+/// Builds the body of a reply.
+#[derive(Default)]
+pub struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.data.push(value);
+        self
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.data.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    /// Strings longer than a u16 can count are cut short; FAT names never are.
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        let length = core::cmp::min(value.len(), u16::MAX as usize);
+        self.u16(length as u16);
+        self.data.extend_from_slice(&value.as_bytes()[..length]);
+        self
+    }
+
+    pub fn qid(&mut self, qid: Qid) -> &mut Self {
+        self.u8(qid.kind).u32(qid.version).u64(qid.path)
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(value);
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Reads the next message, giving its type, tag and body, or `None` if the client has
+/// gone away.
+pub fn read_message<R: Read>(
+    reader: &mut R,
+    max_size: u32,
+) -> io::Result<Option<(u8, u16, Vec<u8>)>> {
+    let mut header = [0u8; HEADER_SIZE];
+
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let size = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+
+    if size < HEADER_SIZE as u32 || size > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes", size),
+        ));
+    }
+
+    let mut body = vec![0u8; size as usize - HEADER_SIZE];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some((
+        header[4],
+        u16::from_le_bytes([header[5], header[6]]),
+        body,
+    )))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, kind: u8, tag: u16, body: &[u8]) -> io::Result<()> {
+    let size = (HEADER_SIZE + body.len()) as u32;
+    let mut message = Vec::with_capacity(size as usize);
+
+    message.extend_from_slice(&size.to_le_bytes());
+    message.push(kind);
+    message.extend_from_slice(&tag.to_le_bytes());
+    message.extend_from_slice(body);
+
+    writer.write_all(&message)?;
+    writer.flush()
+}
@@ -0,0 +1,67 @@
+use crate::{CliError, CliResult};
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+/// A minimal command line parser: options are `--name value` or `--flag`, and may be
+/// interleaved with positional arguments.
+pub struct Args {
+    remaining: VecDeque<String>,
+}
+
+impl Args {
+    pub fn new(args: impl Iterator<Item = String>) -> Self {
+        Self {
+            remaining: args.collect(),
+        }
+    }
+
+    pub fn next_positional(&mut self) -> Option<String> {
+        let index = self
+            .remaining
+            .iter()
+            .position(|arg| !arg.starts_with("--"))?;
+        self.remaining.remove(index)
+    }
+
+    pub fn required_positional(&mut self, name: &str) -> CliResult<String> {
+        self.next_positional()
+            .ok_or_else(|| CliError::Usage(format!("missing {}", name)))
+    }
+
+    pub fn flag(&mut self, name: &str) -> bool {
+        match self.remaining.iter().position(|arg| arg == name) {
+            Some(index) => {
+                self.remaining.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn option<T: FromStr>(&mut self, name: &str) -> CliResult<Option<T>> {
+        let index = match self.remaining.iter().position(|arg| arg == name) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        self.remaining.remove(index);
+
+        let value = self
+            .remaining
+            .remove(index)
+            .ok_or_else(|| CliError::Usage(format!("{} needs a value", name)))?;
+
+        value
+            .parse()
+            .map(Some)
+            .map_err(|_| CliError::Usage(format!("invalid value '{}' for {}", value, name)))
+    }
+
+    /// Fails if anything wasn't consumed, so typos don't go unnoticed.
+    pub fn finish(self) -> CliResult<()> {
+        match self.remaining.front() {
+            Some(arg) => Err(CliError::Usage(format!("unexpected argument '{}'", arg))),
+            None => Ok(()),
+        }
+    }
+}
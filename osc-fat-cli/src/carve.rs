@@ -0,0 +1,42 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let entries = args.flag("--entries");
+    let image = args.required_positional("IMAGE")?;
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+
+    let carved = fs
+        .carve_directories()
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    for directory in &carved {
+        let parent = match directory.parent {
+            Some(parent) => format!("first cluster, parent {}", parent),
+            None => String::from("continuation"),
+        };
+
+        println!(
+            "cluster {:>8}  {:<9}  {:>4} entries  {:>4} long names  {}",
+            directory.cluster,
+            if directory.allocated { "lost" } else { "free" },
+            directory.entries.len(),
+            directory.checksummed_long_names,
+            parent
+        );
+
+        if entries {
+            for entry in &directory.entries {
+                let suffix = if entry.is_directory() { "/" } else { "" };
+                println!("    {:>10}  {}{}", entry.size, entry.name, suffix);
+            }
+        }
+    }
+
+    println!("{} candidate directory clusters", carved.len());
+
+    Ok(0)
+}
@@ -0,0 +1,76 @@
+use crate::args::Args;
+use crate::progress::{cancel_on_interrupt, ProgressDisplay};
+use crate::{open_image, open_image_writable, CliError, CliResult};
+use osc_fat::{CheckOptions, MountOptions};
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let repair = args.flag("--repair");
+    let quick = args.flag("--quick");
+    let image = args.required_positional("IMAGE")?;
+    args.finish()?;
+
+    if quick {
+        if repair {
+            return Err(CliError::Usage(String::from(
+                "--quick can't be used with --repair",
+            )));
+        }
+
+        let fs = open_image(&image, offset)?;
+        let report = fs
+            .quick_check()
+            .map_err(|err| CliError::Fat(image.clone(), fs.context_of(err)))?;
+
+        for issue in &report.issues {
+            println!("{}", issue);
+        }
+
+        return Ok(if report.is_clean() { 0 } else { 1 });
+    }
+
+    let fs = if repair {
+        open_image_writable(&image, offset, MountOptions::default())?
+    } else {
+        open_image(&image, offset)?
+    };
+
+    // NOTE: not a problem with the volume, but space on the device nothing can use
+    let geometry = fs.geometry();
+    let max_volume_bytes = u64::from(u32::MAX) * u64::from(geometry.sector_size_bytes());
+
+    if let Some(unused_bytes) = fs.unused_device_bytes() {
+        if geometry.size_bytes() + unused_bytes > max_volume_bytes {
+            eprintln!(
+                "osc-fat-cli: {}: the device is bigger than a FAT volume can be, and its last {} bytes are unused",
+                image, unused_bytes
+            );
+        }
+    }
+
+    let cancel_token = cancel_on_interrupt();
+    let mut display = ProgressDisplay::new();
+
+    let result = fs
+        .check(CheckOptions { repair }, &mut display, &cancel_token)
+        .map_err(|err| CliError::Fat(image.clone(), fs.context_of(err)));
+    drop(display);
+
+    // NOTE: what's been repaired is synced even if the check was cancelled part way
+    if repair {
+        fs.sync()
+            .map_err(|err| CliError::Fat(image.clone(), fs.context_of(err)))?;
+    }
+
+    let report = result?;
+
+    for finding in &report.findings {
+        if finding.repaired {
+            println!("{} (repaired)", finding.problem);
+        } else {
+            println!("{}", finding.problem);
+        }
+    }
+
+    Ok(if report.is_clean() { 0 } else { 1 })
+}
@@ -0,0 +1,82 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+use osc_fat::{Attributes, Difference, Timestamp};
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset_a = args.option("--offset-a")?.unwrap_or(0);
+    let offset_b = args.option("--offset-b")?.unwrap_or(0);
+    let image_a = args.required_positional("IMAGE_A")?;
+    let image_b = args.required_positional("IMAGE_B")?;
+    args.finish()?;
+
+    let fs_a = open_image(&image_a, offset_a)?;
+    let fs_b = open_image(&image_b, offset_b)?;
+
+    let differences = osc_fat::diff(&fs_a, &fs_b)
+        .map_err(|err| CliError::Fat("comparing images".into(), err.into()))?;
+
+    for difference in &differences {
+        match difference {
+            Difference::Added(path) => println!("+ {}", path),
+            Difference::Removed(path) => println!("- {}", path),
+            Difference::KindChanged(path) => println!("! {} (file/directory)", path),
+            Difference::ContentChanged(path) => println!("M {}", path),
+            Difference::AttributesChanged {
+                path,
+                before,
+                after,
+            } => println!(
+                "a {} ({} -> {})",
+                path,
+                format_attributes(*before),
+                format_attributes(*after)
+            ),
+            Difference::ShortNameChanged {
+                path,
+                before,
+                after,
+            } => println!("n {} ({} -> {})", path, before, after),
+            Difference::TimestampChanged {
+                path,
+                timestamp,
+                before,
+                after,
+            } => println!(
+                "t {} ({} {} -> {})",
+                path,
+                format_timestamp(*timestamp),
+                before,
+                after
+            ),
+        }
+    }
+
+    Ok(if differences.is_empty() { 0 } else { 1 })
+}
+
+fn format_timestamp(timestamp: Timestamp) -> &'static str {
+    match timestamp {
+        Timestamp::Created => "created",
+        Timestamp::Modified => "modified",
+        Timestamp::Accessed => "accessed",
+    }
+}
+
+fn format_attributes(attributes: Attributes) -> String {
+    [
+        (Attributes::READ_ONLY, 'R'),
+        (Attributes::HIDDEN, 'H'),
+        (Attributes::SYSTEM, 'S'),
+        (Attributes::DIRECTORY, 'D'),
+        (Attributes::ARCHIVE, 'A'),
+    ]
+    .iter()
+    .map(|(flag, letter)| {
+        if attributes.contains(*flag) {
+            *letter
+        } else {
+            '-'
+        }
+    })
+    .collect()
+}
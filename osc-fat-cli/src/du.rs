@@ -0,0 +1,52 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+use osc_fat::Usage;
+use std::cmp::Reverse;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let extensions = args.flag("--extensions");
+    let image = args.required_positional("IMAGE")?;
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+
+    let report = fs
+        .usage(extensions)
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    print_usage(&report.root, "/");
+
+    for directory in &report.directories {
+        print_usage(&directory.usage, &format!("/{}/", directory.name));
+    }
+
+    print_usage(&report.total(), "total");
+
+    if extensions {
+        let mut extensions: Vec<_> = report.extensions.iter().collect();
+
+        // The biggest first
+        extensions.sort_by_key(|(_, usage)| Reverse(usage.allocated_bytes));
+
+        println!();
+
+        for (extension, usage) in extensions {
+            let name = match extension.as_str() {
+                "" => String::from("(no extension)"),
+                extension => format!("*.{}", extension),
+            };
+
+            print_usage(usage, &name);
+        }
+    }
+
+    Ok(0)
+}
+
+fn print_usage(usage: &Usage, name: &str) {
+    println!(
+        "{:>12} allocated  {:>12} bytes  {:>8} files  {}",
+        usage.allocated_bytes, usage.bytes, usage.file_count, name
+    );
+}
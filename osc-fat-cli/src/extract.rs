@@ -0,0 +1,279 @@
+use crate::args::Args;
+use crate::progress::{cancel_on_interrupt, ProgressDisplay};
+use crate::{open_image, CliError, CliResult};
+use osc_fat::{
+    CancelToken, FATFileSystem, FatDateTime, Metadata, Progress, ProgressSink, TimeZonePolicy,
+    INVALID_LONG_NAME_CHARS,
+};
+use std::convert::TryFrom;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(target_os = "macos")]
+use std::os::macos::fs::FileTimesExt;
+#[cfg(target_os = "windows")]
+use std::os::windows::fs::FileTimesExt;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let preserve_times = if args.flag("--preserve-times") {
+        Some(args.option("--time-zone")?.unwrap_or_default())
+    } else {
+        None
+    };
+    let image = args.required_positional("IMAGE")?;
+    let source = args.required_positional("PATH")?;
+    let destination = args.required_positional("DEST")?;
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+    let cancel_token = cancel_on_interrupt();
+    let mut display = ProgressDisplay::new();
+
+    let dir = match fs.open_dir(&source) {
+        Ok(dir) => dir,
+        Err(osc_fat::Error::NotADirectory) => {
+            let item = fs
+                .lookup(&source)
+                .map_err(|err| CliError::Fat(source.clone(), fs.context_of(err)))?;
+
+            extract_file(
+                &fs,
+                &item,
+                Path::new(&destination),
+                preserve_times,
+                &mut display,
+                &cancel_token,
+            )?;
+            return Ok(0);
+        }
+        Err(err) => return Err(CliError::Fat(source, fs.context_of(err))),
+    };
+
+    create_dir(Path::new(&destination))?;
+
+    // Progress is in files, and the bytes of them, so the total has to be known first
+    let mut status = Progress::default();
+
+    fs.walk_tree(dir.selector(), |_, item| {
+        if item.is_file() {
+            status.items_total += 1;
+            status.bytes_total += u64::from(item.size);
+        }
+
+        Ok(())
+    })
+    .map_err(|err| CliError::Fat(source.clone(), fs.context_of(err)))?;
+
+    display.report(&status);
+
+    // NOTE: the walk can only fail with a FAT error, so I/O errors are stashed here
+    // and the walk stopped early
+    let mut io_error = None;
+
+    let result = fs.walk_tree(dir.selector(), |path, item| {
+        if cancel_token.is_cancelled() {
+            return Err(osc_fat::Error::Cancelled);
+        }
+
+        let result = target_path(Path::new(&destination), path, item).and_then(|target| {
+            if item.is_directory() {
+                return create_dir(&target);
+            }
+
+            let mut file_progress = FileProgress {
+                sink: &mut display,
+                before: status,
+            };
+
+            extract_file(
+                &fs,
+                item,
+                &target,
+                preserve_times,
+                &mut file_progress,
+                &cancel_token,
+            )?;
+
+            status.items_done += 1;
+            status.bytes_done += u64::from(item.size);
+            display.report(&status);
+
+            Ok(())
+        });
+
+        result.map_err(|err| {
+            io_error = Some(err);
+            osc_fat::Error::Output
+        })
+    });
+
+    drop(display);
+
+    match (io_error, result) {
+        (Some(err), _) => Err(err),
+        (None, Err(err)) => Err(CliError::Fat(source, fs.context_of(err))),
+        (None, Ok(())) => Ok(0),
+    }
+}
+
+/// Where the item at `path`, as `walk_tree` gives it, goes beneath `destination`. The
+/// names come from the image, so any that could lead outside of `destination`, such as
+/// `..` or one with a separator in it, are refused.
+fn target_path(destination: &Path, path: &str, item: &Metadata) -> CliResult<PathBuf> {
+    let is_safe = |name: &str| {
+        !matches!(name, "" | "." | "..")
+            && !name
+                .chars()
+                .any(|ch| ch < ' ' || INVALID_LONG_NAME_CHARS.contains(&ch))
+    };
+
+    let relative = path.trim_start_matches('/');
+    let target = destination.join(relative);
+
+    let is_contained = is_safe(&item.name)
+        && relative.split('/').all(is_safe)
+        && Path::new(relative)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        && target.starts_with(destination);
+
+    if !is_contained {
+        return Err(CliError::Io(
+            path.into(),
+            io::Error::new(io::ErrorKind::InvalidData, "unsafe name in image"),
+        ));
+    }
+
+    Ok(target)
+}
+
+fn create_dir(path: &Path) -> CliResult<()> {
+    fs::create_dir_all(path).map_err(|err| CliError::Io(path.display().to_string(), err))
+}
+
+/// The granularity holes are left at when extracting, which matches the block size of
+/// most host filesystems.
+const HOLE_SIZE: usize = 4096;
+
+/// Passes on the progress of extracting one file as that of extracting everything,
+/// from what had been extracted before it.
+struct FileProgress<'a> {
+    sink: &'a mut dyn ProgressSink,
+    before: Progress,
+}
+
+impl ProgressSink for FileProgress<'_> {
+    fn report(&mut self, progress: &Progress) {
+        self.sink.report(&Progress {
+            bytes_done: self.before.bytes_done + progress.bytes_done,
+            ..self.before
+        });
+    }
+}
+
+fn extract_file(
+    fs: &FATFileSystem,
+    item: &Metadata,
+    path: &Path,
+    preserve_times: Option<TimeZonePolicy>,
+    progress: &mut dyn ProgressSink,
+    cancel_token: &CancelToken,
+) -> CliResult<()> {
+    let context = || path.display().to_string();
+
+    let mut file = File::create(path).map_err(|err| CliError::Io(context(), err))?;
+    let mut writer = SparseWriter {
+        file: &mut file,
+        error: None,
+    };
+
+    let result = fs.read_file_into(
+        item.first_cluster,
+        item.size,
+        &mut writer,
+        progress,
+        cancel_token,
+    );
+
+    match (writer.error, result) {
+        (Some(err), _) => return Err(CliError::Io(context(), err)),
+        (None, Err(err)) => return Err(CliError::Fat(context(), fs.context_of(err))),
+        (None, Ok(_)) => {}
+    }
+
+    // NOTE: a trailing hole is only skipped over, so the length has to be set explicitly
+    file.set_len(u64::from(item.size))
+        .map_err(|err| CliError::Io(context(), err))?;
+
+    if let Some(time_zone) = preserve_times {
+        file.set_times(file_times(item, time_zone))
+            .map_err(|err| CliError::Io(context(), err))?;
+    }
+
+    Ok(())
+}
+
+fn file_times(item: &Metadata, time_zone: TimeZonePolicy) -> FileTimes {
+    let mut times = FileTimes::new();
+
+    if let Some(modified) = system_time(&item.modified, time_zone) {
+        times = times.set_modified(modified);
+    }
+
+    // NOTE: only some platforms let the creation time be set
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    if let Some(created) = system_time(&item.created, time_zone) {
+        times = times.set_created(created);
+    }
+
+    times
+}
+
+fn system_time(timestamp: &FatDateTime, time_zone: TimeZonePolicy) -> Option<SystemTime> {
+    let seconds = u64::try_from(timestamp.to_unix_seconds_in(time_zone)?).ok()?;
+    Some(
+        UNIX_EPOCH
+            + Duration::from_secs(seconds)
+            + Duration::from_millis(timestamp.millisecond.into()),
+    )
+}
+
+/// Writes a file out sparsely: blocks that are entirely zero are skipped over rather
+/// than written, leaving holes on filesystems that support them. The first error is
+/// kept, as the filesystem only reports that the output failed.
+struct SparseWriter<'a> {
+    file: &'a mut File,
+    error: Option<io::Error>,
+}
+
+impl SparseWriter<'_> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if chunk.iter().all(|&byte| byte == 0) {
+            self.file.seek(SeekFrom::Current(chunk.len() as i64))?;
+            Ok(())
+        } else {
+            self.file.write_all(chunk)
+        }
+    }
+}
+
+impl Write for SparseWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        for chunk in data.chunks(HOLE_SIZE) {
+            if let Err(err) = self.write_chunk(chunk) {
+                let kind = err.kind();
+                self.error.get_or_insert(err);
+                return Err(kind.into());
+            }
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,41 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+use std::cmp::Reverse;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let all = args.flag("--all");
+    let image = args.required_positional("IMAGE")?;
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+
+    let report = fs
+        .fragmentation()
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    let mut files: Vec<_> = report
+        .files
+        .iter()
+        .filter(|file| all || file.is_fragmented())
+        .collect();
+
+    // The most fragmented first
+    files.sort_by_key(|file| Reverse(file.extent_count));
+
+    for file in files {
+        println!(
+            "{:>8} extents  {:>10} clusters  {}",
+            file.extent_count, file.cluster_count, file.path
+        );
+    }
+
+    println!(
+        "{} files, {} fragmented, score {}%",
+        report.files.len(),
+        report.fragmented_file_count(),
+        report.score()
+    );
+
+    Ok(0)
+}
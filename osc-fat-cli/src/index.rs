@@ -0,0 +1,19 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+use std::fs;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let image = args.required_positional("IMAGE")?;
+    let output = args.required_positional("OUTPUT")?;
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+    let index = fs
+        .index()
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    fs::write(&output, index.to_bytes()).map_err(|err| CliError::Io(output, err))?;
+
+    Ok(0)
+}
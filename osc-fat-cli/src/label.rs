@@ -0,0 +1,36 @@
+use crate::args::Args;
+use crate::{open_image, open_image_writable, CliError, CliResult};
+use osc_fat::MountOptions;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let image = args.required_positional("IMAGE")?;
+    let label = args.next_positional();
+    args.finish()?;
+
+    let label = match label {
+        Some(label) => label,
+        None => {
+            let fs = open_image(&image, offset)?;
+            let label = fs
+                .label()
+                .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+            if let Some(label) = label {
+                println!("{}", label);
+            }
+
+            return Ok(0);
+        }
+    };
+
+    let fs = open_image_writable(&image, offset, MountOptions::default())?;
+
+    fs.set_label(&label)
+        .map_err(|err| CliError::Fat(label.clone(), fs.context_of(err)))?;
+
+    fs.sync()
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    Ok(0)
+}
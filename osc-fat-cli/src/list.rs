@@ -0,0 +1,57 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+use osc_fat::Metadata;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let bare = args.flag("--bare");
+    let recursive = args.flag("--recursive");
+    let image = args.required_positional("IMAGE")?;
+    let path = args.next_positional().unwrap_or_else(|| "/".into());
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+
+    let dir = match fs.open_dir(&path) {
+        Ok(dir) => dir,
+        Err(osc_fat::Error::NotADirectory) => {
+            let item = fs
+                .lookup(&path)
+                .map_err(|err| CliError::Fat(path.clone(), fs.context_of(err)))?;
+
+            print_item(&path, &item, bare);
+            return Ok(0);
+        }
+        Err(err) => return Err(CliError::Fat(path, fs.context_of(err))),
+    };
+
+    let prefix = dir.path().trim_end_matches('/');
+
+    if recursive {
+        fs.walk_tree(dir.selector(), |item_path, item| {
+            print_item(&format!("{}{}", prefix, item_path), item, bare);
+            Ok(())
+        })
+        .map_err(|err| CliError::Fat(path, fs.context_of(err)))?;
+    } else {
+        let items = dir
+            .list()
+            .map_err(|err| CliError::Fat(path, fs.context_of(err)))?;
+
+        for item in items {
+            print_item(&format!("{}/{}", prefix, item.name), &item, bare);
+        }
+    }
+
+    Ok(0)
+}
+
+fn print_item(path: &str, item: &Metadata, bare: bool) {
+    if bare {
+        println!("{}", path);
+    } else if item.is_directory() {
+        println!("{}  {:>10}  {}", item.modified, "<DIR>", path);
+    } else {
+        println!("{}  {:>10}  {}", item.modified, item.size, path);
+    }
+}
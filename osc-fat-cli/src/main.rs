@@ -0,0 +1,268 @@
+use osc_block_storage::split::SplitImage;
+use osc_block_storage::virt::*;
+use osc_block_storage::BlockDevice;
+use osc_fat::*;
+use std::env;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::process;
+
+mod args;
+mod carve;
+mod check;
+mod diff;
+mod du;
+mod extract;
+mod frag;
+mod index;
+mod label;
+mod list;
+mod manifest;
+mod mkdir;
+mod mtools;
+mod owner;
+mod progress;
+mod put;
+mod serve_nbd;
+mod tree;
+mod verify;
+
+use args::Args;
+
+const USAGE: &str = "\
+usage: osc-fat-cli <command> [options]
+
+commands:
+  carve [--offset BYTES] [--entries] IMAGE
+      look through the clusters no file or directory has for ones that hold what
+      look like directory entries, to find directories lost to damage, and with
+      --entries list the entries in each
+  check [--offset BYTES] [--repair | --quick] IMAGE
+      look for problems in an image, such as long file name entries left behind by
+      writers that don't know about them or clusters no file refers to, and with
+      --repair fix them, saving lost clusters as FOUND.nnn/FILEnnnn.CHK; with
+      --quick only look at the boot sector's signature, the reserved FAT entries,
+      the clean shutdown flag, the FSInfo sector and the root directory; exits
+      with 1 if problems were found and not fixed
+  diff [--offset-a BYTES] [--offset-b BYTES] IMAGE_A IMAGE_B
+      compare the files and directories in two images
+  du [--offset BYTES] [--extensions] IMAGE
+      show how much space the files in the root directory, and each directory
+      in it along with everything beneath it, take up, and with --extensions how
+      much the files with each extension take up, the most first
+  extract [--offset BYTES] [--preserve-times [--time-zone ZONE]] IMAGE PATH DEST
+      copy a file, or a directory and everything beneath it, out of an image,
+      leaving holes for clusters of zeros and optionally keeping file timestamps,
+      which are taken to be in ZONE: utc (the default), local or an offset
+      like +01:00
+  frag [--offset BYTES] [--all] IMAGE
+      show how fragmented the files in an image are, most fragmented first, with
+      --all to include those in one piece, and a score for the whole volume from 0
+      (no fragmentation) to 100
+  index [--offset BYTES] IMAGE OUTPUT
+      save an index of every file and directory in an image to OUTPUT, for
+      FATFileSystem::load_index to load instead of reading the whole tree
+  label [--offset BYTES] IMAGE [LABEL]
+      show the volume label, or set it to LABEL, which is upper-cased and can be up
+      to 11 characters; an empty LABEL removes it
+  list [--offset BYTES] [--bare] [--recursive] IMAGE [PATH]
+      list a directory, or with --bare just the paths of what's in it
+  manifest [--offset BYTES] [--time-zone ZONE] IMAGE
+      print the sha256, size, modification time and path of every file, with the
+      time in ISO 8601 form taken to be in ZONE: utc (the default), local or an
+      offset like +01:00
+  mkdir [--offset BYTES] IMAGE PATH...
+      create directories in an image
+  owner [--offset BYTES] [--clusters] IMAGE SECTOR...
+      show which file or directory each sector (counting from the start of the
+      volume), or with --clusters each cluster, belongs to, or - if none does
+  put [--offset BYTES] [--recursive] [--preserve-times [--time-zone ZONE]]
+      [--preserve-read-only] IMAGE SOURCE... DEST
+      copy files, or with --recursive directories and everything beneath them,
+      into an image, replacing files already there; DEST is the directory to copy
+      into if it exists, and the new name otherwise
+  serve-nbd [--offset BYTES] [--writable] IMAGE HOST:PORT
+      export the raw image over NBD, for the kernel's nbd driver or qemu, read-only
+      unless --writable is given
+  tree [--offset BYTES] [--ascii] [--sizes] [--attributes] [--depth N] IMAGE [PATH]
+      draw a directory and everything beneath it as a tree, with --ascii in plain
+      ASCII, with --sizes and --attributes showing file sizes and the read-only,
+      hidden, system and archive attributes, and only N levels deep if given
+  verify [--offset BYTES] IMAGE
+      read every sector of every file and directory in an image, listing those
+      with sectors that can't be read; exits with 1 if there are any
+
+mdir, mcopy and mmd are also accepted as commands, or as the name the program is
+run by, taking mtools-style arguments: -i IMAGE[@@OFFSET] and ::PATH for paths in
+the image
+
+An IMAGE split into pieces of the same size, such as disk.img.000, disk.img.001
+and so on, can be given as any of its pieces, or as disk.img
+
+Built with the aes-xts feature, images encrypted with AES-XTS, as by dm-crypt's
+aes-xts-plain64 with 512-byte sectors, are read and written with the key given in
+hex by OSC_FAT_XTS_KEY";
+
+pub enum CliError {
+    Usage(String),
+    Io(String, io::Error),
+    Fat(String, osc_fat::ContextError),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            Self::Io(context, err) => write!(f, "{}: {}", context, err),
+            Self::Fat(context, err) => write!(f, "{}: {}", context, err),
+        }
+    }
+}
+
+pub type CliResult<T> = Result<T, CliError>;
+
+pub fn open_image(path: &str, offset: u64) -> CliResult<FATFileSystem> {
+    open_image_with_options(path, offset, MountOptions::default())
+}
+
+pub fn open_image_with_options(
+    path: &str,
+    offset: u64,
+    options: MountOptions,
+) -> CliResult<FATFileSystem> {
+    let device = open_device(path, offset, false)?;
+    FATFileSystem::open_with_options(device, options)
+        .map_err(|err| CliError::Fat(path.into(), err.into()))
+}
+
+/// Refuses a path whose last component can't be given to a new entry, so that it's
+/// found before anything's been changed.
+pub fn validate_new_path(path: &str) -> CliResult<()> {
+    let name = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    validate_long_name(name).map_err(|err| CliError::Fat(path.into(), err.into()))
+}
+
+pub fn open_image_writable(
+    path: &str,
+    offset: u64,
+    options: MountOptions,
+) -> CliResult<FATFileSystem> {
+    let device = open_device(path, offset, true)?;
+    FATFileSystem::open_with_options(device, options)
+        .map_err(|err| CliError::Fat(path.into(), err.into()))
+}
+
+/// Opens the image at `path`, or the split image it's a piece or the name of, if there
+/// are other pieces or there's no such file.
+fn open_device(path: &str, offset: u64, writable: bool) -> CliResult<Box<dyn BlockDevice>> {
+    let split = SplitImage::discover(path)
+        .ok()
+        .filter(|split| split.paths.len() > 1 || !Path::new(path).exists());
+
+    if let Some(split) = split {
+        let device = split
+            .open(offset, writable)
+            .map_err(|err| CliError::Io(path.into(), err))?;
+        return decrypted(device);
+    }
+
+    let device = OpenOptions::new()
+        .read(true)
+        .write(writable)
+        .open(path)
+        .and_then(|file| FileBlockDevice::new(file, offset))
+        .map_err(|err| CliError::Io(path.into(), err))?
+        .writable(writable);
+    decrypted(device)
+}
+
+/// Decrypts `device` with AES-XTS if OSC_FAT_XTS_KEY gives a key, in hex.
+#[cfg(feature = "aes-xts")]
+fn decrypted<D: BlockDevice + 'static>(device: D) -> CliResult<Box<dyn BlockDevice>> {
+    use osc_block_storage::xts::XtsBlockDevice;
+
+    let key = match env::var("OSC_FAT_XTS_KEY") {
+        Ok(key) => key,
+        Err(_) => return Ok(Box::new(device)),
+    };
+
+    let key = (0..key.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(key.get(index..(index + 2))?, 16).ok())
+        .collect::<Option<Vec<_>>>();
+
+    match key.and_then(|key| XtsBlockDevice::new(device, &key)) {
+        Some(device) => Ok(Box::new(device)),
+        None => Err(CliError::Usage(
+            "OSC_FAT_XTS_KEY must be 64 or 128 hex digits".into(),
+        )),
+    }
+}
+
+#[cfg(not(feature = "aes-xts"))]
+fn decrypted<D: BlockDevice + 'static>(device: D) -> CliResult<Box<dyn BlockDevice>> {
+    Ok(Box::new(device))
+}
+
+fn main() {
+    let mut arguments = env::args();
+
+    // Run by a link named after an mtools command
+    let program = arguments.next().unwrap_or_default();
+    let program = Path::new(&program)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+
+    let result = if mtools::is_command(program) {
+        mtools::run(program, arguments.collect())
+    } else {
+        run(arguments.collect())
+    };
+
+    match result {
+        Ok(exit_code) => process::exit(exit_code),
+        Err(err) => {
+            eprintln!("osc-fat-cli: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+fn run(arguments: Vec<String>) -> CliResult<i32> {
+    if let Some(command) = arguments
+        .first()
+        .filter(|command| mtools::is_command(command))
+    {
+        return mtools::run(command, arguments[1..].to_vec());
+    }
+
+    let mut args = Args::new(arguments.into_iter());
+
+    match args.next_positional().as_deref() {
+        Some("carve") => carve::run(args),
+        Some("check") => check::run(args),
+        Some("diff") => diff::run(args),
+        Some("du") => du::run(args),
+        Some("extract") => extract::run(args),
+        Some("frag") => frag::run(args),
+        Some("index") => index::run(args),
+        Some("label") => label::run(args),
+        Some("list") => list::run(args),
+        Some("manifest") => manifest::run(args),
+        Some("mkdir") => mkdir::run(args),
+        Some("owner") => owner::run(args),
+        Some("put") => put::run(args),
+        Some("serve-nbd") => serve_nbd::run(args),
+        Some("tree") => tree::run(args),
+        Some("verify") => verify::run(args),
+        Some(command) => Err(CliError::Usage(format!("unknown command '{}'", command))),
+        None => Err(CliError::Usage("no command given".into())),
+    }
+}
@@ -0,0 +1,26 @@
+use crate::args::Args;
+use crate::{open_image_with_options, CliError, CliResult};
+use osc_fat::MountOptions;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let time_zone = args.option("--time-zone")?.unwrap_or_default();
+    let image = args.required_positional("IMAGE")?;
+    args.finish()?;
+
+    let options = MountOptions {
+        time_zone,
+        ..Default::default()
+    };
+
+    let fs = open_image_with_options(&image, offset, options)?;
+
+    let mut manifest = String::new();
+
+    fs.export_manifest(&mut manifest)
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    print!("{}", manifest);
+
+    Ok(0)
+}
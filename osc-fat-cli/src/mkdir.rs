@@ -0,0 +1,31 @@
+use crate::args::Args;
+use crate::{open_image_writable, validate_new_path, CliError, CliResult};
+use osc_fat::MountOptions;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let image = args.required_positional("IMAGE")?;
+    let mut paths = vec![args.required_positional("PATH")?];
+
+    while let Some(path) = args.next_positional() {
+        paths.push(path);
+    }
+
+    args.finish()?;
+
+    for path in &paths {
+        validate_new_path(path)?;
+    }
+
+    let fs = open_image_writable(&image, offset, MountOptions::default())?;
+
+    for path in paths {
+        fs.create_dir(&path)
+            .map_err(|err| CliError::Fat(path.clone(), fs.context_of(err)))?;
+    }
+
+    fs.sync()
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    Ok(0)
+}
@@ -0,0 +1,244 @@
+use crate::args::Args;
+use crate::{extract, list, mkdir, put, CliError, CliResult};
+use std::path::Path;
+
+const COMMANDS: &[&str] = &["mdir", "mcopy", "mmd"];
+
+pub fn is_command(name: &str) -> bool {
+    COMMANDS.contains(&name)
+}
+
+/// Runs an mtools-style command line by translating it into one for the equivalent
+/// command, so that build scripts written for mtools work unchanged.
+pub fn run(command: &str, arguments: Vec<String>) -> CliResult<i32> {
+    let invocation = Invocation::parse(command, arguments)?;
+
+    match command {
+        "mdir" => invocation.mdir(),
+        "mcopy" => invocation.mcopy(),
+        _ => invocation.mmd(),
+    }
+}
+
+struct Invocation {
+    command: String,
+    image: String,
+    offset: String,
+    flags: Vec<char>,
+    paths: Vec<String>,
+}
+
+impl Invocation {
+    fn parse(command: &str, arguments: Vec<String>) -> CliResult<Self> {
+        let mut image = None;
+        let mut flags = Vec::new();
+        let mut paths = Vec::new();
+
+        let mut arguments = arguments.into_iter();
+
+        while let Some(argument) = arguments.next() {
+            if argument == "-i" {
+                image = Some(
+                    arguments
+                        .next()
+                        .ok_or_else(|| CliError::Usage("-i needs a value".into()))?,
+                );
+            } else if let Some(value) = argument.strip_prefix("-i") {
+                image = Some(value.into());
+            } else if argument.len() > 1 && argument.starts_with('-') {
+                flags.extend(argument.chars().skip(1));
+            } else {
+                paths.push(argument);
+            }
+        }
+
+        // NOTE: mtools drive letters are configured in mtoolsrc, so only -i images,
+        // which are the "::" drive, can be supported
+        let image = image.ok_or_else(|| CliError::Usage(format!("{} needs -i IMAGE", command)))?;
+
+        let (image, offset) = match image.split_once("@@") {
+            Some((image, offset)) => (image.into(), offset.into()),
+            None => (image, "0".into()),
+        };
+
+        Ok(Self {
+            command: command.into(),
+            image,
+            offset,
+            flags,
+            paths,
+        })
+    }
+
+    /// Refuses any flag not in `supported`, rather than quietly doing something other
+    /// than mtools would.
+    fn check_flags(&self, supported: &str) -> CliResult<()> {
+        match self.flags.iter().find(|flag| !supported.contains(**flag)) {
+            Some(flag) => Err(CliError::Usage(format!(
+                "{}: unsupported option -{}",
+                self.command, flag
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn has_flag(&self, flag: char) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    fn base_args(&self) -> Vec<String> {
+        vec!["--offset".into(), self.offset.clone()]
+    }
+
+    /// mdir [-a] [-b] [-/] ::DIR...
+    fn mdir(&self) -> CliResult<i32> {
+        // NOTE: -a (show hidden files) is accepted, as hidden files are always listed
+        self.check_flags("ab/")?;
+
+        let paths = match self.paths.len() {
+            0 => vec![String::from("::/")],
+            _ => self.paths.clone(),
+        };
+
+        for path in &paths {
+            let mut args = self.base_args();
+
+            if self.has_flag('b') {
+                args.push("--bare".into());
+            }
+
+            if self.has_flag('/') {
+                args.push("--recursive".into());
+            }
+
+            args.push(self.image.clone());
+            args.push(self.image_path(path)?.into());
+
+            list::run(Args::new(args.into_iter()))?;
+        }
+
+        Ok(0)
+    }
+
+    /// mcopy [-s] [-m] [-p] SOURCE... ::DEST, or mcopy [-m] ::SOURCE... [DEST]
+    fn mcopy(&self) -> CliResult<i32> {
+        // NOTE: -o, -n, -Q, -v and -b only change prompting, reporting and batching,
+        // which don't apply here
+        self.check_flags("smponQvb")?;
+
+        let mut sources = self.paths.clone();
+
+        let destination = match sources.len() {
+            0 => return Err(CliError::Usage("mcopy needs a source".into())),
+            1 => String::from("."),
+            _ => sources.pop().unwrap_or_default(),
+        };
+
+        match image_path(&destination) {
+            Some(destination) => self.put(&sources, destination),
+            None => self.extract(&sources, &destination),
+        }
+    }
+
+    fn put(&self, sources: &[String], destination: &str) -> CliResult<i32> {
+        let mut args = self.base_args();
+
+        if self.has_flag('s') {
+            args.push("--recursive".into());
+        }
+
+        if self.has_flag('m') {
+            args.push("--preserve-times".into());
+        }
+
+        if self.has_flag('p') {
+            args.push("--preserve-read-only".into());
+        }
+
+        args.push(self.image.clone());
+
+        for source in sources {
+            if image_path(source).is_some() {
+                return Err(CliError::Usage("mcopy can't copy within an image".into()));
+            }
+
+            args.push(source.clone());
+        }
+
+        args.push(destination.into());
+
+        put::run(Args::new(args.into_iter()))
+    }
+
+    fn extract(&self, sources: &[String], destination: &str) -> CliResult<i32> {
+        let into_directory = Path::new(destination).is_dir();
+
+        if sources.len() > 1 && !into_directory {
+            return Err(CliError::Usage(format!(
+                "'{}' isn't a directory",
+                destination
+            )));
+        }
+
+        for source in sources {
+            let source = self.image_path(source)?;
+
+            let target = if into_directory {
+                let name = source
+                    .trim_end_matches('/')
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or("");
+                Path::new(destination).join(name)
+            } else {
+                Path::new(destination).to_path_buf()
+            };
+
+            let mut args = self.base_args();
+
+            if self.has_flag('m') {
+                args.push("--preserve-times".into());
+            }
+
+            args.push(self.image.clone());
+            args.push(source.into());
+            args.push(target.display().to_string());
+
+            extract::run(Args::new(args.into_iter()))?;
+        }
+
+        Ok(0)
+    }
+
+    /// mmd ::DIR...
+    fn mmd(&self) -> CliResult<i32> {
+        self.check_flags("")?;
+
+        let mut args = self.base_args();
+        args.push(self.image.clone());
+
+        for path in &self.paths {
+            args.push(self.image_path(path)?.into());
+        }
+
+        mkdir::run(Args::new(args.into_iter()))
+    }
+
+    fn image_path<'p>(&self, path: &'p str) -> CliResult<&'p str> {
+        image_path(path).ok_or_else(|| {
+            CliError::Usage(format!(
+                "{}: '{}' isn't a path in the image, which start with ::",
+                self.command, path
+            ))
+        })
+    }
+}
+
+/// The path within the image for an mtools path like `::/DIR/FILE`, or `None` for a
+/// host path.
+fn image_path(path: &str) -> Option<&str> {
+    match path.strip_prefix("::")? {
+        "" => Some("/"),
+        path => Some(path),
+    }
+}
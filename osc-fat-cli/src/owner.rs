@@ -0,0 +1,45 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+use std::convert::TryFrom;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let clusters = args.flag("--clusters");
+    let image = args.required_positional("IMAGE")?;
+    let mut numbers = vec![args.required_positional("SECTOR")?];
+
+    while let Some(number) = args.next_positional() {
+        numbers.push(number);
+    }
+
+    args.finish()?;
+
+    let numbers = numbers
+        .iter()
+        .map(|number| {
+            number
+                .parse::<u64>()
+                .map_err(|_| CliError::Usage(format!("invalid number '{}'", number)))
+        })
+        .collect::<CliResult<Vec<_>>>()?;
+
+    let fs = open_image(&image, offset)?;
+
+    let owners = fs
+        .cluster_owners()
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    for number in numbers {
+        let owner = if clusters {
+            u32::try_from(number)
+                .ok()
+                .and_then(|cluster| owners.owner(cluster))
+        } else {
+            owners.owner_of_sector(number)
+        };
+
+        println!("{}: {}", number, owner.unwrap_or("-"));
+    }
+
+    Ok(0)
+}
@@ -0,0 +1,65 @@
+use osc_fat::{CancelToken, Progress, ProgressSink};
+use std::io::{self, IsTerminal, Write};
+
+/// Shows how far an operation has got on stderr, as a percentage of its bytes, if
+/// stderr is a terminal. The line it's shown on is cleared when it's dropped, so
+/// whatever's printed afterwards doesn't follow it.
+pub struct ProgressDisplay {
+    enabled: bool,
+    shown_percent: Option<u64>,
+}
+
+impl ProgressDisplay {
+    pub fn new() -> Self {
+        Self {
+            enabled: io::stderr().is_terminal(),
+            shown_percent: None,
+        }
+    }
+}
+
+impl ProgressSink for ProgressDisplay {
+    fn report(&mut self, progress: &Progress) {
+        if !self.enabled || progress.bytes_total == 0 {
+            return;
+        }
+
+        let percent = progress.bytes_done.min(progress.bytes_total) * 100 / progress.bytes_total;
+
+        // NOTE: the line's only redrawn when it changes, as reports can come in far
+        // faster than a terminal can keep up with
+        if self.shown_percent == Some(percent) {
+            return;
+        }
+
+        self.shown_percent = Some(percent);
+
+        let mut stderr = io::stderr();
+        let _ = write!(
+            stderr,
+            "\r{:3}% ({} of {} bytes)",
+            percent, progress.bytes_done, progress.bytes_total
+        );
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for ProgressDisplay {
+    fn drop(&mut self) {
+        if self.shown_percent.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+/// A token that's cancelled when Ctrl-C is pressed, so what's using it can stop at
+/// the next cluster rather than the process being killed part way through a write.
+pub fn cancel_on_interrupt() -> CancelToken {
+    let cancel_token = CancelToken::new();
+    let handler_token = cancel_token.clone();
+
+    // NOTE: without a handler, Ctrl-C just kills the process, as it always has
+    let _ = ctrlc::set_handler(move || handler_token.cancel());
+
+    cancel_token
+}
@@ -0,0 +1,101 @@
+use crate::args::Args;
+use crate::{open_image_writable, validate_new_path, CliError, CliResult};
+use osc_fat::{CopyOptions, MountOptions};
+use std::fs;
+use std::path::Path;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let recursive = args.flag("--recursive");
+    let preserve_times = if args.flag("--preserve-times") {
+        Some(args.option("--time-zone")?.unwrap_or_default())
+    } else {
+        None
+    };
+    let preserve_read_only = args.flag("--preserve-read-only");
+    let image = args.required_positional("IMAGE")?;
+    let mut sources = vec![args.required_positional("SOURCE")?];
+
+    while let Some(source) = args.next_positional() {
+        sources.push(source);
+    }
+
+    args.finish()?;
+
+    let destination = match sources.len() {
+        1 => return Err(CliError::Usage("missing DEST".into())),
+        _ => sources.pop().unwrap_or_default(),
+    };
+
+    let options = CopyOptions {
+        preserve_modified: preserve_times.is_some(),
+        preserve_read_only,
+    };
+
+    let fs = open_image_writable(
+        &image,
+        offset,
+        MountOptions {
+            time_zone: preserve_times.unwrap_or_default(),
+            ..MountOptions::default()
+        },
+    )?;
+
+    let into_directory = match fs.lookup(&destination) {
+        Ok(item) => item.is_directory(),
+        Err(osc_fat::Error::NotFound) => false,
+        Err(err) => return Err(CliError::Fat(destination, fs.context_of(err))),
+    };
+
+    if sources.len() > 1 && !into_directory {
+        return Err(CliError::Fat(
+            destination,
+            osc_fat::Error::NotADirectory.into(),
+        ));
+    }
+
+    let mut targets = Vec::new();
+
+    for source in &sources {
+        let target = if into_directory {
+            let name = Path::new(source)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| CliError::Usage(format!("no file name in '{}'", source)))?;
+
+            format!("{}/{}", destination.trim_end_matches('/'), name)
+        } else {
+            destination.clone()
+        };
+
+        validate_new_path(&target)?;
+        targets.push(target);
+    }
+
+    for (source, target) in sources.iter().zip(targets) {
+        let source_path = Path::new(source);
+
+        let metadata =
+            fs::metadata(source_path).map_err(|err| CliError::Io(source.clone(), err))?;
+
+        let result = if !metadata.is_dir() {
+            fs.copy_from_host_with_options(source_path, &target, &options)
+                .map(|_| ())
+        } else if recursive {
+            fs.import_tree(source_path, &target, &options, |_, _| true)
+                .map(|_| ())
+        } else {
+            return Err(CliError::Usage(format!(
+                "'{}' is a directory, which needs --recursive",
+                source
+            )));
+        };
+
+        result.map_err(|err| CliError::Fat(source.clone(), fs.context_of(err)))?;
+    }
+
+    fs.sync()
+        .map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    Ok(0)
+}
@@ -0,0 +1,33 @@
+use crate::args::Args;
+use crate::{CliError, CliResult};
+use osc_block_storage::nbd::NbdServer;
+use osc_block_storage::virt::FileBlockDevice;
+use std::fs::OpenOptions;
+use std::net::TcpListener;
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let writable = args.flag("--writable");
+    let image = args.required_positional("IMAGE")?;
+    let address = args.required_positional("HOST:PORT")?;
+    args.finish()?;
+
+    let device = OpenOptions::new()
+        .read(true)
+        .write(writable)
+        .open(&image)
+        .and_then(|file| FileBlockDevice::new(file, offset))
+        .map_err(|err| CliError::Io(image.clone(), err))?
+        .writable(writable);
+    let listener = TcpListener::bind(&address).map_err(|err| CliError::Io(address, err))?;
+    let mut server = NbdServer::new(device);
+
+    // NOTE: clients are served one after another, so that two never write at once
+    for stream in listener.incoming() {
+        if let Err(err) = stream.and_then(|stream| server.serve(stream)) {
+            eprintln!("osc-fat-cli: connection failed: {}", err);
+        }
+    }
+
+    Ok(0)
+}
@@ -0,0 +1,33 @@
+use crate::args::Args;
+use crate::{open_image, CliError, CliResult};
+use osc_fat::{TreeOptions, TreeStyle};
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let ascii = args.flag("--ascii");
+    let sizes = args.flag("--sizes");
+    let attributes = args.flag("--attributes");
+    let max_depth = args.option("--depth")?;
+    let image = args.required_positional("IMAGE")?;
+    let path = args.next_positional().unwrap_or_else(|| "/".into());
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+    let dir = fs
+        .open_dir(&path)
+        .map_err(|err| CliError::Fat(path, fs.context_of(err)))?;
+
+    let options = TreeOptions {
+        style: if ascii {
+            TreeStyle::Ascii
+        } else {
+            TreeStyle::Unicode
+        },
+        sizes,
+        attributes,
+        max_depth,
+    };
+
+    print!("{}", fs.render_tree(dir.selector(), options));
+    Ok(0)
+}
@@ -0,0 +1,51 @@
+use crate::args::Args;
+use crate::progress::{cancel_on_interrupt, ProgressDisplay};
+use crate::{open_image, CliError, CliResult};
+
+pub fn run(mut args: Args) -> CliResult<i32> {
+    let offset = args.option("--offset")?.unwrap_or(0);
+    let image = args.required_positional("IMAGE")?;
+    args.finish()?;
+
+    let fs = open_image(&image, offset)?;
+
+    let cancel_token = cancel_on_interrupt();
+    let mut display = ProgressDisplay::new();
+
+    let result = fs.verify(&mut display, &cancel_token);
+    drop(display);
+
+    let report = result.map_err(|err| CliError::Fat(image, fs.context_of(err)))?;
+
+    for item in &report.unreadable {
+        let kind = if item.is_directory {
+            "directory"
+        } else {
+            "file"
+        };
+
+        if !item.sectors.is_empty() {
+            println!(
+                "{} ({}): {} unreadable sectors, from {}",
+                item.path,
+                kind,
+                item.sectors.len(),
+                item.sectors[0]
+            );
+        }
+
+        if let Some(err) = item.error {
+            println!("{} ({}): not read in full: {}", item.path, kind, err);
+        }
+    }
+
+    println!(
+        "{} files, {} directories, {} clusters read, {} unreadable",
+        report.files,
+        report.directories,
+        report.clusters_read,
+        report.unreadable.len()
+    );
+
+    Ok(if report.is_clean() { 0 } else { 1 })
+}
@@ -0,0 +1,274 @@
+use osc_block_storage::virt::*;
+use osc_block_storage::BlockDevice;
+use osc_fat::*;
+use std::env;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+usage: osc-fat-esp IMAGE SIZE_MIB BOOTX64.EFI [DIR]
+
+Builds IMAGE as an EFI System Partition of SIZE_MIB MiB: a FAT32 volume holding
+BOOTX64.EFI as /EFI/BOOT/BOOTX64.EFI, where firmware looks for a boot loader on
+removable media, along with everything beneath DIR, if given. The image is the
+partition itself, to be written to (or placed in) a partition with the EFI System
+type; at least 33 MiB is needed for the volume to be FAT32.";
+
+/// Where firmware looks for the boot loader when there's no boot entry for it.
+const BOOT_LOADER_PATH: &str = "/EFI/BOOT/BOOTX64.EFI";
+
+fn main() {
+    let arguments = env::args().skip(1).collect::<Vec<_>>();
+
+    let (image, size_mib, boot_loader, dir) = match arguments.as_slice() {
+        [image, size_mib, boot_loader, rest @ ..] if rest.len() <= 1 => {
+            match size_mib.parse::<u64>() {
+                Ok(size_mib) => (image, size_mib, boot_loader, rest.first()),
+                Err(_) => usage(),
+            }
+        }
+        _ => usage(),
+    };
+
+    if let Err(err) = build(image, size_mib, Path::new(boot_loader), dir.map(Path::new)) {
+        eprintln!("osc-fat-esp: {}", err);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn build(image: &str, size_mib: u64, boot_loader: &Path, dir: Option<&Path>) -> Result<(), String> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image)
+        .and_then(|file| file.set_len(size_mib << 20).map(|_| file))
+        .and_then(|file| FileBlockDevice::new(file, 0))
+        .map_err(|err| format!("{}: {}", image, err))?
+        .writable(true);
+
+    let fs = write_esp(image, Box::new(device), boot_loader, dir)?;
+
+    let boot_loader_size = fs
+        .lookup(BOOT_LOADER_PATH)
+        .map_err(|err| format!("{}: {}", BOOT_LOADER_PATH, err))?
+        .size;
+
+    println!(
+        "{}: {} MiB EFI System Partition with {} ({} bytes)",
+        image, size_mib, BOOT_LOADER_PATH, boot_loader_size
+    );
+
+    Ok(())
+}
+
+/// Formats `device` as an EFI System Partition holding `boot_loader` and everything
+/// beneath `dir`, then syncs and checks it. `image` is what errors call the device.
+fn write_esp(
+    image: &str,
+    device: Box<dyn BlockDevice>,
+    boot_loader: &Path,
+    dir: Option<&Path>,
+) -> Result<FATFileSystem, String> {
+    let volume_serial = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as u32)
+        .unwrap_or_default();
+
+    let fs = FATFileSystem::format(
+        device,
+        &FormatOptions {
+            variant: Some(Variant::Fat32),
+            volume_label: *b"ESP        ",
+            volume_serial,
+            ..FormatOptions::default()
+        },
+    )
+    .map_err(|err| format!("formatting {}: {}", image, err))?;
+
+    fs.create_dir("/EFI")
+        .and_then(|_| fs.create_dir("/EFI/BOOT"))
+        .and_then(|_| fs.copy_from_host(boot_loader, BOOT_LOADER_PATH))
+        .map_err(|err| format!("{}: {}", boot_loader.display(), err))?;
+
+    if let Some(dir) = dir {
+        // NOTE: this merges into /EFI, so a boot loader beneath DIR replaces the one
+        // given on the command line
+        let summary = fs
+            .import_tree(dir, "/", &CopyOptions::default(), |_, _| true)
+            .map_err(|err| format!("{}: {}", dir.display(), err))?;
+
+        println!(
+            "imported {} files and {} directories ({} bytes)",
+            summary.files, summary.directories, summary.bytes
+        );
+    }
+
+    fs.sync().map_err(|err| format!("{}: {}", image, err))?;
+
+    // Read back what was written, as firmware will
+    let report = fs
+        .check(
+            CheckOptions::default(),
+            &mut NoProgress,
+            &CancelToken::new(),
+        )
+        .map_err(|err| format!("checking {}: {}", image, err))?;
+
+    if !report.is_clean() {
+        return Err(format!("{} has problems: {:?}", image, report));
+    }
+
+    Ok(fs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osc_block_storage::BlockDeviceError;
+    use std::cell::RefCell;
+    use std::cmp;
+    use std::fs;
+    use std::ops::Range;
+    use std::rc::Rc;
+
+    /// A device held in memory, whose clones share their bytes, so the image can be
+    /// opened again after it's built.
+    #[derive(Clone)]
+    struct MemoryDevice {
+        bytes: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl MemoryDevice {
+        const BLOCK_SIZE: usize = 512;
+
+        fn new(size_mib: u64) -> Self {
+            Self {
+                bytes: Rc::new(RefCell::new(vec![0u8; (size_mib << 20) as usize])),
+            }
+        }
+
+        /// The bytes of as many whole blocks of `len` bytes from `start_block` as the
+        /// device has.
+        fn range(&self, start_block: u64, len: usize) -> Range<usize> {
+            let block_count = (self.bytes.borrow().len() / Self::BLOCK_SIZE) as u64;
+            let start = cmp::min(start_block, block_count);
+            let blocks = cmp::min(block_count - start, (len / Self::BLOCK_SIZE) as u64);
+
+            let start = start as usize * Self::BLOCK_SIZE;
+            start..(start + blocks as usize * Self::BLOCK_SIZE)
+        }
+    }
+
+    impl BlockDevice for MemoryDevice {
+        fn block_size(&self) -> u16 {
+            Self::BLOCK_SIZE as u16
+        }
+
+        fn read_blocks(
+            &mut self,
+            start_block: u64,
+            destination: &mut [u8],
+        ) -> Result<u64, BlockDeviceError> {
+            let range = self.range(start_block, destination.len());
+            let len = range.len();
+
+            destination[..len].copy_from_slice(&self.bytes.borrow()[range]);
+            Ok((len / Self::BLOCK_SIZE) as u64)
+        }
+
+        fn block_count(&self) -> Option<u64> {
+            Some((self.bytes.borrow().len() / Self::BLOCK_SIZE) as u64)
+        }
+
+        fn is_read_only(&self) -> bool {
+            false
+        }
+
+        fn write_blocks(
+            &mut self,
+            start_block: u64,
+            source: &[u8],
+        ) -> Result<u64, BlockDeviceError> {
+            let range = self.range(start_block, source.len());
+            let len = range.len();
+
+            self.bytes.borrow_mut()[range].copy_from_slice(&source[..len]);
+            Ok((len / Self::BLOCK_SIZE) as u64)
+        }
+    }
+
+    /// Bytes that differ from one file, and one cluster, to the next.
+    fn contents(seed: usize, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|index| ((index / 7 + seed) % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn builds_an_esp_that_reads_back() {
+        let scratch = env::temp_dir().join(format!("osc-fat-esp-{}", process::id()));
+        let tree = scratch.join("tree");
+
+        // NOTE: the tree has its own boot loader beneath EFI, which replaces the one
+        // given, and files big enough to take many clusters
+        let files = [
+            ("/EFI/BOOT/BOOTX64.EFI", contents(0, 150_000)),
+            ("/EFI/tools/shell.efi", contents(1, 1_000_000)),
+            ("/loader/entries/a long named entry.conf", contents(2, 300)),
+            ("/loader/loader.conf", contents(3, 20)),
+            ("/startup.nsh", Vec::new()),
+        ];
+
+        let boot_loader = scratch.join("BOOTX64.EFI");
+        fs::create_dir_all(&scratch).unwrap();
+        fs::write(&boot_loader, contents(4, 70_000)).unwrap();
+
+        for (path, data) in files.iter() {
+            let host_path = tree.join(&path[1..]);
+            fs::create_dir_all(host_path.parent().unwrap()).unwrap();
+            fs::write(host_path, data).unwrap();
+        }
+
+        let device = MemoryDevice::new(40);
+        let result = write_esp("esp", Box::new(device.clone()), &boot_loader, Some(&tree));
+        fs::remove_dir_all(&scratch).unwrap();
+        result.unwrap();
+
+        let esp = FATFileSystem::open(Box::new(device)).unwrap();
+
+        for (path, data) in files.iter() {
+            let metadata = esp.lookup(path).unwrap();
+            let mut read = Vec::new();
+
+            esp.read_file_into(
+                metadata.first_cluster,
+                metadata.size,
+                &mut read,
+                &mut NoProgress,
+                &CancelToken::new(),
+            )
+            .unwrap();
+
+            assert!(read == *data, "{} reads back wrong", path);
+        }
+
+        let report = esp
+            .check(
+                CheckOptions::default(),
+                &mut NoProgress,
+                &CancelToken::new(),
+            )
+            .unwrap();
+
+        assert!(report.findings.is_empty(), "{:?}", report.findings);
+    }
+}
@@ -1,66 +1,149 @@
-#![allow(dead_code)]
-
 use osc_block_storage::virt::*;
 use osc_fat::*;
+use std::convert::TryInto;
+use std::env;
 use std::fs::File;
-use std::io::Result;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::process;
+
+const USAGE: &str = "\
+usage: osc-fat-example [--offset BYTES | --partition N] [--depth N] IMAGE
+
+Prints the tree of files and directories in the FAT volume in IMAGE, which starts at
+BYTES into it, or is its Nth partition (counting from 1) in either an MBR or a GPT
+partition table. Only the first N levels of the tree are printed, if given.";
+
+/// Partition tables are laid out in sectors of this size, whatever the volume uses.
+const TABLE_SECTOR_SIZE: u64 = 512;
+
+enum Volume {
+    Offset(u64),
+    Partition(usize),
+}
+
+fn main() {
+    let mut volume = Volume::Offset(0);
+    let mut max_depth = None;
+    let mut image = None;
+
+    let mut arguments = env::args().skip(1);
+
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--offset" => volume = Volume::Offset(number(arguments.next())),
+            "--partition" => volume = Volume::Partition(number(arguments.next())),
+            "--depth" => max_depth = Some(number(arguments.next())),
+            _ if argument.starts_with('-') || image.is_some() => usage(),
+            _ => image = Some(argument),
+        }
+    }
+
+    let image = image.unwrap_or_else(|| usage());
+
+    if let Err(err) = print_tree(&image, volume, max_depth) {
+        eprintln!("osc-fat-example: {}: {}", image, err);
+        process::exit(1);
+    }
+}
 
-fn main() -> Result<()> {
-    let image = "/home/stears/data/simon/nox-rust/target/x86-nox/release/nox-rust.img";
-    let offset = 1048576;
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn number<T: std::str::FromStr>(argument: Option<String>) -> T {
+    argument
+        .and_then(|argument| argument.parse().ok())
+        .unwrap_or_else(|| usage())
+}
 
-    let file = File::open(image)?;
-    let device = Box::new(FileBlockDevice::new(file, offset));
+fn print_tree(image: &str, volume: Volume, max_depth: Option<usize>) -> Result<(), String> {
+    let mut file = File::open(image).map_err(|err| err.to_string())?;
 
-    let fs = FATFileSystem::open(device);
+    let offset = match volume {
+        Volume::Offset(offset) => offset,
+        Volume::Partition(index) => partition_offset(&mut file, index)?,
+    };
 
-    let mut read_buffer = vec![0u8; fs.required_read_buffer_size()];
+    let device = FileBlockDevice::new(file, offset).map_err(|err| err.to_string())?;
+    let fs = FATFileSystem::open(Box::new(device)).map_err(|err| err.to_string())?;
 
-    fs.walk_directory(read_buffer.as_mut_slice(), DirectorySelector::Root)
-        .enumerate_occupied_entries(|entry| {
-            process_entry(&fs, 0, entry);
-        });
+    let options = TreeOptions {
+        sizes: true,
+        max_depth,
+        ..Default::default()
+    };
 
+    print!("{}", fs.render_tree(DirectorySelector::Root, options));
     Ok(())
 }
 
-fn process_entry<'a>(fs: &FATFileSystem, level: usize, entry: DirectoryEntry<'a>) {
-    match entry {
-        DirectoryEntry::LongFileName(entry) => {
-            print!("{:>indent$}", "", indent = level * 2);
-
-            println!(
-                "LFN: {:?}",
-                std::char::decode_utf16(entry.chars())
-                    .filter_map(|ch| ch.ok())
-                    .collect::<String>(),
-            );
-        }
+/// The byte offset of the `index`th partition (from 1) of the image, from its GPT if
+/// its MBR is the protective one that stands in for a GPT, or from its MBR otherwise.
+fn partition_offset(file: &mut File, index: usize) -> Result<u64, String> {
+    let mbr = read_table_sector(file, 0).map_err(|err| err.to_string())?;
 
-        DirectoryEntry::Standard(entry) => {
-            print!("{:>indent$}", "", indent = level * 2);
-
-            if entry.is_directory() {
-                println!("Dir: {}", std::str::from_utf8(entry.name()).unwrap(),);
-
-                if entry.name()[0] != b'.' {
-                    let mut read_buffer = vec![0u8; fs.required_read_buffer_size()];
-
-                    fs.walk_directory(
-                        read_buffer.as_mut_slice(),
-                        DirectorySelector::Normal(entry.first_cluster()),
-                    )
-                    .enumerate_occupied_entries(|child_entry| {
-                        process_entry(&fs, level + 1, child_entry);
-                    });
-                }
-            } else {
-                println!(
-                    "File: {} ({} bytes)",
-                    std::str::from_utf8(entry.name()).unwrap(),
-                    entry.size(),
-                );
-            }
-        }
+    if mbr[510..512] != [0x55, 0xAA] {
+        return Err("there's no partition table".into());
     }
+
+    let entry = |index: usize| &mbr[446 + index * 16..446 + (index + 1) * 16];
+    let first_sector = if (0..4).any(|index| entry(index)[4] == 0xEE) {
+        gpt_partition_first_sector(file, index)?
+    } else {
+        (1..=4)
+            .contains(&index)
+            .then(|| entry(index - 1))
+            .filter(|entry| entry[4] != 0)
+            .map(|entry| u64::from(u32_at(entry, 8)))
+    };
+
+    first_sector
+        .map(|first_sector| first_sector * TABLE_SECTOR_SIZE)
+        .ok_or_else(|| format!("there's no partition {}", index))
+}
+
+fn gpt_partition_first_sector(file: &mut File, index: usize) -> Result<Option<u64>, String> {
+    let header = read_table_sector(file, 1).map_err(|err| err.to_string())?;
+
+    if &header[0..8] != b"EFI PART" {
+        return Err("the GPT header is missing".into());
+    }
+
+    let entries_sector = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80) as usize;
+    let entry_size = u64::from(u32_at(&header, 84));
+
+    if index == 0 || index > entry_count || entry_size < 128 {
+        return Ok(None);
+    }
+
+    let mut entry = [0u8; 128];
+    file.seek(SeekFrom::Start(
+        entries_sector * TABLE_SECTOR_SIZE + (index as u64 - 1) * entry_size,
+    ))
+    .and_then(|_| file.read_exact(&mut entry))
+    .map_err(|err| err.to_string())?;
+
+    // An entry whose type is all zeroes is unused
+    if entry[0..16].iter().all(|&byte| byte == 0) {
+        return Ok(None);
+    }
+
+    Ok(Some(u64_at(&entry, 32)))
+}
+
+fn read_table_sector(file: &mut File, sector: u64) -> io::Result<[u8; 512]> {
+    let mut buffer = [0u8; TABLE_SECTOR_SIZE as usize];
+    file.seek(SeekFrom::Start(sector * TABLE_SECTOR_SIZE))?;
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
 }
@@ -1,180 +1,413 @@
-use fuse::{
-    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
-    FUSE_ROOT_ID,
+use fuser::consts::FUSE_DO_READDIRPLUS;
+use fuser::{
+    FileAttr, FileType, Filesystem, KernelConfig, MountOption, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request,
+    TimeOrNow, FUSE_ROOT_ID,
 };
-use libc::ENOENT;
+use libc::{
+    c_int, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ENODEV, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY, EROFS,
+    O_ACCMODE, O_APPEND, O_RDONLY, O_TRUNC, W_OK,
+};
+use nix::sys::signal::{self, SigHandler, Signal};
 use osc_block_storage::virt::*;
+use osc_block_storage::BlockDevice;
 use osc_fat::*;
 use std::collections::{btree_map, BTreeMap};
+use std::convert::TryFrom;
 use std::env;
 use std::ffi::OsStr;
-use std::fs::File;
-use std::time::{Duration, UNIX_EPOCH};
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+mod permissions;
+
+use permissions::PermissionOptions;
+
+const USAGE: &str = "\
+usage: osc-fat-fuse [-o OPTIONS] [--rw] IMAGE OFFSET MOUNTPOINT
+
+Mounts the FAT volume found OFFSET bytes into IMAGE at MOUNTPOINT, read-only unless
+--rw is given. OPTIONS are a comma separated list of uid=N, gid=N, umask=NNN,
+hidden=show|dotfile|omit, tz=utc|local|+HH:MM and case=insensitive|sensitive.";
 
 const TTL: Duration = Duration::from_secs(1);
 
+/// Set on SIGHUP, e.g. after something else has changed the image, so the filesystem
+/// is refreshed before it's next used.
+static REFRESH_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_refresh(_signal: c_int) {
+    REFRESH_REQUESTED.store(true, Ordering::SeqCst);
+}
+
 struct NodeDetails {
     reference_count: u64,
-    attr: FileAttr,
-    first_cluster: u32,
+    /// Where the entry is, using the names as stored, which is how it's found again, as
+    /// its first cluster changes when it's written.
+    path: String,
 }
 
 struct FSImpl {
     fs: FATFileSystem,
-    buffer: Vec<u8>,
-    nodes_by_cluster: BTreeMap<u32, NodeDetails>,
+    nodes: BTreeMap<u64, NodeDetails>,
+    inodes_by_path: BTreeMap<String, u64>,
+    next_inode: u64,
+    permissions: PermissionOptions,
+    read_write: bool,
 }
 
 impl FSImpl {
-    fn open(image_path: impl AsRef<std::path::Path>, offset: u64) -> Self {
-        let image = File::open(image_path).unwrap();
-        let device = FileBlockDevice::new(image, offset);
-        let fs = FATFileSystem::open(Box::new(device));
+    /// Opens the image, failing with a message saying what went wrong. It's only
+    /// opened for writing if `read_write` is set.
+    fn open(
+        image_path: impl AsRef<std::path::Path>,
+        offset: u64,
+        permissions: PermissionOptions,
+        read_write: bool,
+    ) -> Result<Self, String> {
+        let image_path = image_path.as_ref();
+        let fail = |err: &dyn Display| format!("{}: {}", image_path.display(), err);
+        let open_image = move |image_path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .write(read_write)
+                .open(image_path)
+                .and_then(|image| FileBlockDevice::new(image, offset))
+                .map(|device| device.writable(read_write))
+        };
+
+        let device = open_image(image_path).map_err(|err| fail(&err))?;
+        let options = MountOptions {
+            read_only: !read_write,
+            time_zone: permissions.time_zone,
+            name_matching: permissions.name_matching,
+            // NOTE: names handed to the kernel have to be valid Unicode
+            invalid_utf16: InvalidUtf16Policy::ShortName,
+            quick_check: true,
+            ..MountOptions::default()
+        };
+        let mut fs = FATFileSystem::open_with_options(Box::new(device), options)
+            .map_err(|err| fail(&err))?;
 
-        let buffer = vec![0u8; fs.required_read_buffer_size()];
-        let nodes_by_cluster = BTreeMap::new();
+        for issue in fs
+            .quick_check_report()
+            .into_iter()
+            .flat_map(|report| &report.issues)
+        {
+            eprintln!("warning: {}", issue);
+        }
+
+        // NOTE: the image is opened again if it goes away, e.g. a disk that's pulled
+        // out, and the filesystem carries on if it comes back holding the same volume
+        let image_path = image_path.to_owned();
+        fs.set_reopen_hook(Box::new(move || {
+            let device: Box<dyn BlockDevice> = Box::new(open_image(&image_path).ok()?);
+            Some(device)
+        }));
 
-        Self {
+        let mut inodes_by_path = BTreeMap::new();
+        inodes_by_path.insert(String::new(), FUSE_ROOT_ID);
+
+        Ok(Self {
             fs,
-            buffer,
-            nodes_by_cluster,
+            nodes: BTreeMap::new(),
+            inodes_by_path,
+            next_inode: FUSE_ROOT_ID + 1,
+            permissions,
+            read_write,
+        })
+    }
+
+    /// Drops everything cached of the volume if SIGHUP has asked for it.
+    fn refresh_if_requested(&mut self) {
+        if !REFRESH_REQUESTED.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        match self.fs.refresh() {
+            Ok(()) => println!("Refreshed the filesystem"),
+            Err(err) => eprintln!("warning: failed to refresh the filesystem: {}", err),
         }
     }
 
-    fn get_root_attr(&mut self, req: &Request, reply: ReplyAttr) {
-        let root_attr = FileAttr {
-            ino: FUSE_ROOT_ID,
-            size: 0,
+    fn file_attr(
+        permissions: &PermissionOptions,
+        req: &Request,
+        ino: u64,
+        metadata: &Metadata,
+    ) -> FileAttr {
+        let mtime = permissions.system_time(metadata.modified);
+
+        FileAttr {
+            ino,
+            size: metadata.size.into(),
             blocks: 0,
-            atime: UNIX_EPOCH,
-            mtime: UNIX_EPOCH,
-            ctime: UNIX_EPOCH,
-            crtime: UNIX_EPOCH,
-            kind: FileType::Directory,
-            perm: 0o755,
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: permissions.system_time(metadata.created),
+            kind: if metadata.is_directory() {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            },
+            perm: permissions.perm(metadata.attributes),
             nlink: 1,
-            uid: req.uid(),
-            gid: req.gid(),
+            uid: permissions.uid.unwrap_or_else(|| req.uid()),
+            gid: permissions.gid.unwrap_or_else(|| req.gid()),
             rdev: 0,
+            blksize: 512,
             flags: 0,
+        }
+    }
+
+    /// The path of the entry the kernel knows as `ino`, which is empty for the root.
+    fn path_of(&self, ino: u64) -> Option<String> {
+        if ino == FUSE_ROOT_ID {
+            Some(String::new())
+        } else {
+            self.nodes.get(&ino).map(|details| details.path.clone())
+        }
+    }
+
+    /// The inode of the entry at `path`, giving it a new one if it hasn't got one yet.
+    fn inode_of(&mut self, path: &str) -> u64 {
+        if let Some(&ino) = self.inodes_by_path.get(path) {
+            return ino;
+        }
+
+        let ino = self.next_inode;
+        self.next_inode += 1;
+        self.inodes_by_path.insert(String::from(path), ino);
+        ino
+    }
+
+    /// Records that the kernel has been given an entry, which it counts as a lookup to
+    /// be forgotten later.
+    fn remember_node(&mut self, path: &str) -> u64 {
+        let ino = self.inode_of(path);
+
+        self.nodes
+            .entry(ino)
+            .or_insert_with(|| NodeDetails {
+                reference_count: 0,
+                path: String::from(path),
+            })
+            .reference_count += 1;
+
+        ino
+    }
+
+    /// Finds the entry called `name` in the directory at `parent_path`, matching the
+    /// name it's presented with rather than the one it's stored with.
+    fn find_child(&self, parent_path: &str, name: &str) -> Result<Metadata, Error> {
+        self.fs
+            .open_dir(parent_path)?
+            .list()?
+            .into_iter()
+            .find(
+                |item| match self.permissions.display_name(&item.name, item.attributes) {
+                    Some(display_name) => {
+                        self.permissions.name_matching.matches(name, &display_name)
+                    }
+                    None => false,
+                },
+            )
+            .ok_or(Error::NotFound)
+    }
+
+    /// The path of the parent directory given by `parent` and of the entry called
+    /// `name` in it. The entry needn't exist, in which case its path uses `name`.
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<(String, String), Error> {
+        let parent_path = self.path_of(parent).ok_or(Error::NotFound)?;
+
+        // NOTE: names on the volume are always Unicode, so nothing else can match
+        let name = name.to_str().ok_or(Error::InvalidName)?;
+
+        let path = match self.find_child(&parent_path, name) {
+            Ok(existing) => format!("{}/{}", parent_path, existing.name),
+            Err(Error::NotFound) => format!("{}/{}", parent_path, name),
+            Err(err) => return Err(err),
         };
 
-        reply.attr(&TTL, &root_attr);
+        Ok((parent_path, path))
     }
 
-    // TODO: need to figure out the root cluster details for
-    // all variants before committing to this
-    fn cluster_index_to_inode(cluster_index: u32) -> u64 {
-        (cluster_index + 16).into()
+    /// Stops `path` leading to the inode it had, as the entry there has gone. The
+    /// inode itself lives on until the kernel forgets it.
+    fn unlink_path(&mut self, path: &str) {
+        self.inodes_by_path.remove(path);
     }
 
-    fn inode_to_cluster_index(inode: u64) -> u32 {
-        (inode - 16) as u32
+    /// Moves the inodes of the entry at `from` and everything under it to `to`.
+    fn rename_paths(&mut self, from: &str, to: &str) {
+        let prefix = format!("{}/", from);
+        let moved = self
+            .inodes_by_path
+            .range(String::from(from)..)
+            .take_while(|(path, _)| path.as_str() == from || path.starts_with(&prefix))
+            .map(|(path, &ino)| (path.clone(), ino))
+            .collect::<Vec<_>>();
+
+        for (path, ino) in moved {
+            let new_path = format!("{}{}", to, &path[from.len()..]);
+
+            self.inodes_by_path.remove(&path);
+            self.inodes_by_path.insert(new_path.clone(), ino);
+
+            if let Some(details) = self.nodes.get_mut(&ino) {
+                details.path = new_path;
+            }
+        }
     }
 
-    fn get_directory_selector(&self, inode: u64) -> Option<DirectorySelector> {
-        if inode == FUSE_ROOT_ID {
-            Some(DirectorySelector::Root)
-        } else {
-            self.nodes_by_cluster
-                .get(&Self::inode_to_cluster_index(inode))
-                .map(|details| DirectorySelector::Normal(details.first_cluster))
+    /// Logs why something failed, giving the error the kernel is to be given.
+    fn failed(&self, what: impl Display, err: Error) -> c_int {
+        println!("Failed to {}: {}", what, self.fs.context_of(err));
+        errno(err)
+    }
+
+    /// Writes out everything the filesystem has held back, as happens when a file's
+    /// synced or closed.
+    fn sync(&self, ino: u64, reply: ReplyEmpty) {
+        if !self.read_write {
+            reply.ok();
+            return;
+        }
+
+        match self.fs.sync() {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(self.failed(format_args!("sync {}", ino), err)),
+        }
+    }
+
+    /// Does what `setattr` asks of the entry at `path`, giving its metadata after.
+    fn set_attr(
+        &self,
+        path: &str,
+        mode: Option<u32>,
+        size: Option<u64>,
+        mtime: Option<TimeOrNow>,
+    ) -> Result<Metadata, Error> {
+        if let Some(size) = size {
+            let size = u32::try_from(size).map_err(|_| Error::FileTooLarge)?;
+            self.fs.set_len(path, size, true)?;
+        }
+
+        // NOTE: the only permission FAT has is whether an entry's read-only, which is
+        // what taking away every write permission means
+        if let Some(mode) = mode {
+            let mut attributes = self.fs.lookup(path)?.attributes;
+
+            if mode & 0o222 == 0 {
+                attributes.insert(Attributes::READ_ONLY);
+            } else {
+                attributes.remove(Attributes::READ_ONLY);
+            }
+
+            self.fs.set_attributes(path, attributes)?;
         }
+
+        if let Some(mtime) = mtime {
+            let mtime = match mtime {
+                TimeOrNow::SpecificTime(mtime) => mtime,
+                TimeOrNow::Now => SystemTime::now(),
+            };
+            let modified = self.permissions.fat_time(mtime).ok_or(Error::OutOfRange)?;
+
+            self.fs.set_modified(path, modified)?;
+        }
+
+        self.fs.lookup(path)
+    }
+
+    /// Writes `data` at `offset` in the file at `path`.
+    fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, Error> {
+        let mut file = self
+            .fs
+            .open_file_with_options(path, &osc_fat::OpenOptions::new().write(true))?;
+        let written = file.write_at(offset, data)?;
+
+        // NOTE: the entry's brought up to date with each write, while the FSInfo
+        // sector and the clean shutdown bit wait for fsync or release
+        file.flush()?;
+
+        Ok(written)
     }
 }
 
 impl Filesystem for FSImpl {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // Listing a directory gives the attributes of everything in it too, which saves
+        // a lookup per entry afterwards
+        if config.add_capabilities(FUSE_DO_READDIRPLUS).is_err() {
+            println!("The kernel doesn't support readdirplus");
+        }
+
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        if !self.read_write {
+            return;
+        }
+
+        if let Err(err) = self.fs.sync() {
+            eprintln!(
+                "warning: failed to sync the filesystem: {}",
+                self.fs.context_of(err)
+            );
+        }
+    }
+
     fn lookup(&mut self, req: &Request, parent_inode: u64, name: &OsStr, reply: ReplyEntry) {
         println!("Looking up {:?} in {}", name, parent_inode);
+        self.refresh_if_requested();
 
-        let maybe_directory_selector = self.get_directory_selector(parent_inode);
-
-        let mut directory_walker = match maybe_directory_selector {
-            Some(directory_selector) => self
-                .fs
-                .walk_directory(self.buffer.as_mut_slice(), directory_selector),
+        let parent_path = match self.path_of(parent_inode) {
+            Some(parent_path) => parent_path,
             None => {
                 reply.error(ENOENT);
                 return;
             }
         };
 
-        loop {
-            for entry in directory_walker.occupied_entries() {
-                match entry {
-                    DirectoryEntry::LongFileName(_entry) => {}
-
-                    DirectoryEntry::Standard(entry) => {
-                        let entry_name = std::str::from_utf8(entry.name()).unwrap().trim();
-
-                        if name != entry_name {
-                            continue;
-                        }
-
-                        let node_details = self
-                            .nodes_by_cluster
-                            .entry(entry.first_cluster())
-                            .or_insert_with(|| {
-                                let attr = FileAttr {
-                                    ino: Self::cluster_index_to_inode(entry.first_cluster()),
-                                    size: entry.size() as u64,
-                                    blocks: 0,
-                                    atime: UNIX_EPOCH,
-                                    mtime: UNIX_EPOCH,
-                                    ctime: UNIX_EPOCH,
-                                    crtime: UNIX_EPOCH,
-                                    kind: if entry.is_directory() {
-                                        FileType::Directory
-                                    } else {
-                                        FileType::RegularFile
-                                    },
-                                    perm: 0o755,
-                                    nlink: 1,
-                                    uid: req.uid(),
-                                    gid: req.gid(),
-                                    rdev: 0,
-                                    flags: 0,
-                                };
-
-                                let node_details = NodeDetails {
-                                    reference_count: 0,
-                                    attr,
-                                    first_cluster: entry.first_cluster(),
-                                };
-
-                                node_details
-                            });
-
-                        node_details.reference_count += 1;
-
-                        reply.entry(&TTL, &node_details.attr, 0);
-
-                        println!(
-                            "Found entry {:?} with inode {}",
-                            name, node_details.attr.ino
-                        );
-
-                        return;
-                    }
-                }
+        // NOTE: names on the volume are always Unicode, so nothing else can match
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => {
+                reply.error(ENOENT);
+                return;
             }
+        };
 
-            if let Some(new_directory_walker) = directory_walker.next() {
-                directory_walker = new_directory_walker;
-            } else {
-                break;
+        let metadata = match self.find_child(&parent_path, name) {
+            Ok(metadata) => metadata,
+            Err(Error::NotFound) => {
+                println!("Could not find entry {:?}", name);
+                reply.error(ENOENT);
+                return;
             }
-        }
+            Err(err) => {
+                reply.error(self.failed(format_args!("look up {:?}", name), err));
+                return;
+            }
+        };
+
+        let ino = self.remember_node(&format!("{}/{}", parent_path, metadata.name));
+        let attr = Self::file_attr(&self.permissions, req, ino, &metadata);
+
+        reply.entry(&TTL, &attr, 0);
 
-        println!("Could not find entry {:?}", name);
-        reply.error(ENOENT);
+        println!("Found entry {:?} with inode {}", name, ino);
     }
 
     fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
-        match self
-            .nodes_by_cluster
-            .entry(Self::inode_to_cluster_index(ino))
-        {
+        match self.nodes.entry(ino) {
             btree_map::Entry::Vacant(_) => {
                 println!(
                     "Request to forget {} for count {}, but the entry isn't present.",
@@ -197,28 +430,43 @@ impl Filesystem for FSImpl {
                         entry.get().reference_count,
                         nlookup
                     );
-                    entry.remove();
+
+                    let details = entry.remove();
+
+                    // NOTE: the path may have been given to another entry since
+                    if self.inodes_by_path.get(&details.path) == Some(&ino) {
+                        self.inodes_by_path.remove(&details.path);
+                    }
                 }
             }
         };
     }
 
     fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
-        if ino == FUSE_ROOT_ID {
-            self.get_root_attr(req, reply);
-            return;
-        }
+        self.refresh_if_requested();
 
-        let cluster_index = Self::inode_to_cluster_index(ino);
+        let path = match self.path_of(ino) {
+            Some(path) => path,
+            None => {
+                println!("Request to get attributes for {} returning enoent", ino);
+                reply.error(ENOENT);
+                return;
+            }
+        };
 
-        if let Some(details) = self.nodes_by_cluster.get(&cluster_index) {
-            println!("Request to get attributes for {} succeeded", ino);
-            reply.attr(&TTL, &details.attr);
-            return;
+        // NOTE: looked up afresh each time, as writes change the size and times
+        match self.fs.lookup(&path) {
+            Ok(metadata) => {
+                println!("Request to get attributes for {} succeeded", ino);
+                reply.attr(
+                    &TTL,
+                    &Self::file_attr(&self.permissions, req, ino, &metadata),
+                );
+            }
+            Err(err) => {
+                reply.error(self.failed(format_args!("get attributes for {}", ino), err));
+            }
         }
-
-        println!("Request to get attributes for {} returning enoent", ino);
-        reply.error(ENOENT);
     }
 
     fn read(
@@ -228,22 +476,34 @@ impl Filesystem for FSImpl {
         _fh: u64,
         offset: i64,
         size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
-        let cluster_index = Self::inode_to_cluster_index(ino);
-
         println!(
             "Request to read {} from offset {} with size {}",
             ino, offset, size
         );
-        if let Some(details) = self.nodes_by_cluster.get(&cluster_index) {
-            self.fs
-                .read(details.first_cluster, self.buffer.as_mut_slice());
-            reply.data(&self.buffer[offset as usize..]);
-            return;
-        }
+        self.refresh_if_requested();
+
+        let path = match self.path_of(ino) {
+            Some(path) => path,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
 
-        reply.error(ENOENT);
+        let mut buffer = vec![0u8; size as usize];
+        let result = self
+            .fs
+            .open_file(&path)
+            .and_then(|mut file| file.read_at(offset as u64, &mut buffer));
+
+        match result {
+            Ok(count) => reply.data(&buffer[..count]),
+            Err(err) => reply.error(self.failed(format_args!("read {}", ino), err)),
+        }
     }
 
     fn readdir(
@@ -255,70 +515,614 @@ impl Filesystem for FSImpl {
         mut reply: ReplyDirectory,
     ) {
         println!("Starting enumeration of {} with offset {}", ino, offset);
+        self.refresh_if_requested();
 
-        let maybe_directory_selector = self.get_directory_selector(ino);
+        let path = match self.path_of(ino) {
+            Some(path) => path,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let items = match self
+            .fs
+            .open_dir(&path)
+            .and_then(|dir| self.fs.list_directory(dir.selector()))
+        {
+            Ok(items) => items,
+            Err(err) => {
+                reply.error(self.failed(format_args!("enumerate {}", ino), err));
+                return;
+            }
+        };
+
+        for (index, item) in items.iter().enumerate().skip(offset as usize) {
+            if item.attributes.is_volume_id() {
+                continue;
+            }
+
+            let display_name = match self.permissions.display_name(&item.name, item.attributes) {
+                Some(display_name) => display_name,
+                None => continue,
+            };
+
+            let item_ino = match item.name.as_str() {
+                "." => ino,
+                ".." => self.inode_of(parent_of(&path)),
+                name => self.inode_of(&format!("{}/{}", path, name)),
+            };
+            let kind = if item.is_directory() {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+
+            println!("Returning entry {:?} with inode {}", display_name, item_ino);
+
+            if reply.add(item_ino, index as i64 + 1, kind, display_name.as_ref()) {
+                break;
+            }
+        }
+
+        reply.ok();
+    }
 
-        let directory_walker = match maybe_directory_selector {
-            Some(directory_selector) => self
-                .fs
-                .walk_directory(self.buffer.as_mut_slice(), directory_selector),
+    fn readdirplus(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectoryPlus,
+    ) {
+        println!(
+            "Starting enumeration (plus) of {} with offset {}",
+            ino, offset
+        );
+        self.refresh_if_requested();
+
+        let path = match self.path_of(ino) {
+            Some(path) => path,
             None => {
                 reply.error(ENOENT);
                 return;
             }
         };
 
-        // TODO: what about "." and ".."
-        let mut next_index = 0;
+        let items = match self
+            .fs
+            .open_dir(&path)
+            .and_then(|dir| self.fs.list_directory(dir.selector()))
+        {
+            Ok(items) => items,
+            Err(err) => {
+                reply.error(self.failed(format_args!("enumerate {}", ino), err));
+                return;
+            }
+        };
+
+        for (index, item) in items.iter().enumerate().skip(offset as usize) {
+            if item.attributes.is_volume_id() {
+                continue;
+            }
 
-        directory_walker.enumerate_occupied_entries(|entry| {
-            let index = next_index;
-            next_index += 1;
+            let display_name = match self.permissions.display_name(&item.name, item.attributes) {
+                Some(display_name) => display_name,
+                None => continue,
+            };
 
-            if index < offset {
+            let item_path = match item.name.as_str() {
+                "." => path.clone(),
+                ".." => String::from(parent_of(&path)),
+                name => format!("{}/{}", path, name),
+            };
+            let item_ino = self.inode_of(&item_path);
+            let attr = Self::file_attr(&self.permissions, req, item_ino, item);
+
+            if reply.add(
+                item_ino,
+                index as i64 + 1,
+                display_name.as_ref(),
+                &TTL,
+                &attr,
+                0,
+            ) {
+                break;
+            }
+
+            // NOTE: the kernel counts every entry it's given but "." and ".." as looked
+            // up, so they have to be remembered until they're forgotten
+            if !item.is_dot_entry() {
+                self.remember_node(&item_path);
+            }
+        }
+
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let writing = flags & O_ACCMODE != O_RDONLY || flags & (O_TRUNC | O_APPEND) != 0;
+
+        if writing && !self.read_write {
+            println!("Refusing to open {} for writing", ino);
+            reply.error(EROFS);
+            return;
+        }
+
+        if flags & O_TRUNC != 0 {
+            let result = match self.path_of(ino) {
+                Some(path) => self.fs.set_len(&path, 0, true),
+                None => Err(Error::NotFound),
+            };
+
+            if let Err(err) = result {
+                reply.error(self.failed(format_args!("truncate {}", ino), err));
+                return;
+            }
+        }
+
+        reply.opened(0, 0);
+    }
+
+    fn access(&mut self, _req: &Request, _ino: u64, mask: i32, reply: ReplyEmpty) {
+        if mask & W_OK != 0 && !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
+
+        reply.ok();
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        if !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
+
+        let path = match self.path_of(ino) {
+            Some(path) => path,
+            None => {
+                reply.error(ENOENT);
                 return;
             }
+        };
+
+        // NOTE: FAT has no owners, and only keeps the date of the last access, which is
+        // brought up to date along with the modification time, so those are left alone.
+        // The root has no entry to record anything in.
+        let result = if ino == FUSE_ROOT_ID {
+            self.fs.lookup(&path)
+        } else {
+            self.set_attr(&path, mode, size, mtime)
+        };
+
+        match result {
+            Ok(metadata) => reply.attr(
+                &TTL,
+                &Self::file_attr(&self.permissions, req, ino, &metadata),
+            ),
+            Err(err) => reply.error(self.failed(format_args!("set attributes of {}", ino), err)),
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        reply.error(EROFS);
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        if !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
+
+        let result = self.child_path(parent, name).and_then(|(_, path)| {
+            let metadata = self.fs.create_dir(&path)?.metadata().clone();
+            Ok((path, metadata))
+        });
+
+        match result {
+            Ok((path, metadata)) => {
+                let ino = self.remember_node(&path);
+                reply.entry(
+                    &TTL,
+                    &Self::file_attr(&self.permissions, req, ino, &metadata),
+                    0,
+                );
+            }
+            Err(err) => reply.error(self.failed(format_args!("make {:?}", name), err)),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
+
+        let result = self
+            .child_path(parent, name)
+            .and_then(|(_, path)| self.fs.remove_file(&path).map(|_| path));
+
+        match result {
+            Ok(path) => {
+                self.unlink_path(&path);
+                reply.ok();
+            }
+            Err(err) => reply.error(self.failed(format_args!("remove {:?}", name), err)),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
+
+        let result = self
+            .child_path(parent, name)
+            .and_then(|(_, path)| self.fs.remove_dir(&path).map(|_| path));
+
+        match result {
+            Ok(path) => {
+                self.unlink_path(&path);
+                reply.ok();
+            }
+            Err(err) => reply.error(self.failed(format_args!("remove {:?}", name), err)),
+        }
+    }
 
-            match entry {
-                DirectoryEntry::LongFileName(_entry) => {}
+    fn symlink(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _link: &Path,
+        reply: ReplyEntry,
+    ) {
+        reply.error(EROFS);
+    }
 
-                DirectoryEntry::Standard(entry) => {
-                    let entry_name = std::str::from_utf8(entry.name()).unwrap().trim();
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        if !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
 
-                    let inode = Self::cluster_index_to_inode(entry.first_cluster());
-                    let next_offset = index as i64 + 1;
+        // NOTE: neither exchanging two entries nor refusing to replace one is supported
+        if flags != 0 {
+            reply.error(EINVAL);
+            return;
+        }
+
+        let result = self.child_path(parent, name).and_then(|(_, from)| {
+            let (to_parent_path, _) = self.child_path(newparent, newname)?;
+            let newname = newname.to_str().ok_or(Error::InvalidName)?;
+            let source = self.fs.lookup(&from)?;
+
+            // NOTE: an entry that's already there is replaced, as rename(2) does, unless
+            // it's the one being renamed, as when only the case of its name changes
+            let replaced = match self.find_child(&to_parent_path, newname) {
+                Ok(existing) if parent == newparent && existing.short_name == source.short_name => {
+                    None
+                }
+                Ok(existing) => {
+                    let existing_path = format!("{}/{}", to_parent_path, existing.name);
 
-                    if entry.is_directory() {
-                        println!(
-                            "Returning directory entry {:?} with inode {}",
-                            entry_name, inode
-                        );
-                        reply.add(inode, next_offset, FileType::Directory, entry_name);
-                    } else {
-                        println!("Returning file entry {:?} with inode {}", entry_name, inode);
-                        reply.add(inode, next_offset, FileType::RegularFile, entry_name);
+                    match (source.is_directory(), existing.is_directory()) {
+                        (true, true) => self.fs.remove_dir(&existing_path)?,
+                        (false, false) => self.fs.remove_file(&existing_path)?,
+                        (true, false) => return Err(Error::NotADirectory),
+                        (false, true) => return Err(Error::IsADirectory),
                     }
+
+                    Some(existing_path)
+                }
+                Err(Error::NotFound) => None,
+                Err(err) => return Err(err),
+            };
+
+            let to = format!("{}/{}", to_parent_path, newname);
+            self.fs.rename(&from, &to)?;
+
+            Ok((from, to, replaced))
+        });
+
+        match result {
+            Ok((from, to, replaced)) => {
+                if let Some(replaced) = replaced {
+                    self.unlink_path(&replaced);
                 }
+
+                self.rename_paths(&from, &to);
+                reply.ok();
             }
+            Err(err) => reply.error(self.failed(format_args!("rename {:?}", name), err)),
+        }
+    }
+
+    fn link(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _newparent: u64,
+        _newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        reply.error(EROFS);
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        if !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
+
+        let result = match self.path_of(ino) {
+            Some(path) => self.write_at(&path, offset as u64, data),
+            None => Err(Error::NotFound),
+        };
+
+        match result {
+            Ok(written) => reply.written(written as u32),
+            Err(err) => reply.error(self.failed(format_args!("write {}", ino), err)),
+        }
+    }
+
+    fn flush(&mut self, _req: &Request, ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        self.sync(ino, reply);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.sync(ino, reply);
+    }
+
+    fn fsync(&mut self, _req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        self.sync(ino, reply);
+    }
+
+    fn create(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        if !self.read_write {
+            reply.error(EROFS);
+            return;
+        }
+
+        let result = self.child_path(parent, name).and_then(|(_, path)| {
+            let metadata = self.fs.create_file(&path)?.metadata().clone();
+            Ok((path, metadata))
         });
 
-        reply.ok();
+        match result {
+            Ok((path, metadata)) => {
+                let ino = self.remember_node(&path);
+                let attr = Self::file_attr(&self.permissions, req, ino, &metadata);
+                reply.created(&TTL, &attr, 0, 0, 0);
+            }
+            Err(err) => reply.error(self.failed(format_args!("create {:?}", name), err)),
+        }
+    }
+
+    // NOTE: FAT has nowhere to keep special files, links or extended attributes, so
+    // they're refused explicitly rather than left to fail with ENOSYS
+
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(EROFS);
+    }
+
+    fn removexattr(&mut self, _req: &Request, _ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(EROFS);
+    }
+}
+
+/// The path of the directory holding the entry at `path`.
+fn parent_of(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(index) => &path[..index],
+        None => "",
+    }
+}
+
+/// The error the kernel is given for `err`.
+fn errno(err: Error) -> c_int {
+    match err {
+        Error::WriteProtected | Error::NotOpenForWriting => EROFS,
+        Error::NotFound => ENOENT,
+        Error::NotADirectory => ENOTDIR,
+        Error::IsADirectory => EISDIR,
+        Error::AlreadyExists => EEXIST,
+        Error::DirectoryNotEmpty => ENOTEMPTY,
+        Error::NoSpace => ENOSPC,
+        Error::FileTooLarge => EFBIG,
+        Error::RootDirectory | Error::InvalidName | Error::MoveIntoItself | Error::OutOfRange => {
+            EINVAL
+        }
+        Error::DeviceGone => ENODEV,
+        _ => EIO,
     }
 }
 
+/// Logs spans and events to stderr as filtered by RUST_LOG, and if OSC_FAT_TRACE names a
+/// file, records all of them there too, in the Chrome trace format Perfetto opens.
+#[cfg(feature = "tracing")]
+fn init_tracing() -> Option<tracing_chrome::FlushGuard> {
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::{fmt, EnvFilter};
+
+    let (chrome_layer, guard) = match env::var_os("OSC_FAT_TRACE") {
+        Some(path) => {
+            let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new().file(path).build();
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(EnvFilter::from_default_env()),
+        )
+        .with(chrome_layer)
+        .init();
+
+    guard
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
 fn main() {
+    // NOTE: the trace file is only complete once the guard is dropped
+    #[cfg(feature = "tracing")]
+    let _trace_guard = init_tracing();
+    #[cfg(not(feature = "tracing"))]
     env_logger::init();
 
-    let mountpoint = env::args_os().nth(1).unwrap();
+    let mut args = env::args_os().skip(1);
+    let mut positional = Vec::new();
+    let mut permissions = PermissionOptions::default();
+    let mut read_write = false;
 
-    let options = ["-o", "ro", "-o", "fsname=hello"]
-        .iter()
-        .map(|o| o.as_ref())
-        .collect::<Vec<&OsStr>>();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            let options = args.next().unwrap_or_default();
 
-    let image = "/home/stears/data/simon/nox-rust/target/x86-nox/release/nox-rust.img";
-    let offset = 1048576;
-    let fs = FSImpl::open(image, offset);
+            if let Err(message) = permissions.parse(&options.to_string_lossy()) {
+                eprintln!("osc-fat-fuse: {}", message);
+                process::exit(2);
+            }
+        } else if arg == "--rw" {
+            read_write = true;
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    let (image, offset, mountpoint) = match positional.as_slice() {
+        [image, offset, mountpoint] => match offset.to_string_lossy().parse::<u64>() {
+            Ok(offset) => (Path::new(image), offset, mountpoint),
+            Err(_) => usage(),
+        },
+        _ => usage(),
+    };
+
+    // NOTE: the image is named after its file, as mounts of devices are after the
+    // device, so that it can be told apart from other mounts
+    let fs_name = image
+        .file_name()
+        .unwrap_or(image.as_os_str())
+        .to_string_lossy()
+        .into_owned();
+
+    let access = if read_write {
+        MountOption::RW
+    } else {
+        MountOption::RO
+    };
+    let options = [access, MountOption::FSName(fs_name)];
+
+    let fs = FSImpl::open(image, offset, permissions, read_write).unwrap_or_else(|message| {
+        eprintln!("osc-fat-fuse: {}", message);
+        process::exit(1);
+    });
 
-    fuse::mount(fs, mountpoint, &options).unwrap();
+    // NOTE: SIGHUP picks up changes made to the image by something else, rather than
+    // having to mount it again
+    let handler = unsafe { signal::signal(Signal::SIGHUP, SigHandler::Handler(request_refresh)) };
+
+    if let Err(err) = handler {
+        eprintln!("warning: SIGHUP won't refresh the filesystem: {}", err);
+    }
+
+    if let Err(err) = fuser::mount2(fs, mountpoint, &options) {
+        eprintln!(
+            "osc-fat-fuse: {}: {}",
+            Path::new(&mountpoint).display(),
+            err
+        );
+        process::exit(1);
+    }
 }
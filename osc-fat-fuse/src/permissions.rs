@@ -0,0 +1,125 @@
+use osc_fat::{Attributes, FatDateTime, NameMatching, TimeZonePolicy};
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What to do with entries that have the hidden or system attribute.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HiddenMode {
+    Show,
+    /// Prefix the name with a '.', the Unix convention for hidden files.
+    Dotfile,
+    Omit,
+}
+
+/// How DOS attributes and timestamps are presented as Unix ownership, permissions and
+/// times, and how names are looked up, configured by
+/// `-o uid=N,gid=N,umask=NNN,hidden=show|dotfile|omit,tz=ZONE,case=insensitive|sensitive`
+/// where the zone is `utc`, `local` or an offset like `+01:00`.
+#[derive(Debug, Clone)]
+pub struct PermissionOptions {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub umask: u16,
+    pub hidden: HiddenMode,
+    pub time_zone: TimeZonePolicy,
+    pub name_matching: NameMatching,
+}
+
+impl Default for PermissionOptions {
+    fn default() -> Self {
+        Self {
+            uid: None,
+            gid: None,
+            umask: 0o022,
+            hidden: HiddenMode::Show,
+            time_zone: TimeZonePolicy::Utc,
+            name_matching: NameMatching::CaseInsensitive,
+        }
+    }
+}
+
+impl PermissionOptions {
+    /// Applies a comma separated list of options.
+    pub fn parse(&mut self, options: &str) -> Result<(), String> {
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (name, value) = match option.find('=') {
+                Some(index) => (&option[..index], &option[(index + 1)..]),
+                None => return Err(format!("option '{}' needs a value", option)),
+            };
+
+            match name {
+                "uid" => self.uid = Some(value.parse().map_err(|_| invalid(name, value))?),
+                "gid" => self.gid = Some(value.parse().map_err(|_| invalid(name, value))?),
+                "umask" => {
+                    self.umask =
+                        u16::from_str_radix(value, 8).map_err(|_| invalid(name, value))? & 0o777
+                }
+                "hidden" => {
+                    self.hidden = match value {
+                        "show" => HiddenMode::Show,
+                        "dotfile" => HiddenMode::Dotfile,
+                        "omit" => HiddenMode::Omit,
+                        _ => return Err(invalid(name, value)),
+                    }
+                }
+                "tz" => self.time_zone = value.parse().map_err(|_| invalid(name, value))?,
+                "case" => {
+                    self.name_matching = match value {
+                        "insensitive" => NameMatching::CaseInsensitive,
+                        "sensitive" => NameMatching::CaseSensitive,
+                        _ => return Err(invalid(name, value)),
+                    }
+                }
+                _ => return Err(format!("unknown option '{}'", name)),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn perm(&self, attributes: Attributes) -> u16 {
+        let mut perm = 0o777 & !self.umask;
+
+        if attributes.is_read_only() {
+            perm &= !0o222;
+        }
+
+        perm
+    }
+
+    /// The name an entry is presented with, or `None` if it shouldn't be presented at all.
+    pub fn display_name<'a>(&self, name: &'a str, attributes: Attributes) -> Option<Cow<'a, str>> {
+        let hidden = attributes.is_hidden() || attributes.is_system();
+
+        match self.hidden {
+            HiddenMode::Dotfile if hidden && !name.starts_with('.') => {
+                Some(Cow::Owned(format!(".{}", name)))
+            }
+            HiddenMode::Omit if hidden => None,
+            _ => Some(Cow::Borrowed(name)),
+        }
+    }
+
+    /// The time a timestamp is presented with; unset or nonsensical ones, and ones before
+    /// the Unix epoch, come out as the epoch.
+    pub fn system_time(&self, timestamp: FatDateTime) -> SystemTime {
+        match timestamp.to_unix_seconds_in(self.time_zone) {
+            Some(seconds) if seconds > 0 => {
+                UNIX_EPOCH
+                    + Duration::from_secs(seconds as u64)
+                    + Duration::from_millis(u64::from(timestamp.millisecond))
+            }
+            _ => UNIX_EPOCH,
+        }
+    }
+
+    /// The timestamp a time is recorded as, or `None` if FAT can't record it.
+    pub fn fat_time(&self, time: SystemTime) -> Option<FatDateTime> {
+        let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        FatDateTime::from_unix_seconds_in(seconds as i64, self.time_zone)
+    }
+}
+
+fn invalid(name: &str, value: &str) -> String {
+    format!("invalid value '{}' for {}", value, name)
+}
@@ -0,0 +1,102 @@
+use osc_block_storage::virt::*;
+use osc_fat::*;
+use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::net::TcpListener;
+use std::process;
+use std::str::FromStr;
+
+mod rpc;
+mod server;
+mod xdr;
+
+use server::{AttributeOptions, Server};
+
+const USAGE: &str = "\
+usage: osc-fat-nfs [--offset BYTES] [--uid N] [--gid N] [--umask NNN] [--time-zone ZONE]
+                   IMAGE HOST:PORT
+
+serves the contents of IMAGE, read-only, over NFSv3 on TCP at HOST:PORT, which
+answers the MOUNT protocol too, so there's no need for a portmapper if the client is
+told the port, for example:
+
+  osc-fat-nfs disk.img 0.0.0.0:2049
+  mount -t nfs -o vers=3,proto=tcp,port=2049,mountport=2049,nolock HOST:/ /mnt
+
+any directory in the image can be mounted; timestamps are taken to be in ZONE:
+utc (the default), local or an offset like +01:00";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut offset = 0;
+    let mut attributes = AttributeOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--offset" => offset = parse_value(&arg, args.next()),
+            "--uid" => attributes.uid = Some(parse_value(&arg, args.next())),
+            "--gid" => attributes.gid = Some(parse_value(&arg, args.next())),
+            "--umask" => {
+                let value = args.next().unwrap_or_default();
+
+                attributes.umask = match u16::from_str_radix(&value, 8) {
+                    Ok(umask) => umask & 0o777,
+                    Err(_) => usage(format!("invalid value '{}' for --umask", value)),
+                }
+            }
+            "--time-zone" => attributes.time_zone = parse_value(&arg, args.next()),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => usage(format!("unknown option '{}'", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let (image, address) = match positional.as_slice() {
+        [image, address] => (image, address),
+        _ => usage("expected IMAGE and HOST:PORT".into()),
+    };
+
+    let file = File::open(image).unwrap_or_else(|err| fail(image, err));
+    let device = FileBlockDevice::new(file, offset).unwrap_or_else(|err| fail(image, err));
+    let options = MountOptions {
+        read_only: true,
+        time_zone: attributes.time_zone,
+        ..MountOptions::default()
+    };
+    let fs = FATFileSystem::open_with_options(Box::new(device), options)
+        .unwrap_or_else(|err| fail(image, err));
+
+    let listener = TcpListener::bind(address).unwrap_or_else(|err| fail(address, err));
+    let mut server = Server::new(&fs, attributes);
+
+    // NOTE: the filesystem can only be used from one thread, so connections are
+    // served one after another rather than side by side
+    for stream in listener.incoming() {
+        if let Err(err) = stream.and_then(|stream| server.serve(stream)) {
+            eprintln!("osc-fat-nfs: connection failed: {}", err);
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(name: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+
+    value
+        .parse()
+        .unwrap_or_else(|_| usage(format!("invalid value '{}' for {}", value, name)))
+}
+
+fn usage(message: String) -> ! {
+    eprintln!("osc-fat-nfs: {}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn fail(context: &str, err: impl Display) -> ! {
+    eprintln!("osc-fat-nfs: {}: {}", context, err);
+    process::exit(1);
+}
@@ -0,0 +1,170 @@
+use crate::xdr::{Decoder, Encoder, GarbageArgs};
+use std::io::{self, Read, Write};
+
+const RPC_VERSION: u32 = 2;
+
+const CALL: u32 = 0;
+const REPLY: u32 = 1;
+
+const MSG_ACCEPTED: u32 = 0;
+const MSG_DENIED: u32 = 1;
+const RPC_MISMATCH: u32 = 0;
+
+const AUTH_NONE: u32 = 0;
+const AUTH_UNIX: u32 = 1;
+const MAX_AUTH_SIZE: usize = 400;
+
+/// The largest record accepted, which comfortably holds any call a read-only server
+/// takes.
+const MAX_RECORD_SIZE: usize = 1 << 20;
+const LAST_FRAGMENT: u32 = 0x8000_0000;
+
+/// The outcome of a call the server accepted, for the caller to be told about.
+pub enum Accepted {
+    Success(Encoder),
+    ProgramUnavailable,
+    /// The program is known, but not in the version asked for; the versions it is
+    /// known in are given.
+    ProgramMismatch(u32, u32),
+    ProcedureUnavailable,
+    GarbageArgs,
+}
+
+impl From<GarbageArgs> for Accepted {
+    fn from(_: GarbageArgs) -> Self {
+        Self::GarbageArgs
+    }
+}
+
+pub struct Call<'a> {
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+    /// The user and group the caller claims to be, if it said.
+    pub unix_ids: Option<(u32, u32)>,
+    pub arguments: Decoder<'a>,
+}
+
+/// Reads the next record from a stream, putting the fragments it's made of together,
+/// or gives `None` if the client has gone away.
+pub fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut record = Vec::new();
+
+    loop {
+        let mut marker = [0u8; 4];
+
+        match reader.read_exact(&mut marker) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && record.is_empty() => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        }
+
+        let marker = u32::from_be_bytes(marker);
+        let length = (marker & !LAST_FRAGMENT) as usize;
+
+        if record.len() + length > MAX_RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record of over {} bytes", record.len() + length),
+            ));
+        }
+
+        let start = record.len();
+        record.resize(start + length, 0);
+        reader.read_exact(&mut record[start..])?;
+
+        if marker & LAST_FRAGMENT != 0 {
+            return Ok(Some(record));
+        }
+    }
+}
+
+pub fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(4 + record.len());
+
+    message.extend_from_slice(&(record.len() as u32 | LAST_FRAGMENT).to_be_bytes());
+    message.extend_from_slice(record);
+
+    writer.write_all(&message)?;
+    writer.flush()
+}
+
+/// Answers the call in `record` with `handler`, giving the reply to send, or `None` if
+/// the record isn't a call that can be answered at all.
+pub fn answer<F>(record: &[u8], handler: F) -> Option<Vec<u8>>
+where
+    F: FnOnce(Call) -> Accepted,
+{
+    let mut decoder = Decoder::new(record);
+    let xid = decoder.u32().ok()?;
+
+    if decoder.u32().ok()? != CALL {
+        return None;
+    }
+
+    let mut reply = Encoder::new();
+    reply.u32(xid).u32(REPLY);
+
+    if decoder.u32().ok()? != RPC_VERSION {
+        reply
+            .u32(MSG_DENIED)
+            .u32(RPC_MISMATCH)
+            .u32(RPC_VERSION)
+            .u32(RPC_VERSION);
+        return Some(reply.into_bytes());
+    }
+
+    let program = decoder.u32().ok()?;
+    let version = decoder.u32().ok()?;
+    let procedure = decoder.u32().ok()?;
+
+    let credential_flavor = decoder.u32().ok()?;
+    let credential = decoder.opaque(MAX_AUTH_SIZE).ok()?;
+    let _verifier_flavor = decoder.u32().ok()?;
+    let _verifier = decoder.opaque(MAX_AUTH_SIZE).ok()?;
+
+    let unix_ids = if credential_flavor == AUTH_UNIX {
+        unix_ids(credential).ok()
+    } else {
+        None
+    };
+
+    let accepted = handler(Call {
+        program,
+        version,
+        procedure,
+        unix_ids,
+        arguments: decoder,
+    });
+
+    reply.u32(MSG_ACCEPTED).u32(AUTH_NONE).opaque(&[]);
+
+    match accepted {
+        Accepted::Success(results) => {
+            reply.u32(0).fixed_opaque(&results.into_bytes());
+        }
+        Accepted::ProgramUnavailable => {
+            reply.u32(1);
+        }
+        Accepted::ProgramMismatch(low, high) => {
+            reply.u32(2).u32(low).u32(high);
+        }
+        Accepted::ProcedureUnavailable => {
+            reply.u32(3);
+        }
+        Accepted::GarbageArgs => {
+            reply.u32(4);
+        }
+    }
+
+    Some(reply.into_bytes())
+}
+
+fn unix_ids(credential: &[u8]) -> Result<(u32, u32), GarbageArgs> {
+    let mut decoder = Decoder::new(credential);
+    let _stamp = decoder.u32()?;
+    let _machine_name = decoder.opaque(255)?;
+    Ok((decoder.u32()?, decoder.u32()?))
+}
@@ -0,0 +1,777 @@
+use crate::rpc::{self, Accepted, Call};
+use crate::xdr::{self, Decoder, Encoder, GarbageArgs};
+use osc_fat::{Attributes, FATFileSystem, FatDateTime, FatFile, Metadata, TimeZonePolicy};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+const NFS_PROGRAM: u32 = 100_003;
+const NFS_VERSION: u32 = 3;
+const MOUNT_PROGRAM: u32 = 100_005;
+const MOUNT_VERSION: u32 = 3;
+
+const NFSPROC3_NULL: u32 = 0;
+const NFSPROC3_GETATTR: u32 = 1;
+const NFSPROC3_SETATTR: u32 = 2;
+const NFSPROC3_LOOKUP: u32 = 3;
+const NFSPROC3_ACCESS: u32 = 4;
+const NFSPROC3_READLINK: u32 = 5;
+const NFSPROC3_READ: u32 = 6;
+const NFSPROC3_WRITE: u32 = 7;
+const NFSPROC3_CREATE: u32 = 8;
+const NFSPROC3_MKDIR: u32 = 9;
+const NFSPROC3_SYMLINK: u32 = 10;
+const NFSPROC3_MKNOD: u32 = 11;
+const NFSPROC3_REMOVE: u32 = 12;
+const NFSPROC3_RMDIR: u32 = 13;
+const NFSPROC3_RENAME: u32 = 14;
+const NFSPROC3_LINK: u32 = 15;
+const NFSPROC3_READDIR: u32 = 16;
+const NFSPROC3_READDIRPLUS: u32 = 17;
+const NFSPROC3_FSSTAT: u32 = 18;
+const NFSPROC3_FSINFO: u32 = 19;
+const NFSPROC3_PATHCONF: u32 = 20;
+const NFSPROC3_COMMIT: u32 = 21;
+
+const MOUNTPROC3_NULL: u32 = 0;
+const MOUNTPROC3_MNT: u32 = 1;
+const MOUNTPROC3_DUMP: u32 = 2;
+const MOUNTPROC3_UMNT: u32 = 3;
+const MOUNTPROC3_UMNTALL: u32 = 4;
+const MOUNTPROC3_EXPORT: u32 = 5;
+
+const NFS3_OK: u32 = 0;
+const NFS3ERR_NOENT: u32 = 2;
+const NFS3ERR_IO: u32 = 5;
+const NFS3ERR_NXIO: u32 = 6;
+const NFS3ERR_EXIST: u32 = 17;
+const NFS3ERR_NOTDIR: u32 = 20;
+const NFS3ERR_ISDIR: u32 = 21;
+const NFS3ERR_INVAL: u32 = 22;
+const NFS3ERR_FBIG: u32 = 27;
+const NFS3ERR_NOSPC: u32 = 28;
+const NFS3ERR_ROFS: u32 = 30;
+const NFS3ERR_NOTEMPTY: u32 = 66;
+const NFS3ERR_STALE: u32 = 70;
+const NFS3ERR_BADHANDLE: u32 = 10001;
+const NFS3ERR_TOOSMALL: u32 = 10005;
+
+const NF3REG: u32 = 1;
+const NF3DIR: u32 = 2;
+
+const ACCESS3_READ: u32 = 0x01;
+const ACCESS3_LOOKUP: u32 = 0x02;
+const ACCESS3_EXECUTE: u32 = 0x20;
+
+const FSF3_HOMOGENEOUS: u32 = 0x08;
+
+const AUTH_UNIX: u32 = 1;
+
+const MAX_HANDLE_SIZE: usize = 64;
+const MAX_NAME_SIZE: usize = 255;
+const MAX_PATH_SIZE: usize = 1024;
+const MAX_READ_SIZE: u32 = 1 << 16;
+
+/// The encoded sizes of the fixed parts of replies, which readdir needs to keep within
+/// the size the client asked for.
+const POST_OP_ATTR_SIZE: usize = 88;
+const POST_OP_FH_SIZE: usize = 16;
+const READDIR_OVERHEAD: usize = 4 + POST_OP_ATTR_SIZE + 8 + 4 + 4;
+
+/// How many files are kept open between reads, so that reading through a file doesn't
+/// walk its cluster chain from the start every time.
+const OPEN_FILE_CACHE_SIZE: usize = 16;
+
+/// How DOS attributes and timestamps are presented as Unix ownership, permissions and
+/// times.
+#[derive(Debug, Clone)]
+pub struct AttributeOptions {
+    /// The owner of everything, or the user making each call if `None`.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub umask: u16,
+    pub time_zone: TimeZonePolicy,
+}
+
+impl Default for AttributeOptions {
+    fn default() -> Self {
+        Self {
+            uid: None,
+            gid: None,
+            umask: 0o022,
+            time_zone: TimeZonePolicy::Utc,
+        }
+    }
+}
+
+/// Serves the contents of a filesystem, read-only, to NFSv3 clients, along with the
+/// MOUNT protocol they get the root file handle from.
+pub struct Server<'a> {
+    fs: &'a FATFileSystem,
+    attributes: AttributeOptions,
+    /// File handles hold ids, which are handed out as paths are first seen. The path of
+    /// id `n` is at index `n - 1`.
+    paths: Vec<String>,
+    ids: HashMap<String, u64>,
+    open_files: Vec<(u64, FatFile<'a>)>,
+    caller: (u32, u32),
+}
+
+/// A file handle that has been looked up.
+struct Resolved {
+    id: u64,
+    path: String,
+    metadata: Metadata,
+}
+
+type Results = Result<Encoder, GarbageArgs>;
+
+impl<'a> Server<'a> {
+    pub fn new(fs: &'a FATFileSystem, attributes: AttributeOptions) -> Self {
+        let mut server = Self {
+            fs,
+            attributes,
+            paths: Vec::new(),
+            ids: HashMap::new(),
+            open_files: Vec::new(),
+            caller: (0, 0),
+        };
+
+        server.id("");
+        server
+    }
+
+    /// Answers calls on `stream` until the client disconnects.
+    pub fn serve<S: Read + Write>(&mut self, mut stream: S) -> io::Result<()> {
+        while let Some(record) = rpc::read_record(&mut stream)? {
+            if let Some(reply) = rpc::answer(&record, |call| self.dispatch(call)) {
+                rpc::write_record(&mut stream, &reply)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn dispatch(&mut self, call: Call) -> Accepted {
+        let Call {
+            program,
+            version,
+            procedure,
+            unix_ids,
+            mut arguments,
+        } = call;
+
+        self.caller = unix_ids.unwrap_or((0, 0));
+
+        let results = match (program, version) {
+            (NFS_PROGRAM, NFS_VERSION) => self.nfs(procedure, &mut arguments),
+            (MOUNT_PROGRAM, MOUNT_VERSION) => self.mount(procedure, &mut arguments),
+            (NFS_PROGRAM, _) => return Accepted::ProgramMismatch(NFS_VERSION, NFS_VERSION),
+            (MOUNT_PROGRAM, _) => return Accepted::ProgramMismatch(MOUNT_VERSION, MOUNT_VERSION),
+            _ => return Accepted::ProgramUnavailable,
+        };
+
+        match results {
+            Ok(Some(results)) => Accepted::Success(results),
+            Ok(None) => Accepted::ProcedureUnavailable,
+            Err(GarbageArgs) => Accepted::GarbageArgs,
+        }
+    }
+
+    fn nfs(
+        &mut self,
+        procedure: u32,
+        arguments: &mut Decoder,
+    ) -> Result<Option<Encoder>, GarbageArgs> {
+        let results = match procedure {
+            NFSPROC3_NULL => Encoder::new(),
+            NFSPROC3_GETATTR => self.getattr(arguments)?,
+            NFSPROC3_LOOKUP => self.lookup(arguments)?,
+            NFSPROC3_ACCESS => self.access(arguments)?,
+            NFSPROC3_READLINK => {
+                let mut results = Encoder::new();
+                results.u32(NFS3ERR_INVAL).bool(false);
+                results
+            }
+            NFSPROC3_READ => self.read(arguments)?,
+            NFSPROC3_READDIR => self.readdir(arguments, false)?,
+            NFSPROC3_READDIRPLUS => self.readdir(arguments, true)?,
+            NFSPROC3_FSSTAT => self.fsstat(arguments)?,
+            NFSPROC3_FSINFO => self.fsinfo(arguments)?,
+            NFSPROC3_PATHCONF => self.pathconf(arguments)?,
+            NFSPROC3_SETATTR | NFSPROC3_WRITE | NFSPROC3_CREATE | NFSPROC3_MKDIR
+            | NFSPROC3_SYMLINK | NFSPROC3_MKNOD | NFSPROC3_REMOVE | NFSPROC3_RMDIR
+            | NFSPROC3_RENAME | NFSPROC3_LINK | NFSPROC3_COMMIT => {
+                // The failure results of these are all made of attributes from before
+                // and after, which are optional, and are left out
+                let optional_attributes = match procedure {
+                    NFSPROC3_RENAME => 4,
+                    NFSPROC3_LINK => 3,
+                    _ => 2,
+                };
+
+                let mut results = Encoder::new();
+                results.u32(NFS3ERR_ROFS);
+
+                for _ in 0..optional_attributes {
+                    results.bool(false);
+                }
+
+                results
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(results))
+    }
+
+    fn mount(
+        &mut self,
+        procedure: u32,
+        arguments: &mut Decoder,
+    ) -> Result<Option<Encoder>, GarbageArgs> {
+        let mut results = Encoder::new();
+
+        match procedure {
+            MOUNTPROC3_NULL | MOUNTPROC3_UMNT | MOUNTPROC3_UMNTALL => {}
+            MOUNTPROC3_MNT => {
+                let path = arguments.string(MAX_PATH_SIZE)?;
+
+                match self.walk(&path) {
+                    Ok((path, metadata)) if metadata.is_directory() => {
+                        let id = self.id(&path);
+                        results.u32(NFS3_OK);
+                        handle(&mut results, id);
+                        results.u32(1).u32(AUTH_UNIX);
+                    }
+                    Ok(_) => {
+                        results.u32(NFS3ERR_NOTDIR);
+                    }
+                    Err(status) => {
+                        results.u32(status);
+                    }
+                }
+            }
+            MOUNTPROC3_DUMP => {
+                results.bool(false);
+            }
+            MOUNTPROC3_EXPORT => {
+                results.bool(true).string("/").bool(false).bool(false);
+            }
+            _ => return Ok(None),
+        }
+
+        Ok(Some(results))
+    }
+
+    fn getattr(&mut self, arguments: &mut Decoder) -> Results {
+        let mut results = Encoder::new();
+
+        match self.resolve(arguments)? {
+            Ok(object) => {
+                results.u32(NFS3_OK);
+                self.fattr(&mut results, object.id, &object.metadata);
+            }
+            Err(status) => {
+                results.u32(status);
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn lookup(&mut self, arguments: &mut Decoder) -> Results {
+        let directory = self.resolve(arguments)?;
+        let name = arguments.string(MAX_NAME_SIZE)?;
+        let mut results = Encoder::new();
+
+        let directory = match directory {
+            Ok(directory) => directory,
+            Err(status) => {
+                results.u32(status).bool(false);
+                return Ok(results);
+            }
+        };
+
+        match self.lookup_child(&directory, &name) {
+            Ok((path, metadata)) => {
+                let id = self.id(&path);
+                results.u32(NFS3_OK);
+                handle(&mut results, id);
+                self.post_op_attr(&mut results, Some((id, &metadata)));
+            }
+            Err(status) => {
+                results.u32(status);
+            }
+        }
+
+        self.post_op_attr(&mut results, Some((directory.id, &directory.metadata)));
+        Ok(results)
+    }
+
+    fn lookup_child(&self, directory: &Resolved, name: &str) -> Result<(String, Metadata), u32> {
+        if !directory.metadata.is_directory() {
+            return Err(NFS3ERR_NOTDIR);
+        }
+
+        match name {
+            "." => Ok((directory.path.clone(), directory.metadata.clone())),
+            ".." => {
+                let parent = parent_path(&directory.path);
+                let metadata = self.fs.lookup(parent).map_err(status)?;
+                Ok((parent.into(), metadata))
+            }
+            _ if name.is_empty() || name.contains('/') => Err(NFS3ERR_NOENT),
+            _ => {
+                let metadata = self
+                    .fs
+                    .lookup(&join(&directory.path, name))
+                    .map_err(status)?;
+
+                // NOTE: names match case-insensitively, so the path is rebuilt with the
+                // name as it's stored, giving every spelling of it the same handle
+                Ok((join(&directory.path, &metadata.name), metadata))
+            }
+        }
+    }
+
+    /// Looks up an absolute path a component at a time, giving it back spelt as it's
+    /// stored.
+    fn walk(&self, path: &str) -> Result<(String, Metadata), u32> {
+        let mut current = Resolved {
+            id: 0,
+            path: String::new(),
+            metadata: self.fs.lookup("").map_err(status)?,
+        };
+
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            let (path, metadata) = self.lookup_child(&current, component)?;
+            current = Resolved {
+                id: 0,
+                path,
+                metadata,
+            };
+        }
+
+        Ok((current.path, current.metadata))
+    }
+
+    fn access(&mut self, arguments: &mut Decoder) -> Results {
+        let object = self.resolve(arguments)?;
+        let access = arguments.u32()?;
+        let mut results = Encoder::new();
+
+        match object {
+            Ok(object) => {
+                results.u32(NFS3_OK);
+                self.post_op_attr(&mut results, Some((object.id, &object.metadata)));
+                results.u32(access & (ACCESS3_READ | ACCESS3_LOOKUP | ACCESS3_EXECUTE));
+            }
+            Err(status) => {
+                results.u32(status).bool(false);
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn read(&mut self, arguments: &mut Decoder) -> Results {
+        let object = self.resolve(arguments)?;
+        let offset = arguments.u64()?;
+        let count = arguments.u32()?.min(MAX_READ_SIZE);
+        let mut results = Encoder::new();
+
+        let object = match object {
+            Ok(object) if object.metadata.is_directory() => {
+                results.u32(NFS3ERR_ISDIR);
+                self.post_op_attr(&mut results, Some((object.id, &object.metadata)));
+                return Ok(results);
+            }
+            Ok(object) => object,
+            Err(status) => {
+                results.u32(status).bool(false);
+                return Ok(results);
+            }
+        };
+
+        let mut data = vec![0u8; count as usize];
+        let read = self
+            .open_file(object.id, &object.path)
+            .and_then(|file| file.read_at(offset, &mut data).map_err(status));
+
+        match read {
+            Ok(read) => {
+                results.u32(NFS3_OK);
+                self.post_op_attr(&mut results, Some((object.id, &object.metadata)));
+                results
+                    .u32(read as u32)
+                    .bool(offset + read as u64 >= u64::from(object.metadata.size))
+                    .opaque(&data[..read]);
+            }
+            Err(status) => {
+                results.u32(status);
+                self.post_op_attr(&mut results, Some((object.id, &object.metadata)));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Handles both READDIR and READDIRPLUS, which also gives the attributes and handle
+    /// of every entry. Cookies are the index of the entry after, so listing carries on
+    /// from there.
+    fn readdir(&mut self, arguments: &mut Decoder, plus: bool) -> Results {
+        let directory = self.resolve(arguments)?;
+        let cookie = arguments.u64()?;
+        let _cookie_verifier = arguments.fixed_opaque(8)?;
+        let count = arguments.u32()? as usize;
+        let max_count = if plus {
+            arguments.u32()? as usize
+        } else {
+            count
+        };
+
+        let mut results = Encoder::new();
+
+        let directory = match directory {
+            Ok(directory) if directory.metadata.is_directory() => directory,
+            Ok(directory) => {
+                results.u32(NFS3ERR_NOTDIR);
+                self.post_op_attr(&mut results, Some((directory.id, &directory.metadata)));
+                return Ok(results);
+            }
+            Err(status) => {
+                results.u32(status).bool(false);
+                return Ok(results);
+            }
+        };
+
+        let entries = match self.list(&directory) {
+            Ok(entries) => entries,
+            Err(status) => {
+                results.u32(status);
+                self.post_op_attr(&mut results, Some((directory.id, &directory.metadata)));
+                return Ok(results);
+            }
+        };
+
+        let mut listed = Encoder::new();
+        let mut names_size = 0;
+        let mut eof = true;
+
+        for (index, (name, path, metadata)) in entries.iter().enumerate().skip(cookie as usize) {
+            let id = self.id(path);
+            let name_size = 8 + 4 + name.len() + xdr::padding(name.len()) + 8;
+            let entry_size = 4
+                + name_size
+                + if plus {
+                    POST_OP_ATTR_SIZE + POST_OP_FH_SIZE
+                } else {
+                    0
+                };
+
+            if names_size + name_size > count
+                || READDIR_OVERHEAD + listed.len() + entry_size > max_count
+            {
+                eof = false;
+                break;
+            }
+
+            listed.bool(true).u64(id).string(name).u64(index as u64 + 1);
+
+            if plus {
+                self.post_op_attr(&mut listed, Some((id, metadata)));
+                listed.bool(true);
+                handle(&mut listed, id);
+            }
+
+            names_size += name_size;
+        }
+
+        if !eof && listed.is_empty() {
+            results.u32(NFS3ERR_TOOSMALL);
+            self.post_op_attr(&mut results, Some((directory.id, &directory.metadata)));
+            return Ok(results);
+        }
+
+        results.u32(NFS3_OK);
+        self.post_op_attr(&mut results, Some((directory.id, &directory.metadata)));
+        results
+            .fixed_opaque(&[0; 8])
+            .fixed_opaque(&listed.into_bytes())
+            .bool(false)
+            .bool(eof);
+
+        Ok(results)
+    }
+
+    /// The entries of a directory, including "." and "..", with their paths.
+    fn list(&self, directory: &Resolved) -> Result<Vec<(String, String, Metadata)>, u32> {
+        let parent = parent_path(&directory.path);
+        let parent_metadata = self.fs.lookup(parent).map_err(status)?;
+
+        let mut entries = vec![
+            (
+                ".".into(),
+                directory.path.clone(),
+                directory.metadata.clone(),
+            ),
+            ("..".into(), parent.into(), parent_metadata),
+        ];
+
+        for item in self
+            .fs
+            .open_dir(&directory.path)
+            .and_then(|dir| dir.list())
+            .map_err(status)?
+        {
+            entries.push((item.name.clone(), join(&directory.path, &item.name), item));
+        }
+
+        Ok(entries)
+    }
+
+    fn fsstat(&mut self, arguments: &mut Decoder) -> Results {
+        let mut results = Encoder::new();
+
+        match self.resolve(arguments)? {
+            Ok(object) => {
+                // TODO: report the size of the filesystem and how much of it is free
+                results.u32(NFS3_OK);
+                self.post_op_attr(&mut results, Some((object.id, &object.metadata)));
+                results.u64(0).u64(0).u64(0).u64(0).u64(0).u64(0).u32(0);
+            }
+            Err(status) => {
+                results.u32(status).bool(false);
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn fsinfo(&mut self, arguments: &mut Decoder) -> Results {
+        let mut results = Encoder::new();
+
+        match self.resolve(arguments)? {
+            Ok(object) => {
+                let cluster_size_bytes = self.fs.cluster_size_bytes() as u32;
+
+                results.u32(NFS3_OK);
+                self.post_op_attr(&mut results, Some((object.id, &object.metadata)));
+                results
+                    .u32(MAX_READ_SIZE)
+                    .u32(MAX_READ_SIZE)
+                    .u32(cluster_size_bytes)
+                    .u32(0)
+                    .u32(0)
+                    .u32(0)
+                    .u32(MAX_READ_SIZE)
+                    .u64(u64::from(osc_fat::MAX_FILE_SIZE))
+                    .u32(2)
+                    .u32(0)
+                    .u32(FSF3_HOMOGENEOUS);
+            }
+            Err(status) => {
+                results.u32(status).bool(false);
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn pathconf(&mut self, arguments: &mut Decoder) -> Results {
+        let mut results = Encoder::new();
+
+        match self.resolve(arguments)? {
+            Ok(object) => {
+                results.u32(NFS3_OK);
+                self.post_op_attr(&mut results, Some((object.id, &object.metadata)));
+                results
+                    .u32(1)
+                    .u32(MAX_NAME_SIZE as u32)
+                    .bool(true)
+                    .bool(true)
+                    .bool(true)
+                    .bool(true);
+            }
+            Err(status) => {
+                results.u32(status).bool(false);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Takes a file handle from the arguments and looks up what it refers to, giving
+    /// the status to fail with if it can't be.
+    fn resolve(&self, arguments: &mut Decoder) -> Result<Result<Resolved, u32>, GarbageArgs> {
+        let handle = arguments.opaque(MAX_HANDLE_SIZE)?;
+
+        if handle.len() != 8 {
+            return Ok(Err(NFS3ERR_BADHANDLE));
+        }
+
+        let mut id = [0u8; 8];
+        id.copy_from_slice(handle);
+        let id = u64::from_be_bytes(id);
+
+        // NOTE: ids are only handed out by this server, so handles from before it was
+        // restarted are stale
+        let path = match id
+            .checked_sub(1)
+            .and_then(|index| self.paths.get(index as usize))
+        {
+            Some(path) => path.clone(),
+            None => return Ok(Err(NFS3ERR_STALE)),
+        };
+
+        Ok(match self.fs.lookup(&path) {
+            Ok(metadata) => Ok(Resolved { id, path, metadata }),
+            Err(osc_fat::Error::NotFound) => Err(NFS3ERR_STALE),
+            Err(err) => Err(status(err)),
+        })
+    }
+
+    fn id(&mut self, path: &str) -> u64 {
+        if let Some(&id) = self.ids.get(path) {
+            return id;
+        }
+
+        self.paths.push(path.into());
+        let id = self.paths.len() as u64;
+        self.ids.insert(path.into(), id);
+        id
+    }
+
+    fn open_file(&mut self, id: u64, path: &str) -> Result<&mut FatFile<'a>, u32> {
+        match self.open_files.iter().position(|(open, _)| *open == id) {
+            Some(index) => {
+                let entry = self.open_files.remove(index);
+                self.open_files.push(entry);
+            }
+            None => {
+                let file = self.fs.open_file(path).map_err(status)?;
+
+                if self.open_files.len() == OPEN_FILE_CACHE_SIZE {
+                    self.open_files.remove(0);
+                }
+
+                self.open_files.push((id, file));
+            }
+        }
+
+        let index = self.open_files.len() - 1;
+        Ok(&mut self.open_files[index].1)
+    }
+
+    fn post_op_attr(&self, results: &mut Encoder, object: Option<(u64, &Metadata)>) {
+        match object {
+            Some((id, metadata)) => {
+                results.bool(true);
+                self.fattr(results, id, metadata);
+            }
+            None => {
+                results.bool(false);
+            }
+        }
+    }
+
+    fn fattr(&self, results: &mut Encoder, id: u64, metadata: &Metadata) {
+        let (file_type, links) = if metadata.is_directory() {
+            (NF3DIR, 2)
+        } else {
+            (NF3REG, 1)
+        };
+
+        let size = u64::from(metadata.size);
+        let cluster_size_bytes = self.fs.cluster_size_bytes() as u64;
+        let used = size.div_ceil(cluster_size_bytes) * cluster_size_bytes;
+        let (modified_seconds, modified_nanoseconds) = self.unix_time(metadata.modified);
+
+        results
+            .u32(file_type)
+            .u32(u32::from(self.perm(metadata.attributes)))
+            .u32(links)
+            .u32(self.attributes.uid.unwrap_or(self.caller.0))
+            .u32(self.attributes.gid.unwrap_or(self.caller.1))
+            .u64(size)
+            .u64(used)
+            .u32(0)
+            .u32(0)
+            .u64(0)
+            .u64(id);
+
+        // FAT doesn't track changes separately from modifications, and keeps only the
+        // date of the last access, so those times are all the modification time
+        for _ in 0..3 {
+            results.u32(modified_seconds).u32(modified_nanoseconds);
+        }
+    }
+
+    fn perm(&self, attributes: Attributes) -> u16 {
+        let mut perm = 0o777 & !self.attributes.umask;
+
+        if attributes.is_read_only() {
+            perm &= !0o222;
+        }
+
+        perm
+    }
+
+    /// Unset or nonsensical timestamps, and ones outside what NFSv3 times can hold, come
+    /// out as the epoch.
+    fn unix_time(&self, timestamp: FatDateTime) -> (u32, u32) {
+        match timestamp.to_unix_seconds_in(self.attributes.time_zone) {
+            Some(seconds) if seconds > 0 && seconds <= i64::from(u32::MAX) => {
+                (seconds as u32, u32::from(timestamp.millisecond) * 1_000_000)
+            }
+            _ => (0, 0),
+        }
+    }
+}
+
+fn handle(results: &mut Encoder, id: u64) {
+    results.opaque(&id.to_be_bytes());
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.into()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+fn parent_path(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(index) => &path[..index],
+        None => "",
+    }
+}
+
+fn status(err: osc_fat::Error) -> u32 {
+    match err {
+        osc_fat::Error::WriteProtected | osc_fat::Error::NotOpenForWriting => NFS3ERR_ROFS,
+        osc_fat::Error::NotFound => NFS3ERR_NOENT,
+        osc_fat::Error::NotADirectory => NFS3ERR_NOTDIR,
+        osc_fat::Error::IsADirectory => NFS3ERR_ISDIR,
+        osc_fat::Error::RootDirectory
+        | osc_fat::Error::InvalidName
+        | osc_fat::Error::InvalidGeometry
+        | osc_fat::Error::InvalidIndex
+        | osc_fat::Error::OutOfRange
+        | osc_fat::Error::BufferTooSmall
+        | osc_fat::Error::MoveIntoItself => NFS3ERR_INVAL,
+        osc_fat::Error::AlreadyExists => NFS3ERR_EXIST,
+        osc_fat::Error::DirectoryNotEmpty => NFS3ERR_NOTEMPTY,
+        osc_fat::Error::NoSpace => NFS3ERR_NOSPC,
+        osc_fat::Error::FileTooLarge => NFS3ERR_FBIG,
+        osc_fat::Error::DeviceGone => NFS3ERR_NXIO,
+        osc_fat::Error::Cancelled
+        | osc_fat::Error::Device(_)
+        | osc_fat::Error::Output
+        | osc_fat::Error::BadCluster
+        | osc_fat::Error::Corrupt
+        | osc_fat::Error::LimitExceeded(_)
+        | osc_fat::Error::Host(_) => NFS3ERR_IO,
+    }
+}
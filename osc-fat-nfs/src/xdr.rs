@@ -0,0 +1,114 @@
+/// The arguments of a call didn't match what the procedure takes.
+#[derive(Debug)]
+pub struct GarbageArgs;
+
+/// Takes XDR-encoded values apart, in order.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], GarbageArgs> {
+        if self.data.len() < count {
+            return Err(GarbageArgs);
+        }
+
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, GarbageArgs> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, GarbageArgs> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn fixed_opaque(&mut self, length: usize) -> Result<&'a [u8], GarbageArgs> {
+        let data = self.take(length)?;
+        self.take(padding(length))?;
+        Ok(data)
+    }
+
+    pub fn opaque(&mut self, max_length: usize) -> Result<&'a [u8], GarbageArgs> {
+        let length = self.u32()? as usize;
+
+        if length > max_length {
+            return Err(GarbageArgs);
+        }
+
+        self.fixed_opaque(length)
+    }
+
+    pub fn string(&mut self, max_length: usize) -> Result<String, GarbageArgs> {
+        let bytes = self.opaque(max_length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| GarbageArgs)
+    }
+}
+
+/// Builds up XDR-encoded values.
+#[derive(Default)]
+pub struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.data.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.data.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u32(value as u32)
+    }
+
+    pub fn fixed_opaque(&mut self, value: &[u8]) -> &mut Self {
+        self.data.extend_from_slice(value);
+        self.data.resize(self.data.len() + padding(value.len()), 0);
+        self
+    }
+
+    pub fn opaque(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32).fixed_opaque(value)
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.opaque(value.as_bytes())
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Everything in XDR takes up a multiple of four bytes.
+pub fn padding(length: usize) -> usize {
+    (4 - length % 4) % 4
+}
@@ -0,0 +1,70 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use osc_block_storage::{BlockDevice, BlockDeviceError};
+use osc_fat::{
+    CancelToken, CheckOptions, DirectorySelector, FATFileSystem, MountOptions, NoProgress,
+};
+
+struct MemoryBlockDevice(Vec<u8>);
+
+impl BlockDevice for MemoryBlockDevice {
+    fn block_size(&self) -> u16 {
+        512
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let block_count = self.0.len() as u64 / 512;
+        let read_blocks =
+            (destination.len() as u64 / 512).min(block_count.saturating_sub(start_block));
+        let start = (start_block * 512) as usize;
+        let end = start + (read_blocks * 512) as usize;
+
+        destination[..(end - start)].copy_from_slice(&self.0[start..end]);
+        Ok(read_blocks)
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        Some(self.0.len() as u64 / 512)
+    }
+}
+
+// Mounts the image with the hardened options and reads everything on it, none of
+// which may panic whatever the image holds
+fuzz_target!(|data: &[u8]| {
+    let device = Box::new(MemoryBlockDevice(data.to_vec()));
+
+    let fs = match FATFileSystem::open_with_options(device, MountOptions::hardened()) {
+        Ok(fs) => fs,
+        Err(_) => return,
+    };
+
+    let mut items = Vec::new();
+
+    let _ = fs.walk_tree(DirectorySelector::Root, |path, item| {
+        items.push((String::from(path), item.clone()));
+        Ok(())
+    });
+
+    for (path, item) in items {
+        let _ = fs.lookup(&path);
+
+        if item.is_directory() {
+            let _ = fs.open_dir(&path).map(|dir| dir.list());
+        } else if let Ok(mut file) = fs.open_file(&path) {
+            let mut buffer = [0u8; 4096];
+            let _ = file.read_at(u64::from(item.size / 2), &mut buffer);
+        }
+    }
+
+    let _ = fs.check(
+        CheckOptions::default(),
+        &mut NoProgress,
+        &CancelToken::new(),
+    );
+    let _ = fs.fragmentation();
+});
@@ -0,0 +1,205 @@
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::prim::first_sector_of_cluster;
+use crate::support::{
+    raise_at_fat_entry, read_fat_value, write_fat_value, FatReadPolicy, ReadBuffer,
+};
+use crate::{AllocationPolicy, Cluster, FATFileSystem, FATGeometry};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The FAT value of a cluster that isn't in use.
+pub(crate) const FREE_CLUSTER: u32 = 0;
+
+/// The FAT value written to end a chain.
+pub(crate) const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+
+impl FATFileSystem {
+    /// Whether `cluster` is in the data region, i.e. a cluster a chain can contain.
+    pub(crate) fn is_data_cluster(&self, cluster: Cluster) -> bool {
+        self.geo.is_data_cluster(cluster)
+    }
+
+    /// Gives a reader for FAT entries that loads the FAT through `buffer`.
+    pub(crate) fn fat_reader<'a>(&self, buffer: &'a mut [u8]) -> FatReader<'a> {
+        FatReader {
+            buffer: ReadBuffer::new(self.device.clone(), buffer, self.geo.sector_size_bytes),
+            geo: self.geo,
+            fat_read_policy: self.options.fat_read_policy(),
+        }
+    }
+
+    pub(crate) fn write_fat_value(&self, cluster: Cluster, value: u32) -> Result<()> {
+        let mut device = self.device.borrow_mut();
+        write_fat_value(&mut **device, &self.geo, cluster, value)
+    }
+
+    /// Takes a free cluster and makes it the end of a chain, appending it to the chain
+    /// ending at `previous` if there is one.
+    pub(crate) fn allocate_cluster(&self, previous: Option<Cluster>) -> Result<Cluster> {
+        let cluster = self.find_free_cluster()?;
+
+        self.write_fat_value(cluster, END_OF_CHAIN)?;
+
+        if let Some(previous) = previous {
+            self.write_fat_value(previous, cluster)?;
+        }
+
+        if let Some(free_cluster_count) = self.free_cluster_count.get() {
+            self.free_cluster_count
+                .set(Some(free_cluster_count.saturating_sub(1)));
+        }
+
+        self.next_free_cluster.set(Some(cluster + 1));
+
+        Ok(cluster)
+    }
+
+    /// Frees `first_cluster` and everything after it in its chain.
+    pub(crate) fn free_chain(&self, first_cluster: Cluster) -> Result<()> {
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut fat = self.fat_reader(&mut buffer);
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+
+        // NOTE: the whole chain is read before any of it is freed, with a limit so that
+        // a corrupt chain that loops back on itself isn't followed forever
+        while self.is_data_cluster(cluster) && (chain.len() as u32) < self.geo.cluster_count {
+            chain.push(cluster);
+            cluster = fat.read(cluster)?;
+        }
+
+        for &cluster in chain.iter() {
+            self.write_fat_value(cluster, FREE_CLUSTER)?;
+        }
+
+        if self.options.discard {
+            self.discard_clusters(&mut chain);
+        }
+
+        if let Some(free_cluster_count) = self.free_cluster_count.get() {
+            self.free_cluster_count
+                .set(Some(free_cluster_count + chain.len() as u32));
+        }
+
+        // NOTE: the search starts from the lowest free cluster when deterministic, so
+        // that what's allocated doesn't depend on the history of the volume
+        if self.options.deterministic.is_some() {
+            if let Some(&lowest) = chain.iter().min() {
+                let next_free = self.next_free_cluster.get().unwrap_or(2);
+                self.next_free_cluster.set(Some(next_free.min(lowest)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tells the device that `clusters` no longer hold anything, a run of contiguous
+    /// clusters at a time. Only the blocks wholly within a run are discarded.
+    fn discard_clusters(&self, clusters: &mut [Cluster]) {
+        let sector_size_bytes = u64::from(self.geo.sector_size_bytes);
+        let block_size_bytes = u64::from(self.device_block_size);
+        let cluster_size_bytes = self.cluster_size_bytes() as u64;
+
+        clusters.sort_unstable();
+
+        let mut runs = clusters.iter().peekable();
+
+        while let Some(&first_cluster) = runs.next() {
+            let mut cluster_count = 1;
+
+            while runs
+                .next_if(|&&next| next == first_cluster + cluster_count)
+                .is_some()
+            {
+                cluster_count += 1;
+            }
+
+            let first_sector = first_sector_of_cluster(
+                first_cluster,
+                self.geo.cluster_size_sectors,
+                self.geo.first_data_sector as u32,
+            );
+
+            let start_byte = u64::from(first_sector) * sector_size_bytes;
+            let end_byte = start_byte + u64::from(cluster_count) * cluster_size_bytes;
+
+            let start_block = start_byte.div_ceiling(block_size_bytes);
+            let end_block = end_byte / block_size_bytes;
+
+            // NOTE: a discard is only a hint, so it failing doesn't undo the freeing
+            if end_block > start_block {
+                let _ = self
+                    .device
+                    .borrow_mut()
+                    .discard(start_block, end_block - start_block);
+            }
+        }
+    }
+
+    fn find_free_cluster(&self) -> Result<Cluster> {
+        let end = self.geo.cluster_count + 2;
+
+        let start = match self.next_free_cluster.get() {
+            Some(cluster) if self.is_data_cluster(cluster) => cluster,
+            None if self.options.allocation_policy == AllocationPolicy::Rotating
+                && self.options.deterministic.is_none() =>
+            {
+                self.rotating_start()?
+            }
+            Some(_) | None => 2,
+        };
+
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut fat = self.fat_reader(&mut buffer);
+
+        for cluster in (start..end).chain(2..start) {
+            if fat.read(cluster)? == FREE_CLUSTER {
+                return Ok(cluster);
+            }
+        }
+
+        Err(Error::NoSpace)
+    }
+
+    /// Where `AllocationPolicy::Rotating` starts searching before anything's been
+    /// allocated since mounting: the next free cluster the FSInfo sector records, or
+    /// the cluster after the highest one in use where there's no record of it.
+    fn rotating_start(&self) -> Result<Cluster> {
+        if let Some((_, next_free)) = self.read_fs_info()? {
+            if self.is_data_cluster(next_free) {
+                return Ok(next_free);
+            }
+        }
+
+        let end = self.geo.cluster_count + 2;
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut fat = self.fat_reader(&mut buffer);
+
+        for cluster in (2..end).rev() {
+            if fat.read(cluster)? != FREE_CLUSTER {
+                return Ok(if cluster + 1 < end { cluster + 1 } else { 2 });
+            }
+        }
+
+        Ok(2)
+    }
+}
+
+/// Reads FAT entries, keeping the sectors it loads around for the next read.
+pub(crate) struct FatReader<'a> {
+    buffer: ReadBuffer<'a>,
+    geo: FATGeometry,
+    fat_read_policy: FatReadPolicy,
+}
+
+impl<'a> FatReader<'a> {
+    pub fn read(&mut self, cluster: Cluster) -> Result<u32> {
+        read_fat_value(&mut self.buffer, &self.geo, self.fat_read_policy, cluster)
+    }
+
+    /// Records that `error` was found in the FAT entry for `cluster`, returning it.
+    pub fn raise_at(&self, error: Error, cluster: Cluster) -> Error {
+        raise_at_fat_entry(&self.buffer, &self.geo, error, cluster)
+    }
+}
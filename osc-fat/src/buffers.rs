@@ -0,0 +1,122 @@
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::{FATFileSystem, ReadGranularity};
+use core::cmp;
+
+/// How big the buffers the filesystem reads through (such as the one given to
+/// `walk_directory`) need to be, and should be.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BufferRequirements {
+    /// The smallest buffer that works, which is a sector or a device block, whichever
+    /// is larger.
+    pub min: usize,
+    /// The size that reads with the configured `ReadGranularity`, short of more than
+    /// the sector cache can hold, so that one read doesn't push out everything else.
+    /// Bigger buffers mean fewer device calls.
+    pub recommended: usize,
+    /// Buffers should be a multiple of this, as anything after the last multiple of
+    /// it isn't used.
+    pub alignment: usize,
+}
+
+impl FATFileSystem {
+    pub fn buffer_requirements(&self) -> BufferRequirements {
+        let block_size = usize::from(self.device_block_size);
+        let min = cmp::max(usize::from(self.geo.sector_size_bytes), block_size);
+
+        let granularity_bytes = match self.options.read_granularity {
+            ReadGranularity::Sector => 0,
+            ReadGranularity::Cluster => self.cluster_size_bytes(),
+            ReadGranularity::Sectors(count) => {
+                usize::from(count) * usize::from(self.geo.sector_size_bytes)
+            }
+        };
+
+        let mut recommended = granularity_bytes.div_ceiling(min) * min;
+
+        if let Some(capacity_blocks) = self.sector_cache.capacity_blocks() {
+            if capacity_blocks > 0 {
+                let capacity_bytes = capacity_blocks * block_size / min * min;
+                recommended = cmp::min(recommended, capacity_bytes);
+            }
+        }
+
+        BufferRequirements {
+            min,
+            recommended: cmp::max(min, recommended),
+            alignment: min,
+        }
+    }
+
+    /// Refuses a buffer lent to the filesystem that a sector can't be read into.
+    pub(crate) fn check_buffer(&self, buffer: &[u8]) -> Result<()> {
+        if buffer.len() < self.buffer_requirements().min {
+            return Err(Error::BufferTooSmall);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::format_volume;
+    use crate::{DirectorySelector, Variant};
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn walks_through_a_one_block_buffer() {
+        let (_, fs) = format_volume(Variant::Fat16, 16 << 20);
+        let directory = fs.create_dir("/DIR").unwrap().selector();
+
+        // NOTE: "." and ".." and these take 14 of the first sector's 16 entries, so the
+        // next name's three long name entries run into the second cluster
+        let mut names = (0..12)
+            .map(|index| format!("FILE{}.TXT", index))
+            .collect::<Vec<_>>();
+        names.push(String::from("a long name that needs three.txt"));
+        names.extend((0..40).map(|index| format!("another long name {}.txt", index)));
+
+        for name in &names {
+            fs.create_file(&format!("/DIR/{}", name)).unwrap();
+        }
+
+        let requirements = fs.buffer_requirements();
+        assert_eq!(requirements.min, 512);
+
+        let mut buffer = vec![0u8; requirements.min];
+        let mut found = Vec::new();
+
+        fs.walk_directory(&mut buffer, directory)
+            .unwrap()
+            .enumerate_entry_views(|view| {
+                let item = view.metadata();
+
+                if !item.is_dot_entry() {
+                    found.push(item.name);
+                }
+            })
+            .unwrap();
+
+        assert_eq!(found, names);
+    }
+
+    #[test]
+    fn undersized_buffers_are_refused() {
+        let (_, fs) = format_volume(Variant::Fat16, 16 << 20);
+        let min = fs.buffer_requirements().min;
+
+        for len in [0, 1, min - 1].iter() {
+            let mut buffer = vec![0u8; *len];
+
+            assert!(matches!(
+                fs.walk_directory(&mut buffer, DirectorySelector::Root),
+                Err(Error::BufferTooSmall)
+            ));
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use crate::{Cluster, FATFileSystem, Metadata};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::ops::Range;
+use osc_block_storage::{BlockDevice, BlockDeviceError};
+
+/// Holds device blocks the filesystem has read or written, so that reading them again
+/// doesn't go to the device. Blocks are keyed by their index on the device, and are
+/// always the device's block size.
+///
+/// Every write goes to the device as well as the cache, so a cache can drop anything
+/// it holds at any time.
+pub trait SectorCache {
+    /// Copies block `block` into `destination` if it's held, saying whether it was.
+    fn get(&mut self, block: u64, destination: &mut [u8]) -> bool;
+
+    /// Holds `data` as the contents of block `block`, replacing whatever was held for it.
+    fn insert(&mut self, block: u64, data: &[u8]);
+
+    /// Drops everything held.
+    fn clear(&mut self) {}
+
+    /// Drops block `block` if it's held. Caches that can't drop a single block drop
+    /// everything instead.
+    fn remove(&mut self, block: u64) {
+        let _ = block;
+        self.clear();
+    }
+
+    /// How many blocks the cache can hold, if there's a limit, which reads are kept
+    /// within so they don't push out what they've just read.
+    fn capacity_blocks(&self) -> Option<usize> {
+        None
+    }
+
+    /// How many blocks the cache holds, if it keeps count.
+    fn held_blocks(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Holds up to a fixed number of blocks, dropping the least recently used first.
+pub struct LruSectorCache {
+    capacity: usize,
+    clock: u64,
+    /// Held blocks with when they were last used.
+    blocks: BTreeMap<u64, (u64, Vec<u8>)>,
+    /// Held blocks by when they were last used.
+    by_use: BTreeMap<u64, u64>,
+}
+
+impl LruSectorCache {
+    /// How many blocks the cache a filesystem starts with holds.
+    pub const DEFAULT_CAPACITY: usize = 128;
+
+    /// A cache of `capacity` blocks, where none holds nothing at all.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            blocks: BTreeMap::new(),
+            by_use: BTreeMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+impl Default for LruSectorCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl SectorCache for LruSectorCache {
+    fn get(&mut self, block: u64, destination: &mut [u8]) -> bool {
+        let now = self.tick();
+
+        match self.blocks.get_mut(&block) {
+            Some((last_used, data)) if data.len() == destination.len() => {
+                self.by_use.remove(last_used);
+                self.by_use.insert(now, block);
+                *last_used = now;
+
+                destination.copy_from_slice(data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn insert(&mut self, block: u64, data: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let now = self.tick();
+
+        if let Some((last_used, held)) = self.blocks.get_mut(&block) {
+            self.by_use.remove(last_used);
+            self.by_use.insert(now, block);
+            *last_used = now;
+
+            held.clear();
+            held.extend_from_slice(data);
+            return;
+        }
+
+        // NOTE: the evicted block's buffer is reused for the new one
+        let mut held = Vec::new();
+
+        if self.blocks.len() >= self.capacity {
+            let oldest = self.by_use.keys().next().copied();
+
+            if let Some(oldest) = oldest {
+                let evicted = self.by_use.remove(&oldest).unwrap_or_default();
+
+                if let Some((_, data)) = self.blocks.remove(&evicted) {
+                    held = data;
+                }
+            }
+        }
+
+        held.clear();
+        held.extend_from_slice(data);
+
+        self.blocks.insert(block, (now, held));
+        self.by_use.insert(now, block);
+    }
+
+    fn clear(&mut self) {
+        self.blocks.clear();
+        self.by_use.clear();
+    }
+
+    fn remove(&mut self, block: u64) {
+        if let Some((last_used, _)) = self.blocks.remove(&block) {
+            self.by_use.remove(&last_used);
+        }
+    }
+
+    fn capacity_blocks(&self) -> Option<usize> {
+        Some(self.capacity)
+    }
+
+    fn held_blocks(&self) -> Option<usize> {
+        Some(self.blocks.len())
+    }
+}
+
+/// How much one of the filesystem's caches holds, and how often it's had what was
+/// asked of it since the filesystem was opened.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheUsage {
+    pub hits: u64,
+    pub misses: u64,
+    /// In bytes for the block caches and entries for the directory cache, if the
+    /// cache keeps count.
+    pub held: Option<usize>,
+    /// In the same units as `held`, if there's a limit.
+    pub capacity: Option<usize>,
+}
+
+/// What each of the caches `CacheConfig` bounds holds, from
+/// `FATFileSystem::cache_stats`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub sectors: CacheUsage,
+    pub fat: CacheUsage,
+    pub directories: CacheUsage,
+}
+
+/// A `SectorCache` with counts of how often it's had what was asked for.
+pub(crate) struct BlockCache {
+    cache: RefCell<Box<dyn SectorCache>>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl BlockCache {
+    pub(crate) fn new(cache: Box<dyn SectorCache>) -> Self {
+        Self {
+            cache: RefCell::new(cache),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    fn get(&self, block: u64, destination: &mut [u8]) -> bool {
+        let held = self.cache.borrow_mut().get(block, destination);
+        let count = if held { &self.hits } else { &self.misses };
+
+        count.set(count.get() + 1);
+        held
+    }
+
+    fn insert(&self, block: u64, data: &[u8]) {
+        self.cache.borrow_mut().insert(block, data);
+    }
+
+    fn remove(&self, block: u64) {
+        self.cache.borrow_mut().remove(block);
+    }
+
+    pub(crate) fn capacity_blocks(&self) -> Option<usize> {
+        self.cache.borrow().capacity_blocks()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    fn usage(&self, block_size: usize) -> CacheUsage {
+        let cache = self.cache.borrow();
+
+        CacheUsage {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+            held: cache.held_blocks().map(|blocks| blocks * block_size),
+            capacity: cache.capacity_blocks().map(|blocks| blocks * block_size),
+        }
+    }
+}
+
+/// Holds the entries that names have been looked up to, keyed by the directory they
+/// were looked up in and the name, dropping the least recently used first. Entries are
+/// held along with the generation of their directory, and dropped once it's changed.
+pub(crate) struct DirectoryCache {
+    capacity: usize,
+    clock: u64,
+    /// Held entries with when they were last used and their directory's generation.
+    entries: BTreeMap<(Cluster, String), (u64, u64, Metadata)>,
+    /// Held entries by when they were last used.
+    by_use: BTreeMap<u64, (Cluster, String)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl DirectoryCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            entries: BTreeMap::new(),
+            by_use: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: &(Cluster, String), generation: u64) -> Option<Metadata> {
+        self.clock += 1;
+
+        if let Some(&(last_used, held_generation, _)) = self.entries.get(key) {
+            if held_generation != generation {
+                self.entries.remove(key);
+                self.by_use.remove(&last_used);
+            }
+        }
+
+        match self.entries.get_mut(key) {
+            Some((last_used, _, metadata)) => {
+                self.by_use.remove(last_used);
+                self.by_use.insert(self.clock, key.clone());
+                *last_used = self.clock;
+
+                self.hits += 1;
+                Some(metadata.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: (Cluster, String), metadata: Metadata, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.clock += 1;
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self.by_use.keys().next().copied();
+
+            if let Some(evicted) = oldest.and_then(|oldest| self.by_use.remove(&oldest)) {
+                self.entries.remove(&evicted);
+            }
+        }
+
+        if let Some((last_used, _, _)) = self
+            .entries
+            .insert(key.clone(), (self.clock, generation, metadata))
+        {
+            self.by_use.remove(&last_used);
+        }
+
+        self.by_use.insert(self.clock, key);
+    }
+
+    fn usage(&self) -> CacheUsage {
+        CacheUsage {
+            hits: self.hits,
+            misses: self.misses,
+            held: Some(self.entries.len()),
+            capacity: Some(self.capacity),
+        }
+    }
+}
+
+/// Sits between the filesystem and its device, so everything that reads or writes
+/// through the device goes through the caches too. Blocks of the FATs go to the FAT
+/// cache, and everything else to the sector cache.
+pub(crate) struct CachedBlockDevice {
+    inner: Box<dyn BlockDevice>,
+    sectors: Rc<BlockCache>,
+    fat: Rc<BlockCache>,
+    fat_blocks: Range<u64>,
+    write_generation: Rc<Cell<u64>>,
+}
+
+impl CachedBlockDevice {
+    pub(crate) fn new(
+        inner: Box<dyn BlockDevice>,
+        sectors: Rc<BlockCache>,
+        fat: Rc<BlockCache>,
+        fat_blocks: Range<u64>,
+        write_generation: Rc<Cell<u64>>,
+    ) -> Self {
+        Self {
+            inner,
+            sectors,
+            fat,
+            fat_blocks,
+            write_generation,
+        }
+    }
+
+    fn cache_for(&self, block: u64) -> &BlockCache {
+        if self.fat_blocks.contains(&block) {
+            &self.fat
+        } else {
+            &self.sectors
+        }
+    }
+
+    /// Drops `blocks` from whichever cache holds them, for when what's on the device
+    /// isn't known.
+    fn evict(&self, blocks: Range<u64>) {
+        for block in blocks {
+            self.cache_for(block).remove(block);
+        }
+    }
+}
+
+impl BlockDevice for CachedBlockDevice {
+    fn block_size(&self) -> u16 {
+        self.inner.block_size()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        self.inner.block_count()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.inner.block_size());
+        let block_count = destination.len() / block_size;
+        let block_range = |index: usize| (index * block_size)..((index + 1) * block_size);
+        let mut index = 0;
+
+        while index < block_count {
+            let block = start_block + index as u64;
+
+            if self
+                .cache_for(block)
+                .get(block, &mut destination[block_range(index)])
+            {
+                #[cfg(feature = "tracing")]
+                tracing::trace!(block, "sector cache hit");
+
+                index += 1;
+                continue;
+            }
+
+            // Read the run of blocks that aren't held in one go
+            let mut run_end = index + 1;
+            let mut held_after_run = false;
+
+            while run_end < block_count {
+                let block = start_block + run_end as u64;
+
+                if self
+                    .cache_for(block)
+                    .get(block, &mut destination[block_range(run_end)])
+                {
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(block, "sector cache hit");
+
+                    held_after_run = true;
+                    break;
+                }
+
+                run_end += 1;
+            }
+
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                start_block = block,
+                block_count = run_end - index,
+                "device read"
+            );
+
+            let run = &mut destination[(index * block_size)..(run_end * block_size)];
+            let read = self.inner.read_blocks(block, run)? as usize;
+
+            for (offset, data) in run.chunks(block_size).take(read).enumerate() {
+                let block = start_block + (index + offset) as u64;
+                self.cache_for(block).insert(block, data);
+            }
+
+            if read < run_end - index {
+                return Ok((index + read) as u64);
+            }
+
+            index = if held_after_run { run_end + 1 } else { run_end };
+        }
+
+        Ok(block_count as u64)
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let block_size = usize::from(self.inner.block_size());
+
+        // NOTE: bumped even if the write fails, as it may have partially happened
+        self.write_generation.set(self.write_generation.get() + 1);
+
+        let block_count = (source.len() / block_size) as u64;
+        let end_block = start_block + block_count;
+
+        // NOTE: a failed or short write may still have changed any of the blocks it
+        // didn't report, so none of what's held for them can be trusted
+        let written = match self.inner.write_blocks(start_block, source) {
+            Ok(written) => written,
+            Err(err) => {
+                self.evict(start_block..end_block);
+                return Err(err);
+            }
+        };
+
+        if written < block_count {
+            self.evict((start_block + written)..end_block);
+        }
+
+        for (offset, data) in source
+            .chunks_exact(block_size)
+            .take(written as usize)
+            .enumerate()
+        {
+            let block = start_block + offset as u64;
+            self.cache_for(block).insert(block, data);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.inner.flush()
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        self.inner.prefetch(start_block, block_count)
+    }
+
+    // NOTE: the cache can go on holding what discarded blocks held, as what they read
+    // back as is unspecified
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        self.inner.discard(start_block, block_count)
+    }
+}
+
+impl FATFileSystem {
+    /// Replaces the cache of device blocks outside the FAT, which is an
+    /// `LruSectorCache` of `CacheConfig::sector_cache_bytes` to begin with. Callers with
+    /// a cache of their own, such as a kernel's page cache, can supply it here to avoid
+    /// caching twice, or use an `LruSectorCache` of no blocks to turn caching off.
+    pub fn set_sector_cache(&mut self, cache: Box<dyn SectorCache>) {
+        *self.sector_cache.cache.borrow_mut() = cache;
+    }
+
+    /// How much each of the caches holds, and how often they've been of use.
+    pub fn cache_stats(&self) -> CacheStats {
+        let block_size = usize::from(self.device_block_size);
+
+        CacheStats {
+            sectors: self.sector_cache.usage(block_size),
+            fat: self.fat_cache.usage(block_size),
+            directories: self.directory_cache.borrow().usage(),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::error::Result;
+use crate::names::{LongNameAssembler, INVALID_SHORT_NAME_CHARS};
+use crate::prim::first_sector_of_cluster;
+use crate::support::DataStructure;
+use crate::{
+    Cluster, DirectoryEntry, FATFileSystem, FatEntry, LongFileNameEntry, Metadata,
+    StandardDirectoryEntry, Variant,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+
+const DOT: &[u8] = b".          ";
+const DOT_DOT: &[u8] = b"..         ";
+
+/// A cluster that no file or directory on the volume has, but that holds what look
+/// like directory entries, so it's likely to have been part of a directory that's
+/// been lost.
+#[derive(Debug, Clone)]
+pub struct CarvedDirectory {
+    pub cluster: Cluster,
+    /// Whether the FAT has the cluster allocated, i.e. it's in a lost chain, rather
+    /// than free.
+    pub allocated: bool,
+    /// The first cluster of the directory this one was in, as its ".." entry gives
+    /// it, which is 0 for the root. Only the first cluster of a directory has "." and
+    /// ".." entries, so this is `None` for the rest.
+    pub parent: Option<Cluster>,
+    /// The entries in use, apart from "." and "..", with their long names where they
+    /// have them.
+    pub entries: Vec<Metadata>,
+    /// How many of the entries have a complete set of long name entries with the
+    /// right checksum, which is the best evidence that these are directory entries,
+    /// as it's unlikely to happen by chance.
+    pub checksummed_long_names: usize,
+}
+
+impl FATFileSystem {
+    /// Looks through the clusters that aren't part of any file or directory, whether
+    /// free or in lost chains, for ones that hold directory entries, to find
+    /// directories lost to damage. A cluster is taken to be one if everything up to the
+    /// end marker is a plausible entry: attributes with no undefined bits, valid dates
+    /// and times, first clusters in the data region, and long name entries in sets
+    /// that fit together. Clusters with no entries in use aren't reported, unless
+    /// they start with "." and "..". Every cluster is read, so this takes as long as
+    /// reading the volume's free space.
+    pub fn carve_directories(&self) -> Result<Vec<CarvedDirectory>> {
+        let owners = self.cluster_owners()?;
+
+        let mut fat_buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut fat = self.fat_reader(&mut fat_buffer);
+        let mut cluster_bytes = vec![0u8; self.cluster_size_bytes()];
+        let mut carved = Vec::new();
+
+        for cluster in 2..(self.geo.cluster_count + 2) {
+            if owners.owner(cluster).is_some() {
+                continue;
+            }
+
+            let allocated = match FatEntry::from_value(fat.read(cluster)?, self.geo.variant) {
+                FatEntry::Bad => continue,
+                FatEntry::Free => false,
+                _ => true,
+            };
+
+            let first_sector = first_sector_of_cluster(
+                cluster,
+                self.geo.cluster_size_sectors,
+                self.geo.first_data_sector as u32,
+            ) as u64;
+
+            self.device
+                .borrow_mut()
+                .read_blocks(first_sector, &mut cluster_bytes)?;
+
+            if let Some(mut directory) = self.carve_cluster(cluster, &cluster_bytes) {
+                directory.allocated = allocated;
+                carved.push(directory);
+            }
+        }
+
+        Ok(carved)
+    }
+
+    /// Reads `bytes` as the entries of a directory's cluster, giving `None` if they
+    /// don't look like they are.
+    fn carve_cluster(&self, cluster: Cluster, bytes: &[u8]) -> Option<CarvedDirectory> {
+        let mut long_name = LongNameAssembler::default();
+        let mut has_dot = false;
+        let mut parent = None;
+        let mut entries = Vec::new();
+        let mut checksummed_long_names = 0;
+
+        for (index, bytes) in bytes.chunks_exact(DirectoryEntry::SIZE).enumerate() {
+            let deleted = match bytes[0] {
+                0x00 => break,
+                0xE5 => true,
+                _ => false,
+            };
+
+            match DirectoryEntry::from(bytes) {
+                DirectoryEntry::LongFileName(entry) => {
+                    if !self.is_plausible_long_name_entry(&entry, deleted) {
+                        return None;
+                    }
+
+                    if deleted {
+                        long_name.reset();
+                    } else {
+                        long_name.push(&entry);
+                    }
+                }
+                DirectoryEntry::Standard(entry) => {
+                    if !self.is_plausible_standard_entry(&entry, deleted) {
+                        return None;
+                    }
+
+                    if deleted {
+                        long_name.reset();
+                        continue;
+                    }
+
+                    // NOTE: the dot entries are only where they ought to be in the first
+                    // cluster of a directory, and "." has to be the cluster itself
+                    match (index, entry.short_name()) {
+                        (0, DOT) if entry.first_cluster() == cluster => {
+                            has_dot = true;
+                            continue;
+                        }
+                        (1, DOT_DOT) if has_dot => {
+                            parent = Some(entry.first_cluster());
+                            continue;
+                        }
+                        (_, DOT) | (_, DOT_DOT) => return None,
+                        _ => {}
+                    }
+
+                    let assembled = long_name.take(&entry);
+                    checksummed_long_names += usize::from(assembled.is_some());
+
+                    entries.push(Metadata::new(&entry, assembled, self.options.invalid_utf16));
+                }
+            }
+        }
+
+        if entries.is_empty() && parent.is_none() {
+            return None;
+        }
+
+        Some(CarvedDirectory {
+            cluster,
+            allocated: false,
+            parent,
+            entries,
+            checksummed_long_names,
+        })
+    }
+
+    fn is_plausible_long_name_entry(&self, entry: &LongFileNameEntry, deleted: bool) -> bool {
+        let order = entry.order();
+        let flags = order & !LongNameAssembler::ORDER_MASK;
+
+        // NOTE: a deleted entry's order is lost under its 0xE5
+        let order_valid = deleted
+            || (flags & !LongNameAssembler::LAST_ENTRY_FLAG == 0
+                && (1..=20).contains(&(order & LongNameAssembler::ORDER_MASK)));
+
+        order_valid
+            && entry.0.u8(LongFileNameEntry::RANGE_LONG_ENTRY_TYPE) == 0
+            && entry.0.u16(LongFileNameEntry::RANGE_ZERO) == 0
+    }
+
+    fn is_plausible_standard_entry(&self, entry: &StandardDirectoryEntry, deleted: bool) -> bool {
+        let short_name = entry.short_name();
+        let attributes = entry.attributes();
+
+        let dot_entry = short_name == DOT || short_name == DOT_DOT;
+
+        // NOTE: a leading 0x05 stands for 0xE5, and a deleted entry's first byte is lost
+        let name_valid = dot_entry
+            || (short_name[0] != b' '
+                && short_name.iter().enumerate().all(|(index, &byte)| {
+                    (index == 0 && (deleted || byte == 0x05))
+                        || (byte >= 0x20 && !INVALID_SHORT_NAME_CHARS.contains(&char::from(byte)))
+                }));
+
+        let first_cluster = entry.first_cluster();
+
+        let first_cluster_valid = match self.geo.variant {
+            Variant::Fat32 => true,
+            Variant::Fat12 | Variant::Fat16 => entry.first_cluster_high() == 0,
+        } && (first_cluster == 0 || self.is_data_cluster(first_cluster));
+
+        let extent_valid = if attributes.is_directory() {
+            entry.size() == 0
+        } else {
+            first_cluster != 0 || entry.size() == 0
+        };
+
+        let raw = &entry.0;
+
+        name_valid
+            && first_cluster_valid
+            && extent_valid
+            && attributes.bits() & 0xC0 == 0
+            && is_valid_date(raw.u16(StandardDirectoryEntry::RANGE_MOD_DATE))
+            && is_valid_date(raw.u16(StandardDirectoryEntry::RANGE_CREATION_DATE))
+            && is_valid_date(raw.u16(StandardDirectoryEntry::RANGE_ACCESS_DATE))
+            && is_valid_time(raw.u16(StandardDirectoryEntry::RANGE_MOD_TIME))
+            && is_valid_time(raw.u16(StandardDirectoryEntry::RANGE_CREATION_TIME))
+            && raw.u8(StandardDirectoryEntry::RANGE_CREATION_TIME_DECISECS) < 200
+    }
+}
+
+/// Whether a FAT date has a day and month that exist, or is 0, which writers that
+/// don't keep a date leave it as.
+fn is_valid_date(date: u16) -> bool {
+    let day = date & 0x1F;
+    let month = (date >> 5) & 0x0F;
+
+    date == 0 || ((1..=31).contains(&day) && (1..=12).contains(&month))
+}
+
+fn is_valid_time(time: u16) -> bool {
+    let two_seconds = time & 0x1F;
+    let minute = (time >> 5) & 0x3F;
+    let hour = time >> 11;
+
+    two_seconds < 30 && minute < 60 && hour < 24
+}
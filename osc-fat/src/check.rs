@@ -0,0 +1,765 @@
+use crate::allocator::{END_OF_CHAIN, FREE_CLUSTER};
+use crate::error::{Error, Result};
+use crate::locate::EntryLocation;
+use crate::math::DivCeiling;
+use crate::modify::set_extent;
+use crate::names::{format_short_name, short_name_checksum, short_name_key, LongNameAssembler};
+use crate::prim::{FileAllocationTable32Result, FileSystemInfo};
+use crate::{
+    Attributes, CancelToken, Cluster, DirectoryEntry, DirectorySelector, FATFileSystem,
+    LongFileNameEntry, Progress, ProgressSink, RootDirectory, StandardDirectoryEntry,
+};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// How many `FOUND.nnn` directories and `FILEnnnn.CHK` files there can be.
+const MAX_FOUND_DIRECTORIES: u32 = 1000;
+const MAX_RECOVERED_FILES: u32 = 10000;
+
+/// How `check` goes about checking a volume.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CheckOptions {
+    /// Fixes the problems that can be fixed, rather than only reporting them.
+    pub repair: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Long file name entries that don't belong to the entry after them, because their
+    /// checksum is for another short name, the set is incomplete, or they're followed
+    /// by free entries. Writers that don't know about long names leave these behind
+    /// when they delete or rename files.
+    OrphanedLongName {
+        directory: String,
+        entries: Vec<EntryLocation>,
+    },
+    /// A chain of clusters that's allocated but that no entry refers to, usually left
+    /// behind when writing a file was interrupted. Repairing it links it into a
+    /// `FOUND.nnn` directory as a `FILEnnnn.CHK` file, so what it holds can be salvaged.
+    LostChain {
+        first_cluster: Cluster,
+        cluster_count: u32,
+        recovered_as: Option<String>,
+    },
+    /// Entries in one directory with the same short name, which Windows handles badly
+    /// and which the long names don't tell apart for software that only sees short
+    /// names. This isn't repaired, as it can't be without renaming files.
+    DuplicateShortName {
+        directory: String,
+        short_name: String,
+        entries: Vec<EntryLocation>,
+    },
+    /// FAT32's backup boot sector isn't a copy of the boot sector, usually because
+    /// something changed the boot sector alone. Repairing it copies the boot sector
+    /// over the backup.
+    BackupBootSectorMismatch,
+    /// FAT32's FSInfo sector records a free cluster count that isn't what the FAT
+    /// says, which is what's reported as free space. Repairing it records the count
+    /// from the FAT.
+    WrongFreeClusterCount { recorded: u32, actual: u32 },
+    /// FAT32's FSInfo sector records a next free cluster that's outside the data
+    /// region. Repairing it records the first free cluster instead.
+    InvalidNextFreeCluster { recorded: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OrphanedLongName { directory, entries } => {
+                write!(f, "{}: orphaned long name entries at", directory)?;
+
+                for entry in entries {
+                    write!(f, " {}:{}", entry.sector, entry.offset)?;
+                }
+
+                Ok(())
+            }
+            Self::LostChain {
+                first_cluster,
+                cluster_count,
+                recovered_as,
+            } => {
+                write!(
+                    f,
+                    "lost chain of {} clusters starting at cluster {}",
+                    cluster_count, first_cluster
+                )?;
+
+                match recovered_as {
+                    Some(path) => write!(f, ", recovered as {}", path),
+                    None => Ok(()),
+                }
+            }
+            Self::DuplicateShortName {
+                directory,
+                short_name,
+                entries,
+            } => {
+                write!(f, "{}: duplicate short name {} at", directory, short_name)?;
+
+                for entry in entries {
+                    write!(f, " {}:{}", entry.sector, entry.offset)?;
+                }
+
+                Ok(())
+            }
+            Self::BackupBootSectorMismatch => {
+                write!(f, "the backup boot sector differs from the boot sector")
+            }
+            Self::WrongFreeClusterCount { recorded, actual } => write!(
+                f,
+                "the FSInfo sector records {} free clusters rather than {}",
+                recorded, actual
+            ),
+            Self::InvalidNextFreeCluster { recorded } => write!(
+                f,
+                "the FSInfo sector's next free cluster {} isn't a data cluster",
+                recorded
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub problem: Problem,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CheckReport {
+    pub findings: Vec<Finding>,
+}
+
+impl CheckReport {
+    /// Whether every problem found was repaired, which is the case when none were.
+    pub fn is_clean(&self) -> bool {
+        self.findings.iter().all(|finding| finding.repaired)
+    }
+}
+
+/// What's been found so far, and the clusters that what's been checked uses.
+struct CheckState<'a> {
+    options: CheckOptions,
+    report: CheckReport,
+    status: Progress,
+    progress: &'a mut dyn ProgressSink,
+    cancel_token: &'a CancelToken,
+    referenced: ClusterSet,
+    fat_buffer: Vec<u8>,
+    /// The directory lost chains are recovered into, once it's been created.
+    found_directory: Option<(String, DirectorySelector)>,
+    recovered_count: u32,
+}
+
+/// What going through a directory's entries as they're stored turned up.
+#[derive(Default)]
+struct DirectoryEntryScan {
+    orphaned_long_names: Vec<Vec<EntryLocation>>,
+    /// The entries using each short name, with the name as it's shown.
+    short_names: BTreeMap<[u8; 11], (String, Vec<EntryLocation>)>,
+}
+
+impl CheckState<'_> {
+    fn advance(&mut self, clusters: u64, cluster_size_bytes: u64) {
+        self.status.items_done += clusters;
+        self.status.bytes_done += clusters * cluster_size_bytes;
+        self.progress.report(&self.status);
+    }
+}
+
+/// A lost chain, and how it ends.
+struct LostChain {
+    first_cluster: Cluster,
+    last_cluster: Cluster,
+    cluster_count: u32,
+    /// Whether the chain has a proper end, rather than running into a cluster that's
+    /// free, out of range or part of another chain.
+    terminated: bool,
+}
+
+impl FATFileSystem {
+    /// Checks the volume for problems, and with `options.repair` fixes them, flushing
+    /// the device afterwards. Progress is reported in clusters, as those used by what's
+    /// beneath the root are found and then the rest are looked at for lost chains. A
+    /// repair that's cancelled leaves what it's repaired so far.
+    pub fn check(
+        &self,
+        options: CheckOptions,
+        progress: &mut dyn ProgressSink,
+        cancel_token: &CancelToken,
+    ) -> Result<CheckReport> {
+        let items_total = u64::from(self.geo.cluster_count);
+
+        let mut state = CheckState {
+            options,
+            report: CheckReport::default(),
+            status: Progress {
+                items_done: 0,
+                items_total,
+                bytes_done: 0,
+                bytes_total: items_total * self.cluster_size_bytes() as u64,
+            },
+            progress,
+            cancel_token,
+            referenced: ClusterSet::new(self.geo.cluster_count + 2),
+            fat_buffer: vec![0u8; self.buffer_requirements().recommended],
+            found_directory: None,
+            recovered_count: 0,
+        };
+
+        state.progress.report(&state.status);
+
+        if self.backup_boot_sector_matches()? == Some(false) {
+            if state.options.repair {
+                self.sync_backup_boot_sector()?;
+            }
+
+            state.report.findings.push(Finding {
+                problem: Problem::BackupBootSectorMismatch,
+                repaired: state.options.repair,
+            });
+        }
+
+        if let RootDirectory::Chain(cluster) = self.root {
+            self.mark_chain(&mut state, cluster)?;
+        }
+
+        self.check_directory(DirectorySelector::Root, &mut String::new(), 0, &mut state)?;
+        self.check_lost_chains(&mut state)?;
+        self.check_fs_info(&mut state)?;
+
+        state.status.items_done = items_total;
+        state.status.bytes_done = state.status.bytes_total;
+        state.progress.report(&state.status);
+
+        // NOTE: a repaired volume is consistent again, so the next sync can mark it
+        // clean, even if it wasn't when it was mounted
+        if state.options.repair && !self.read_only && !self.clean_at_mount.get() {
+            self.clean_at_mount.set(true);
+            self.mark_dirty()?;
+        }
+
+        if state.report.findings.iter().any(|finding| finding.repaired) {
+            self.flush()?;
+        }
+
+        Ok(state.report)
+    }
+
+    fn check_directory(
+        &self,
+        directory: DirectorySelector,
+        path: &mut String,
+        depth: usize,
+        state: &mut CheckState,
+    ) -> Result<()> {
+        self.check_depth(depth)?;
+        state.cancel_token.check()?;
+
+        let directory_path = if path.is_empty() { "/" } else { path.as_str() };
+
+        let scan = self.scan_directory_entries(directory)?;
+
+        for entries in scan.orphaned_long_names {
+            if state.options.repair {
+                self.mark_dirty()?;
+
+                for location in &entries {
+                    self.update_entry(*location, |entry| entry[0] = 0xE5)?;
+                }
+            }
+
+            state.report.findings.push(Finding {
+                problem: Problem::OrphanedLongName {
+                    directory: String::from(directory_path),
+                    entries,
+                },
+                repaired: state.options.repair,
+            });
+        }
+
+        for (short_name, entries) in scan.short_names.into_values() {
+            if entries.len() > 1 {
+                state.report.findings.push(Finding {
+                    problem: Problem::DuplicateShortName {
+                        directory: String::from(directory_path),
+                        short_name,
+                        entries,
+                    },
+                    repaired: false,
+                });
+            }
+        }
+
+        for item in self.list_directory(directory)? {
+            if item.is_dot_entry() || item.attributes.is_volume_id() {
+                continue;
+            }
+
+            // NOTE: a directory whose clusters have already been seen is part of a
+            // loop, which would otherwise never end
+            if !self.mark_chain(state, item.first_cluster)? || !item.is_directory() {
+                continue;
+            }
+
+            let parent_len = path.len();
+            path.push('/');
+            path.push_str(&item.name);
+
+            self.check_directory(
+                DirectorySelector::from_cluster(item.first_cluster),
+                path,
+                depth + 1,
+                state,
+            )?;
+
+            path.truncate(parent_len);
+        }
+
+        Ok(())
+    }
+
+    /// Marks the chain starting at `first_cluster` as in use, saying whether its first
+    /// cluster wasn't already. A chain that runs into clusters already marked is
+    /// followed no further.
+    fn mark_chain(&self, state: &mut CheckState, first_cluster: Cluster) -> Result<bool> {
+        if !self.is_data_cluster(first_cluster) || !state.referenced.insert(first_cluster) {
+            return Ok(false);
+        }
+
+        let mut fat = self.fat_reader(&mut state.fat_buffer);
+        let mut cluster = first_cluster;
+        let mut marked = 1;
+
+        while let FileAllocationTable32Result::NextClusterIndex(next) =
+            FileAllocationTable32Result::from(fat.read(cluster)?)
+        {
+            state.cancel_token.check()?;
+
+            if !self.is_data_cluster(next) || !state.referenced.insert(next) {
+                break;
+            }
+
+            cluster = next;
+            marked += 1;
+        }
+
+        state.advance(marked, self.cluster_size_bytes() as u64);
+        Ok(true)
+    }
+
+    /// Finds the clusters that are allocated but weren't marked while checking the
+    /// directories, and with `options.repair` recovers them.
+    fn check_lost_chains(&self, state: &mut CheckState) -> Result<()> {
+        // The FAT value of every lost cluster
+        let mut lost = BTreeMap::new();
+        let mut fat = self.fat_reader(&mut state.fat_buffer);
+
+        for cluster in 2..(self.geo.cluster_count + 2) {
+            if state.referenced.contains(cluster) {
+                continue;
+            }
+
+            state.cancel_token.check()?;
+
+            let value = fat.read(cluster)?;
+
+            state.status.items_done += 1;
+            state.status.bytes_done += self.cluster_size_bytes() as u64;
+            state.progress.report(&state.status);
+
+            match FileAllocationTable32Result::from(value) {
+                FileAllocationTable32Result::NextClusterIndex(FREE_CLUSTER)
+                | FileAllocationTable32Result::BadCluster => {}
+                _ => {
+                    lost.insert(cluster, value);
+                }
+            }
+        }
+
+        let pointed_to: BTreeSet<Cluster> = lost
+            .values()
+            .copied()
+            .filter(|next| lost.contains_key(next))
+            .collect();
+
+        let heads: Vec<Cluster> = lost
+            .keys()
+            .copied()
+            .filter(|cluster| !pointed_to.contains(cluster))
+            .collect();
+
+        let mut chains = Vec::new();
+
+        for head in heads {
+            chains.push(take_lost_chain(&mut lost, head));
+        }
+
+        // What's left loops back on itself, with nothing leading into it
+        while let Some(&head) = lost.keys().next() {
+            chains.push(take_lost_chain(&mut lost, head));
+        }
+
+        for chain in chains {
+            let recovered_as = if state.options.repair {
+                self.recover_lost_chain(state, &chain)?
+            } else {
+                None
+            };
+
+            state.report.findings.push(Finding {
+                repaired: recovered_as.is_some(),
+                problem: Problem::LostChain {
+                    first_cluster: chain.first_cluster,
+                    cluster_count: chain.cluster_count,
+                    recovered_as,
+                },
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Compares the free cluster count and next free cluster FAT32's FSInfo sector
+    /// records with the FAT, and with `options.repair` records what the FAT says.
+    /// Either being unknown isn't a problem.
+    fn check_fs_info(&self, state: &mut CheckState) -> Result<()> {
+        let (recorded_free_count, recorded_next_free) = match self.read_fs_info()? {
+            Some(recorded) => recorded,
+            None => return Ok(()),
+        };
+
+        // NOTE: allocations since the last sync are only counted in memory, as FSInfo
+        // isn't written until then, so the count sync will record is what's checked
+        let recorded_free_count = match (self.dirty.get(), self.free_cluster_count.get()) {
+            (true, Some(free_cluster_count)) => free_cluster_count,
+            _ => recorded_free_count,
+        };
+
+        let mut fat = self.fat_reader(&mut state.fat_buffer);
+        let mut free_count = 0;
+        let mut first_free = None;
+
+        for cluster in 2..(self.geo.cluster_count + 2) {
+            if fat.read(cluster)? == FREE_CLUSTER {
+                free_count += 1;
+                first_free.get_or_insert(cluster);
+            }
+        }
+
+        let mut problems = Vec::new();
+
+        if recorded_free_count != FileSystemInfo::UNKNOWN && recorded_free_count != free_count {
+            problems.push(Problem::WrongFreeClusterCount {
+                recorded: recorded_free_count,
+                actual: free_count,
+            });
+        }
+
+        if recorded_next_free != FileSystemInfo::UNKNOWN
+            && !self.is_data_cluster(recorded_next_free)
+        {
+            problems.push(Problem::InvalidNextFreeCluster {
+                recorded: recorded_next_free,
+            });
+        }
+
+        // NOTE: the count is taken as what's kept up to date from here on whenever
+        // repairing, as what FSInfo records can't be trusted if the volume was dirty
+        if state.options.repair {
+            self.free_cluster_count.set(Some(free_count));
+        }
+
+        if state.options.repair && !problems.is_empty() {
+            self.mark_dirty()?;
+
+            self.rewrite_fs_info(|fs_info| {
+                fs_info.set_free_count(free_count);
+
+                if !self.is_data_cluster(fs_info.next_free()) {
+                    fs_info.set_next_free(first_free.unwrap_or(FileSystemInfo::UNKNOWN));
+                }
+            })?;
+        }
+
+        for problem in problems {
+            state.report.findings.push(Finding {
+                problem,
+                repaired: state.options.repair,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Ends a lost chain properly, and links it into the `FOUND.nnn` directory as the
+    /// next `FILEnnnn.CHK`, giving back its path, or `None` if there are too many.
+    fn recover_lost_chain(
+        &self,
+        state: &mut CheckState,
+        chain: &LostChain,
+    ) -> Result<Option<String>> {
+        if state.recovered_count >= MAX_RECOVERED_FILES {
+            return Ok(None);
+        }
+
+        self.mark_dirty()?;
+
+        if !chain.terminated {
+            self.write_fat_value(chain.last_cluster, END_OF_CHAIN)?;
+        }
+
+        let (directory_path, directory) = match state.found_directory.clone() {
+            Some(found_directory) => found_directory,
+            None => {
+                let found_directory = self.create_found_directory()?;
+                state.found_directory = Some(found_directory.clone());
+                found_directory
+            }
+        };
+
+        let size = (u64::from(chain.cluster_count) * self.cluster_size_bytes() as u64)
+            .min(u64::from(u32::MAX)) as u32;
+
+        while state.recovered_count < MAX_RECOVERED_FILES {
+            let name = format!("FILE{:04}.CHK", state.recovered_count);
+            state.recovered_count += 1;
+
+            match self.create_entry(directory, &name, Attributes::ARCHIVE, chain.first_cluster) {
+                Ok(located) => {
+                    self.update_entry(located.location, |entry| {
+                        set_extent(entry, chain.first_cluster, size)
+                    })?;
+
+                    return Ok(Some(format!("{}/{}", directory_path, name)));
+                }
+                Err(Error::AlreadyExists) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Creates the first of `FOUND.000` to `FOUND.999` in the root that doesn't exist.
+    fn create_found_directory(&self) -> Result<(String, DirectorySelector)> {
+        for index in 0..MAX_FOUND_DIRECTORIES {
+            let name = format!("FOUND.{:03}", index);
+
+            match self.find_in_directory(DirectorySelector::Root, &name) {
+                Ok(_) => continue,
+                Err(Error::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+
+            let metadata = self.create_directory_entry(DirectorySelector::Root, &name)?;
+
+            return Ok((
+                format!("/{}", name),
+                DirectorySelector::from_cluster(metadata.first_cluster),
+            ));
+        }
+
+        Err(Error::AlreadyExists)
+    }
+
+    /// Goes through a directory's entries as they're stored, finding the sets of long
+    /// name entries that don't belong to the entry after them, and where each short
+    /// name is used.
+    fn scan_directory_entries(&self, directory: DirectorySelector) -> Result<DirectoryEntryScan> {
+        let mut walker = self.walk_directory_owned(directory)?;
+        let mut run = LongNameRun::default();
+        let mut scan = DirectoryEntryScan::default();
+
+        'sectors: loop {
+            let sector = walker.current_sector_index();
+
+            for (index, bytes) in walker
+                .current_sector()
+                .chunks_exact(DirectoryEntry::SIZE)
+                .enumerate()
+            {
+                let location = walker.entry_location(sector, index);
+
+                let orphaned = match bytes[0] {
+                    0x00 => {
+                        scan.orphaned_long_names.extend(run.abandon());
+                        break 'sectors;
+                    }
+                    0xE5 => run.abandon(),
+                    _ => match DirectoryEntry::from(bytes) {
+                        DirectoryEntry::LongFileName(entry) => run.push(location, &entry),
+                        DirectoryEntry::Standard(entry) => {
+                            if !entry.is_volume_id() && entry.name()[0] != b'.' {
+                                scan.short_names
+                                    .entry(short_name_key(entry.short_name()))
+                                    .or_insert_with(|| (format_short_name(&entry), Vec::new()))
+                                    .1
+                                    .push(location);
+                            }
+
+                            run.finish(&entry)
+                        }
+                    },
+                };
+
+                scan.orphaned_long_names.extend(orphaned);
+            }
+
+            match walker.next()? {
+                Some(next_walker) => walker = next_walker,
+                None => break,
+            }
+        }
+
+        scan.orphaned_long_names.extend(run.abandon());
+
+        Ok(scan)
+    }
+}
+
+/// Takes the lost chain starting at `head` out of `lost`, stopping where it leaves
+/// the clusters that are still in `lost`.
+fn take_lost_chain(lost: &mut BTreeMap<Cluster, u32>, head: Cluster) -> LostChain {
+    let mut cluster = head;
+    let mut cluster_count = 0;
+
+    loop {
+        let value = lost.remove(&cluster).unwrap_or(END_OF_CHAIN);
+        cluster_count += 1;
+
+        match FileAllocationTable32Result::from(value) {
+            FileAllocationTable32Result::NextClusterIndex(next) if lost.contains_key(&next) => {
+                cluster = next;
+            }
+            result => {
+                return LostChain {
+                    first_cluster: head,
+                    last_cluster: cluster,
+                    cluster_count,
+                    terminated: matches!(result, FileAllocationTable32Result::EndOfChain),
+                };
+            }
+        }
+    }
+}
+
+/// A set of clusters, held as a bit for each one.
+struct ClusterSet(Vec<u64>);
+
+impl ClusterSet {
+    fn new(cluster_count: u32) -> Self {
+        Self(vec![0; (cluster_count as usize).div_ceiling(64)])
+    }
+
+    fn contains(&self, cluster: Cluster) -> bool {
+        self.0[cluster as usize / 64] & (1 << (cluster % 64)) != 0
+    }
+
+    /// Adds `cluster`, saying whether it wasn't already there.
+    fn insert(&mut self, cluster: Cluster) -> bool {
+        let added = !self.contains(cluster);
+        self.0[cluster as usize / 64] |= 1 << (cluster % 64);
+        added
+    }
+}
+
+/// The long name entries seen since the last short entry, and whether they still make
+/// up a valid set.
+#[derive(Default)]
+struct LongNameRun {
+    entries: Vec<EntryLocation>,
+    next_order: u8,
+    checksum: u8,
+    broken: bool,
+}
+
+impl LongNameRun {
+    /// Adds an entry to the run, handing back the previous run if the entry starts a
+    /// new one before it was finished.
+    fn push(
+        &mut self,
+        location: EntryLocation,
+        entry: &LongFileNameEntry,
+    ) -> Option<Vec<EntryLocation>> {
+        let order = entry.order() & LongNameAssembler::ORDER_MASK;
+        let mut orphaned = None;
+
+        if entry.order() & LongNameAssembler::LAST_ENTRY_FLAG != 0 {
+            orphaned = self.abandon();
+            self.checksum = entry.checksum();
+        } else if self.entries.is_empty()
+            || order != self.next_order
+            || entry.checksum() != self.checksum
+        {
+            self.broken = true;
+        }
+
+        self.broken = self.broken || order == 0;
+        self.next_order = order.saturating_sub(1);
+        self.entries.push(location);
+
+        orphaned
+    }
+
+    /// Ends the run at a short entry, handing it back if it doesn't belong to it.
+    fn finish(&mut self, entry: &StandardDirectoryEntry) -> Option<Vec<EntryLocation>> {
+        let belongs = !self.broken
+            && self.next_order == 0
+            && !entry.is_volume_id()
+            && short_name_checksum(entry.short_name()) == self.checksum;
+
+        if belongs {
+            self.entries.clear();
+            None
+        } else {
+            self.abandon()
+        }
+    }
+
+    /// Ends the run without a short entry, handing it back if there was one.
+    fn abandon(&mut self) -> Option<Vec<EntryLocation>> {
+        self.broken = false;
+        self.next_order = 0;
+
+        if self.entries.is_empty() {
+            None
+        } else {
+            Some(core::mem::take(&mut self.entries))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::format_volume;
+    use crate::{NoProgress, Variant};
+
+    #[test]
+    fn allocating_before_sync_leaves_the_free_count_right() {
+        let (_, fs) = format_volume(Variant::Fat32, 40 << 20);
+
+        let mut file = fs.create_file("/FILE.BIN").unwrap();
+        file.write(&[0x5A; 3000]).unwrap();
+        file.flush().unwrap();
+        drop(file);
+
+        for repair in [false, true].iter() {
+            let report = fs
+                .check(
+                    CheckOptions { repair: *repair },
+                    &mut NoProgress,
+                    &CancelToken::new(),
+                )
+                .unwrap();
+
+            assert!(report.findings.is_empty(), "{:?}", report.findings);
+        }
+    }
+}
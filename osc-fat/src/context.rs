@@ -0,0 +1,181 @@
+use crate::error::{Error, Result};
+use crate::support::SharedDevice;
+use crate::{Cluster, FATFileSystem};
+use alloc::string::String;
+use core::fmt;
+
+/// Where an error happened, as far as is known: the absolute sector being read or
+/// written, or holding the FAT entry that was found to be wrong, the cluster of the
+/// chain it was reached through, and the path of what was being looked up or walked.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub sector: Option<u64>,
+    pub cluster: Option<Cluster>,
+    pub path: Option<String>,
+}
+
+impl ErrorContext {
+    pub fn at_sector(sector: u64) -> Self {
+        Self {
+            sector: Some(sector),
+            ..Self::default()
+        }
+    }
+
+    pub fn at_cluster(cluster: Cluster) -> Self {
+        Self {
+            cluster: Some(cluster),
+            ..Self::default()
+        }
+    }
+
+    pub fn at_path(path: &str) -> Self {
+        Self {
+            path: Some(String::from(path)),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_sector(mut self, sector: u64) -> Self {
+        self.sector = Some(sector);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sector.is_none() && self.cluster.is_none() && self.path.is_none()
+    }
+
+    /// Fills in what isn't known yet from `other`, which was learnt further from
+    /// where the error happened.
+    fn merge(&mut self, other: ErrorContext) {
+        self.sector = self.sector.or(other.sector);
+        self.cluster = self.cluster.or(other.cluster);
+        self.path = self.path.take().or(other.path);
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+
+        if let Some(sector) = self.sector {
+            write!(f, "sector {}", sector)?;
+            separator = ", ";
+        }
+
+        if let Some(cluster) = self.cluster {
+            write!(f, "{}cluster {}", separator, cluster)?;
+            separator = ", ";
+        }
+
+        if let Some(ref path) = self.path {
+            write!(f, "{}{}", separator, path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An error along with where it happened, as `FATFileSystem::with_context` gives it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextError {
+    pub error: Error,
+    pub context: ErrorContext,
+}
+
+impl From<Error> for ContextError {
+    fn from(error: Error) -> Self {
+        Self {
+            error,
+            context: ErrorContext::default(),
+        }
+    }
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.context.is_empty() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{} ({})", self.error, self.context)
+        }
+    }
+}
+
+impl FATFileSystem {
+    /// Gives the error of `result`, which has to be what an operation on this
+    /// filesystem just returned, along with where it happened. Only errors that have a
+    /// place on the volume get one: device errors, corruption, and chains running into
+    /// bad clusters. `Error` itself stays a plain value that can be matched on, so
+    /// this is how to get a message that says which sector or cluster to look at.
+    pub fn with_context<T>(&self, result: Result<T>) -> core::result::Result<T, ContextError> {
+        result.map_err(|error| self.context_of(error))
+    }
+
+    /// Like `with_context`, for an error that's already been taken out of its result.
+    pub fn context_of(&self, error: Error) -> ContextError {
+        ContextError {
+            error,
+            context: self.device.take_error_context(error),
+        }
+    }
+}
+
+/// Whether `error` happened somewhere on the volume, and so is worth recording the
+/// context of.
+pub(crate) fn has_location(error: Error) -> bool {
+    matches!(
+        error,
+        Error::Device(_) | Error::DeviceGone | Error::Corrupt | Error::BadCluster
+    )
+}
+
+/// Adds to the context of an error as it's passed up through something that knows
+/// more about where it happened.
+pub(crate) trait NoteContext: Sized {
+    fn note_context<F>(self, device: &SharedDevice, context: F) -> Self
+    where
+        F: FnOnce() -> ErrorContext;
+}
+
+impl<T> NoteContext for Result<T> {
+    fn note_context<F>(self, device: &SharedDevice, context: F) -> Self
+    where
+        F: FnOnce() -> ErrorContext,
+    {
+        if let Err(error) = self {
+            device.note_error(error, context);
+        }
+
+        self
+    }
+}
+
+/// The most recent error with a location, and where it happened, which is built up
+/// as the error's passed up.
+#[derive(Default)]
+pub(crate) struct LastError(Option<(Error, ErrorContext)>);
+
+impl LastError {
+    pub(crate) fn raise(&mut self, error: Error, context: ErrorContext) {
+        if has_location(error) {
+            self.0 = Some((error, context));
+        }
+    }
+
+    // NOTE: a different error means the last one was dealt with, and this one was
+    // raised somewhere that didn't record where
+    pub(crate) fn note(&mut self, error: Error, context: ErrorContext) {
+        match self.0 {
+            Some((last, ref mut last_context)) if last == error => last_context.merge(context),
+            _ => self.raise(error, context),
+        }
+    }
+
+    pub(crate) fn take(&mut self, error: Error) -> ErrorContext {
+        match self.0.take() {
+            Some((last, context)) if last == error => context,
+            _ => ErrorContext::default(),
+        }
+    }
+}
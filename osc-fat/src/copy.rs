@@ -0,0 +1,337 @@
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::prim::{first_sector_of_cluster, FileAllocationTable32Result};
+use crate::support::{raise_at_fat_entry, read_fat_entry, ReadBuffer};
+use crate::{
+    Attributes, CancelToken, Cluster, ErrorContext, FATFileSystem, FatDateTime, FatDir,
+    OpenOptions, Progress, ProgressSink, MAX_FILE_SIZE,
+};
+use osc_block_storage::BlockDeviceError;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The most `read_file_into` reads from the device in one call.
+const BULK_READ_BYTES: u64 = 1024 * 1024;
+
+/// What `copy_from_host_with_options` carries over from the host file besides its
+/// contents.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CopyOptions {
+    /// Give the copy the host file's modification time, interpreted in the
+    /// filesystem's time zone, rather than the current time.
+    pub preserve_modified: bool,
+
+    /// Make the copy read-only if the host file is.
+    pub preserve_read_only: bool,
+}
+
+/// What `import_tree` added to the image.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub files: u64,
+    /// The directories created, not counting ones that were already there.
+    pub directories: u64,
+    pub bytes: u64,
+}
+
+impl FATFileSystem {
+    /// Copies a file from the host into the image at `image_path`, replacing anything
+    /// already there, and returns the number of bytes copied.
+    pub fn copy_from_host<P: AsRef<Path>>(&self, host_path: P, image_path: &str) -> Result<u64> {
+        self.copy_from_host_with_options(host_path, image_path, &CopyOptions::default())
+    }
+
+    pub fn copy_from_host_with_options<P: AsRef<Path>>(
+        &self,
+        host_path: P,
+        image_path: &str,
+        options: &CopyOptions,
+    ) -> Result<u64> {
+        let mut host_file = File::open(host_path)?;
+        let host_metadata = host_file.metadata()?;
+
+        if host_metadata.len() > u64::from(MAX_FILE_SIZE) {
+            return Err(Error::FileTooLarge);
+        }
+
+        let mut file = self.open_file_with_options(
+            image_path,
+            &OpenOptions::new().write(true).create(true).truncate(true),
+        )?;
+
+        let mut buffer = vec![0u8; self.cluster_size_bytes()];
+        let mut copied = 0;
+
+        loop {
+            let count = match host_file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            file.write(&buffer[..count])?;
+            copied += count as u64;
+        }
+
+        if options.preserve_modified {
+            // NOTE: times FAT can't represent are left as the current time
+            let modified = host_metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .and_then(|since_epoch| i64::try_from(since_epoch.as_secs()).ok())
+                .and_then(|seconds| FatDateTime::from_unix_seconds_in(seconds, self.time_zone()));
+
+            if let Some(modified) = modified {
+                file.set_modified(modified)?;
+            }
+        }
+
+        let mut attributes = file.metadata().attributes;
+        file.flush()?;
+        drop(file);
+
+        if options.preserve_read_only && host_metadata.permissions().readonly() {
+            attributes.insert(Attributes::READ_ONLY);
+            self.set_attributes(image_path, attributes)?;
+        }
+
+        Ok(copied)
+    }
+
+    /// Copies everything beneath `host_dir` into `image_dir`, creating directories as
+    /// needed and merging into any that already exist. `filter` is given each host
+    /// entry's path relative to `host_dir`, and entries it refuses are left out, along
+    /// with everything beneath them. Entries are copied in name order.
+    pub fn import_tree<P, F>(
+        &self,
+        host_dir: P,
+        image_dir: &str,
+        options: &CopyOptions,
+        mut filter: F,
+    ) -> Result<ImportSummary>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&Path, &fs::Metadata) -> bool,
+    {
+        let mut summary = ImportSummary::default();
+
+        let dir = match self.open_dir(image_dir) {
+            Err(Error::NotFound) => {
+                summary.directories += 1;
+                self.create_dir(image_dir)?
+            }
+            result => result?,
+        };
+
+        self.import_directory(
+            host_dir.as_ref(),
+            &mut PathBuf::new(),
+            &dir,
+            options,
+            &mut filter,
+            &mut summary,
+        )?;
+
+        Ok(summary)
+    }
+
+    fn import_directory(
+        &self,
+        host_dir: &Path,
+        relative_path: &mut PathBuf,
+        dir: &FatDir,
+        options: &CopyOptions,
+        filter: &mut dyn FnMut(&Path, &fs::Metadata) -> bool,
+        summary: &mut ImportSummary,
+    ) -> Result<()> {
+        let mut entries =
+            fs::read_dir(host_dir.join(&relative_path))?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let file_name = entry.file_name();
+            let name = file_name.to_str().ok_or(Error::InvalidName)?;
+
+            // NOTE: this doesn't follow symlinks, which are skipped along with anything
+            // else that's neither a file nor a directory
+            let host_metadata = entry.metadata()?;
+
+            relative_path.push(name);
+
+            if filter(relative_path, &host_metadata) {
+                if host_metadata.is_dir() {
+                    let child = match dir.open_dir(name) {
+                        Err(Error::NotFound) => {
+                            summary.directories += 1;
+                            dir.create_dir(name)?
+                        }
+                        result => result?,
+                    };
+
+                    self.import_directory(
+                        host_dir,
+                        relative_path,
+                        &child,
+                        options,
+                        filter,
+                        summary,
+                    )?;
+                } else if host_metadata.is_file() {
+                    let image_path = format!("{}/{}", dir.path().trim_end_matches('/'), name);
+
+                    summary.bytes +=
+                        self.copy_from_host_with_options(entry.path(), &image_path, options)?;
+                    summary.files += 1;
+                }
+            }
+
+            relative_path.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Writes the contents of a file to `writer`, returning the number of bytes
+    /// written, which is only short of `size` if the chain ends early. Each run of
+    /// contiguous clusters is read from the device in as few calls as possible, straight
+    /// into the buffer that is written out, which makes this the fastest way to get a
+    /// whole file out of the image. Fails with `Error::Output` if `writer` does.
+    /// Progress is reported in clusters after every run of them.
+    pub fn read_file_into<W: Write>(
+        &self,
+        first_cluster: Cluster,
+        size: u32,
+        mut writer: W,
+        progress: &mut dyn ProgressSink,
+        cancel_token: &CancelToken,
+    ) -> Result<u64> {
+        let cluster_size_bytes = self.cluster_size_bytes() as u64;
+
+        let mut status = Progress {
+            items_done: 0,
+            items_total: u64::from(size).div_ceiling(cluster_size_bytes),
+            bytes_done: 0,
+            bytes_total: u64::from(size),
+        };
+
+        progress.report(&status);
+
+        if size == 0 || first_cluster < 2 {
+            return Ok(0);
+        }
+
+        let sector_size_bytes = u64::from(self.geo.sector_size_bytes);
+        let block_size_bytes = u64::from(self.device_block_size);
+        let max_run_clusters = core::cmp::max(1, BULK_READ_BYTES / cluster_size_bytes);
+
+        let mut fat_buffer = vec![0u8; self.buffer_requirements().min];
+        let mut fat = ReadBuffer::new(
+            self.device.clone(),
+            &mut fat_buffer,
+            self.geo.sector_size_bytes,
+        );
+
+        let mut data = Vec::new();
+        let mut remaining_bytes = u64::from(size);
+        let mut next_run = Some(first_cluster);
+
+        while remaining_bytes > 0 {
+            cancel_token.check()?;
+
+            let run_start = match next_run {
+                Some(cluster) if self.is_data_cluster(cluster) => cluster,
+                Some(cluster) => {
+                    let context = ErrorContext::at_cluster(cluster);
+                    return Err(self.device.raise(Error::Corrupt, context));
+                }
+                None => break,
+            };
+            let wanted_clusters = remaining_bytes.div_ceiling(cluster_size_bytes);
+            let mut run_end = run_start;
+            let mut run_clusters = 1;
+
+            next_run = None;
+
+            // NOTE: the chain is only followed as far as the file's size needs it to be
+            while run_clusters < wanted_clusters {
+                let entry =
+                    read_fat_entry(&mut fat, &self.geo, self.options.fat_read_policy(), run_end)?;
+
+                match entry {
+                    FileAllocationTable32Result::NextClusterIndex(next)
+                        if !self.is_data_cluster(next) =>
+                    {
+                        return Err(raise_at_fat_entry(&fat, &self.geo, Error::Corrupt, run_end));
+                    }
+                    FileAllocationTable32Result::NextClusterIndex(next)
+                        if next == run_end + 1 && run_clusters < max_run_clusters =>
+                    {
+                        run_end = next;
+                        run_clusters += 1;
+                    }
+                    FileAllocationTable32Result::NextClusterIndex(next) => {
+                        next_run = Some(next);
+                        break;
+                    }
+                    FileAllocationTable32Result::EndOfChain => break,
+                    FileAllocationTable32Result::BadCluster => {
+                        return Err(raise_at_fat_entry(
+                            &fat,
+                            &self.geo,
+                            Error::BadCluster,
+                            run_end,
+                        ));
+                    }
+                }
+            }
+
+            let first_sector = first_sector_of_cluster(
+                run_start,
+                self.geo.cluster_size_sectors,
+                self.geo.first_data_sector as u32,
+            );
+
+            let start_byte = u64::from(first_sector) * sector_size_bytes;
+            let length = core::cmp::min(run_clusters * cluster_size_bytes, remaining_bytes);
+
+            // NOTE: a run only starts part way into a block if blocks are bigger than
+            // sectors
+            let first_block = start_byte / block_size_bytes;
+            let end_block = (start_byte + length).div_ceiling(block_size_bytes);
+            let skip = (start_byte - first_block * block_size_bytes) as usize;
+
+            data.resize(((end_block - first_block) * block_size_bytes) as usize, 0);
+
+            let run_context =
+                || ErrorContext::at_cluster(run_start).with_sector(u64::from(first_sector));
+
+            let blocks_read = self
+                .device
+                .borrow_mut()
+                .read_blocks(first_block, &mut data)
+                .map_err(|err| self.device.raise(err.into(), run_context()))?;
+
+            if blocks_read != end_block - first_block {
+                let err = Error::Device(BlockDeviceError::Io);
+                return Err(self.device.raise(err, run_context()));
+            }
+
+            writer
+                .write_all(&data[skip..(skip + length as usize)])
+                .map_err(|_| Error::Output)?;
+
+            remaining_bytes -= length;
+
+            status.items_done += run_clusters;
+            status.bytes_done += length;
+            progress.report(&status);
+        }
+
+        Ok(u64::from(size) - remaining_bytes)
+    }
+}
@@ -0,0 +1,491 @@
+use crate::error::{Error, Result};
+use crate::locate::{split_path, EntryLocation, LocatedEntry};
+use crate::modify::set_extent;
+use crate::names::{
+    long_name_entries, short_name_checksum, short_name_key, validate_long_name, ShortNameBasis,
+};
+use crate::prim::first_sector_of_cluster;
+use crate::support::DataStructureMut;
+use crate::{
+    Attributes, Cluster, DirectoryEntry, DirectorySelector, FATFileSystem, FatDir, FatFile,
+    Metadata, RootDirectory, StandardDirectoryEntry,
+};
+use alloc::vec::Vec;
+
+/// The most entries a directory can have, which keeps it within 2 MiB.
+const MAX_DIRECTORY_ENTRIES: usize = 65536;
+
+/// What a directory has room for, and the short names it already has.
+pub(crate) struct DirectoryScan {
+    free_entries: Option<Vec<EntryLocation>>,
+    /// The free entries at the very end of the directory, which entries that need it
+    /// to grow start with.
+    trailing_free_entries: Vec<EntryLocation>,
+    last_sector: u64,
+    entry_count: usize,
+    short_names: Vec<[u8; 11]>,
+}
+
+impl FATFileSystem {
+    /// Creates an empty file at `path`, which mustn't exist yet, and opens it for
+    /// writing.
+    pub fn create_file(&self, path: &str) -> Result<FatFile<'_>> {
+        let (parent, name) = self.split_parent(path)?;
+        let located = self.create_entry(parent, name, Attributes::ARCHIVE, 0)?;
+
+        self.flush()?;
+
+        Ok(FatFile::for_writing(self, located, false))
+    }
+
+    /// Creates an empty directory at `path`, which mustn't exist yet.
+    pub fn create_dir(&self, path: &str) -> Result<FatDir<'_>> {
+        let (parent_path, name) = split_path(path)?;
+        self.open_dir(parent_path)?.create_dir(name)
+    }
+
+    /// Adds a directory called `name` to `parent`, with a cluster of its own holding
+    /// the "." and ".." entries.
+    pub(crate) fn create_directory_entry(
+        &self,
+        parent: DirectorySelector,
+        name: &str,
+    ) -> Result<Metadata> {
+        self.mark_dirty()?;
+
+        let cluster = self.allocate_cluster(None)?;
+
+        let result = self
+            .write_dot_entries(cluster, parent)
+            .and_then(|_| self.create_entry(parent, name, Attributes::DIRECTORY, cluster));
+
+        match result {
+            Ok(located) => Ok(located.metadata),
+            Err(err) => {
+                self.free_chain(cluster)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Fills a new directory's cluster, which is empty apart from "." and "..".
+    fn write_dot_entries(&self, cluster: Cluster, parent: DirectorySelector) -> Result<()> {
+        let parent_cluster = match parent {
+            DirectorySelector::Root => 0,
+            DirectorySelector::Cluster(parent_cluster) => parent_cluster,
+        };
+
+        let mut entries = [0u8; 2 * DirectoryEntry::SIZE];
+
+        for (index, (dots, first_cluster)) in
+            [(".", cluster), ("..", parent_cluster)].iter().enumerate()
+        {
+            let mut short_name = [b' '; 11];
+            short_name[..dots.len()].copy_from_slice(dots.as_bytes());
+
+            let mut entry = self.new_standard_entry(&short_name, Attributes::DIRECTORY);
+            set_extent(&mut entry, *first_cluster, 0);
+
+            entries[(index * DirectoryEntry::SIZE)..((index + 1) * DirectoryEntry::SIZE)]
+                .copy_from_slice(&entry);
+        }
+
+        // NOTE: the cluster could have been a directory's before, whose entries are
+        // gone now
+        self.directory_changed(DirectorySelector::Cluster(cluster));
+
+        self.write_cluster_range(cluster, 0, self.cluster_size_bytes(), None)?;
+        self.write_cluster_range(cluster, 0, entries.len(), Some(&entries))
+    }
+
+    /// Adds an entry called `name` with no clusters to a directory, with long file name
+    /// entries ahead of it if the name doesn't fit in 8.3.
+    pub(crate) fn create_entry(
+        &self,
+        directory: DirectorySelector,
+        name: &str,
+        attributes: Attributes,
+        first_cluster: Cluster,
+    ) -> Result<LocatedEntry> {
+        validate_long_name(name)?;
+
+        match self.find_in_directory(directory, name) {
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(Error::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+
+        let mut basis = ShortNameBasis::new(name);
+        let mut long_name_count = long_name_entry_count(name, &basis);
+        let mut scan = self.scan_for_entries(directory, long_name_count + 1)?;
+
+        // NOTE: when case matters, the short name a name would be stored as can belong
+        // to an entry whose name only differs in case, which leaves a long name as the
+        // only way to keep this one
+        if basis.is_exact()
+            && basis
+                .candidates()
+                .all(|candidate| scan.short_names.contains(&short_name_key(&candidate)))
+        {
+            basis = basis.inexact();
+            long_name_count = long_name_entry_count(name, &basis);
+            scan = self.scan_for_entries(directory, long_name_count + 1)?;
+        }
+
+        let short_name = basis
+            .candidates()
+            .find(|candidate| !scan.short_names.contains(&short_name_key(candidate)))
+            .ok_or(Error::AlreadyExists)?;
+
+        let free_entries = self.allocate_entries(directory, scan, long_name_count + 1)?;
+
+        let mut entries = if basis.is_exact() {
+            Vec::new()
+        } else {
+            long_name_entries(name, short_name_checksum(&short_name))
+        };
+
+        let mut standard_entry = self.new_standard_entry(&short_name, attributes);
+        set_extent(&mut standard_entry, first_cluster, 0);
+        entries.push(standard_entry);
+
+        self.mark_dirty()?;
+
+        // NOTE: the standard entry goes last, so an interruption leaves orphaned long
+        // name entries at worst, which are ignored
+        for (location, entry) in free_entries.iter().zip(entries.iter()) {
+            self.update_entry(*location, |bytes| bytes.copy_from_slice(entry))?;
+        }
+
+        let long_name = name.encode_utf16().collect::<Vec<_>>();
+
+        Ok(LocatedEntry {
+            metadata: Metadata::new(
+                &StandardDirectoryEntry(&standard_entry),
+                Some(&long_name),
+                self.options.invalid_utf16,
+            ),
+            location: free_entries[free_entries.len() - 1],
+        })
+    }
+
+    pub(crate) fn new_standard_entry(
+        &self,
+        short_name: &[u8; 11],
+        attributes: Attributes,
+    ) -> [u8; DirectoryEntry::SIZE] {
+        let (date, time, centiseconds) = self.now().to_raw_with_centiseconds();
+
+        let mut entry = [0u8; DirectoryEntry::SIZE];
+
+        entry[StandardDirectoryEntry::RANGE_NAME.start..StandardDirectoryEntry::RANGE_EXT.end]
+            .copy_from_slice(short_name);
+        entry.range_mut(StandardDirectoryEntry::RANGE_ATTR)[0] = attributes.bits();
+        entry.range_mut(StandardDirectoryEntry::RANGE_CREATION_TIME_DECISECS)[0] = centiseconds;
+        entry.set_u16(StandardDirectoryEntry::RANGE_CREATION_TIME, time);
+        entry.set_u16(StandardDirectoryEntry::RANGE_CREATION_DATE, date);
+        entry.set_u16(StandardDirectoryEntry::RANGE_ACCESS_DATE, date);
+        entry.set_u16(StandardDirectoryEntry::RANGE_MOD_TIME, time);
+        entry.set_u16(StandardDirectoryEntry::RANGE_MOD_DATE, date);
+
+        entry
+    }
+
+    /// Gives `count` free entries in a row in a directory that has been scanned for
+    /// them, growing it a cluster at a time if it hasn't got them. Entries that were
+    /// free at the end of the directory come first, so a run can span the old end and
+    /// the new clusters. The FAT12/16 root directory can't grow, and nor can a
+    /// directory beyond `MAX_DIRECTORY_ENTRIES`.
+    pub(crate) fn allocate_entries(
+        &self,
+        directory: DirectorySelector,
+        scan: DirectoryScan,
+        count: usize,
+    ) -> Result<Vec<EntryLocation>> {
+        if let Some(free_entries) = scan.free_entries {
+            return Ok(free_entries);
+        }
+
+        if let (DirectorySelector::Root, RootDirectory::Region { .. }) = (directory, self.root) {
+            return Err(Error::NoSpace);
+        }
+
+        let sector_size_bytes = usize::from(self.geo.sector_size_bytes);
+        let entries_per_sector = sector_size_bytes / DirectoryEntry::SIZE;
+        let entries_per_cluster = self.cluster_size_bytes() / DirectoryEntry::SIZE;
+
+        let mut last_cluster = self.cluster_of_sector(scan.last_sector);
+        let mut entry_count = scan.entry_count;
+        let mut free_entries = scan.trailing_free_entries;
+
+        while free_entries.len() < count {
+            if entry_count + entries_per_cluster > MAX_DIRECTORY_ENTRIES {
+                return Err(Error::NoSpace);
+            }
+
+            let cluster = self.grow_directory(last_cluster)?;
+            let first_sector = u64::from(first_sector_of_cluster(
+                cluster,
+                self.geo.cluster_size_sectors,
+                self.geo.first_data_sector as u32,
+            ));
+
+            let new_entries = core::cmp::min(count - free_entries.len(), entries_per_cluster);
+
+            free_entries.extend((0..new_entries).map(|index| EntryLocation {
+                directory,
+                index: (entry_count + index) as u32,
+                sector: first_sector + (index / entries_per_sector) as u64,
+                offset: (index % entries_per_sector) * DirectoryEntry::SIZE,
+            }));
+
+            last_cluster = cluster;
+            entry_count += entries_per_cluster;
+        }
+
+        Ok(free_entries)
+    }
+
+    /// Adds a zeroed cluster to the end of a directory's chain, after `last_cluster`.
+    fn grow_directory(&self, last_cluster: Cluster) -> Result<Cluster> {
+        self.mark_dirty()?;
+
+        let cluster = self.allocate_cluster(None)?;
+
+        // NOTE: the cluster is zeroed before it joins the chain, so the directory never
+        // has garbage in it, and zeroed entries mark the end of the directory
+        let result = self
+            .write_cluster_range(cluster, 0, self.cluster_size_bytes(), None)
+            .and_then(|_| self.write_fat_value(last_cluster, cluster));
+
+        match result {
+            Ok(()) => Ok(cluster),
+            Err(err) => {
+                self.free_chain(cluster)?;
+                Err(err)
+            }
+        }
+    }
+
+    fn cluster_of_sector(&self, sector: u64) -> Cluster {
+        ((sector - self.geo.first_data_sector) / u64::from(self.geo.cluster_size_sectors)) as u32
+            + 2
+    }
+
+    /// Looks for `count` free entries in a row in a directory, collecting the short
+    /// names in use on the way.
+    pub(crate) fn scan_for_entries(
+        &self,
+        directory: DirectorySelector,
+        count: usize,
+    ) -> Result<DirectoryScan> {
+        let mut walker = self.walk_directory_owned(directory)?;
+
+        let mut run = Vec::with_capacity(count);
+        let mut found = None;
+        let mut short_names = Vec::new();
+        let mut last_sector;
+        let mut entry_count = 0;
+
+        // Everything after the end-of-directory marker is free
+        let mut ended = false;
+
+        loop {
+            let sector = walker.current_sector_index();
+            last_sector = sector;
+
+            for (index, bytes) in walker
+                .current_sector()
+                .chunks_exact(DirectoryEntry::SIZE)
+                .enumerate()
+            {
+                entry_count += 1;
+                ended = ended || bytes[0] == 0x00;
+
+                if ended || bytes[0] == 0xE5 {
+                    if found.is_none() {
+                        run.push(walker.entry_location(sector, index));
+
+                        if run.len() == count {
+                            found = Some(core::mem::take(&mut run));
+                        }
+                    }
+                } else {
+                    run.clear();
+
+                    if let DirectoryEntry::Standard(entry) = DirectoryEntry::from(bytes) {
+                        short_names.push(short_name_key(entry.short_name()));
+                    }
+                }
+            }
+
+            if ended && found.is_some() {
+                break;
+            }
+
+            match walker.next()? {
+                Some(next_walker) => walker = next_walker,
+                None => break,
+            }
+        }
+
+        Ok(DirectoryScan {
+            free_entries: found,
+            trailing_free_entries: run,
+            last_sector,
+            entry_count,
+            short_names,
+        })
+    }
+}
+
+/// How many long file name entries `name` needs ahead of its standard entry.
+fn long_name_entry_count(name: &str, basis: &ShortNameBasis) -> usize {
+    if basis.is_exact() {
+        0
+    } else {
+        long_name_entries(name, 0).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::format_volume;
+    use crate::{CancelToken, CheckOptions, FatEntry, NoProgress, Variant};
+    use alloc::format;
+
+    /// Asserts that `check` finds nothing wrong with the volume.
+    fn assert_checks_clean(fs: &FATFileSystem) {
+        let report = fs
+            .check(
+                CheckOptions::default(),
+                &mut NoProgress,
+                &CancelToken::new(),
+            )
+            .unwrap();
+
+        assert!(report.findings.is_empty(), "{:?}", report.findings);
+    }
+
+    #[test]
+    fn long_name_spills_into_a_new_cluster() {
+        let (_, fs) = format_volume(Variant::Fat16, 16 << 20);
+        let directory = fs.create_dir("/DIR").unwrap().selector();
+
+        // NOTE: "." and ".." and these leave two of the cluster's 16 entries free
+        for index in 0..12 {
+            fs.create_file(&format!("/DIR/FILE{}.TXT", index)).unwrap();
+        }
+
+        // NOTE: three long name entries and the standard entry
+        let name = "a long name that needs three.txt";
+        fs.create_file(&format!("/DIR/{}", name)).unwrap();
+
+        let (located, long_name_locations) = fs.locate_with_long_name(directory, name).unwrap();
+        let indices = long_name_locations
+            .iter()
+            .map(|location| location.index)
+            .collect::<Vec<_>>();
+
+        assert_eq!(indices, [14, 15, 16]);
+        assert_eq!(located.location.index, 17);
+        assert_ne!(
+            fs.cluster_of_sector(long_name_locations[0].sector),
+            fs.cluster_of_sector(located.location.sector)
+        );
+        assert_eq!(located.metadata.name, name);
+        assert_eq!(fs.open_dir("/DIR").unwrap().list().unwrap().len(), 13);
+
+        assert_checks_clean(&fs);
+    }
+
+    #[test]
+    fn directory_grows_a_cluster_at_a_time() {
+        let (_, fs) = format_volume(Variant::Fat32, 40 << 20);
+        fs.create_dir("/DIR").unwrap();
+
+        for index in 0..100 {
+            fs.create_file(&format!("/DIR/file with a long name {}", index))
+                .unwrap();
+        }
+
+        let names = fs
+            .open_dir("/DIR")
+            .unwrap()
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|item| item.name)
+            .collect::<Vec<_>>();
+        let expected = (0..100)
+            .map(|index| format!("file with a long name {}", index))
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, expected);
+        assert_checks_clean(&fs);
+    }
+
+    /// How many clusters the FAT has as free.
+    fn count_free_clusters(fs: &FATFileSystem) -> usize {
+        let mut fat = fs.fat_table();
+
+        (2..(fs.geometry().cluster_count() + 2))
+            .filter(|&cluster| fat.fat_entry(cluster).unwrap() == FatEntry::Free)
+            .count()
+    }
+
+    /// Fills the root directory of a fixed size with files, which should end with
+    /// `Error::NoSpace` rather than the root growing.
+    fn fill_fixed_root(variant: Variant, size_bytes: u64) {
+        let (_, fs) = format_volume(variant, size_bytes);
+        let free_clusters = count_free_clusters(&fs);
+
+        let result = (0..10000)
+            .map(|index| fs.create_file(&format!("/FILE{}.TXT", index)).map(drop))
+            .find(Result::is_err)
+            .unwrap();
+
+        assert_eq!(result, Err(Error::NoSpace));
+        assert_eq!(count_free_clusters(&fs), free_clusters);
+        assert_checks_clean(&fs);
+    }
+
+    #[test]
+    fn fat12_root_doesnt_grow() {
+        fill_fixed_root(Variant::Fat12, 1 << 20);
+    }
+
+    #[test]
+    fn fat16_root_doesnt_grow() {
+        fill_fixed_root(Variant::Fat16, 16 << 20);
+    }
+
+    #[test]
+    fn directory_stops_growing_at_the_entry_limit() {
+        let (_, fs) = format_volume(Variant::Fat16, 16 << 20);
+        let directory = fs.create_dir("/DIR").unwrap().selector();
+
+        // NOTE: everything but "." and ".." up to the limit, which takes the directory
+        // right up to it
+        let count = MAX_DIRECTORY_ENTRIES - 2;
+        let scan = fs.scan_for_entries(directory, count).unwrap();
+        let free_entries = fs.allocate_entries(directory, scan, count).unwrap();
+
+        assert_eq!(free_entries.len(), count);
+        assert_eq!(
+            free_entries.last().unwrap().index as usize,
+            MAX_DIRECTORY_ENTRIES - 1
+        );
+
+        let scan = fs.scan_for_entries(directory, count + 1).unwrap();
+        assert_eq!(
+            fs.allocate_entries(directory, scan, count + 1),
+            Err(Error::NoSpace)
+        );
+
+        // NOTE: the entries the directory grew by are free, so there's still room
+        fs.create_file("/DIR/FILE.TXT").unwrap();
+        assert_checks_clean(&fs);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::error::Result;
+use crate::{Attributes, DirectorySelector, FATFileSystem, FatDateTime, Metadata};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Added(String),
+    Removed(String),
+    /// A file became a directory, or the other way around.
+    KindChanged(String),
+    ContentChanged(String),
+    AttributesChanged {
+        path: String,
+        before: Attributes,
+        after: Attributes,
+    },
+    ShortNameChanged {
+        path: String,
+        before: String,
+        after: String,
+    },
+    TimestampChanged {
+        path: String,
+        timestamp: Timestamp,
+        before: FatDateTime,
+        after: FatDateTime,
+    },
+}
+
+/// Which of an entry's timestamps a `Difference::TimestampChanged` is about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Timestamp {
+    Created,
+    Modified,
+    Accessed,
+}
+
+impl Difference {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Added(path)
+            | Self::Removed(path)
+            | Self::KindChanged(path)
+            | Self::ContentChanged(path)
+            | Self::AttributesChanged { path, .. }
+            | Self::ShortNameChanged { path, .. }
+            | Self::TimestampChanged { path, .. } => path,
+        }
+    }
+}
+
+/// Compares two volumes at the filesystem level, returning the differences sorted by
+/// path. Only what's visible through the filesystem is compared, so differences in
+/// allocation, FSInfo hints and the like are ignored.
+pub fn diff(before: &FATFileSystem, after: &FATFileSystem) -> Result<Vec<Difference>> {
+    let before_items = collect_tree(before)?;
+    let after_items = collect_tree(after)?;
+
+    let mut result = Vec::new();
+
+    for (path, before_item) in &before_items {
+        match after_items.get(path) {
+            Some(after_item) => {
+                compare_items(path, before, before_item, after, after_item, &mut result)?
+            }
+            None => result.push(Difference::Removed(path.clone())),
+        }
+    }
+
+    for path in after_items.keys() {
+        if !before_items.contains_key(path) {
+            result.push(Difference::Added(path.clone()));
+        }
+    }
+
+    // NOTE: the sort is stable, so multiple differences for one path keep their order
+    result.sort_by(|a, b| a.path().cmp(b.path()));
+
+    Ok(result)
+}
+
+fn collect_tree(fs: &FATFileSystem) -> Result<BTreeMap<String, Metadata>> {
+    let mut items = BTreeMap::new();
+
+    fs.walk_tree(DirectorySelector::Root, |path, item| {
+        items.insert(String::from(path), item.clone());
+        Ok(())
+    })?;
+
+    Ok(items)
+}
+
+fn compare_items(
+    path: &str,
+    before: &FATFileSystem,
+    before_item: &Metadata,
+    after: &FATFileSystem,
+    after_item: &Metadata,
+    result: &mut Vec<Difference>,
+) -> Result<()> {
+    if before_item.is_directory() != after_item.is_directory() {
+        result.push(Difference::KindChanged(String::from(path)));
+        return Ok(());
+    }
+
+    if before_item.is_file() && !same_content(before, before_item, after, after_item)? {
+        result.push(Difference::ContentChanged(String::from(path)));
+    }
+
+    if before_item.attributes != after_item.attributes {
+        result.push(Difference::AttributesChanged {
+            path: String::from(path),
+            before: before_item.attributes,
+            after: after_item.attributes,
+        });
+    }
+
+    if before_item.short_name != after_item.short_name {
+        result.push(Difference::ShortNameChanged {
+            path: String::from(path),
+            before: before_item.short_name.clone(),
+            after: after_item.short_name.clone(),
+        });
+    }
+
+    let timestamps = [
+        (Timestamp::Created, before_item.created, after_item.created),
+        (
+            Timestamp::Modified,
+            before_item.modified,
+            after_item.modified,
+        ),
+        (
+            Timestamp::Accessed,
+            before_item.accessed,
+            after_item.accessed,
+        ),
+    ];
+
+    for &(timestamp, before, after) in &timestamps {
+        if before != after {
+            result.push(Difference::TimestampChanged {
+                path: String::from(path),
+                timestamp,
+                before,
+                after,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn same_content(
+    before: &FATFileSystem,
+    before_item: &Metadata,
+    after: &FATFileSystem,
+    after_item: &Metadata,
+) -> Result<bool> {
+    if before_item.size != after_item.size {
+        return Ok(false);
+    }
+
+    let mut before_buffer = vec![0u8; before.buffer_requirements().recommended];
+    let mut after_buffer = vec![0u8; after.buffer_requirements().recommended];
+
+    let mut before_reader = before.open_file_reader(
+        &mut before_buffer,
+        before_item.first_cluster,
+        before_item.size,
+    )?;
+    let mut after_reader =
+        after.open_file_reader(&mut after_buffer, after_item.first_cluster, after_item.size)?;
+
+    let mut before_chunk = [0u8; 4096];
+    let mut after_chunk = [0u8; 4096];
+
+    loop {
+        let before_count = before_reader.read(&mut before_chunk)?;
+        let after_count = after_reader.read(&mut after_chunk)?;
+
+        if before_chunk[..before_count] != after_chunk[..after_count] {
+            return Ok(false);
+        }
+
+        if before_count == 0 {
+            return Ok(true);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::{Error, Result};
+use crate::{
+    Attributes, DirectorySelector, ErrorContext, FATFileSystem, FatFile, Metadata, NoteContext,
+};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// An open directory, which knows where it is in the tree so that frontends can work
+/// with names rather than cluster numbers.
+pub struct FatDir<'a> {
+    fs: &'a FATFileSystem,
+    metadata: Metadata,
+    parent: Option<DirectorySelector>,
+    path: String,
+}
+
+impl FATFileSystem {
+    pub fn root_dir(&self) -> FatDir<'_> {
+        FatDir {
+            fs: self,
+            metadata: Metadata::root(),
+            parent: None,
+            path: String::from("/"),
+        }
+    }
+
+    /// Opens the directory at `path`, matching components as `lookup` does.
+    pub fn open_dir(&self, path: &str) -> Result<FatDir<'_>> {
+        let mut dir = self.root_dir();
+
+        for (depth, component) in path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .enumerate()
+        {
+            self.check_depth(depth + 1)?;
+            dir = dir
+                .open_dir(component)
+                .note_context(&self.device, || ErrorContext::at_path(path))?;
+        }
+
+        Ok(dir)
+    }
+}
+
+impl<'a> FatDir<'a> {
+    /// The directory's entry in its parent, or a stand-in for the root.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// The path the directory was opened by, using the names as stored.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn selector(&self) -> DirectorySelector {
+        DirectorySelector::from_cluster(self.metadata.first_cluster)
+    }
+
+    /// The directory containing this one, or `None` for the root.
+    pub fn parent(&self) -> Option<DirectorySelector> {
+        self.parent
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+
+    /// Lists the directory's files and subdirectories, leaving out "." and ".." and
+    /// the volume label.
+    pub fn list(&self) -> Result<Vec<Metadata>> {
+        let mut items = self
+            .fs
+            .list_directory(self.selector())
+            .note_context(&self.fs.device, || ErrorContext::at_path(&self.path))?;
+        items.retain(|item| !item.is_dot_entry() && !item.attributes.is_volume_id());
+        Ok(items)
+    }
+
+    /// The entry at `index` in what `list` gives, or `None` past the end, without
+    /// building anything for the entries before it, so that a directory can be read a
+    /// page at a time.
+    pub fn entry_at(&self, index: u32) -> Result<Option<Metadata>> {
+        self.fs
+            .walk_directory_owned(self.selector())?
+            .entry_at(index)
+    }
+
+    /// Finds an entry by its long or short name, ignoring case.
+    pub fn find(&self, name: &str) -> Result<Metadata> {
+        self.fs
+            .find_in_directory(self.selector(), name)
+            .note_context(&self.fs.device, || ErrorContext::at_path(&self.path))
+    }
+
+    pub fn open_file(&self, name: &str) -> Result<FatFile<'a>> {
+        let metadata = self.find(name)?;
+
+        if metadata.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        Ok(FatFile::new(self.fs, metadata))
+    }
+
+    pub fn open_dir(&self, name: &str) -> Result<FatDir<'a>> {
+        let metadata = self.find(name)?;
+
+        if !metadata.is_directory() {
+            return Err(Error::NotADirectory);
+        }
+
+        Ok(self.child(metadata))
+    }
+
+    /// Creates an empty directory in this one and opens it.
+    pub fn create_dir(&self, name: &str) -> Result<FatDir<'a>> {
+        let metadata = self.fs.create_directory_entry(self.selector(), name)?;
+
+        self.fs.flush()?;
+
+        Ok(self.child(metadata))
+    }
+
+    fn child(&self, metadata: Metadata) -> FatDir<'a> {
+        let mut path = self.path.clone();
+
+        if !self.is_root() {
+            path.push('/');
+        }
+
+        path.push_str(&metadata.name);
+
+        FatDir {
+            fs: self.fs,
+            metadata,
+            parent: Some(self.selector()),
+            path,
+        }
+    }
+
+    /// Creates an empty file in the directory and opens it for writing.
+    pub fn create(&self, name: &str) -> Result<FatFile<'a>> {
+        let located = self
+            .fs
+            .create_entry(self.selector(), name, Attributes::ARCHIVE, 0)?;
+
+        self.fs.flush()?;
+
+        Ok(FatFile::for_writing(self.fs, located, false))
+    }
+}
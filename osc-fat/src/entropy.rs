@@ -0,0 +1,67 @@
+use crate::FATFileSystem;
+use alloc::boxed::Box;
+
+/// Where the filesystem gets new volume serial numbers from, when it's asked to
+/// make one up.
+pub trait EntropySource {
+    fn next_u32(&self) -> u32;
+}
+
+impl<F: Fn() -> u32> EntropySource for F {
+    fn next_u32(&self) -> u32 {
+        self()
+    }
+}
+
+/// Numbers from the standard library's randomly seeded hasher, which is random
+/// enough to keep cloned images apart, but not for anything secret.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemEntropy;
+
+#[cfg(feature = "std")]
+impl EntropySource for SystemEntropy {
+    fn next_u32(&self) -> u32 {
+        use std::collections::hash_map::RandomState;
+        use std::hash::{BuildHasher, Hasher};
+
+        // NOTE: every RandomState has different keys, so hashing nothing with a new one
+        // gives a different number each time
+        let hash = RandomState::new().build_hasher().finish();
+        (hash ^ (hash >> 32)) as u32
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn default_entropy_source() -> Option<Box<dyn EntropySource>> {
+    Some(Box::new(SystemEntropy))
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn default_entropy_source() -> Option<Box<dyn EntropySource>> {
+    None
+}
+
+impl FATFileSystem {
+    /// Replaces the source of volume serial numbers `set_volume_serial` makes up,
+    /// which is `SystemEntropy` with the `std` feature. Without one, serial numbers
+    /// are made from the time, as DOS does.
+    pub fn set_entropy_source(&mut self, entropy_source: Box<dyn EntropySource>) {
+        self.entropy_source = Some(entropy_source);
+    }
+
+    pub(crate) fn new_volume_serial(&self) -> u32 {
+        if let Some(entropy_source) = &self.entropy_source {
+            return entropy_source.next_u32();
+        }
+
+        let now = self.now();
+        let centiseconds = now.millisecond / 10;
+
+        let high = (u16::from(now.month) << 8 | u16::from(now.day))
+            .wrapping_add(u16::from(now.second) << 8 | centiseconds);
+        let low = (u16::from(now.hour) << 8 | u16::from(now.minute)).wrapping_add(now.year);
+
+        u32::from(high) << 16 | u32::from(low)
+    }
+}
@@ -0,0 +1,114 @@
+use crate::Limit;
+use core::fmt;
+use osc_block_storage::BlockDeviceError;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The operation was aborted through its `CancelToken`.
+    Cancelled,
+    /// A mutating operation was attempted on a filesystem or device that is read-only.
+    WriteProtected,
+    Device(BlockDeviceError),
+    /// The device has gone away, and hasn't come back through the hook given to
+    /// `FATFileSystem::set_reopen_hook`.
+    DeviceGone,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    /// The operation needs a directory entry, which the root directory doesn't have.
+    RootDirectory,
+    AlreadyExists,
+    /// A directory can't be removed while it has entries other than "." and "..".
+    DirectoryNotEmpty,
+    /// A directory can't be moved into itself or one of its subdirectories.
+    MoveIntoItself,
+    /// A name can't be given to an entry, because it's empty, too long, or has
+    /// characters FAT doesn't allow.
+    InvalidName,
+    /// A caller-provided writer refused further output.
+    Output,
+    /// A cluster chain runs into a cluster marked as bad.
+    BadCluster,
+    /// There are no free clusters left.
+    NoSpace,
+    /// A write was attempted through a file that wasn't opened for writing.
+    NotOpenForWriting,
+    /// A write would take a file past `MAX_FILE_SIZE`, the largest size an entry can
+    /// record.
+    FileTooLarge,
+    /// The volume's structures don't make sense: the boot sector describes an
+    /// impossible layout, a chain leads out of the data region, or the volume is
+    /// bigger than the device holding it.
+    Corrupt,
+    /// A volume can't be laid out as asked: the device is too small or too big for
+    /// the FAT variant or cluster size, or its blocks are bigger than a sector can be.
+    InvalidGeometry,
+    /// A buffer lent to the filesystem is smaller than `BufferRequirements::min`.
+    BufferTooSmall,
+    /// Going on would go past one of the `Limits` the filesystem was mounted with.
+    LimitExceeded(Limit),
+    /// A saved `VolumeIndex` is malformed, or was made from a different volume.
+    InvalidIndex,
+    /// A read or write would go past the end of the region it's confined to, such as
+    /// the reserved sectors' payload.
+    OutOfRange,
+    /// A file on the host couldn't be read.
+    #[cfg(feature = "std")]
+    Host(std::io::ErrorKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "the operation was cancelled"),
+            Self::WriteProtected => write!(f, "the filesystem is read-only"),
+            Self::Device(err) => write!(f, "device error: {}", err),
+            Self::DeviceGone => write!(f, "the device has gone away"),
+            Self::NotFound => write!(f, "no such file or directory"),
+            Self::NotADirectory => write!(f, "not a directory"),
+            Self::IsADirectory => write!(f, "is a directory"),
+            Self::RootDirectory => write!(f, "not possible on the root directory"),
+            Self::AlreadyExists => write!(f, "already exists"),
+            Self::DirectoryNotEmpty => write!(f, "the directory isn't empty"),
+            Self::MoveIntoItself => write!(f, "a directory can't be moved into itself"),
+            Self::InvalidName => write!(f, "invalid name"),
+            Self::Output => write!(f, "failed to write output"),
+            Self::BadCluster => write!(f, "a cluster chain contains a bad cluster"),
+            Self::NoSpace => write!(f, "no space left on the filesystem"),
+            Self::NotOpenForWriting => write!(f, "the file isn't open for writing"),
+            Self::FileTooLarge => write!(f, "the file would be too large"),
+            Self::Corrupt => write!(f, "the filesystem is corrupt"),
+            Self::InvalidGeometry => write!(f, "the volume can't be laid out on the device"),
+            Self::BufferTooSmall => write!(f, "the buffer is smaller than a sector or block"),
+            Self::LimitExceeded(limit) => write!(f, "the {} limit was exceeded", limit),
+            Self::InvalidIndex => write!(f, "the index isn't one of this volume"),
+            Self::OutOfRange => write!(f, "past the end of the region"),
+            #[cfg(feature = "std")]
+            Self::Host(kind) => write!(f, "host error: {:?}", kind),
+        }
+    }
+}
+
+impl From<BlockDeviceError> for Error {
+    fn from(other: BlockDeviceError) -> Self {
+        match other {
+            BlockDeviceError::Gone => Self::DeviceGone,
+            other => Self::Device(other),
+        }
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Self::Output
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(other: std::io::Error) -> Self {
+        Self::Host(other.kind())
+    }
+}
+
+pub(crate) type Result<T> = core::result::Result<T, Error>;
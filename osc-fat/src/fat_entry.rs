@@ -0,0 +1,160 @@
+use crate::error::{Error, Result};
+use crate::support::{read_fat_value, BufferPool, FatReadPolicy, ReadBuffer, SharedDevice};
+use crate::{Cluster, FATFileSystem, FATGeometry, Variant};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::Cell;
+use core::cmp;
+use osc_block_storage::BlockDevice;
+
+/// What the FAT says about a cluster, whatever the FAT variant.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatEntry {
+    Free,
+    /// The cluster is followed by this one in its chain. This isn't checked to be a
+    /// data cluster, so that a damaged FAT can be seen as it is.
+    Next(Cluster),
+    Bad,
+    EndOfChain,
+    /// One of the values reserved by the specification, rather than a cluster or one
+    /// of the markers above.
+    Reserved(u32),
+}
+
+impl FatEntry {
+    /// Interprets a FAT entry, with the FAT12/16 bad cluster and end of chain values
+    /// widened to their FAT32 equivalents, as `read_fat_value` gives them.
+    pub(crate) fn from_value(value: u32, variant: Variant) -> Self {
+        let first_reserved = match variant {
+            Variant::Fat12 => 0xFF0,
+            Variant::Fat16 => 0xFFF0,
+            Variant::Fat32 => 0x0FFFFFF0,
+        };
+
+        match value {
+            0 => Self::Free,
+            0x0FFFFFF7 => Self::Bad,
+            value if value >= 0x0FFFFFF8 => Self::EndOfChain,
+            value if value == 1 || value >= first_reserved => Self::Reserved(value),
+            value => Self::Next(value),
+        }
+    }
+
+    /// The value that's written for the entry, which is narrowed for FAT12/16.
+    pub(crate) fn value(self) -> u32 {
+        match self {
+            Self::Free => 0,
+            Self::Next(cluster) => cluster,
+            Self::Bad => 0x0FFFFFF7,
+            Self::EndOfChain => 0x0FFFFFFF,
+            Self::Reserved(value) => value,
+        }
+    }
+}
+
+/// Reads the entries of a volume's FAT, on its own or alongside a mounted filesystem,
+/// keeping the sectors it loads around for the next read.
+pub struct FatTable {
+    buffer: ReadBuffer<'static>,
+    geo: FATGeometry,
+    fat_read_policy: FatReadPolicy,
+}
+
+impl FatTable {
+    /// Reads the FAT of the volume on `device` with the given geometry, e.g. from
+    /// `FATGeometry::read`, without mounting it.
+    pub fn new(device: Box<dyn BlockDevice>, geometry: FATGeometry) -> Self {
+        let buffer_size = cmp::max(geometry.sector_size_bytes(), device.block_size());
+        let buffer = BufferPool::default().take(usize::from(buffer_size));
+        let device = SharedDevice::new(device, Rc::new(Cell::new(0)));
+
+        Self {
+            buffer: ReadBuffer::pooled(device, buffer, geometry.sector_size_bytes()),
+            geo: geometry,
+            fat_read_policy: FatReadPolicy::default(),
+        }
+    }
+
+    /// Reads sectors of the FAT that can't be read from the first copy that can.
+    pub fn with_fat_mirror_fallback(mut self, fat_mirror_fallback: bool) -> Self {
+        self.fat_read_policy.mirror_fallback = fat_mirror_fallback;
+        self
+    }
+
+    /// Reads every copy of the FAT, going with what most of them say where they
+    /// disagree, as `MountOptions::fat_majority_vote` does.
+    pub fn with_fat_majority_vote(mut self, fat_majority_vote: bool) -> Self {
+        self.fat_read_policy.majority_vote = fat_majority_vote;
+        self
+    }
+
+    pub fn geometry(&self) -> FATGeometry {
+        self.geo
+    }
+
+    /// The entry for `cluster`, which can be any cluster the FAT has an entry for,
+    /// including the reserved clusters 0 and 1.
+    pub fn fat_entry(&mut self, cluster: Cluster) -> Result<FatEntry> {
+        if cluster > self.geo.cluster_count() + 1 {
+            return Err(Error::NotFound);
+        }
+
+        let value = read_fat_value(&mut self.buffer, &self.geo, self.fat_read_policy, cluster)?;
+
+        Ok(FatEntry::from_value(value, self.geo.variant()))
+    }
+}
+
+impl FATFileSystem {
+    pub fn geometry(&self) -> FATGeometry {
+        self.geo
+    }
+
+    /// A reader for the filesystem's FAT, which sees changes made through the
+    /// filesystem as they're made.
+    pub fn fat_table(&self) -> FatTable {
+        let buffer = self.buffer_pool.take(self.buffer_requirements().min);
+
+        FatTable {
+            buffer: ReadBuffer::pooled(self.device.clone(), buffer, self.geo.sector_size_bytes),
+            geo: self.geo,
+            fat_read_policy: self.options.fat_read_policy(),
+        }
+    }
+
+    /// The entry for `cluster` in the FAT, as `FatTable::fat_entry` gives it.
+    pub fn fat_entry(&self, cluster: Cluster) -> Result<FatEntry> {
+        self.fat_table().fat_entry(cluster)
+    }
+
+    /// Sets the entry for `cluster` in every copy of the FAT in use, which is only the
+    /// active one if FAT32's mirroring has been turned off. On FAT32 only the low 28
+    /// bits of the entry are set, and the reserved top four are left as they are.
+    /// Nothing else is changed, besides the free cluster count, so it's up to the
+    /// caller to keep chains and the directory entries that refer to them consistent.
+    pub fn set_fat_entry(&self, cluster: Cluster, entry: FatEntry) -> Result<()> {
+        if cluster > self.geo.cluster_count() + 1 {
+            return Err(Error::NotFound);
+        }
+
+        self.mark_dirty()?;
+
+        let previous = self.fat_entry(cluster)?;
+        self.write_fat_value(cluster, entry.value())?;
+
+        if let (Some(free_cluster_count), true) =
+            (self.free_cluster_count.get(), self.is_data_cluster(cluster))
+        {
+            let free_cluster_count = match (previous, entry) {
+                (FatEntry::Free, FatEntry::Free) => free_cluster_count,
+                (FatEntry::Free, _) => free_cluster_count.saturating_sub(1),
+                (_, FatEntry::Free) => free_cluster_count + 1,
+                _ => free_cluster_count,
+            };
+
+            self.free_cluster_count.set(Some(free_cluster_count));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+use crate::error::{Error, Result};
+use crate::locate::{EntryLocation, LocatedEntry};
+use crate::modify::{set_extent, stamp_modified};
+use crate::support::{ClusterChainIndex, ClusterWalker, FileReader, ReadBuffer};
+use crate::{Cluster, FATFileSystem, FatDateTime, Metadata, OpenOptions};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The largest a file can be whatever the FAT variant, as entries record sizes in 32
+/// bits.
+pub const MAX_FILE_SIZE: u32 = u32::MAX;
+
+/// An open file supporting reads (and writes, if opened for writing) at arbitrary
+/// offsets. Positions within the cluster chain are remembered as they're found, so
+/// random access into large files doesn't mean walking the chain from the start each
+/// time.
+pub struct FatFile<'a> {
+    fs: &'a FATFileSystem,
+    metadata: Metadata,
+    buffer: Vec<u8>,
+    chain_index: Option<ClusterChainIndex>,
+    position: u64,
+    /// Where the file's entry is, when it's open for writing.
+    location: Option<EntryLocation>,
+    append: bool,
+    /// The last cluster in the chain and its logical cluster number, once known.
+    last_cluster: Option<(u32, Cluster)>,
+    /// Whether the entry is behind the size and first cluster held here.
+    entry_dirty: bool,
+    /// The modification time to give the entry in place of the current time.
+    modified: Option<FatDateTime>,
+}
+
+impl FATFileSystem {
+    pub fn open_file(&self, path: &str) -> Result<FatFile<'_>> {
+        let metadata = self.lookup(path)?;
+
+        if metadata.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        Ok(FatFile::new(self, metadata))
+    }
+
+    pub fn open_file_with_options(&self, path: &str, options: &OpenOptions) -> Result<FatFile<'_>> {
+        if !options.is_writing() {
+            return self.open_file(path);
+        }
+
+        if self.read_only {
+            return Err(Error::WriteProtected);
+        }
+
+        let located = match self.locate(path) {
+            Err(Error::NotFound) if options.is_creating() => {
+                let mut file = self.create_file(path)?;
+                file.append = options.is_appending();
+                return Ok(file);
+            }
+            result => result?,
+        };
+
+        if located.metadata.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        let mut file = FatFile::for_writing(self, located, options.is_appending());
+
+        if options.is_truncating() {
+            file.set_len(0)?;
+        }
+
+        Ok(file)
+    }
+}
+
+impl<'a> FatFile<'a> {
+    pub(crate) fn new(fs: &'a FATFileSystem, metadata: Metadata) -> Self {
+        let chain_index = if !fs.is_data_cluster(metadata.first_cluster) {
+            None
+        } else {
+            Some(ClusterChainIndex::new(
+                metadata.first_cluster,
+                fs.max_chain_length(),
+            ))
+        };
+
+        Self {
+            fs,
+            buffer: vec![0u8; fs.buffer_requirements().recommended],
+            metadata,
+            chain_index,
+            position: 0,
+            location: None,
+            append: false,
+            last_cluster: None,
+            entry_dirty: false,
+            modified: None,
+        }
+    }
+
+    pub(crate) fn for_writing(fs: &'a FATFileSystem, located: LocatedEntry, append: bool) -> Self {
+        let mut file = Self::new(fs, located.metadata);
+        file.location = Some(located.location);
+        file.append = append;
+        file
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn size(&self) -> u64 {
+        u64::from(self.metadata.size)
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Moves to `position`, which may be past the end of the file (where reads
+    /// return nothing).
+    pub fn seek(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// Reads from the current position, advancing it by the number of bytes read.
+    pub fn read(&mut self, destination: &mut [u8]) -> Result<usize> {
+        let count = self.read_at(self.position, destination)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    /// Reads from `offset` without moving the current position, returning the number
+    /// of bytes read, which is only short at the end of the file.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "trace",
+            skip(self, destination),
+            fields(name = %self.metadata.name, length = destination.len())
+        )
+    )]
+    pub fn read_at(&mut self, offset: u64, destination: &mut [u8]) -> Result<usize> {
+        if offset >= self.size() || destination.is_empty() {
+            return Ok(0);
+        }
+
+        let remaining_bytes = (self.size() - offset) as u32;
+
+        let chain_index = match self.chain_index {
+            Some(ref mut chain_index) => chain_index,
+            None => return Ok(0),
+        };
+
+        let geo = self.fs.geo;
+        let sector_size_bytes = u64::from(geo.sector_size_bytes);
+        let cluster_size_bytes = self.fs.cluster_size_bytes() as u64;
+
+        let logical_cluster = (offset / cluster_size_bytes) as u32;
+        let cluster_offset = offset % cluster_size_bytes;
+
+        let mut buffer = ReadBuffer::new(
+            self.fs.device.clone(),
+            &mut self.buffer,
+            geo.sector_size_bytes,
+        );
+
+        let cluster = match chain_index.resolve(
+            &mut buffer,
+            &geo,
+            self.fs.options.fat_read_policy(),
+            logical_cluster,
+        )? {
+            Some(cluster) => cluster,
+            None => return Ok(0),
+        };
+
+        let mut cluster_walker = ClusterWalker::open_at(
+            buffer,
+            cluster,
+            (cluster_offset / sector_size_bytes) as u8,
+            geo,
+        )?;
+        cluster_walker.set_fat_read_policy(self.fs.options.fat_read_policy());
+        cluster_walker.set_max_chain_length(self.fs.max_chain_length());
+
+        let mut reader = FileReader::starting_at(
+            Some(cluster_walker),
+            (cluster_offset % sector_size_bytes) as usize,
+            remaining_bytes,
+        );
+
+        reader.read(destination)
+    }
+
+    /// Writes at the current position (or at the end of the file, when appending),
+    /// advancing it past what was written.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let offset = if self.append {
+            self.size()
+        } else {
+            self.position
+        };
+
+        let count = self.write_at(offset, data)?;
+        self.position = offset + count as u64;
+        Ok(count)
+    }
+
+    /// Writes all of `data` at `offset` without moving the current position, extending
+    /// the file if need be. Any gap between the old end of the file and `offset` reads
+    /// as zeros. The entry isn't brought up to date until `flush`.
+    pub fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<usize> {
+        if self.location.is_none() {
+            return Err(Error::NotOpenForWriting);
+        }
+
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        match offset.checked_add(data.len() as u64) {
+            Some(end) if end <= u64::from(MAX_FILE_SIZE) => {}
+            _ => return Err(Error::FileTooLarge),
+        }
+
+        self.fs.mark_dirty()?;
+
+        let size = self.size();
+
+        if offset > size {
+            self.write_range(size, (offset - size) as usize, None)?;
+        }
+
+        self.write_range(offset, data.len(), Some(data))?;
+
+        Ok(data.len())
+    }
+
+    /// Truncates or extends the file to `size` bytes, with any new part reading as
+    /// zeros, and brings the entry up to date.
+    pub fn set_len(&mut self, size: u32) -> Result<()> {
+        let location = self.location.ok_or(Error::NotOpenForWriting)?;
+
+        let first_cluster = self.fs.resize(location, &self.metadata, size, true)?;
+
+        self.metadata.size = size;
+        self.metadata.first_cluster = first_cluster;
+        self.chain_index = if self.fs.is_data_cluster(first_cluster) {
+            Some(ClusterChainIndex::new(
+                first_cluster,
+                self.fs.max_chain_length(),
+            ))
+        } else {
+            None
+        };
+        self.last_cluster = None;
+        self.entry_dirty = false;
+
+        Ok(())
+    }
+
+    /// Gives the entry `modified` as its modification time when it's next brought up to
+    /// date, rather than the time then.
+    pub fn set_modified(&mut self, modified: FatDateTime) -> Result<()> {
+        if self.location.is_none() {
+            return Err(Error::NotOpenForWriting);
+        }
+
+        self.modified = Some(modified);
+        self.entry_dirty = true;
+
+        Ok(())
+    }
+
+    /// Brings the file's entry up to date with what's been written, then flushes the
+    /// device.
+    pub fn flush(&mut self) -> Result<()> {
+        if let (true, Some(location)) = (self.entry_dirty, self.location) {
+            let first_cluster = self.metadata.first_cluster;
+            let size = self.metadata.size;
+            let now = self.modified.unwrap_or_else(|| self.fs.now());
+
+            self.fs.update_entry(location, |entry| {
+                set_extent(entry, first_cluster, size);
+                stamp_modified(entry, now);
+            })?;
+
+            self.entry_dirty = false;
+        }
+
+        self.fs.flush()
+    }
+
+    /// Writes `length` bytes at `offset` from `data`, or zeros if there's no data,
+    /// allocating clusters as needed.
+    fn write_range(&mut self, offset: u64, length: usize, data: Option<&[u8]>) -> Result<()> {
+        let cluster_size = self.fs.cluster_size_bytes();
+        let mut written = 0;
+
+        while written < length {
+            let position = offset + written as u64;
+            let cluster_offset = (position % cluster_size as u64) as usize;
+            let count = core::cmp::min(length - written, cluster_size - cluster_offset);
+
+            let cluster = self.cluster_for_write((position / cluster_size as u64) as u32)?;
+
+            self.fs.write_cluster_range(
+                cluster,
+                cluster_offset,
+                cluster_offset + count,
+                data.map(|data| &data[written..(written + count)]),
+            )?;
+
+            written += count;
+
+            let end = (position + count as u64) as u32;
+
+            if end > self.metadata.size {
+                self.metadata.size = end;
+            }
+        }
+
+        self.entry_dirty = true;
+
+        Ok(())
+    }
+
+    /// Finds the cluster holding logical cluster `logical`, extending the chain up to
+    /// it if it's past the end.
+    fn cluster_for_write(&mut self, logical: u32) -> Result<Cluster> {
+        let (mut last_logical, mut last_cluster) = match self.last_cluster {
+            Some(last) => last,
+            None => self.find_last_cluster()?,
+        };
+
+        if logical < last_logical {
+            let chain_index = self.chain_index.as_mut().ok_or(Error::NotFound)?;
+
+            let mut buffer = ReadBuffer::new(
+                self.fs.device.clone(),
+                &mut self.buffer,
+                self.fs.geo.sector_size_bytes,
+            );
+
+            // NOTE: the chain reaches `last_logical`, so this only fails if the FAT has
+            // been changed from under the file
+            return chain_index
+                .resolve(
+                    &mut buffer,
+                    &self.fs.geo,
+                    self.fs.options.fat_read_policy(),
+                    logical,
+                )?
+                .ok_or(Error::NotFound);
+        }
+
+        while last_logical < logical {
+            last_cluster = self.fs.allocate_cluster(Some(last_cluster))?;
+            last_logical += 1;
+        }
+
+        self.last_cluster = Some((last_logical, last_cluster));
+
+        Ok(last_cluster)
+    }
+
+    /// Walks to the end of the chain, or starts one if the file has no clusters yet.
+    fn find_last_cluster(&mut self) -> Result<(u32, Cluster)> {
+        match self.chain_index {
+            Some(ref mut chain_index) => {
+                let mut buffer = ReadBuffer::new(
+                    self.fs.device.clone(),
+                    &mut self.buffer,
+                    self.fs.geo.sector_size_bytes,
+                );
+
+                chain_index.last(&mut buffer, &self.fs.geo, self.fs.options.fat_read_policy())
+            }
+            None => {
+                let cluster = self.fs.allocate_cluster(None)?;
+
+                self.metadata.first_cluster = cluster;
+                self.chain_index =
+                    Some(ClusterChainIndex::new(cluster, self.fs.max_chain_length()));
+                self.entry_dirty = true;
+
+                Ok((0, cluster))
+            }
+        }
+    }
+}
+
+impl Drop for FatFile<'_> {
+    fn drop(&mut self) {
+        // NOTE: errors can't be reported from here, so anything that cares whether the
+        // entry made it to the device should call flush itself
+        if self.entry_dirty {
+            let _ = self.flush();
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::error::Result;
+use crate::{Attributes, DirectoryEntryView, DirectoryWalker, StandardDirectoryEntry};
+
+/// A directory walk that only hands over the entries that pass its filters, made with
+/// `DirectoryWalker::files_only` and the like, which can be chained. Entries are
+/// turned down by what their short entries say wherever they can be, before their
+/// long names are checked or anything is handed over.
+pub struct FilteredWalker<'a, 'e> {
+    walker: DirectoryWalker<'a>,
+    required: Attributes,
+    excluded: Attributes,
+    extension: Option<&'e str>,
+    larger_than: Option<u32>,
+}
+
+impl<'a> DirectoryWalker<'a> {
+    /// Only files, leaving out directories and the volume label.
+    pub fn files_only(self) -> FilteredWalker<'a, 'static> {
+        FilteredWalker::new(self).files_only()
+    }
+
+    /// Only directories, including the "." and ".." entries.
+    pub fn directories_only(self) -> FilteredWalker<'a, 'static> {
+        FilteredWalker::new(self).directories_only()
+    }
+
+    /// Only entries with all of `attributes`.
+    pub fn with_attributes(self, attributes: Attributes) -> FilteredWalker<'a, 'static> {
+        FilteredWalker::new(self).with_attributes(attributes)
+    }
+
+    /// Only entries whose names end in `.` and `extension`, ignoring the case of ASCII
+    /// letters. The empty extension is that of names without a `.`.
+    pub fn with_extension(self, extension: &str) -> FilteredWalker<'a, '_> {
+        FilteredWalker::new(self).with_extension(extension)
+    }
+
+    /// Only entries bigger than `size` bytes, which directories never are.
+    pub fn larger_than(self, size: u32) -> FilteredWalker<'a, 'static> {
+        FilteredWalker::new(self).larger_than(size)
+    }
+}
+
+impl<'a, 'e> FilteredWalker<'a, 'e> {
+    fn new(walker: DirectoryWalker<'a>) -> Self {
+        Self {
+            walker,
+            required: Attributes::default(),
+            excluded: Attributes::default(),
+            extension: None,
+            larger_than: None,
+        }
+    }
+
+    pub fn files_only(mut self) -> Self {
+        self.excluded.insert(Attributes::DIRECTORY);
+        self.excluded.insert(Attributes::VOLUME_ID);
+        self
+    }
+
+    pub fn directories_only(mut self) -> Self {
+        self.required.insert(Attributes::DIRECTORY);
+        self.excluded.insert(Attributes::VOLUME_ID);
+        self
+    }
+
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.required.insert(attributes);
+        self
+    }
+
+    pub fn with_extension(mut self, extension: &'e str) -> Self {
+        self.extension = Some(extension);
+        self
+    }
+
+    pub fn larger_than(mut self, size: u32) -> Self {
+        self.larger_than = Some(
+            self.larger_than
+                .map_or(size, |larger_than| larger_than.max(size)),
+        );
+        self
+    }
+
+    /// Like `DirectoryWalker::enumerate_entry_views`, with only the entries that pass
+    /// the filters.
+    pub fn enumerate_entry_views<F>(self, mut func: F) -> Result<()>
+    where
+        F: FnMut(&DirectoryEntryView<'_>),
+    {
+        let Self {
+            walker,
+            required,
+            excluded,
+            extension,
+            larger_than,
+        } = self;
+
+        let admits = |entry: &StandardDirectoryEntry<'_>| {
+            let attributes = entry.attributes();
+
+            if !attributes.contains(required) || attributes.bits() & excluded.bits() != 0 {
+                return false;
+            }
+
+            if matches!(larger_than, Some(larger_than) if entry.size() <= larger_than) {
+                return false;
+            }
+
+            match extension {
+                Some(extension) => short_extension_admits(entry, extension),
+                None => true,
+            }
+        };
+
+        walker.enumerate_entry_views_where(admits, |view| match extension {
+            Some(extension) if !has_extension(view, extension) => {}
+            _ => func(view),
+        })
+    }
+}
+
+/// Whether an entry with the extension `extension` could have the short entry `entry`,
+/// whose extension is made from the first three characters of the long name's, in
+/// upper case. Extensions starting with characters that short names leave out or
+/// replace aren't ruled out.
+fn short_extension_admits(entry: &StandardDirectoryEntry<'_>, extension: &str) -> bool {
+    let stored = entry.ext();
+    let stored_len = stored
+        .iter()
+        .rposition(|&b| b != b' ')
+        .map_or(0, |end| end + 1);
+    let expected = extension.as_bytes().iter().take(3);
+
+    if !expected.clone().all(u8::is_ascii_alphanumeric) {
+        return true;
+    }
+
+    expected.len() == stored_len
+        && expected
+            .zip(stored)
+            .all(|(expected, stored)| expected.eq_ignore_ascii_case(stored))
+}
+
+/// Whether the entry's name, its long name if it has one, ends in `.` and `extension`.
+fn has_extension(view: &DirectoryEntryView<'_>, extension: &str) -> bool {
+    let long_name = match view.long_name_utf16() {
+        Some(long_name) => long_name,
+        None => {
+            let stored = view.entry().ext();
+            let stored_len = stored
+                .iter()
+                .rposition(|&b| b != b' ')
+                .map_or(0, |end| end + 1);
+
+            return stored[..stored_len].eq_ignore_ascii_case(extension.as_bytes());
+        }
+    };
+
+    // NOTE: a name starting with its only '.', such as ".profile", has no extension
+    let name_extension = match long_name.iter().rposition(|&c| c == u16::from(b'.')) {
+        Some(dot) if dot > 0 => &long_name[(dot + 1)..],
+        _ => &[],
+    };
+
+    let mut name_extension = core::char::decode_utf16(name_extension.iter().copied());
+    let mut extension = extension.chars();
+
+    loop {
+        match (name_extension.next(), extension.next()) {
+            (None, None) => return true,
+            (Some(Ok(a)), Some(b)) if a.eq_ignore_ascii_case(&b) => {}
+            _ => return false,
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::names::NO_LABEL;
+use crate::prim::{
+    root_dir_sector_count, CommonBiosParameterBlock as Common,
+    ExtendedBiosParameterBlock as Extended, ExtendedFat32BiosParameterBlock as Extended32,
+    FileSystemInfo,
+};
+use crate::support::{write_sector, DataStructureMut};
+use crate::{Attributes, FATFileSystem, Variant};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp;
+use osc_block_storage::{BlockDevice, BlockDeviceError};
+
+/// How `FATFileSystem::format` lays out a new volume.
+#[derive(Debug, Copy, Clone)]
+pub struct FormatOptions {
+    /// The FAT variant, or `None` to pick one by the size of the device: FAT12 below
+    /// 16 MiB, FAT16 below 512 MiB and FAT32 from there.
+    pub variant: Option<Variant>,
+
+    /// Sectors per cluster, or `None` for the usual size for the variant and the size
+    /// of the device. Clusters can be at most 32 KiB.
+    pub cluster_size_sectors: Option<u8>,
+
+    /// The volume label, padded with spaces. `NO NAME` is the label of a volume
+    /// without one.
+    pub volume_label: [u8; 11],
+
+    pub volume_serial: u32,
+
+    /// How many sectors there are before the volume on its disk, which is the first
+    /// sector of its partition, and which some boot code needs.
+    pub hidden_sectors: u32,
+
+    /// Formats as much of a device that's bigger than a volume can be (2 TiB with
+    /// 512-byte sectors) as a volume can take, rather than refusing it with
+    /// `Error::InvalidGeometry`. The rest of the device is left as it is.
+    pub cap_to_addressable: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            variant: None,
+            cluster_size_sectors: None,
+            volume_label: NO_LABEL,
+            volume_serial: 0,
+            hidden_sectors: 0,
+            cap_to_addressable: false,
+        }
+    }
+}
+
+/// Where everything goes on a volume about to be formatted.
+struct Plan {
+    variant: Variant,
+    sector_size_bytes: u16,
+    total_sectors: u32,
+    cluster_size_sectors: u8,
+    reserved_sectors: u16,
+    root_entry_count: u16,
+    root_dir_sectors: u32,
+    sectors_per_fat: u32,
+    cluster_count: u32,
+}
+
+impl Plan {
+    const FAT_COUNT: u8 = 2;
+    const MEDIA: u8 = 0xF8;
+
+    // FAT32 only
+    const ROOT_CLUSTER: u32 = 2;
+    const FS_INFO_SECTOR: u16 = 1;
+    const BACKUP_BOOT_SECTOR: u16 = 6;
+
+    fn new(device: &dyn BlockDevice, options: &FormatOptions) -> Result<Self> {
+        let block_size = device.block_size();
+        let sector_size_bytes = cmp::max(512, block_size);
+
+        if !block_size.is_power_of_two() || sector_size_bytes > 4096 {
+            return Err(Error::InvalidGeometry);
+        }
+
+        let device_sectors = device
+            .block_count()
+            .ok_or(Error::InvalidGeometry)?
+            .saturating_mul(u64::from(block_size))
+            / u64::from(sector_size_bytes);
+
+        // NOTE: the boot sector can only record a 32-bit sector count
+        let total_sectors = match device_sectors {
+            sectors if sectors <= u64::from(u32::MAX) => sectors as u32,
+            _ if options.cap_to_addressable => u32::MAX,
+            _ => return Err(Error::InvalidGeometry),
+        };
+
+        let device_bytes = u64::from(total_sectors) * u64::from(sector_size_bytes);
+
+        let variant = options.variant.unwrap_or(match device_bytes {
+            bytes if bytes < 16 << 20 => Variant::Fat12,
+            bytes if bytes < 512 << 20 => Variant::Fat16,
+            _ => Variant::Fat32,
+        });
+
+        let (reserved_sectors, root_entry_count) = match variant {
+            Variant::Fat12 | Variant::Fat16 => (1, 512),
+            Variant::Fat32 => (32, 0),
+        };
+
+        let mut plan = Self {
+            variant,
+            sector_size_bytes,
+            total_sectors,
+            cluster_size_sectors: 0,
+            reserved_sectors,
+            root_entry_count,
+            root_dir_sectors: root_dir_sector_count(u32::from(root_entry_count), sector_size_bytes),
+            sectors_per_fat: 0,
+            cluster_count: 0,
+        };
+
+        // NOTE: bigger clusters let FAT16 go past 2 GiB, which most drivers can't mount
+        let max_cluster_size_sectors = (32768 / usize::from(sector_size_bytes)) as u8;
+
+        if let Some(cluster_size_sectors) = options.cluster_size_sectors {
+            if !cluster_size_sectors.is_power_of_two()
+                || cluster_size_sectors > max_cluster_size_sectors
+                || !plan.fit(cluster_size_sectors)
+            {
+                return Err(Error::InvalidGeometry);
+            }
+
+            return Ok(plan);
+        }
+
+        // Take the smallest clusters that don't give FAT12/16 more clusters than they can
+        // have, and for FAT32 the usual size for the volume size, or smaller if that
+        // leaves too few clusters
+        let usual_cluster_size_bytes: u64 = match (variant, device_bytes) {
+            (Variant::Fat12, _) | (Variant::Fat16, _) => 512,
+            (Variant::Fat32, bytes) if bytes <= 260 << 20 => 512,
+            (Variant::Fat32, bytes) if bytes <= 8 << 30 => 4096,
+            (Variant::Fat32, bytes) if bytes <= 16 << 30 => 8192,
+            (Variant::Fat32, bytes) if bytes <= 32 << 30 => 16384,
+            (Variant::Fat32, _) => 32768,
+        };
+
+        let mut cluster_size_sectors =
+            cmp::max(1, usual_cluster_size_bytes / u64::from(sector_size_bytes)) as u8;
+
+        loop {
+            if plan.fit(cluster_size_sectors) {
+                return Ok(plan);
+            }
+
+            cluster_size_sectors = match variant {
+                Variant::Fat12 | Variant::Fat16
+                    if plan.cluster_count > variant.max_cluster_count()
+                        && cluster_size_sectors < max_cluster_size_sectors =>
+                {
+                    cluster_size_sectors * 2
+                }
+                Variant::Fat32
+                    if plan.cluster_count < variant.min_cluster_count()
+                        && cluster_size_sectors > 1 =>
+                {
+                    cluster_size_sectors / 2
+                }
+                _ => return Err(Error::InvalidGeometry),
+            };
+        }
+    }
+
+    /// Lays out the FATs for clusters of `cluster_size_sectors`, saying whether that
+    /// gives the number of clusters the variant needs.
+    fn fit(&mut self, cluster_size_sectors: u8) -> bool {
+        let sector_size_bytes = u64::from(self.sector_size_bytes);
+        let fixed_sectors = u64::from(self.reserved_sectors) + u64::from(self.root_dir_sectors);
+
+        self.cluster_size_sectors = cluster_size_sectors;
+        self.sectors_per_fat = 1;
+        self.cluster_count = 0;
+
+        // NOTE: the FAT takes space from the data region, so needs fewer sectors the
+        // more it has, which this settles on in a few rounds
+        loop {
+            let meta_sectors =
+                fixed_sectors + u64::from(Self::FAT_COUNT) * u64::from(self.sectors_per_fat);
+
+            if meta_sectors >= u64::from(self.total_sectors) {
+                return false;
+            }
+
+            let data_sectors = u64::from(self.total_sectors) - meta_sectors;
+            let cluster_count = data_sectors / u64::from(cluster_size_sectors);
+
+            let fat_bytes = match self.variant {
+                Variant::Fat12 => (cluster_count + 2) * 3 / 2 + 1,
+                Variant::Fat16 => (cluster_count + 2) * 2,
+                Variant::Fat32 => (cluster_count + 2) * 4,
+            };
+
+            let sectors_per_fat = fat_bytes.div_ceiling(sector_size_bytes);
+
+            if sectors_per_fat <= u64::from(self.sectors_per_fat) {
+                self.cluster_count = cluster_count as u32;
+                break;
+            }
+
+            self.sectors_per_fat = sectors_per_fat as u32;
+        }
+
+        // NOTE: the variant of a volume is decided by its cluster count when it's
+        // mounted, so the count has to be in the variant's range
+        (self.variant.min_cluster_count()..=self.variant.max_cluster_count())
+            .contains(&self.cluster_count)
+            && (self.variant == Variant::Fat32 || self.sectors_per_fat <= 0xFFFF)
+    }
+
+    fn first_fat_sector(&self) -> u64 {
+        u64::from(self.reserved_sectors)
+    }
+
+    fn first_root_dir_sector(&self) -> u64 {
+        self.first_fat_sector() + u64::from(Self::FAT_COUNT) * u64::from(self.sectors_per_fat)
+    }
+
+    fn first_data_sector(&self) -> u64 {
+        self.first_root_dir_sector() + u64::from(self.root_dir_sectors)
+    }
+
+    fn boot_sector(&self, options: &FormatOptions) -> Vec<u8> {
+        let mut sector = vec![0u8; usize::from(self.sector_size_bytes)];
+
+        let jump = match self.variant {
+            Variant::Fat12 | Variant::Fat16 => [0xEB, 0x3C, 0x90],
+            Variant::Fat32 => [0xEB, 0x58, 0x90],
+        };
+
+        sector[Common::RANGE_JUMP].copy_from_slice(&jump);
+        sector[Common::RANGE_OEM].copy_from_slice(b"MSWIN4.1");
+        sector.set_u16(Common::RANGE_BYTES_PER_SECTOR, self.sector_size_bytes);
+        sector[Common::RANGE_SECTORS_PER_CLUSTER.start] = self.cluster_size_sectors;
+        sector.set_u16(Common::RANGE_RESERVED_SECTOR_COUNT, self.reserved_sectors);
+        sector[Common::RANGE_NUM_FATS.start] = Self::FAT_COUNT;
+        sector.set_u16(Common::RANGE_ROOT_ENTRY_COUNT, self.root_entry_count);
+        sector[Common::RANGE_MEDIA.start] = Self::MEDIA;
+        sector.set_u16(Common::RANGE_SECTORS_PER_TRACK, 63);
+        sector.set_u16(Common::RANGE_NUM_HEADS, 255);
+        sector.set_u32(Common::RANGE_HIDDEN_SECTORS, options.hidden_sectors);
+
+        match self.total_sectors {
+            total_sectors if total_sectors <= 0xFFFF && self.variant != Variant::Fat32 => {
+                sector.set_u16(Common::RANGE_TOTAL_SECTORS_16, total_sectors as u16)
+            }
+            total_sectors => sector.set_u32(Common::RANGE_TOTAL_SECTORS_32, total_sectors),
+        }
+
+        match self.variant {
+            Variant::Fat12 | Variant::Fat16 => {
+                let fs_type = match self.variant {
+                    Variant::Fat12 => b"FAT12   ",
+                    _ => b"FAT16   ",
+                };
+
+                sector.set_u16(
+                    Common::RANGE_SECTORS_PER_FAT_16,
+                    self.sectors_per_fat as u16,
+                );
+                sector[Extended::RANGE_DRIVE_NUM.start] = 0x80;
+                sector[Extended::RANGE_BOOT_SIG.start] = 0x29;
+                sector.set_u32(Extended::RANGE_VOL_ID, options.volume_serial);
+                sector[Extended::RANGE_VOL_LAB].copy_from_slice(&options.volume_label);
+                sector[Extended::RANGE_FS_TYPE].copy_from_slice(fs_type);
+            }
+
+            Variant::Fat32 => {
+                sector.set_u32(Extended32::RANGE_SECTORS_PER_FAT_32, self.sectors_per_fat);
+                sector.set_u32(Extended32::RANGE_ROOT_CLUSTER, Self::ROOT_CLUSTER);
+                sector.set_u16(Extended32::RANGE_FS_INFO_SECTOR, Self::FS_INFO_SECTOR);
+                sector.set_u16(
+                    Extended32::RANGE_BACKUP_BOOT_SECTOR,
+                    Self::BACKUP_BOOT_SECTOR,
+                );
+                sector[Extended32::RANGE_DRIVE_NUM.start] = 0x80;
+                sector[Extended32::RANGE_BOOT_SIG.start] = 0x29;
+                sector.set_u32(Extended32::RANGE_VOL_ID, options.volume_serial);
+                sector[Extended32::RANGE_VOL_LAB].copy_from_slice(&options.volume_label);
+                sector[Extended32::RANGE_FS_TYPE].copy_from_slice(b"FAT32   ");
+            }
+        }
+
+        // NOTE: the signature is at the same place whatever the sector size
+        sector[Extended::RANGE_SIG_WORD].copy_from_slice(&[0x55, 0xAA]);
+        sector
+    }
+
+    /// The first sector of each FAT, which has the entries for the reserved clusters,
+    /// and on FAT32 the end of the root directory's chain.
+    fn first_fat_sector_data(&self) -> Vec<u8> {
+        let mut sector = vec![0u8; usize::from(self.sector_size_bytes)];
+
+        // NOTE: the clean shutdown and no error bits of cluster 1's entry are set
+        let entries: &[u8] = match self.variant {
+            Variant::Fat12 => &[Self::MEDIA, 0xFF, 0xFF],
+            Variant::Fat16 => &[Self::MEDIA, 0xFF, 0xFF, 0xFF],
+            Variant::Fat32 => &[
+                Self::MEDIA,
+                0xFF,
+                0xFF,
+                0x0F,
+                0xFF,
+                0xFF,
+                0xFF,
+                0x0F,
+                0xFF,
+                0xFF,
+                0xFF,
+                0x0F,
+            ],
+        };
+
+        sector[..entries.len()].copy_from_slice(entries);
+        sector
+    }
+}
+
+impl FATFileSystem {
+    /// Writes a new, empty volume over the whole of `device`, and mounts it. Anything
+    /// the device held is lost, as the FATs and root directory are cleared; the rest of
+    /// the data region is left as it was.
+    pub fn format(mut device: Box<dyn BlockDevice>, options: &FormatOptions) -> Result<Self> {
+        if device.is_read_only() {
+            return Err(Error::WriteProtected);
+        }
+
+        let plan = Plan::new(&*device, options)?;
+        let sector_size_bytes = plan.sector_size_bytes;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            variant = ?plan.variant,
+            cluster_count = plan.cluster_count,
+            cluster_size_sectors = plan.cluster_size_sectors,
+            "formatting"
+        );
+
+        let root_dir_sectors = match plan.variant {
+            Variant::Fat12 | Variant::Fat16 => u64::from(plan.root_dir_sectors),
+            Variant::Fat32 => u64::from(plan.cluster_size_sectors),
+        };
+
+        // The boot sector goes last, so that a volume whose formatting fails part way
+        // can't be mounted
+        zero_sectors(&mut *device, sector_size_bytes, 0, plan.first_data_sector())?;
+        zero_sectors(
+            &mut *device,
+            sector_size_bytes,
+            plan.first_data_sector(),
+            root_dir_sectors,
+        )?;
+
+        let fat_sector = plan.first_fat_sector_data();
+
+        for fat_index in 0..Plan::FAT_COUNT {
+            let sector_index =
+                plan.first_fat_sector() + u64::from(fat_index) * u64::from(plan.sectors_per_fat);
+            write_sector(&mut *device, sector_size_bytes, sector_index, &fat_sector)?;
+        }
+
+        if options.volume_label != NO_LABEL {
+            let mut sector = vec![0u8; usize::from(sector_size_bytes)];
+            sector[..11].copy_from_slice(&options.volume_label);
+            sector[11] = Attributes::VOLUME_ID.bits();
+
+            let root_sector = match plan.variant {
+                Variant::Fat12 | Variant::Fat16 => plan.first_root_dir_sector(),
+                Variant::Fat32 => plan.first_data_sector(),
+            };
+
+            write_sector(&mut *device, sector_size_bytes, root_sector, &sector)?;
+        }
+
+        let boot_sector = plan.boot_sector(options);
+
+        if plan.variant == Variant::Fat32 {
+            let mut sector = vec![0u8; usize::from(sector_size_bytes)];
+            let mut fs_info = FileSystemInfo::from(&mut sector[..]);
+
+            fs_info.initialize();
+            // NOTE: the root directory has the first cluster
+            fs_info.set_free_count(plan.cluster_count - 1);
+            fs_info.set_next_free(Plan::ROOT_CLUSTER + 1);
+
+            for sector_index in [0, u64::from(Plan::BACKUP_BOOT_SECTOR)].iter() {
+                write_sector(
+                    &mut *device,
+                    sector_size_bytes,
+                    sector_index + u64::from(Plan::FS_INFO_SECTOR),
+                    &sector,
+                )?;
+            }
+
+            write_sector(
+                &mut *device,
+                sector_size_bytes,
+                u64::from(Plan::BACKUP_BOOT_SECTOR),
+                &boot_sector,
+            )?;
+        }
+
+        write_sector(&mut *device, sector_size_bytes, 0, &boot_sector)?;
+        device.flush()?;
+
+        Self::open(device)
+    }
+}
+
+/// Writes zeros over `sector_count` sectors from `first_sector`, a run at a time.
+fn zero_sectors(
+    device: &mut dyn BlockDevice,
+    sector_size_bytes: u16,
+    first_sector: u64,
+    sector_count: u64,
+) -> Result<()> {
+    const RUN_BYTES: u64 = 64 * 1024;
+
+    let block_size = u64::from(device.block_size());
+    let sector_size_bytes = u64::from(sector_size_bytes);
+    let zeros = vec![0u8; RUN_BYTES as usize];
+
+    let mut block = first_sector * sector_size_bytes / block_size;
+    let end_block = (first_sector + sector_count) * sector_size_bytes / block_size;
+
+    while block < end_block {
+        let run_blocks = cmp::min(end_block - block, RUN_BYTES / block_size);
+        let written = device.write_blocks(block, &zeros[..(run_blocks * block_size) as usize])?;
+
+        if written < run_blocks {
+            return Err(BlockDeviceError::Io.into());
+        }
+
+        block += run_blocks;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryDevice;
+
+    /// Formats a device with blocks of `block_size` bytes, and opens it again.
+    fn format_and_reopen(block_size: u16) {
+        let device = MemoryDevice::new(block_size, 8 * 1024 * 1024 / u64::from(block_size));
+
+        let fs =
+            FATFileSystem::format(Box::new(device.clone()), &FormatOptions::default()).unwrap();
+        assert_eq!(fs.geometry().sector_size_bytes(), block_size);
+        drop(fs);
+
+        let fs = FATFileSystem::open(Box::new(device)).unwrap();
+        assert_eq!(fs.geometry().sector_size_bytes(), block_size);
+        assert!(fs.root_dir().list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn formats_512_byte_blocks() {
+        format_and_reopen(512);
+    }
+
+    #[test]
+    fn formats_1024_byte_blocks() {
+        format_and_reopen(1024);
+    }
+
+    #[test]
+    fn formats_4096_byte_blocks() {
+        format_and_reopen(4096);
+    }
+}
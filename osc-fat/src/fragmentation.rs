@@ -0,0 +1,112 @@
+use crate::error::Result;
+use crate::prim::FileAllocationTable32Result;
+use crate::{Cluster, DirectorySelector, FATFileSystem};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileFragmentation {
+    pub path: String,
+    pub cluster_count: u32,
+    /// How many runs of contiguous clusters the file is in, which is 1 for a file that
+    /// isn't fragmented and 0 for an empty one.
+    pub extent_count: u32,
+}
+
+impl FileFragmentation {
+    pub fn is_fragmented(&self) -> bool {
+        self.extent_count > 1
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FragmentationReport {
+    /// Every file on the volume, in the order `walk_tree` visits them.
+    pub files: Vec<FileFragmentation>,
+}
+
+impl FragmentationReport {
+    pub fn fragmented_file_count(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.is_fragmented())
+            .count()
+    }
+
+    /// How fragmented the volume is, from 0 when every file is in one piece to 100
+    /// when no file has two clusters in a row: the percentage of steps from one
+    /// cluster of a file to the next that aren't to the cluster after it.
+    pub fn score(&self) -> u8 {
+        let (steps, breaks) = self
+            .files
+            .iter()
+            .fold((0u64, 0u64), |(steps, breaks), file| {
+                (
+                    steps + u64::from(file.cluster_count.saturating_sub(1)),
+                    breaks + u64::from(file.extent_count.saturating_sub(1)),
+                )
+            });
+
+        (breaks * 100)
+            .checked_div(steps)
+            .map_or(0, |score| score as u8)
+    }
+}
+
+impl FATFileSystem {
+    /// Measures how fragmented each file on the volume is, to help decide whether
+    /// defragmenting it is worthwhile.
+    pub fn fragmentation(&self) -> Result<FragmentationReport> {
+        let mut report = FragmentationReport::default();
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+
+        self.walk_tree(DirectorySelector::Root, |path, item| {
+            if !item.is_directory() {
+                let (cluster_count, extent_count) =
+                    self.measure_chain(&mut buffer, item.first_cluster)?;
+
+                report.files.push(FileFragmentation {
+                    path: String::from(path),
+                    cluster_count,
+                    extent_count,
+                });
+            }
+
+            Ok(())
+        })?;
+
+        Ok(report)
+    }
+
+    /// Counts the clusters in a chain, and the runs of contiguous clusters they're in.
+    pub(crate) fn measure_chain(
+        &self,
+        buffer: &mut [u8],
+        first_cluster: Cluster,
+    ) -> Result<(u32, u32)> {
+        let mut fat = self.fat_reader(buffer);
+        let mut cluster = first_cluster;
+        let mut previous: Option<Cluster> = None;
+        let mut cluster_count = 0;
+        let mut extent_count = 0;
+
+        // NOTE: a corrupt chain that loops back on itself is only followed for as many
+        // clusters as the volume has
+        while self.is_data_cluster(cluster) && cluster_count < self.geo.cluster_count {
+            if previous.map(|previous| previous + 1) != Some(cluster) {
+                extent_count += 1;
+            }
+
+            cluster_count += 1;
+            previous = Some(cluster);
+
+            cluster = match FileAllocationTable32Result::from(fat.read(cluster)?) {
+                FileAllocationTable32Result::NextClusterIndex(next) => next,
+                _ => break,
+            };
+        }
+
+        Ok((cluster_count, extent_count))
+    }
+}
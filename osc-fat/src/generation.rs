@@ -0,0 +1,83 @@
+use crate::{Cluster, DirectorySelector, FATFileSystem, RootDirectory};
+use alloc::collections::BTreeMap;
+use core::cmp;
+
+/// When each directory's entries were last changed, as numbers from a count that's
+/// bumped with every change, so no two changes have the same one.
+#[derive(Default)]
+pub(crate) struct DirectoryGenerations {
+    latest: u64,
+    /// What every directory's generation is at least, which is moved on whenever any of
+    /// them could have been changed unseen, i.e. the filesystem's been refreshed or its
+    /// device reopened.
+    floor: u64,
+    /// How many times the device had been reopened when it was last looked at.
+    reopen_count: u64,
+    /// Keyed by the directory's first cluster, or 0 for the root.
+    changed: BTreeMap<Cluster, u64>,
+}
+
+impl DirectoryGenerations {
+    fn get(&mut self, directory: Cluster, reopen_count: u64) -> u64 {
+        self.check_reopen_count(reopen_count);
+
+        let changed = self.changed.get(&directory).copied().unwrap_or(0);
+        cmp::max(changed, self.floor)
+    }
+
+    fn bump(&mut self, directory: Cluster, reopen_count: u64) {
+        self.check_reopen_count(reopen_count);
+
+        self.latest += 1;
+        self.changed.insert(directory, self.latest);
+    }
+
+    pub(crate) fn bump_all(&mut self) {
+        self.latest += 1;
+        self.floor = self.latest;
+        self.changed.clear();
+    }
+
+    fn check_reopen_count(&mut self, reopen_count: u64) {
+        if reopen_count != self.reopen_count {
+            self.reopen_count = reopen_count;
+            self.bump_all();
+        }
+    }
+}
+
+impl FATFileSystem {
+    /// A number that changes whenever the entries of `directory` are changed through
+    /// the filesystem, so that what's been read of it, such as a listing or the entries
+    /// looked up in it, can be kept until then rather than dropped on any write. Every
+    /// directory's changes after `refresh`, or when the device is reopened, as anything
+    /// could have changed then.
+    pub fn directory_generation(&self, directory: DirectorySelector) -> u64 {
+        self.directory_generations.borrow_mut().get(
+            self.directory_key(directory),
+            self.raw_device.reopen_count(),
+        )
+    }
+
+    /// Notes that the entries of `directory` have been changed.
+    pub(crate) fn directory_changed(&self, directory: DirectorySelector) {
+        self.directory_generations.borrow_mut().bump(
+            self.directory_key(directory),
+            self.raw_device.reopen_count(),
+        );
+    }
+
+    /// What `directory` is known by, whichever way it's selected: FAT32's root can
+    /// also be selected by its cluster.
+    fn directory_key(&self, directory: DirectorySelector) -> Cluster {
+        match (directory, self.root) {
+            (DirectorySelector::Cluster(cluster), RootDirectory::Chain(root))
+                if cluster == root =>
+            {
+                0
+            }
+            (DirectorySelector::Cluster(cluster), _) => cluster,
+            (DirectorySelector::Root, _) => 0,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::error::{Error, Result};
+use crate::{DirectorySelector, FATFileSystem, Metadata};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Something file contents can be streamed through to produce a digest. With the
+/// `digest` feature this is implemented for every `digest::Digest`, e.g. `sha2::Sha256`.
+pub trait ContentHasher {
+    type Output;
+
+    fn update(&mut self, data: &[u8]);
+    fn finish(self) -> Self::Output;
+}
+
+#[cfg(feature = "digest")]
+impl<D> ContentHasher for D
+where
+    D: digest::Digest,
+{
+    type Output = digest::Output<D>;
+
+    fn update(&mut self, data: &[u8]) {
+        digest::Digest::update(self, data);
+    }
+
+    fn finish(self) -> Self::Output {
+        self.finalize()
+    }
+}
+
+impl FATFileSystem {
+    /// Streams the contents of the file at `path` through `hasher`.
+    pub fn hash_file<H>(&self, path: &str, hasher: H) -> Result<H::Output>
+    where
+        H: ContentHasher,
+    {
+        let item = self.lookup(path)?;
+
+        if item.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        self.hash_contents(&item, hasher)
+    }
+
+    /// Hashes every file beneath `directory` with a fresh hasher from `new_hasher`,
+    /// returning each file's path and digest in the order the tree was walked.
+    pub fn hash_tree<H, F>(
+        &self,
+        directory: DirectorySelector,
+        mut new_hasher: F,
+    ) -> Result<Vec<(String, H::Output)>>
+    where
+        H: ContentHasher,
+        F: FnMut() -> H,
+    {
+        let mut result = Vec::new();
+
+        self.walk_tree(directory, |path, item| {
+            if item.is_file() {
+                let digest = self.hash_contents(item, new_hasher())?;
+                result.push((String::from(path), digest));
+            }
+
+            Ok(())
+        })?;
+
+        Ok(result)
+    }
+
+    pub(crate) fn hash_contents<H>(&self, item: &Metadata, mut hasher: H) -> Result<H::Output>
+    where
+        H: ContentHasher,
+    {
+        self.stream_file(item, |chunk| {
+            hasher.update(chunk);
+            Ok(())
+        })?;
+
+        Ok(hasher.finish())
+    }
+}
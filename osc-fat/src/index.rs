@@ -0,0 +1,488 @@
+use crate::allocator::FatReader;
+use crate::error::{Error, Result};
+use crate::{
+    Attributes, Cluster, DirectorySelector, ErrorContext, FATFileSystem, FATGeometry, FatDateTime,
+    FatEntry, Limit, Metadata, NameMatching, RootDirectory, Variant,
+};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::ops::Range;
+
+/// What a saved index starts with, followed by the version of its format.
+const INDEX_MAGIC: &[u8; 8] = b"OSCFATIX";
+const INDEX_VERSION: u8 = 2;
+
+/// The whole tree of a volume's files and directories, read once and held in memory,
+/// so that paths can be looked up and files located without going back to the
+/// device. It's only right for as long as the volume isn't written to, which suits
+/// serving files from an image that doesn't change.
+#[derive(Debug, Clone)]
+pub struct VolumeIndex {
+    geo: FATGeometry,
+    /// The serial number of the volume it was made from, to tell it from others.
+    volume_serial: Option<u32>,
+    name_matching: NameMatching,
+    /// Every item, with the root directory first.
+    items: Vec<IndexedItem>,
+    /// Each item by the index of its directory and the key of each of its names.
+    by_name: BTreeMap<(usize, String), usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexedItem {
+    pub path: String,
+    pub metadata: Metadata,
+    /// The runs of contiguous clusters the item's chain is made of, in order.
+    pub extents: Vec<Range<Cluster>>,
+    children: Vec<usize>,
+}
+
+impl FATFileSystem {
+    /// Reads the whole tree of files and directories, along with where each one is
+    /// on the volume, into an index. Directories that have already been indexed, which
+    /// only happens on a corrupt volume, aren't indexed again.
+    pub fn index(&self) -> Result<VolumeIndex> {
+        let mut index = VolumeIndex {
+            geo: self.geo,
+            volume_serial: self.volume_serial()?,
+            name_matching: self.options.name_matching,
+            items: Vec::new(),
+            by_name: BTreeMap::new(),
+        };
+
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut fat = self.fat_reader(&mut buffer);
+        let mut indexed_directories = BTreeSet::new();
+
+        let root = Metadata::root();
+        let root_extents = match self.root {
+            RootDirectory::Chain(cluster) => self.extents(&mut fat, cluster)?,
+            RootDirectory::Region { .. } => Vec::new(),
+        };
+
+        index.push_item(0, root, root_extents);
+
+        self.index_directory(&mut index, &mut fat, &mut indexed_directories, 0, 0)?;
+
+        Ok(index)
+    }
+
+    /// Loads an index saved by `VolumeIndex::to_bytes`, which has to have been made
+    /// from this volume. Only its geometry and serial number are compared, so an index
+    /// made before the volume was last written to is loaded, but is out of date.
+    pub fn load_index(&self, bytes: &[u8]) -> Result<VolumeIndex> {
+        let mut reader = IndexReader(bytes);
+
+        if reader.take(INDEX_MAGIC.len())? != INDEX_MAGIC || reader.u8()? != INDEX_VERSION {
+            return Err(Error::InvalidIndex);
+        }
+
+        let volume_serial = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.u32()?),
+        };
+
+        let geometry = reader.take(GEOMETRY_BYTES)?;
+
+        if geometry != geometry_bytes(&self.geo) || volume_serial != self.volume_serial()? {
+            return Err(Error::InvalidIndex);
+        }
+
+        let mut index = VolumeIndex {
+            geo: self.geo,
+            volume_serial,
+            name_matching: self.options.name_matching,
+            items: Vec::new(),
+            by_name: BTreeMap::new(),
+        };
+
+        let item_count = reader.u32()?;
+
+        for item_index in 0..item_count {
+            let parent = reader.u32()? as usize;
+
+            // NOTE: parents are saved before their children, with the root directory,
+            // which has none, first
+            if item_index > 0
+                && !matches!(index.items.get(parent), Some(parent) if parent.metadata.is_directory())
+            {
+                return Err(Error::InvalidIndex);
+            }
+
+            // NOTE: the root directory has no entry, and so no metadata was saved for it
+            let metadata = match item_index {
+                0 => Metadata::root(),
+                _ => reader.metadata()?,
+            };
+
+            let extents = (0..reader.u32()?)
+                .map(|_| {
+                    let extent = reader.u32()?..reader.u32()?;
+
+                    if extent.start >= extent.end
+                        || !self.is_data_cluster(extent.start)
+                        || !self.is_data_cluster(extent.end - 1)
+                    {
+                        return Err(Error::InvalidIndex);
+                    }
+
+                    Ok(extent)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            index.push_item(parent, metadata, extents);
+        }
+
+        if index.items.is_empty() || !reader.0.is_empty() {
+            return Err(Error::InvalidIndex);
+        }
+
+        Ok(index)
+    }
+
+    fn index_directory(
+        &self,
+        index: &mut VolumeIndex,
+        fat: &mut FatReader,
+        indexed_directories: &mut BTreeSet<Cluster>,
+        parent: usize,
+        depth: usize,
+    ) -> Result<()> {
+        self.check_depth(depth)?;
+
+        let directory = DirectorySelector::from_cluster(index.items[parent].metadata.first_cluster);
+
+        for item in self.list_directory(directory)? {
+            if item.is_dot_entry() || item.attributes.is_volume_id() {
+                continue;
+            }
+
+            let is_directory = item.is_directory();
+            let first_cluster = item.first_cluster;
+
+            let extents = self.extents(fat, first_cluster)?;
+            let child = index.push_item(parent, item, extents);
+
+            // NOTE: a directory that's already been indexed is part of a loop, which
+            // would otherwise never end
+            if is_directory && indexed_directories.insert(first_cluster) {
+                self.index_directory(index, fat, indexed_directories, child, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The runs of contiguous clusters in the chain starting at `first_cluster`.
+    fn extents(&self, fat: &mut FatReader, first_cluster: Cluster) -> Result<Vec<Range<Cluster>>> {
+        let max_chain_length = self.max_chain_length();
+
+        let mut extents: Vec<Range<Cluster>> = Vec::new();
+        let mut cluster = first_cluster;
+        let mut previous = None;
+        let mut cluster_count = 0;
+
+        if first_cluster == 0 {
+            return Ok(extents);
+        }
+
+        loop {
+            if !self.is_data_cluster(cluster) {
+                return Err(match previous {
+                    Some(previous) => fat.raise_at(Error::Corrupt, previous),
+                    None => {
+                        let context = ErrorContext::at_cluster(cluster);
+                        self.device.raise(Error::Corrupt, context)
+                    }
+                });
+            }
+
+            if cluster_count == max_chain_length {
+                return Err(Error::LimitExceeded(Limit::ChainLength));
+            }
+
+            match extents.last_mut() {
+                Some(extent) if extent.end == cluster => extent.end += 1,
+                _ => extents.push(cluster..(cluster + 1)),
+            }
+
+            cluster_count += 1;
+            previous = Some(cluster);
+
+            cluster = match FatEntry::from_value(fat.read(cluster)?, self.geo.variant) {
+                FatEntry::Next(next) => next,
+                FatEntry::Bad => return Err(fat.raise_at(Error::BadCluster, cluster)),
+                _ => return Ok(extents),
+            };
+        }
+    }
+}
+
+impl VolumeIndex {
+    /// Saves the index, to be loaded by `FATFileSystem::load_index` instead of being
+    /// made again.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(INDEX_MAGIC);
+        bytes.push(INDEX_VERSION);
+
+        match self.volume_serial {
+            Some(volume_serial) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&volume_serial.to_le_bytes());
+            }
+            None => bytes.push(0),
+        }
+
+        bytes.extend_from_slice(&geometry_bytes(&self.geo));
+        bytes.extend_from_slice(&(self.items.len() as u32).to_le_bytes());
+
+        let mut parents = vec![0u32; self.items.len()];
+
+        for (parent, item) in self.items.iter().enumerate() {
+            for &child in &item.children {
+                parents[child] = parent as u32;
+            }
+        }
+
+        for (item_index, (item, parent)) in self.items.iter().zip(parents).enumerate() {
+            let metadata = &item.metadata;
+
+            bytes.extend_from_slice(&parent.to_le_bytes());
+
+            if item_index > 0 {
+                put_string(&mut bytes, &metadata.name);
+                put_string(&mut bytes, &metadata.short_name);
+                bytes.push(metadata.attributes.bits());
+                bytes.extend_from_slice(&metadata.size.to_le_bytes());
+                bytes.extend_from_slice(&metadata.first_cluster.to_le_bytes());
+                put_date_time(&mut bytes, metadata.modified);
+                put_date_time(&mut bytes, metadata.created);
+                put_date_time(&mut bytes, metadata.accessed);
+            }
+
+            bytes.extend_from_slice(&(item.extents.len() as u32).to_le_bytes());
+
+            for extent in &item.extents {
+                bytes.extend_from_slice(&extent.start.to_le_bytes());
+                bytes.extend_from_slice(&extent.end.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    pub fn root(&self) -> &IndexedItem {
+        &self.items[0]
+    }
+
+    /// How many items there are, counting the root directory.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.len() == 1
+    }
+
+    /// Finds the item at `path`, matching names as the filesystem does.
+    pub fn lookup(&self, path: &str) -> Result<&IndexedItem> {
+        let mut current = 0;
+
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if !self.items[current].metadata.is_directory() {
+                return Err(Error::NotADirectory);
+            }
+
+            current = *self
+                .by_name
+                .get(&(current, self.name_matching.key(component)))
+                .ok_or(Error::NotFound)?;
+        }
+
+        Ok(&self.items[current])
+    }
+
+    /// The items in `directory`, in the order their entries are in.
+    pub fn children<'a>(
+        &'a self,
+        directory: &'a IndexedItem,
+    ) -> impl Iterator<Item = &'a IndexedItem> + 'a {
+        directory
+            .children
+            .iter()
+            .map(move |&child| &self.items[child])
+    }
+
+    /// Every item, parents before their children, starting with the root directory.
+    pub fn iter(&self) -> impl Iterator<Item = &IndexedItem> {
+        self.items.iter()
+    }
+
+    /// Where the byte at `offset` into `item` is, as a byte offset from the start of
+    /// the volume, along with how many bytes from there on are contiguous on the
+    /// volume and belong to the item. `None` if `offset` is past the end of its
+    /// clusters.
+    pub fn locate(&self, item: &IndexedItem, offset: u64) -> Option<(u64, u64)> {
+        let cluster_size_bytes =
+            u64::from(self.geo.cluster_size_sectors()) * u64::from(self.geo.sector_size_bytes());
+
+        let mut extent_offset = 0;
+
+        for extent in &item.extents {
+            let extent_bytes = u64::from(extent.end - extent.start) * cluster_size_bytes;
+
+            if offset < extent_offset + extent_bytes {
+                let into_extent = offset - extent_offset;
+                let first_sector = self.geo.first_data_sector()
+                    + u64::from(extent.start - 2) * u64::from(self.geo.cluster_size_sectors());
+
+                return Some((
+                    first_sector * u64::from(self.geo.sector_size_bytes()) + into_extent,
+                    extent_bytes - into_extent,
+                ));
+            }
+
+            extent_offset += extent_bytes;
+        }
+
+        None
+    }
+
+    /// Adds an item to the directory `parent`, giving back its index. The first item
+    /// added is the root directory, which has no parent.
+    fn push_item(
+        &mut self,
+        parent: usize,
+        metadata: Metadata,
+        extents: Vec<Range<Cluster>>,
+    ) -> usize {
+        let index = self.items.len();
+
+        let path = match (index, parent) {
+            (0, _) => String::from("/"),
+            (_, 0) => format!("/{}", metadata.name),
+            _ => format!("{}/{}", self.items[parent].path, metadata.name),
+        };
+
+        if index > 0 {
+            for name in self.name_matching.lookup_names(&metadata) {
+                self.by_name
+                    .entry((parent, self.name_matching.key(name)))
+                    .or_insert(index);
+            }
+
+            self.items[parent].children.push(index);
+        }
+
+        self.items.push(IndexedItem {
+            path,
+            metadata,
+            extents,
+            children: Vec::new(),
+        });
+
+        index
+    }
+}
+
+const GEOMETRY_BYTES: usize = 34;
+
+/// The geometry as it's saved with an index, which is only ever compared with the
+/// geometry of the volume it's loaded for.
+fn geometry_bytes(geo: &FATGeometry) -> [u8; GEOMETRY_BYTES] {
+    let mut bytes = [0u8; GEOMETRY_BYTES];
+
+    bytes[0] = match geo.variant {
+        Variant::Fat12 => 12,
+        Variant::Fat16 => 16,
+        Variant::Fat32 => 32,
+    };
+    bytes[1] = geo.cluster_size_sectors;
+    bytes[2..4].copy_from_slice(&geo.sector_size_bytes.to_le_bytes());
+    bytes[4..8].copy_from_slice(&geo.total_sectors.to_le_bytes());
+    bytes[8..16].copy_from_slice(&geo.first_fat_sector.to_le_bytes());
+    bytes[16..20].copy_from_slice(&geo.sectors_per_fat.to_le_bytes());
+    bytes[20] = geo.fat_count;
+    bytes[21] = geo.active_fat.unwrap_or(0xFF);
+    bytes[22..30].copy_from_slice(&geo.first_data_sector.to_le_bytes());
+    bytes[30..34].copy_from_slice(&geo.cluster_count.to_le_bytes());
+
+    bytes
+}
+
+fn put_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(&(string.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+fn put_date_time(bytes: &mut Vec<u8>, date_time: FatDateTime) {
+    let (date, time, centiseconds) = date_time.to_raw_with_centiseconds();
+
+    bytes.extend_from_slice(&date.to_le_bytes());
+    bytes.extend_from_slice(&time.to_le_bytes());
+    bytes.push(centiseconds);
+}
+
+/// Reads a saved index from the front of what's left of it.
+struct IndexReader<'a>(&'a [u8]);
+
+impl<'a> IndexReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.0.len() < count {
+            return Err(Error::InvalidIndex);
+        }
+
+        let (taken, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+
+        core::str::from_utf8(bytes)
+            .map(String::from)
+            .map_err(|_| Error::InvalidIndex)
+    }
+
+    fn date_time(&mut self) -> Result<FatDateTime> {
+        let (date, time) = (self.u16()?, self.u16()?);
+        Ok(FatDateTime::from_raw_with_centiseconds(
+            date,
+            time,
+            self.u8()?,
+        ))
+    }
+
+    fn metadata(&mut self) -> Result<Metadata> {
+        Ok(Metadata {
+            name: self.string()?,
+            short_name: self.string()?,
+            attributes: Attributes::from_bits(self.u8()?),
+            size: self.u32()?,
+            first_cluster: self.u32()?,
+            modified: self.date_time()?,
+            created: self.date_time()?,
+            accessed: self.date_time()?,
+            raw_long_name: None,
+        })
+    }
+}
@@ -0,0 +1,60 @@
+use crate::FatDateTime;
+
+// NOTE: timestamps are taken to be UTC here, as with FatDateTime::to_unix_seconds
+
+impl FatDateTime {
+    /// Like `from_unix_seconds`, but clamps to the range FAT can represent.
+    fn from_unix_seconds_saturating(seconds: i64) -> Self {
+        match FatDateTime::from_unix_seconds(seconds) {
+            Some(value) => value,
+            None if Some(seconds) < FatDateTime::EPOCH.to_unix_seconds() => FatDateTime::EPOCH,
+            None => FatDateTime::MAX,
+        }
+    }
+
+    /// Like `to_unix_seconds`, but treats unset or nonsensical dates as the FAT epoch.
+    fn unix_seconds_or_epoch(&self) -> i64 {
+        self.to_unix_seconds()
+            .or_else(|| FatDateTime::EPOCH.to_unix_seconds())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<FatDateTime> for ::time::OffsetDateTime {
+    /// Unset or nonsensical dates come out as the FAT epoch.
+    fn from(value: FatDateTime) -> Self {
+        let millisecond = ::time::Duration::milliseconds(i64::from(value.millisecond));
+
+        ::time::OffsetDateTime::from_unix_timestamp(value.unix_seconds_or_epoch())
+            .map(|seconds| seconds + millisecond)
+            .unwrap_or(::time::OffsetDateTime::UNIX_EPOCH)
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<::time::OffsetDateTime> for FatDateTime {
+    /// Truncates to two seconds, and clamps to the years FAT can represent.
+    fn from(value: ::time::OffsetDateTime) -> Self {
+        FatDateTime::from_unix_seconds_saturating(value.unix_timestamp())
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<FatDateTime> for chrono::DateTime<chrono::Utc> {
+    /// Unset or nonsensical dates come out as the FAT epoch.
+    fn from(value: FatDateTime) -> Self {
+        let nanosecond = u32::from(value.millisecond) * 1_000_000;
+
+        chrono::DateTime::from_timestamp(value.unix_seconds_or_epoch(), nanosecond)
+            .unwrap_or(chrono::DateTime::UNIX_EPOCH)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> From<chrono::DateTime<Tz>> for FatDateTime {
+    /// Truncates to two seconds, and clamps to the years FAT can represent.
+    fn from(value: chrono::DateTime<Tz>) -> Self {
+        FatDateTime::from_unix_seconds_saturating(value.timestamp())
+    }
+}
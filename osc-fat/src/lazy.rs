@@ -0,0 +1,54 @@
+use crate::error::Result;
+use crate::{FATFileSystem, MountOptions};
+use alloc::boxed::Box;
+use osc_block_storage::BlockDevice;
+
+/// A filesystem that isn't opened until it's first needed, made with
+/// `FATFileSystem::open_lazily`, so that tools going through many partitions only
+/// read the boot sectors of those they look into. Whatever opening it fails with is
+/// given back then, and every time after.
+pub struct LazyFileSystem {
+    pending: Option<(Box<dyn BlockDevice>, MountOptions)>,
+    opened: Option<Result<FATFileSystem>>,
+}
+
+impl FATFileSystem {
+    /// Like `open_with_options`, but nothing is read from `device` until the
+    /// filesystem is first needed.
+    pub fn open_lazily(device: Box<dyn BlockDevice>, options: MountOptions) -> LazyFileSystem {
+        LazyFileSystem {
+            pending: Some((device, options)),
+            opened: None,
+        }
+    }
+}
+
+impl LazyFileSystem {
+    /// Opens the filesystem if it hasn't been already, giving it back, or the error
+    /// opening it failed with.
+    pub fn ensure_open(&mut self) -> Result<&mut FATFileSystem> {
+        let pending = &mut self.pending;
+        let opened = self.opened.get_or_insert_with(|| {
+            let (device, options) = pending.take().unwrap_or_else(|| unreachable!());
+            FATFileSystem::open_with_options(device, options)
+        });
+
+        opened.as_mut().map_err(|err| *err)
+    }
+
+    /// Whether the filesystem has been opened, or tried to be.
+    pub fn is_opened(&self) -> bool {
+        self.opened.is_some()
+    }
+
+    /// The filesystem, if it's been opened.
+    pub fn get(&self) -> Option<&FATFileSystem> {
+        self.opened.as_ref().and_then(|opened| opened.as_ref().ok())
+    }
+
+    /// Opens the filesystem if it hasn't been already, and gives it up.
+    pub fn into_inner(mut self) -> Result<FATFileSystem> {
+        self.ensure_open()?;
+        self.opened.unwrap_or_else(|| unreachable!())
+    }
+}
@@ -1,30 +1,177 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(unsafe_code)]
 
 extern crate alloc;
 
 use alloc::boxed::Box;
 use alloc::rc::Rc;
-use core::{cell::RefCell, slice};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::{
+    cell::{Cell, RefCell},
+    ops::Range,
+    slice,
+};
+use math::DivCeiling;
 use osc_block_storage::BlockDevice;
 use prim::*;
 
 pub mod prim;
 
+mod allocator;
 mod math;
 mod support;
 
+mod buffers;
+pub use buffers::*;
+
+mod cache;
+pub use cache::*;
+
+mod cancel;
+pub use cancel::*;
+
+mod carve;
+pub use carve::*;
+
+mod check;
+pub use check::*;
+
+mod context;
+pub use context::*;
+
+#[cfg(feature = "std")]
+mod copy;
+#[cfg(feature = "std")]
+pub use copy::*;
+
+mod create;
+
+mod diff;
+pub use diff::*;
+
+mod dir;
+pub use dir::*;
+
+mod entropy;
+pub use entropy::*;
+
+mod error;
+pub use error::Error;
+use error::Result;
+
+#[cfg(any(feature = "chrono", feature = "time"))]
+mod interop;
+
+mod file;
+pub use file::*;
+
+mod filter;
+pub use filter::*;
+
+mod fat_entry;
+pub use fat_entry::*;
+
+mod format;
+pub use format::*;
+
+mod fragmentation;
+pub use fragmentation::*;
+
+mod generation;
+use generation::DirectoryGenerations;
+
+mod hash;
+pub use hash::*;
+
+mod index;
+pub use index::*;
+
+mod lazy;
+pub use lazy::*;
+
+#[cfg(feature = "manifest")]
+mod manifest;
+
+mod locate;
+pub use locate::{EntryLocation, LocatedEntry};
+
+mod metadata;
+pub use metadata::*;
+
+mod modify;
+
+mod names;
+pub use names::{
+    short_name_checksum, validate_long_name, validate_short_name, INVALID_LONG_NAME_CHARS,
+    INVALID_SHORT_NAME_CHARS, RESERVED_DEVICE_NAMES,
+};
+
+mod options;
+pub use options::*;
+
+mod owners;
+pub use owners::*;
+
+mod progress;
+pub use progress::*;
+
+mod quick_check;
+pub use quick_check::*;
+
+mod remove;
+
+mod reopen;
+pub use reopen::*;
+
+mod reserved;
+
+mod snapshot;
+pub use snapshot::*;
+
+mod sync;
+
+#[cfg(test)]
+mod testing;
+
+mod time;
+pub use time::*;
+
+mod tree;
+pub use tree::*;
+
+mod usage;
+pub use usage::*;
+
+mod verify;
+pub use verify::*;
+
+mod view;
+pub use view::*;
+
+mod volume;
+
 use support::*;
 
-pub struct DirectoryEntriesIterator<'a>(slice::ChunksExact<'a, u8>);
+pub struct DirectoryEntriesIterator<'a> {
+    entries: slice::ChunksExact<'a, u8>,
+    /// Whether end markers are skipped like free entries rather than ending the
+    /// directory.
+    past_end: bool,
+}
 
 impl<'a> Iterator for DirectoryEntriesIterator<'a> {
     type Item = DirectoryEntry<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            let entry = self.0.next()?;
+            let entry = self.entries.next()?;
 
             match entry[0] {
+                0x00 if self.past_end => {
+                    continue;
+                }
                 0x00 => {
                     return None;
                 }
@@ -62,19 +209,19 @@ pub struct StandardDirectoryEntry<'a>(&'a [u8]);
 
 #[allow(dead_code)]
 impl<'a> StandardDirectoryEntry<'a> {
-    const RANGE_NAME: ByteRange = 0..8;
-    const RANGE_EXT: ByteRange = 8..11;
-    const RANGE_ATTR: ByteRange = 11..12;
-    const RANGE_RESERVED_WINNT: ByteRange = 12..13;
-    const RANGE_CREATION_TIME_DECISECS: ByteRange = 13..14;
-    const RANGE_CREATION_TIME: ByteRange = 14..16;
-    const RANGE_CREATION_DATE: ByteRange = 16..18;
-    const RANGE_ACCESS_DATE: ByteRange = 18..20;
-    const RANGE_FIRST_CLUSTER_HIGH: ByteRange = 20..22;
-    const RANGE_MOD_TIME: ByteRange = 22..24;
-    const RANGE_MOD_DATE: ByteRange = 24..26;
-    const RANGE_FIRST_CLUSTER_LOW: ByteRange = 26..28;
-    const RANGE_SIZE: ByteRange = 28..32;
+    pub(crate) const RANGE_NAME: ByteRange = 0..8;
+    pub(crate) const RANGE_EXT: ByteRange = 8..11;
+    pub(crate) const RANGE_ATTR: ByteRange = 11..12;
+    pub(crate) const RANGE_RESERVED_WINNT: ByteRange = 12..13;
+    pub(crate) const RANGE_CREATION_TIME_DECISECS: ByteRange = 13..14;
+    pub(crate) const RANGE_CREATION_TIME: ByteRange = 14..16;
+    pub(crate) const RANGE_CREATION_DATE: ByteRange = 16..18;
+    pub(crate) const RANGE_ACCESS_DATE: ByteRange = 18..20;
+    pub(crate) const RANGE_FIRST_CLUSTER_HIGH: ByteRange = 20..22;
+    pub(crate) const RANGE_MOD_TIME: ByteRange = 22..24;
+    pub(crate) const RANGE_MOD_DATE: ByteRange = 24..26;
+    pub(crate) const RANGE_FIRST_CLUSTER_LOW: ByteRange = 26..28;
+    pub(crate) const RANGE_SIZE: ByteRange = 28..32;
 
     pub fn name(&self) -> &[u8] {
         self.0.range(Self::RANGE_NAME)
@@ -84,6 +231,20 @@ impl<'a> StandardDirectoryEntry<'a> {
         self.0.range(Self::RANGE_EXT)
     }
 
+    /// The name and extension as stored, 11 bytes padded with spaces.
+    pub fn short_name(&self) -> &[u8] {
+        self.0.range(Self::RANGE_NAME.start..Self::RANGE_EXT.end)
+    }
+
+    /// The Windows NT flags marking the name and/or extension as lower case.
+    pub fn case_flags(&self) -> u8 {
+        self.0.u8(Self::RANGE_RESERVED_WINNT)
+    }
+
+    pub fn attributes(&self) -> Attributes {
+        Attributes::from_bits(self.0.u8(Self::RANGE_ATTR))
+    }
+
     pub fn size(&self) -> u32 {
         self.0.u32(Self::RANGE_SIZE)
     }
@@ -123,6 +284,27 @@ impl<'a> StandardDirectoryEntry<'a> {
     pub fn first_cluster(&self) -> u32 {
         ((self.first_cluster_high() as u32) << 16) | (self.first_cluster_low() as u32)
     }
+
+    pub fn modified(&self) -> FatDateTime {
+        FatDateTime::from_raw(
+            self.0.u16(Self::RANGE_MOD_DATE),
+            self.0.u16(Self::RANGE_MOD_TIME),
+        )
+    }
+
+    pub fn created(&self) -> FatDateTime {
+        // NOTE: despite the name, the deciseconds field counts 10ms units up to 1.99s
+        FatDateTime::from_raw_with_centiseconds(
+            self.0.u16(Self::RANGE_CREATION_DATE),
+            self.0.u16(Self::RANGE_CREATION_TIME),
+            self.0.u8(Self::RANGE_CREATION_TIME_DECISECS),
+        )
+    }
+
+    /// The date of the last access, which has no time.
+    pub fn accessed(&self) -> FatDateTime {
+        FatDateTime::from_raw(self.0.u16(Self::RANGE_ACCESS_DATE), 0)
+    }
 }
 
 pub struct LongFileNameEntry<'a>(&'a [u8]);
@@ -142,6 +324,14 @@ impl<'a> LongFileNameEntry<'a> {
         LongFileNameCharIterator::new(self)
     }
 
+    pub fn order(&self) -> u8 {
+        self.0.u8(Self::RANGE_ORDER)
+    }
+
+    pub fn checksum(&self) -> u8 {
+        self.0.u8(Self::RANGE_CHECKSUM)
+    }
+
     fn portion1(&self) -> &[u8] {
         self.0.range(Self::RANGE_PORTION1)
     }
@@ -227,17 +417,17 @@ impl<'a> Iterator for U16Iterator<'a> {
     type Item = u16;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.0.next() {
-            None => None,
-            Some(first_byte) => match self.0.next() {
-                None => panic!("Incomplete number of bytes"),
-                Some(second_byte) => Some((*second_byte as u16) << 8 | (*first_byte as u16)),
-            },
-        }
+        let first_byte = self.0.next()?;
+
+        // NOTE: the portions of a long name entry are all whole numbers of characters,
+        // so a missing second byte can't happen
+        let second_byte = self.0.next()?;
+
+        Some((*second_byte as u16) << 8 | (*first_byte as u16))
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Variant {
     Fat12,
     Fat16,
@@ -254,38 +444,177 @@ impl Variant {
             Self::Fat32
         }
     }
+
+    /// The fewest clusters a volume of the variant can have.
+    pub(crate) fn min_cluster_count(self) -> u32 {
+        match self {
+            Self::Fat12 => 1,
+            Self::Fat16 => 4085,
+            Self::Fat32 => 65525,
+        }
+    }
+
+    /// The most clusters a volume of the variant can have.
+    pub(crate) fn max_cluster_count(self) -> u32 {
+        match self {
+            Self::Fat12 => 4084,
+            Self::Fat16 => 65524,
+            Self::Fat32 => 0x0FFFFFF5,
+        }
+    }
+}
+
+/// How far past the end marker of a directory a walker is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EndOfDirectory {
+    NotReached,
+    /// The marker was in the cluster the walker's in, but not its current sector.
+    InCluster,
+    Passed,
 }
 
 pub struct DirectoryWalker<'a> {
     cluster_walker: ClusterWalker<'a>,
+    directory: DirectorySelector,
+    limits: Limits,
+    invalid_utf16: InvalidUtf16Policy,
+    end_of_directory_policy: EndOfDirectoryPolicy,
+    end_of_directory: EndOfDirectory,
+    /// How many entries were in the sectors before the current one.
+    entries_walked: u32,
 }
 
 impl<'a> DirectoryWalker<'a> {
-    fn new(cluster_walker: ClusterWalker<'a>) -> Self {
-        Self { cluster_walker }
+    fn new(
+        cluster_walker: ClusterWalker<'a>,
+        directory: DirectorySelector,
+        limits: Limits,
+        invalid_utf16: InvalidUtf16Policy,
+        end_of_directory_policy: EndOfDirectoryPolicy,
+    ) -> Self {
+        Self {
+            cluster_walker,
+            directory,
+            limits,
+            invalid_utf16,
+            end_of_directory_policy,
+            end_of_directory: EndOfDirectory::NotReached,
+            entries_walked: 0,
+        }
     }
 
+    /// The index of the sector whose entries `occupied_entries` returns.
+    pub(crate) fn current_sector_index(&self) -> u64 {
+        self.cluster_walker.absolute_sector_index()
+    }
+
+    pub(crate) fn current_sector(&self) -> &[u8] {
+        self.cluster_walker.current_sector()
+    }
+
+    /// Where the entry at `index` in the current sector is, given the current sector's
+    /// index.
+    pub(crate) fn entry_location(&self, sector: u64, index: usize) -> EntryLocation {
+        EntryLocation {
+            directory: self.directory,
+            index: self.entries_walked + index as u32,
+            sector,
+            offset: index * DirectoryEntry::SIZE,
+        }
+    }
+
+    /// The entries in use in the current sector, up to the directory's end marker, or
+    /// past it as `MountOptions::end_of_directory` has it.
     pub fn occupied_entries(&self) -> DirectoryEntriesIterator<'_> {
-        DirectoryEntriesIterator(
-            self.cluster_walker
-                .current_sector()
-                .chunks_exact(DirectoryEntry::SIZE),
-        )
+        let sector = match (self.end_of_directory, self.end_of_directory_policy) {
+            (EndOfDirectory::NotReached, _)
+            | (EndOfDirectory::InCluster, EndOfDirectoryPolicy::ScanCluster) => {
+                self.cluster_walker.current_sector()
+            }
+            _ => &[],
+        };
+
+        DirectoryEntriesIterator {
+            entries: sector.chunks_exact(DirectoryEntry::SIZE),
+            past_end: self.end_of_directory_policy == EndOfDirectoryPolicy::ScanCluster,
+        }
     }
 
-    pub fn next(mut self) -> Option<Self> {
-        if self.cluster_walker.next_sector() {
-            return Some(self);
+    /// Makes the walker return `Error::Cancelled` from the next sector or cluster
+    /// transition after `cancel_token` is cancelled.
+    pub fn with_cancel_token(mut self, cancel_token: CancelToken) -> Self {
+        self.cluster_walker.set_cancel_token(cancel_token);
+        self
+    }
+
+    pub fn next(mut self) -> Result<Option<Self>> {
+        if self.advance()? {
+            Ok(Some(self))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Moves on to the next sector of the directory like `next`, but stays on the last
+    /// sector at the end, so that the walker can still be rewound.
+    pub fn advance(&mut self) -> Result<bool> {
+        let entries_walked =
+            self.entries_walked + (self.current_sector().len() / DirectoryEntry::SIZE) as u32;
+        let has_end_marker = self
+            .current_sector()
+            .chunks_exact(DirectoryEntry::SIZE)
+            .any(|entry| entry[0] == 0x00);
+
+        let next_cluster = !self.cluster_walker.next_sector()?;
+
+        if next_cluster && !self.cluster_walker.advance_cluster()? {
+            return Ok(false);
+        }
+
+        self.entries_walked = entries_walked;
+        self.end_of_directory = match self.end_of_directory {
+            EndOfDirectory::NotReached if !has_end_marker => EndOfDirectory::NotReached,
+            EndOfDirectory::NotReached | EndOfDirectory::InCluster if !next_cluster => {
+                EndOfDirectory::InCluster
+            }
+            _ => EndOfDirectory::Passed,
+        };
+
+        match self.limits.max_directory_entries {
+            Some(max) if entries_walked >= max => {
+                Err(Error::LimitExceeded(Limit::DirectoryEntries))
+            }
+            _ => Ok(true),
         }
+    }
 
-        self.cluster_walker
-            .next_cluster()
-            .map(|new_cluster_walker| Self {
-                cluster_walker: new_cluster_walker,
-            })
+    /// Goes back to the first sector of the directory, for algorithms that need two
+    /// passes over it. This doesn't repeat the work of opening the directory, and only
+    /// goes to the device if the first sector is no longer in the walker's buffer.
+    pub fn rewind(&mut self) -> Result<()> {
+        self.cluster_walker.rewind()?;
+        self.end_of_directory = EndOfDirectory::NotReached;
+        self.entries_walked = 0;
+        Ok(())
     }
 
-    pub fn enumerate_occupied_entries<F>(self, mut func: F)
+    /// A copy of the walker at its current position that reads through `buffer`, which
+    /// starts out holding whatever the walker's buffer does, as far as it fits, and has
+    /// to be at least `BufferRequirements::min` bytes. The copy can be walked on without
+    /// affecting the original, e.g. to look ahead.
+    pub fn clone_with_buffer<'b>(&self, buffer: &'b mut [u8]) -> Result<DirectoryWalker<'b>> {
+        Ok(DirectoryWalker {
+            cluster_walker: self.cluster_walker.clone_with_buffer(buffer)?,
+            directory: self.directory,
+            limits: self.limits,
+            invalid_utf16: self.invalid_utf16,
+            end_of_directory_policy: self.end_of_directory_policy,
+            end_of_directory: self.end_of_directory,
+            entries_walked: self.entries_walked,
+        })
+    }
+
+    pub fn enumerate_occupied_entries<F>(self, mut func: F) -> Result<()>
     where
         F: FnMut(DirectoryEntry<'_>),
     {
@@ -296,143 +625,936 @@ impl<'a> DirectoryWalker<'a> {
                 func(entry)
             }
 
-            if let Some(new_walker) = walker.next() {
+            if let Some(new_walker) = walker.next()? {
                 walker = new_walker;
             } else {
                 break;
             }
         }
+
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct FATGeometry {
+/// Where the structures of a volume are, as described by its boot sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FATGeometry {
+    variant: Variant,
     cluster_size_sectors: u8,
     sector_size_bytes: u16,
+    total_sectors: u32,
     first_fat_sector: u64,
+    sectors_per_fat: u32,
+    fat_count: u8,
+    /// The only copy of the FAT in use, when FAT32's mirroring has been turned off.
+    active_fat: Option<u8>,
     first_data_sector: u64,
+    /// The number of clusters in the data region, which are numbered from 2.
+    cluster_count: u32,
+}
+
+impl FATGeometry {
+    /// Reads the geometry from the boot sector of `device`, without mounting it.
+    pub fn read(device: &mut dyn BlockDevice) -> Result<Self> {
+        Ok(read_layout(device)?.geometry)
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn cluster_size_sectors(&self) -> u8 {
+        self.cluster_size_sectors
+    }
+
+    pub fn sector_size_bytes(&self) -> u16 {
+        self.sector_size_bytes
+    }
+
+    /// The number of sectors in the volume, which the boot sector records in 32 bits,
+    /// so a volume can be at most 2 TiB with 512-byte sectors however big its device.
+    pub fn total_sectors(&self) -> u32 {
+        self.total_sectors
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        u64::from(self.total_sectors) * u64::from(self.sector_size_bytes)
+    }
+
+    pub fn first_fat_sector(&self) -> u64 {
+        self.first_fat_sector
+    }
+
+    pub fn sectors_per_fat(&self) -> u32 {
+        self.sectors_per_fat
+    }
+
+    pub fn fat_count(&self) -> u8 {
+        self.fat_count
+    }
+
+    /// The copy of the FAT that's used when FAT32's mirroring has been turned off, in
+    /// which case the other copies are out of date and aren't read or written.
+    pub fn active_fat(&self) -> Option<u8> {
+        self.active_fat
+    }
+
+    /// The copies of the FAT that writes go to, which is all of them unless mirroring
+    /// has been turned off.
+    pub(crate) fn written_fats(&self) -> Range<u8> {
+        match self.active_fat {
+            Some(active_fat) => active_fat..(active_fat + 1),
+            None => 0..self.fat_count,
+        }
+    }
+
+    /// The first sector of the given copy of the FAT.
+    pub(crate) fn first_sector_of_fat(&self, fat_index: u8) -> u64 {
+        self.first_fat_sector + u64::from(fat_index) * u64::from(self.sectors_per_fat)
+    }
+
+    pub fn first_data_sector(&self) -> u64 {
+        self.first_data_sector
+    }
+
+    /// The number of clusters in the data region, which are numbered from 2.
+    pub fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// Whether `cluster` is in the data region, i.e. a cluster a chain can contain.
+    pub fn is_data_cluster(&self, cluster: Cluster) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+}
+
+/// What mounting needs to know from the boot sector.
+struct Layout {
+    geometry: FATGeometry,
+    root: RootDirectory,
+    // FAT32 only
+    fs_info_sector: Option<u64>,
+}
+
+fn read_layout(device: &mut dyn BlockDevice) -> Result<Layout> {
+    // Read the BPB
+    // NOTE: a buffer shorter than a block reads nothing, so it has room for a whole one
+    let mut read_buffer = vec![0u8; core::cmp::max(512, usize::from(device.block_size()))];
+
+    if device.read_blocks(0, &mut read_buffer)? == 0 {
+        return Err(Error::Corrupt);
+    }
+
+    let read_buffer_slice = &read_buffer[..];
+
+    // Right, what version of FAT are we dealing with?
+    let bpb: CommonBiosParameterBlock = read_buffer_slice.into();
+
+    let bytes_per_sector = bpb.bytes_per_sector();
+    let sectors_per_fat = sectors_per_fat(read_buffer_slice);
+    let sectors_per_cluster = bpb.sectors_per_cluster();
+    let reserved_sectors = bpb.reserved_sector_count();
+    let fat_count = bpb.fat_count();
+
+    // NOTE: a layout that's impossible, or that would overflow the arithmetic
+    // below, is refused rather than trusted
+    if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+        || !sectors_per_cluster.is_power_of_two()
+        || reserved_sectors == 0
+        || fat_count == 0
+        || sectors_per_fat == 0
+    {
+        return Err(Error::Corrupt);
+    }
+
+    let root_dir_sector_count =
+        root_dir_sector_count(bpb.root_entry_count() as u32, bytes_per_sector);
+
+    let meta_sector_count_wide = u64::from(reserved_sectors)
+        + u64::from(sectors_per_fat) * u64::from(fat_count)
+        + u64::from(root_dir_sector_count);
+
+    if meta_sector_count_wide >= u64::from(bpb.total_sectors()) {
+        return Err(Error::Corrupt);
+    }
+
+    let meta_sectors = meta_sector_count(
+        reserved_sectors,
+        sectors_per_fat,
+        fat_count,
+        root_dir_sector_count,
+    );
+
+    let first_data_sector = meta_sectors;
+
+    let data_sectors = bpb.total_sectors() - meta_sectors;
+
+    let count_of_clusters = data_sectors / u32::from(sectors_per_cluster);
+
+    let variant = Variant::from_cluster_count(count_of_clusters);
+
+    let fat_size_bytes = match variant {
+        Variant::Fat12 => (u64::from(count_of_clusters) + 2) * 3 / 2 + 1,
+        Variant::Fat16 => (u64::from(count_of_clusters) + 2) * 2,
+        Variant::Fat32 => (u64::from(count_of_clusters) + 2) * 4,
+    };
+
+    if count_of_clusters == 0
+        || fat_size_bytes > u64::from(sectors_per_fat) * u64::from(bytes_per_sector)
+    {
+        return Err(Error::Corrupt);
+    }
+
+    let (root, fs_info_sector, active_fat) = match variant {
+        Variant::Fat12 | Variant::Fat16 => {
+            if root_dir_sector_count == 0 {
+                return Err(Error::Corrupt);
+            }
+
+            let root = RootDirectory::Region {
+                first_sector: u64::from(reserved_sectors)
+                    + u64::from(fat_count) * u64::from(sectors_per_fat),
+                sector_count: root_dir_sector_count,
+            };
+
+            (root, None, None)
+        }
+
+        Variant::Fat32 => {
+            let bpb = ExtendedFat32BiosParameterBlock::from(read_buffer_slice);
+
+            let fs_info_sector = match bpb.fs_info_sector() {
+                0 | 0xFFFF => None,
+                n => Some(u64::from(n)),
+            };
+
+            let root_cluster = bpb.root_cluster();
+
+            if root_cluster < 2 || root_cluster - 2 >= count_of_clusters {
+                return Err(Error::Corrupt);
+            }
+
+            // NOTE: with mirroring off, only the active copy of the FAT is used
+            let ext_flags = bpb.ext_flags();
+
+            let active_fat = match ext_flags & 0x80 {
+                0 => None,
+                _ => Some((ext_flags & 0x0F) as u8),
+            };
+
+            if matches!(active_fat, Some(active_fat) if active_fat >= fat_count) {
+                return Err(Error::Corrupt);
+            }
+
+            (
+                RootDirectory::Chain(root_cluster),
+                fs_info_sector,
+                active_fat,
+            )
+        }
+    };
+
+    let geometry = FATGeometry {
+        variant,
+        cluster_size_sectors: sectors_per_cluster,
+        sector_size_bytes: bytes_per_sector,
+        total_sectors: bpb.total_sectors(),
+        first_fat_sector: reserved_sectors.into(),
+        sectors_per_fat,
+        fat_count,
+        active_fat,
+        first_data_sector: first_data_sector.into(),
+        cluster_count: count_of_clusters,
+    };
+
+    Ok(Layout {
+        geometry,
+        root,
+        fs_info_sector,
+    })
 }
 
 pub type Cluster = u32;
 
 pub type DirectoryInitialCluster = Cluster;
 
+/// Identifies a directory without needing to know how the FAT variant stores it: the
+/// root is a cluster chain on FAT32 but a fixed region on FAT12/16.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DirectorySelector {
     Root,
-    Normal(DirectoryInitialCluster),
+    Cluster(DirectoryInitialCluster),
+}
+
+impl DirectorySelector {
+    /// Selects the directory starting at `cluster`, where cluster 0 is how ".." entries
+    /// refer to the root directory.
+    pub fn from_cluster(cluster: DirectoryInitialCluster) -> Self {
+        match cluster {
+            0 => Self::Root,
+            n => Self::Cluster(n),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum RootDirectory {
+    /// FAT32
+    Chain(Cluster),
+    /// FAT12/16, where the root sits between the FATs and the data region
+    Region {
+        first_sector: u64,
+        sector_count: u32,
+    },
 }
 
 pub struct FATFileSystem {
-    device: Rc<RefCell<Box<dyn BlockDevice>>>,
+    device: SharedDevice,
     device_block_size: u16,
 
     variant: Variant,
     geo: FATGeometry,
 
-    // TODO: Fat32 only
-    root_cluster: u32,
+    root: RootDirectory,
+
+    options: MountOptions,
+    read_only: bool,
+
+    // FAT32 only
+    fs_info_sector: Option<u64>,
+    // NOTE: the count is only known once the volume has been marked dirty, see
+    // mark_dirty
+    free_cluster_count: Cell<Option<u32>>,
+    next_free_cluster: Cell<Option<u32>>,
+    dirty: Cell<bool>,
+    // NOTE: whether the volume was marked clean when it was mounted, or has been
+    // repaired since, without which sync leaves it marked dirty and the FSInfo free
+    // count isn't trusted
+    clean_at_mount: Cell<bool>,
+
+    time_provider: Box<dyn TimeProvider>,
+    entropy_source: Option<Box<dyn EntropySource>>,
+    sector_cache: Rc<BlockCache>,
+    fat_cache: Rc<BlockCache>,
+    directory_cache: RefCell<DirectoryCache>,
+    directory_generations: RefCell<DirectoryGenerations>,
+    raw_device: Rc<RawDevice>,
+    buffer_pool: BufferPool,
+    quick_check_report: Option<QuickCheckReport>,
 }
 
 impl FATFileSystem {
-    pub fn open(mut device: Box<dyn BlockDevice>) -> Self {
-        // Read the BPB
-        let mut read_buffer = [0u8; 512];
-        device.read_blocks(0, &mut read_buffer);
+    pub fn open(device: Box<dyn BlockDevice>) -> Result<Self> {
+        Self::open_with_options(device, MountOptions::default())
+    }
 
-        let read_buffer_slice = &read_buffer[..];
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "mount", skip_all))]
+    pub fn open_with_options(
+        mut device: Box<dyn BlockDevice>,
+        options: MountOptions,
+    ) -> Result<Self> {
+        let Layout {
+            geometry: geo,
+            root,
+            fs_info_sector,
+        } = read_layout(&mut *device)?;
+        let variant = geo.variant;
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            ?variant,
+            cluster_count = geo.cluster_count,
+            cluster_size_sectors = geo.cluster_size_sectors,
+            sector_size_bytes = geo.sector_size_bytes,
+            "mounted"
+        );
 
-        // Right, what version of FAT are we dealing with?
-        let bpb: CommonBiosParameterBlock = read_buffer_slice.into();
+        let block_size = u64::from(device.block_size());
+        let block_cache = |bytes: usize| {
+            let capacity = bytes / block_size as usize;
+            Rc::new(BlockCache::new(Box::new(LruSectorCache::new(capacity))))
+        };
+        let sector_cache = block_cache(options.cache.sector_cache_bytes);
+        let fat_cache = block_cache(options.cache.fat_cache_bytes);
+
+        let sector_size_bytes = u64::from(geo.sector_size_bytes);
+        let fat_sector_count = u64::from(geo.fat_count) * u64::from(geo.sectors_per_fat);
+        let fat_blocks = (geo.first_fat_sector * sector_size_bytes / block_size)
+            ..((geo.first_fat_sector + fat_sector_count) * sector_size_bytes)
+                .div_ceiling(block_size);
+
+        let write_generation = Rc::new(Cell::new(0));
+        let raw_device = RawDevice::new(device)?;
+        let device = ReopeningBlockDevice::new(
+            raw_device.clone(),
+            [sector_cache.clone(), fat_cache.clone()],
+            write_generation.clone(),
+        );
+        let device = CachedBlockDevice::new(
+            Box::new(device),
+            sector_cache.clone(),
+            fat_cache.clone(),
+            fat_blocks,
+            write_generation.clone(),
+        );
 
-        let bytes_per_sector = bpb.bytes_per_sector();
-        let root_dir_sector_count =
-            root_dir_sector_count(bpb.root_entry_count() as u32, bytes_per_sector);
+        let mut fs = Self {
+            device_block_size: device.block_size(),
+            read_only: options.read_only || device.is_read_only(),
+            device: SharedDevice::new(Box::new(device), write_generation),
 
-        let sectors_per_fat = sectors_per_fat(read_buffer_slice);
-        let sectors_per_cluster = bpb.sectors_per_cluster();
-        let reserved_sectors = bpb.reserved_sector_count();
+            variant,
+            root,
+            geo,
+            options,
 
-        let meta_sectors = meta_sector_count(
-            reserved_sectors,
-            sectors_per_fat,
-            bpb.fat_count(),
-            root_dir_sector_count,
-        );
+            fs_info_sector,
+            free_cluster_count: Cell::new(None),
+            next_free_cluster: Cell::new(None),
+            dirty: Cell::new(false),
+            clean_at_mount: Cell::new(true),
 
-        let first_data_sector = meta_sectors;
+            time_provider: match options.deterministic {
+                Some(deterministic) => Box::new(FixedTime(deterministic.timestamp)),
+                None => default_time_provider(options.time_zone),
+            },
+            entropy_source: match options.deterministic {
+                Some(_) => None,
+                None => default_entropy_source(),
+            },
+            sector_cache,
+            fat_cache,
+            directory_cache: RefCell::new(DirectoryCache::new(options.cache.dir_cache_entries)),
+            directory_generations: RefCell::new(DirectoryGenerations::default()),
+            raw_device,
+            buffer_pool: BufferPool::default(),
+            quick_check_report: None,
+        };
 
-        let data_sectors = bpb.total_sectors() - meta_sectors;
+        fs.clean_at_mount.set(fs.read_clean_shutdown()?);
 
-        let count_of_clusters = data_sectors / u32::from(sectors_per_cluster);
+        if options.quick_check {
+            fs.quick_check_report = Some(fs.quick_check()?);
+        }
 
-        let variant = Variant::from_cluster_count(count_of_clusters);
+        Ok(fs)
+    }
 
-        let root_cluster = match variant {
-            Variant::Fat12 | Variant::Fat16 => {
-                unimplemented!();
-            }
+    /// Whether mutating operations will be refused, either because the filesystem
+    /// was opened read-only or because the device itself is read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
 
-            Variant::Fat32 => {
-                ExtendedFat32BiosParameterBlock::from(read_buffer_slice).root_cluster()
+    pub fn cluster_size_bytes(&self) -> usize {
+        usize::from(self.geo.cluster_size_sectors) * usize::from(self.geo.sector_size_bytes)
+    }
+
+    /// Walks a directory a sector at a time, reading through `buffer`, which has to be
+    /// at least `BufferRequirements::min` bytes. Directories of any size can be walked
+    /// through a buffer of that size, which is refilled as the walk moves on.
+    pub fn walk_directory<'a>(
+        &self,
+        buffer: &'a mut [u8],
+        directory: DirectorySelector,
+    ) -> Result<DirectoryWalker<'a>> {
+        self.check_buffer(buffer)?;
+
+        let buffer = ReadBuffer::new(self.device.clone(), buffer, self.geo.sector_size_bytes);
+        self.walk_directory_through(buffer, directory)
+    }
+
+    /// Like `walk_directory`, but the walker has a buffer of its own, which it takes
+    /// from a pool the filesystem keeps and gives back when dropped. The walker doesn't
+    /// borrow the filesystem, and any number of them can be open at once, nested or
+    /// interleaved, e.g. to walk a tree recursively from within `enumerate_*`
+    /// callbacks. Changes made while a walker is open are seen by it from its next
+    /// sector on.
+    pub fn walk_directory_owned(
+        &self,
+        directory: DirectorySelector,
+    ) -> Result<DirectoryWalker<'static>> {
+        let buffer = ReadBuffer::pooled(
+            self.device.clone(),
+            self.buffer_pool
+                .take(self.buffer_requirements().recommended),
+            self.geo.sector_size_bytes,
+        );
+
+        self.walk_directory_through(buffer, directory)
+    }
+
+    fn walk_directory_through<'a>(
+        &self,
+        buffer: ReadBuffer<'a>,
+        directory: DirectorySelector,
+    ) -> Result<DirectoryWalker<'a>> {
+        let cluster_walker = match (directory, self.root) {
+            (DirectorySelector::Cluster(cluster_index), _)
+            | (DirectorySelector::Root, RootDirectory::Chain(cluster_index)) => {
+                self.cluster_walker_through(buffer, cluster_index)?
             }
-        };
 
-        let geo = FATGeometry {
-            cluster_size_sectors: sectors_per_cluster,
-            sector_size_bytes: bytes_per_sector,
-            first_fat_sector: reserved_sectors.into(),
-            first_data_sector: first_data_sector.into(),
+            (
+                DirectorySelector::Root,
+                RootDirectory::Region {
+                    first_sector,
+                    sector_count,
+                },
+            ) => ClusterWalker::open_region(buffer, first_sector, sector_count, self.geo)?,
         };
 
-        Self {
-            device_block_size: device.block_size(),
-            device: Rc::new(RefCell::new(device)),
+        Ok(DirectoryWalker::new(
+            cluster_walker,
+            directory,
+            self.options.limits,
+            self.options.invalid_utf16,
+            self.options.end_of_directory,
+        ))
+    }
 
-            variant,
-            root_cluster,
-            geo,
+    pub fn read(&mut self, file_first_cluster: u32, cluster_buffer: &mut [u8]) -> Result<()> {
+        if !self.is_data_cluster(file_first_cluster) {
+            let context = ErrorContext::at_cluster(file_first_cluster);
+            return Err(self.device.raise(Error::Corrupt, context));
         }
+
+        let first_sector = first_sector_of_cluster(
+            file_first_cluster,
+            self.geo.cluster_size_sectors,
+            self.geo.first_data_sector as u32,
+        ) as u64;
+        self.device
+            .borrow_mut()
+            .read_blocks(first_sector, cluster_buffer)
+            .map_err(|err| {
+                let context =
+                    ErrorContext::at_cluster(file_first_cluster).with_sector(first_sector);
+                self.device.raise(err.into(), context)
+            })?;
+        Ok(())
     }
 
-    pub fn required_read_buffer_size(&self) -> usize {
-        core::cmp::max(
-            usize::from(self.geo.sector_size_bytes),
-            usize::from(self.device_block_size),
+    /// Reads the contents of a file into `destination`, following its cluster chain
+    /// and reporting progress after every cluster. Returns the number of bytes read,
+    /// which is the smaller of the file size and the destination size. `buffer` has to
+    /// be at least `BufferRequirements::min` bytes.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip(self, buffer, destination, progress, cancel_token)
         )
+    )]
+    pub fn read_file(
+        &self,
+        buffer: &mut [u8],
+        file_first_cluster: Cluster,
+        file_size: u32,
+        destination: &mut [u8],
+        progress: &mut dyn ProgressSink,
+        cancel_token: &CancelToken,
+    ) -> Result<usize> {
+        let total_bytes = core::cmp::min(file_size as usize, destination.len());
+        let cluster_size_bytes = self.cluster_size_bytes();
+
+        let mut status = Progress {
+            items_done: 0,
+            items_total: (total_bytes + cluster_size_bytes - 1) as u64 / cluster_size_bytes as u64,
+            bytes_done: 0,
+            bytes_total: total_bytes as u64,
+        };
+
+        progress.report(&status);
+
+        if total_bytes == 0 {
+            return Ok(0);
+        }
+
+        cancel_token.check()?;
+
+        let mut cluster_walker = self.open_cluster_walker(buffer, file_first_cluster)?;
+        cluster_walker.set_cancel_token(cancel_token.clone());
+
+        let mut bytes_read = 0;
+
+        loop {
+            let sector = cluster_walker.current_sector();
+            let count = core::cmp::min(sector.len(), total_bytes - bytes_read);
+
+            destination[bytes_read..(bytes_read + count)].copy_from_slice(&sector[..count]);
+            bytes_read += count;
+
+            if bytes_read == total_bytes {
+                break;
+            }
+
+            if cluster_walker.next_sector()? {
+                continue;
+            }
+
+            status.items_done += 1;
+            status.bytes_done = bytes_read as u64;
+            progress.report(&status);
+
+            match cluster_walker.next_cluster()? {
+                Some(next_cluster_walker) => cluster_walker = next_cluster_walker,
+                None => break,
+            }
+        }
+
+        status.items_done = status.items_total;
+        status.bytes_done = bytes_read as u64;
+        progress.report(&status);
+
+        Ok(bytes_read)
     }
 
-    pub fn walk_directory<'a>(
+    /// Passes the contents of a file to `sink` one cluster at a time, with only the
+    /// final chunk being shorter, so callers can spot whole clusters of zeros.
+    pub fn stream_file<F>(&self, item: &Metadata, mut sink: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut reader = self.open_file_reader(&mut buffer, item.first_cluster, item.size)?;
+        let mut chunk = vec![0u8; self.cluster_size_bytes()];
+
+        loop {
+            match reader.read(&mut chunk)? {
+                0 => return Ok(()),
+                count => sink(&chunk[..count])?,
+            }
+        }
+    }
+
+    /// Lists every occupied entry of a directory (including "." and "..", and the
+    /// volume label in the root) with long file names assembled.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn list_directory(&self, directory: DirectorySelector) -> Result<Vec<Metadata>> {
+        let mut result = Vec::new();
+
+        self.walk_directory_owned(directory)?
+            .enumerate_entry_views(|view| {
+                result.push(view.metadata());
+            })?;
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(entries = result.len(), "listed");
+
+        Ok(result)
+    }
+
+    /// Finds the entry at `path`, whose components are separated by '/' and matched
+    /// case-insensitively against both long and short names. The empty path (or "/")
+    /// refers to the root directory.
+    pub fn lookup(&self, path: &str) -> Result<Metadata> {
+        let mut current = Metadata::root();
+
+        for (depth, component) in path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .enumerate()
+        {
+            self.check_depth(depth + 1)?;
+
+            if !current.is_directory() {
+                return Err(Error::NotADirectory);
+            }
+
+            current = self
+                .find_in_directory(
+                    DirectorySelector::from_cluster(current.first_cluster),
+                    component,
+                )
+                .note_context(&self.device, || ErrorContext::at_path(path))?;
+        }
+
+        Ok(current)
+    }
+
+    fn find_in_directory(&self, directory: DirectorySelector, name: &str) -> Result<Metadata> {
+        let directory_cluster = match directory {
+            DirectorySelector::Root => 0,
+            DirectorySelector::Cluster(cluster) => cluster,
+        };
+        let key = (directory_cluster, String::from(name));
+        let generation = self.directory_generation(directory);
+        let cached = self.directory_cache.borrow_mut().get(&key, generation);
+
+        if let Some(item) = cached {
+            return Ok(item);
+        }
+
+        let item = self
+            .walk_directory_owned(directory)?
+            .find_entry(name, self.options.name_matching)?
+            .ok_or(Error::NotFound)?;
+
+        self.directory_cache
+            .borrow_mut()
+            .insert(key, item.clone(), generation);
+
+        Ok(item)
+    }
+
+    /// Whether `item` is the entry `name` refers to, as `MountOptions::name_matching`
+    /// has names matched.
+    pub(crate) fn is_called(&self, item: &Metadata, name: &str) -> bool {
+        self.options.name_matching.is_called(item, name)
+    }
+
+    pub(crate) fn lookup_names<'m>(&self, item: &'m Metadata) -> impl Iterator<Item = &'m str> {
+        self.options.name_matching.lookup_names(item)
+    }
+
+    /// Visits every file and directory beneath `directory`, depth first, passing each
+    /// one's path (relative to `directory`, with a leading '/') to `visitor`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, visitor))
+    )]
+    pub fn walk_tree<F>(&self, directory: DirectorySelector, mut visitor: F) -> Result<()>
+    where
+        F: FnMut(&str, &Metadata) -> Result<()>,
+    {
+        let mut path = String::new();
+        self.walk_tree_prime(directory, &mut path, 0, &mut visitor)
+    }
+
+    fn walk_tree_prime(
         &self,
-        buffer: &'a mut [u8],
         directory: DirectorySelector,
-    ) -> DirectoryWalker<'a> {
-        let buffer = ReadBuffer::new(self.device.clone(), buffer, self.geo.sector_size_bytes);
+        path: &mut String,
+        depth: usize,
+        visitor: &mut dyn FnMut(&str, &Metadata) -> Result<()>,
+    ) -> Result<()> {
+        self.check_depth(depth)?;
+
+        let items = self
+            .list_directory(directory)
+            .note_context(&self.device, || ErrorContext::at_path(path))?;
+
+        // Let the device start fetching the subdirectories before they're needed
+        for item in &items {
+            if item.is_directory()
+                && !item.is_dot_entry()
+                && self.is_data_cluster(item.first_cluster)
+            {
+                self.prefetch_cluster(item.first_cluster);
+            }
+        }
 
-        let cluster_walker = match directory {
-            DirectorySelector::Normal(cluster_index) => {
-                ClusterWalker::open(buffer, cluster_index, self.geo).unwrap()
+        for item in items {
+            if item.is_dot_entry() || item.attributes.is_volume_id() {
+                continue;
             }
-            DirectorySelector::Root => match self.variant {
-                Variant::Fat12 | Variant::Fat16 => {
-                    unimplemented!();
-                }
 
-                Variant::Fat32 => ClusterWalker::open(buffer, self.root_cluster, self.geo).unwrap(),
-            },
-        };
+            let parent_len = path.len();
+            path.push('/');
+            path.push_str(&item.name);
 
-        let dir_walker = DirectoryWalker::new(cluster_walker);
-        dir_walker
+            visitor(path, &item).note_context(&self.device, || ErrorContext::at_path(path))?;
+
+            if item.is_directory() {
+                self.walk_tree_prime(
+                    DirectorySelector::from_cluster(item.first_cluster),
+                    path,
+                    depth + 1,
+                    visitor,
+                )?;
+            }
+
+            path.truncate(parent_len);
+        }
+
+        Ok(())
     }
 
-    pub fn read<'a>(&mut self, file_first_cluster: u32, cluster_buffer: &'a mut [u8]) {
+    fn prefetch_cluster(&self, cluster: Cluster) {
+        let sector_size_bytes = u64::from(self.geo.sector_size_bytes);
+        let block_size_bytes = u64::from(self.device_block_size);
+
         let first_sector = first_sector_of_cluster(
-            file_first_cluster,
+            cluster,
             self.geo.cluster_size_sectors,
             self.geo.first_data_sector as u32,
-        ) as u64;
+        );
+
+        let start_byte = u64::from(first_sector) * sector_size_bytes;
+        let end_byte = start_byte + self.cluster_size_bytes() as u64;
+
+        let start_block = start_byte / block_size_bytes;
+        let end_block = end_byte.div_ceiling(block_size_bytes);
+
         self.device
             .borrow_mut()
-            .read_blocks(first_sector, cluster_buffer);
+            .prefetch(start_block, end_block - start_block);
+    }
+
+    fn open_file_reader<'a>(
+        &self,
+        buffer: &'a mut [u8],
+        file_first_cluster: Cluster,
+        file_size: u32,
+    ) -> Result<FileReader<'a>> {
+        let cluster_walker = if file_size == 0 || file_first_cluster < 2 {
+            None
+        } else {
+            Some(self.open_cluster_walker(buffer, file_first_cluster)?)
+        };
+
+        Ok(FileReader::new(cluster_walker, file_size))
+    }
+
+    fn open_cluster_walker<'a>(
+        &self,
+        buffer: &'a mut [u8],
+        first_cluster: Cluster,
+    ) -> Result<ClusterWalker<'a>> {
+        self.check_buffer(buffer)?;
+
+        let buffer = ReadBuffer::new(self.device.clone(), buffer, self.geo.sector_size_bytes);
+        self.cluster_walker_through(buffer, first_cluster)
+    }
+
+    fn cluster_walker_through<'a>(
+        &self,
+        buffer: ReadBuffer<'a>,
+        first_cluster: Cluster,
+    ) -> Result<ClusterWalker<'a>> {
+        let mut cluster_walker = ClusterWalker::open(buffer, first_cluster, self.geo)?;
+        cluster_walker.set_fat_read_policy(self.options.fat_read_policy());
+        cluster_walker.set_max_chain_length(self.max_chain_length());
+        Ok(cluster_walker)
+    }
+
+    /// The most clusters a chain is followed for.
+    pub(crate) fn max_chain_length(&self) -> u32 {
+        let limit = self.options.limits.max_chain_length.unwrap_or(u32::MAX);
+        core::cmp::min(limit, self.geo.cluster_count)
+    }
+
+    /// Fails if a directory `depth` levels down is deeper than the limit allows.
+    pub(crate) fn check_depth(&self, depth: usize) -> Result<()> {
+        match self.options.limits.max_depth {
+            Some(max) if depth > max as usize => Err(Error::LimitExceeded(Limit::Depth)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::format_volume;
+    use alloc::format;
+
+    /// Makes a directory three levels deep, each level with a subdirectory ahead of
+    /// enough long named files to take several clusters, giving every path made.
+    fn make_tree(fs: &FATFileSystem) -> Vec<String> {
+        let mut paths = Vec::new();
+        let mut directory = String::new();
+
+        for level in ["level one", "level two", "level three"].iter() {
+            directory = format!("{}/{}", directory, level);
+            fs.create_dir(&directory).unwrap();
+            paths.push(directory.clone());
+
+            for index in 0..20 {
+                let path = format!("{}/a file with a long name {}.txt", directory, index);
+                fs.create_file(&path).unwrap();
+                paths.push(path);
+            }
+        }
+
+        paths
+    }
+
+    /// Lists everything beneath `directory` by walking each subdirectory from within
+    /// the callback of the walk that finds it, adding paths to `paths`.
+    fn walk_nested(
+        fs: &FATFileSystem,
+        directory: DirectorySelector,
+        path: &str,
+        paths: &mut Vec<String>,
+    ) -> Result<()> {
+        let mut result = Ok(());
+
+        fs.walk_directory_owned(directory)?
+            .enumerate_entry_views(|view| {
+                let item = view.metadata();
+
+                if result.is_err() || item.is_dot_entry() || item.attributes.is_volume_id() {
+                    return;
+                }
+
+                let item_path = format!("{}/{}", path, item.name);
+                paths.push(item_path.clone());
+
+                if item.is_directory() {
+                    result = walk_nested(
+                        fs,
+                        DirectorySelector::from_cluster(item.first_cluster),
+                        &item_path,
+                        paths,
+                    );
+                }
+            })?;
+
+        result
+    }
+
+    /// Lists everything beneath the root one directory at a time, with no walk open
+    /// while another is.
+    fn list_flat(fs: &FATFileSystem) -> Vec<String> {
+        let mut paths = Vec::new();
+        let mut pending = vec![(DirectorySelector::Root, String::new())];
+
+        while let Some((directory, path)) = pending.pop() {
+            for item in fs.list_directory(directory).unwrap() {
+                if item.is_dot_entry() || item.attributes.is_volume_id() {
+                    continue;
+                }
+
+                let item_path = format!("{}/{}", path, item.name);
+
+                if item.is_directory() {
+                    pending.push((
+                        DirectorySelector::from_cluster(item.first_cluster),
+                        item_path.clone(),
+                    ));
+                }
+
+                paths.push(item_path);
+            }
+        }
+
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn nested_walks_find_what_a_flat_listing_does() {
+        let (_, fs) = format_volume(Variant::Fat32, 40 << 20);
+        let mut made = make_tree(&fs);
+        made.sort();
+
+        let flat = list_flat(&fs);
+        assert_eq!(flat, made);
+
+        let mut nested = Vec::new();
+        walk_nested(&fs, DirectorySelector::Root, "", &mut nested).unwrap();
+        nested.sort();
+        assert_eq!(nested, flat);
+
+        let mut tree = Vec::new();
+        fs.walk_tree(DirectorySelector::Root, |path, _| {
+            tree.push(String::from(path));
+            Ok(())
+        })
+        .unwrap();
+        tree.sort();
+        assert_eq!(tree, flat);
     }
 }
@@ -0,0 +1,271 @@
+use crate::error::{Error, Result};
+use crate::names::{format_short_name, LongNameAssembler};
+use crate::support::{read_sector, write_sector};
+use crate::{DirectoryEntry, DirectorySelector, ErrorContext, FATFileSystem, Metadata};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Where a directory entry is, both in its directory and on disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EntryLocation {
+    pub directory: DirectorySelector,
+    /// Which of the directory's entries it is, counting from 0, in use or not.
+    pub index: u32,
+    pub sector: u64,
+    /// The byte offset of the entry within the sector.
+    pub offset: usize,
+}
+
+/// An entry found by name, along with where it is so it can be changed in place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocatedEntry {
+    pub metadata: Metadata,
+    pub location: EntryLocation,
+}
+
+impl FATFileSystem {
+    /// Like `lookup`, but also gives where the entry is, so that changing it afterwards
+    /// (with `set_entry_attributes` or `set_entry_len`) goes straight to it rather
+    /// than searching its directory again. The root directory has no entry, so it's
+    /// refused with `Error::RootDirectory`.
+    pub fn locate(&self, path: &str) -> Result<LocatedEntry> {
+        let (parent, name) = self.split_parent(path)?;
+        self.locate_in_directory(parent, name)
+    }
+
+    /// Looks up each of `names` in `directory` as `lookup` would, but with a single
+    /// pass over the directory rather than one per name. The result has an item for
+    /// each name, in the same order, which is `None` if there's no entry called that.
+    pub fn stat_many(
+        &self,
+        directory: DirectorySelector,
+        names: &[&str],
+    ) -> Result<Vec<Option<Metadata>>> {
+        let matching = self.options.name_matching;
+        let mut wanted: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+        for (index, name) in names.iter().enumerate() {
+            wanted.entry(matching.key(name)).or_default().push(index);
+        }
+
+        let mut result = vec![None; names.len()];
+
+        self.walk_directory_owned(directory)?
+            .enumerate_entry_views(|view| {
+                if wanted.is_empty() || view.entry().is_volume_id() {
+                    return;
+                }
+
+                let metadata = view.metadata();
+
+                // NOTE: an entry can be asked for by both its names, and the first entry
+                // with a name is the one found, as with lookup
+                let indices = self
+                    .lookup_names(&metadata)
+                    .filter_map(|name| wanted.remove(&matching.key(name)))
+                    .flatten()
+                    .collect::<Vec<_>>();
+
+                for index in indices {
+                    result[index] = Some(metadata.clone());
+                }
+            })?;
+
+        Ok(result)
+    }
+
+    /// Splits `path` into the directory holding it and its last component.
+    pub(crate) fn split_parent<'p>(&self, path: &'p str) -> Result<(DirectorySelector, &'p str)> {
+        let (parent_path, name) = split_path(path)?;
+        let parent = self.lookup(parent_path)?;
+
+        if !parent.is_directory() {
+            return Err(Error::NotADirectory);
+        }
+
+        Ok((DirectorySelector::from_cluster(parent.first_cluster), name))
+    }
+
+    /// Finds an entry as `find_in_directory` does, keeping track of where it's stored.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub(crate) fn locate_in_directory(
+        &self,
+        directory: DirectorySelector,
+        name: &str,
+    ) -> Result<LocatedEntry> {
+        self.locate_with_long_name(directory, name)
+            .map(|(located, _)| located)
+    }
+
+    /// Like `locate_in_directory`, but also gives where the long file name entries
+    /// ahead of the entry are, which go along with it when it's removed.
+    pub(crate) fn locate_with_long_name(
+        &self,
+        directory: DirectorySelector,
+        name: &str,
+    ) -> Result<(LocatedEntry, Vec<EntryLocation>)> {
+        let mut walker = self.walk_directory_owned(directory)?;
+
+        let mut long_name = LongNameAssembler::default();
+        let mut long_name_locations = Vec::new();
+
+        loop {
+            let sector = walker.current_sector_index();
+
+            // NOTE: the same entries as occupied_entries, but with their offsets
+            for (index, bytes) in walker
+                .current_sector()
+                .chunks_exact(DirectoryEntry::SIZE)
+                .enumerate()
+            {
+                let location = walker.entry_location(sector, index);
+
+                // NOTE: entries past the end marker are only ever read, never changed
+                match bytes[0] {
+                    0x00 => return Err(Error::NotFound),
+                    0xE5 => {
+                        long_name_locations.clear();
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                match DirectoryEntry::from(bytes) {
+                    DirectoryEntry::LongFileName(entry) => {
+                        long_name.push(&entry);
+                        long_name_locations.push(location);
+                    }
+                    DirectoryEntry::Standard(entry) => {
+                        let entry_long_name = long_name.take(&entry);
+
+                        // NOTE: long name entries that don't belong to the entry are
+                        // left alone, as they're ignored anyway
+                        if entry_long_name.is_none() {
+                            long_name_locations.clear();
+                        }
+
+                        let metadata =
+                            Metadata::new(&entry, entry_long_name, self.options.invalid_utf16);
+
+                        if self.is_called(&metadata, name) {
+                            return Ok((LocatedEntry { metadata, location }, long_name_locations));
+                        }
+
+                        long_name_locations.clear();
+                    }
+                }
+            }
+
+            match walker.next()? {
+                Some(next_walker) => walker = next_walker,
+                None => return Err(Error::NotFound),
+            }
+        }
+    }
+
+    /// Makes sure a located entry is still where it was found, as the directory may
+    /// have changed since, failing with `Error::NotFound` if it isn't.
+    pub(crate) fn check_located(&self, entry: &LocatedEntry) -> Result<()> {
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+        let location = entry.location;
+
+        read_sector(
+            &mut **device,
+            self.geo.sector_size_bytes,
+            location.sector,
+            &mut sector,
+        )
+        .map_err(|err| self.raise_at_entry(err, location))?;
+
+        let bytes = &sector[location.offset..(location.offset + DirectoryEntry::SIZE)];
+
+        match (bytes[0], DirectoryEntry::from(bytes)) {
+            (0x00, _) | (0xE5, _) => Err(Error::NotFound),
+            (_, DirectoryEntry::Standard(found))
+                if format_short_name(&found) == entry.metadata.short_name
+                    && found.first_cluster() == entry.metadata.first_cluster =>
+            {
+                Ok(())
+            }
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    /// Reads a single directory entry as it's stored.
+    pub(crate) fn read_entry(&self, location: EntryLocation) -> Result<[u8; DirectoryEntry::SIZE]> {
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+        read_sector(
+            &mut **device,
+            self.geo.sector_size_bytes,
+            location.sector,
+            &mut sector,
+        )
+        .map_err(|err| self.raise_at_entry(err, location))?;
+
+        let mut entry = [0u8; DirectoryEntry::SIZE];
+        entry.copy_from_slice(&sector[location.offset..(location.offset + DirectoryEntry::SIZE)]);
+
+        Ok(entry)
+    }
+
+    /// Rewrites a single directory entry through `update`.
+    pub(crate) fn update_entry<F>(&self, location: EntryLocation, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+        read_sector(
+            &mut **device,
+            self.geo.sector_size_bytes,
+            location.sector,
+            &mut sector,
+        )
+        .map_err(|err| self.raise_at_entry(err, location))?;
+
+        update(&mut sector[location.offset..(location.offset + DirectoryEntry::SIZE)]);
+
+        // NOTE: noted before writing, as the write may partially happen if it fails
+        self.directory_changed(location.directory);
+
+        write_sector(
+            &mut **device,
+            self.geo.sector_size_bytes,
+            location.sector,
+            &sector,
+        )
+        .map_err(|err| self.raise_at_entry(err, location))
+    }
+
+    fn raise_at_entry(&self, error: Error, location: EntryLocation) -> Error {
+        let context = match location.directory {
+            DirectorySelector::Cluster(cluster) => ErrorContext::at_cluster(cluster),
+            DirectorySelector::Root => ErrorContext::default(),
+        };
+
+        self.device
+            .raise(error, context.with_sector(location.sector))
+    }
+}
+
+/// Splits `path` into its parent's path and its last component, refusing the root.
+pub(crate) fn split_path(path: &str) -> Result<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+
+    let (parent_path, name) = match path.rfind('/') {
+        Some(index) => (&path[..index], &path[(index + 1)..]),
+        None => ("", path),
+    };
+
+    if name.is_empty() {
+        return Err(Error::RootDirectory);
+    }
+
+    Ok((parent_path, name))
+}
@@ -0,0 +1,47 @@
+use crate::error::Result;
+use crate::{DirectorySelector, FATFileSystem, Metadata};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use sha2::{Digest, Sha256};
+
+impl FATFileSystem {
+    /// Writes a line of `<sha256> <size> <mtime> <path>` for every file in the filesystem,
+    /// sorted by path, so that the same contents always produce the same manifest. The
+    /// modification time is in ISO 8601 form, with the offset of the mount options'
+    /// zone.
+    pub fn export_manifest<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        let mut files: Vec<(String, Metadata)> = Vec::new();
+
+        self.walk_tree(DirectorySelector::Root, |path, item| {
+            if item.is_file() {
+                files.push((String::from(path), item.clone()));
+            }
+
+            Ok(())
+        })?;
+
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (path, item) in files {
+            let digest = self.hash_contents(&item, Sha256::new())?;
+
+            for byte in digest {
+                write!(writer, "{:02x}", byte)?;
+            }
+
+            writeln!(
+                writer,
+                " {} {} {}",
+                item.size,
+                item.modified.in_zone(self.time_zone()),
+                path
+            )?;
+        }
+
+        Ok(())
+    }
+}
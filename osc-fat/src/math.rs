@@ -6,11 +6,19 @@ pub(crate) trait DivCeiling {
     fn div_ceiling(self, divisor: Self::Value) -> Self::Value;
 }
 
-impl DivCeiling for u32 {
-    type Value = Self;
+macro_rules! impl_div_ceiling {
+    ($($t:ty),*) => {
+        $(
+            impl DivCeiling for $t {
+                type Value = Self;
 
-    #[inline]
-    fn div_ceiling(self, divisor: Self::Value) -> Self::Value {
-        (self + (divisor - 1)) / divisor
-    }
+                #[inline]
+                fn div_ceiling(self, divisor: Self::Value) -> Self::Value {
+                    (self + (divisor - 1)) / divisor
+                }
+            }
+        )*
+    };
 }
+
+impl_div_ceiling!(u32, u64, usize);
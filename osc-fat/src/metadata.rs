@@ -0,0 +1,151 @@
+use crate::names::{is_invalid_utf16, long_name_chars};
+use crate::{Cluster, FatDateTime, InvalidUtf16Policy, StandardDirectoryEntry};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::BitOr;
+
+/// The DOS attribute byte of a directory entry.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Attributes(u8);
+
+impl Attributes {
+    pub const READ_ONLY: Self = Self(0x01);
+    pub const HIDDEN: Self = Self(0x02);
+    pub const SYSTEM: Self = Self(0x04);
+    pub const VOLUME_ID: Self = Self(0x08);
+    pub const DIRECTORY: Self = Self(0x10);
+    pub const ARCHIVE: Self = Self(0x20);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    pub fn is_read_only(self) -> bool {
+        self.contains(Self::READ_ONLY)
+    }
+
+    pub fn is_hidden(self) -> bool {
+        self.contains(Self::HIDDEN)
+    }
+
+    pub fn is_system(self) -> bool {
+        self.contains(Self::SYSTEM)
+    }
+
+    pub fn is_volume_id(self) -> bool {
+        self.contains(Self::VOLUME_ID)
+    }
+
+    pub fn is_directory(self) -> bool {
+        self.contains(Self::DIRECTORY)
+    }
+
+    pub fn is_archive(self) -> bool {
+        self.contains(Self::ARCHIVE)
+    }
+}
+
+impl BitOr for Attributes {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// An owned description of a directory entry, with its long file name (if any)
+/// already assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// The long file name, or the formatted short name if there isn't one.
+    pub name: String,
+    pub short_name: String,
+    pub attributes: Attributes,
+    pub size: u32,
+    pub first_cluster: Cluster,
+    pub modified: FatDateTime,
+    pub created: FatDateTime,
+    pub accessed: FatDateTime,
+    /// The long file name exactly as it's stored, if it isn't valid UTF-16, in which
+    /// case `name` is it decoded as `MountOptions::invalid_utf16` says, or the short
+    /// name standing in for it.
+    pub raw_long_name: Option<Vec<u16>>,
+}
+
+impl Metadata {
+    pub(crate) fn new(
+        entry: &StandardDirectoryEntry,
+        long_name: Option<&[u16]>,
+        invalid_utf16: InvalidUtf16Policy,
+    ) -> Self {
+        let short_name = crate::names::format_short_name(entry);
+        let name = match long_name.and_then(|long_name| long_name_chars(long_name, invalid_utf16)) {
+            Some(long_name) => long_name.collect(),
+            None => short_name.clone(),
+        };
+
+        Self {
+            name,
+            short_name,
+            attributes: entry.attributes(),
+            size: entry.size(),
+            first_cluster: entry.first_cluster(),
+            modified: entry.modified(),
+            created: entry.created(),
+            accessed: entry.accessed(),
+            raw_long_name: long_name
+                .filter(|long_name| is_invalid_utf16(long_name))
+                .map(<[u16]>::to_vec),
+        }
+    }
+
+    /// Stands in for the root directory, which has no entry of its own.
+    pub(crate) fn root() -> Self {
+        Self {
+            name: String::new(),
+            short_name: String::new(),
+            attributes: Attributes::DIRECTORY,
+            size: 0,
+            first_cluster: 0,
+            modified: FatDateTime::default(),
+            created: FatDateTime::default(),
+            accessed: FatDateTime::default(),
+            raw_long_name: None,
+        }
+    }
+
+    /// Whether `name` is the short name standing in for a long name that isn't valid
+    /// UTF-16, as `InvalidUtf16Policy::ShortName` has it, which is `raw_long_name`.
+    pub fn has_fallback_name(&self) -> bool {
+        self.raw_long_name.is_some() && self.name == self.short_name
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes.is_directory()
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.attributes.is_directory() && !self.attributes.is_volume_id()
+    }
+
+    /// Whether this is the "." or ".." entry at the start of a subdirectory.
+    pub fn is_dot_entry(&self) -> bool {
+        self.short_name == "." || self.short_name == ".."
+    }
+}
@@ -0,0 +1,337 @@
+use crate::allocator::END_OF_CHAIN;
+use crate::error::{Error, Result};
+use crate::locate::{EntryLocation, LocatedEntry};
+use crate::math::DivCeiling;
+use crate::support::{read_sector, write_sector, DataStructure, DataStructureMut};
+use crate::{
+    Attributes, Cluster, DirectorySelector, FATFileSystem, FatDateTime, FatFile, Metadata,
+    StandardDirectoryEntry, MAX_FILE_SIZE,
+};
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+impl FATFileSystem {
+    /// Replaces the read-only, hidden, system and archive attributes of the entry at
+    /// `path`, then flushes the device. The directory and volume label bits can't be
+    /// changed, and are left as they are whatever `attributes` has.
+    pub fn set_attributes(&self, path: &str, attributes: Attributes) -> Result<()> {
+        self.set_entry_attributes(&mut self.locate(path)?, attributes)
+    }
+
+    /// Like `set_attributes`, for an entry found with `locate`, whose metadata is
+    /// brought up to date. Fails with `Error::NotFound` if the entry isn't where it
+    /// was found any more.
+    pub fn set_entry_attributes(
+        &self,
+        entry: &mut LocatedEntry,
+        attributes: Attributes,
+    ) -> Result<()> {
+        const SETTABLE: u8 = Attributes::READ_ONLY.bits()
+            | Attributes::HIDDEN.bits()
+            | Attributes::SYSTEM.bits()
+            | Attributes::ARCHIVE.bits();
+
+        self.check_located(entry)?;
+
+        let bits = (entry.metadata.attributes.bits() & !SETTABLE) | (attributes.bits() & SETTABLE);
+
+        self.mark_dirty()?;
+        self.update_entry(entry.location, |mut entry| {
+            entry.range_mut(StandardDirectoryEntry::RANGE_ATTR)[0] = bits;
+        })?;
+
+        entry.metadata.attributes = Attributes::from_bits(bits);
+
+        self.flush()
+    }
+
+    /// Records a modification at `modified` in the entry at `path`, which can be a file
+    /// or a directory, as writing to it would, then flushes the device.
+    pub fn set_modified(&self, path: &str, modified: FatDateTime) -> Result<()> {
+        let entry = self.locate(path)?;
+
+        self.mark_dirty()?;
+        self.update_entry(entry.location, |entry| stamp_modified(entry, modified))?;
+
+        self.flush()
+    }
+
+    /// Truncates or extends the file at `path` to `size` bytes, freeing the clusters
+    /// that are no longer needed or allocating new ones, then flushes the device. When
+    /// extending, `zero_fill` decides whether the new part of the file reads as zeros
+    /// or as whatever the clusters held before.
+    pub fn set_len(&self, path: &str, size: u32, zero_fill: bool) -> Result<()> {
+        self.set_entry_len(&mut self.locate(path)?, size, zero_fill)
+    }
+
+    /// Like `set_len`, for an entry found with `locate`, whose size, first cluster and
+    /// attributes are brought up to date. Fails with `Error::NotFound` if the entry
+    /// isn't where it was found any more.
+    pub fn set_entry_len(
+        &self,
+        entry: &mut LocatedEntry,
+        size: u32,
+        zero_fill: bool,
+    ) -> Result<()> {
+        if entry.metadata.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        self.check_located(entry)?;
+
+        entry.metadata.first_cluster =
+            self.resize(entry.location, &entry.metadata, size, zero_fill)?;
+        entry.metadata.size = size;
+        entry.metadata.attributes.insert(Attributes::ARCHIVE);
+
+        self.flush()
+    }
+
+    /// Replaces the contents of the file at `path` with `data`, creating the file if
+    /// there isn't one, such that an interruption leaves either the old contents or the
+    /// new ones. The new contents are written out under a temporary entry first, then
+    /// the file's entry is pointed at them with a single sector write, and only then
+    /// are the old clusters freed.
+    pub fn replace(&self, path: &str, data: &[u8]) -> Result<()> {
+        if data.len() as u64 > u64::from(MAX_FILE_SIZE) {
+            return Err(Error::FileTooLarge);
+        }
+
+        let (parent, name) = self.split_parent(path)?;
+
+        // NOTE: a new file is created empty first, so it's never seen part written
+        let located = match self.locate(path) {
+            Err(Error::NotFound) => self.create_entry(parent, name, Attributes::ARCHIVE, 0)?,
+            result => result?,
+        };
+
+        if located.metadata.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        let (temporary, first_cluster, size) = self.write_temporary(parent, data)?;
+
+        // NOTE: the temporary entry goes before the file's entry is changed, so the new
+        // chain is never referred to by both, and an interruption in between leaves
+        // lost clusters at worst
+        self.update_entry(temporary, |entry| entry[0] = 0xE5)?;
+        self.flush()?;
+
+        let now = self.now();
+
+        self.update_entry(located.location, |entry| {
+            set_extent(entry, first_cluster, size);
+            stamp_modified(entry, now);
+        })?;
+        self.flush()?;
+
+        if self.is_data_cluster(located.metadata.first_cluster) {
+            self.free_chain(located.metadata.first_cluster)?;
+        }
+
+        self.flush()
+    }
+
+    /// Writes `data` to a new file in `directory` with a name nothing else has, giving
+    /// where its entry is along with its first cluster and size. Nothing is left behind
+    /// if it fails.
+    fn write_temporary(
+        &self,
+        directory: DirectorySelector,
+        data: &[u8],
+    ) -> Result<(EntryLocation, Cluster, u32)> {
+        // NOTE: the names are exact short names, so there are no long name entries to
+        // remove along with the entry
+        let located = (0..10000)
+            .map(|index| {
+                let name = format!("~RPL{:04}.TMP", index);
+                self.create_entry(directory, &name, Attributes::ARCHIVE, 0)
+            })
+            .find(|result| !matches!(result, Err(Error::AlreadyExists)))
+            .unwrap_or(Err(Error::AlreadyExists))?;
+
+        let location = located.location;
+        let mut file = FatFile::for_writing(self, located, false);
+
+        match file.write(data).and_then(|_| file.flush()) {
+            Ok(()) => Ok((
+                location,
+                file.metadata().first_cluster,
+                file.metadata().size,
+            )),
+            Err(err) => {
+                let first_cluster = file.metadata().first_cluster;
+                drop(file);
+
+                self.update_entry(location, |entry| entry[0] = 0xE5)?;
+
+                if self.is_data_cluster(first_cluster) {
+                    self.free_chain(first_cluster)?;
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Does the work of `set_len` for the file with the given entry, returning its new
+    /// first cluster.
+    pub(crate) fn resize(
+        &self,
+        location: EntryLocation,
+        metadata: &Metadata,
+        size: u32,
+        zero_fill: bool,
+    ) -> Result<Cluster> {
+        let old_size = metadata.size;
+
+        self.mark_dirty()?;
+
+        let cluster_size = self.cluster_size_bytes() as u64;
+        let wanted_clusters = u64::from(size).div_ceiling(cluster_size) as usize;
+
+        // The part of the existing chain that's being kept, and what follows it
+        let mut chain = Vec::new();
+        let mut next_cluster = metadata.first_cluster;
+
+        {
+            let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+            let mut fat = self.fat_reader(&mut buffer);
+
+            while chain.len() < wanted_clusters && self.is_data_cluster(next_cluster) {
+                chain.push(next_cluster);
+                next_cluster = fat.read(next_cluster)?;
+            }
+        }
+
+        if zero_fill && size > old_size {
+            // NOTE: the slack after the old end of the file may not be zero
+            let mut position = u64::from(old_size);
+
+            for (index, &cluster) in chain.iter().enumerate() {
+                let cluster_start = index as u64 * cluster_size;
+                let cluster_end = cluster_start + cluster_size;
+
+                if position < cluster_end {
+                    self.write_cluster_range(
+                        cluster,
+                        (position - cluster_start) as usize,
+                        cluster_size as usize,
+                        None,
+                    )?;
+                    position = cluster_end;
+                }
+            }
+        }
+
+        while chain.len() < wanted_clusters {
+            let cluster = self.allocate_cluster(chain.last().copied())?;
+
+            if zero_fill {
+                self.write_cluster_range(cluster, 0, cluster_size as usize, None)?;
+            }
+
+            chain.push(cluster);
+        }
+
+        let first_cluster = chain.first().copied().unwrap_or(0);
+        let now = self.now();
+
+        // NOTE: the entry is updated after any new clusters are in place and before any
+        // old ones are freed, so that an interruption leaves lost clusters at worst
+        // rather than an entry that refers to free ones
+        self.update_entry(location, |entry| {
+            set_extent(entry, first_cluster, size);
+            stamp_modified(entry, now);
+        })?;
+
+        if self.is_data_cluster(next_cluster) {
+            if let Some(&last_cluster) = chain.last() {
+                self.write_fat_value(last_cluster, END_OF_CHAIN)?;
+            }
+
+            self.free_chain(next_cluster)?;
+        }
+
+        Ok(first_cluster)
+    }
+
+    /// Writes the bytes `start..end` of a cluster from `data`, or with zeros if there's
+    /// no data.
+    pub(crate) fn write_cluster_range(
+        &self,
+        cluster: Cluster,
+        start: usize,
+        end: usize,
+        data: Option<&[u8]>,
+    ) -> Result<()> {
+        let sector_size = usize::from(self.geo.sector_size_bytes);
+        let first_sector = self.geo.first_data_sector
+            + u64::from(cluster - 2) * u64::from(self.geo.cluster_size_sectors);
+
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; sector_size];
+
+        for sector_index in (start / sector_size)..end.div_ceiling(sector_size) {
+            let sector_start = sector_index * sector_size;
+            let write_start = start.max(sector_start) - sector_start;
+            let write_end = end.min(sector_start + sector_size) - sector_start;
+            let absolute_sector = first_sector + sector_index as u64;
+
+            if write_end - write_start < sector_size {
+                read_sector(
+                    &mut **device,
+                    self.geo.sector_size_bytes,
+                    absolute_sector,
+                    &mut sector,
+                )?;
+            }
+
+            match data {
+                Some(data) => sector[write_start..write_end].copy_from_slice(
+                    &data[(sector_start + write_start - start)..(sector_start + write_end - start)],
+                ),
+                None => sector[write_start..write_end]
+                    .iter_mut()
+                    .for_each(|byte| *byte = 0),
+            }
+
+            write_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                absolute_sector,
+                &sector,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Points an entry at a new cluster chain and size.
+pub(crate) fn set_extent(mut entry: &mut [u8], first_cluster: Cluster, size: u32) {
+    entry.set_u32(StandardDirectoryEntry::RANGE_SIZE, size);
+    entry.set_u16(
+        StandardDirectoryEntry::RANGE_FIRST_CLUSTER_HIGH,
+        (first_cluster >> 16) as u16,
+    );
+    entry.set_u16(
+        StandardDirectoryEntry::RANGE_FIRST_CLUSTER_LOW,
+        first_cluster as u16,
+    );
+}
+
+/// Records a modification in an entry: the modification time and access date are set
+/// to `now`, and the archive attribute is set, as DOS does.
+pub(crate) fn stamp_modified(mut entry: &mut [u8], now: FatDateTime) {
+    let (date, time) = now.to_raw();
+
+    entry.set_u16(StandardDirectoryEntry::RANGE_MOD_TIME, time);
+    entry.set_u16(StandardDirectoryEntry::RANGE_MOD_DATE, date);
+    entry.set_u16(StandardDirectoryEntry::RANGE_ACCESS_DATE, date);
+
+    let attributes = entry.u8(StandardDirectoryEntry::RANGE_ATTR);
+    entry.range_mut(StandardDirectoryEntry::RANGE_ATTR)[0] =
+        attributes | Attributes::ARCHIVE.bits();
+}
@@ -0,0 +1,510 @@
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::{DirectoryEntry, InvalidUtf16Policy, LongFileNameEntry, StandardDirectoryEntry};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::char::DecodeUtf16;
+use core::iter::Copied;
+use core::slice;
+
+/// The checksum of an 11-byte short name that every long file name entry belonging
+/// to it carries.
+pub fn short_name_checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// A short name as it's compared when looking for names already in use, which is in
+/// upper case and with a leading 0x05 read as the 0xE5 it stands for, as not every
+/// writer stores names the way it should.
+pub(crate) fn short_name_key(short_name: &[u8]) -> [u8; 11] {
+    let mut key = [b' '; 11];
+
+    for (key_byte, byte) in key.iter_mut().zip(short_name) {
+        *key_byte = byte.to_ascii_uppercase();
+    }
+
+    if key[0] == 0x05 {
+        key[0] = 0xE5;
+    }
+
+    key
+}
+
+/// Compares two names the way FAT does, ignoring case.
+pub(crate) fn names_equal(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_uppercase)
+        .eq(b.chars().flat_map(char::to_uppercase))
+}
+
+/// What `names_equal` compares, for looking names up in maps.
+pub(crate) fn name_key(name: &str) -> String {
+    name.chars().flat_map(char::to_uppercase).collect()
+}
+
+/// The characters long names can't have, besides control characters.
+pub const INVALID_LONG_NAME_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// The characters short names can't have, besides control characters, which long names
+/// can. They're replaced with `_` in the short names made for long names.
+pub const INVALID_SHORT_NAME_CHARS: &[char] = &[
+    '"', '*', '+', ',', '.', '/', ':', ';', '<', '=', '>', '?', '[', '\\', ']', '|',
+];
+
+/// The names Windows keeps for devices, which it won't open as files whatever their
+/// extension, e.g. `nul.txt`.
+pub const RESERVED_DEVICE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks that `name` can be given to a new entry. Names ending in a space or a period
+/// are refused rather than trimmed, as Windows would, as are reserved device names.
+pub fn validate_long_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.ends_with([' ', '.'])
+        && name.encode_utf16().count() <= 255
+        && !name
+            .chars()
+            .any(|ch| ch < ' ' || INVALID_LONG_NAME_CHARS.contains(&ch))
+        && !is_reserved_device_name(name);
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidName)
+    }
+}
+
+/// Checks that `name` is an 8.3 name, which can be stored as a short name as it is,
+/// ignoring case: up to eight characters, then optionally a period and up to three
+/// more, none of them spaces, non-ASCII or among `INVALID_SHORT_NAME_CHARS`. Reserved
+/// device names are refused.
+pub fn validate_short_name(name: &str) -> Result<()> {
+    let (base, ext) = match name.split_once('.') {
+        Some((base, ext)) => (base, Some(ext)),
+        None => (name, None),
+    };
+
+    let valid_ext = match ext {
+        Some(ext) => (1..=3).contains(&ext.len()) && ext.chars().all(is_short_name_char),
+        None => true,
+    };
+
+    let valid = (1..=8).contains(&base.len())
+        && base.chars().all(is_short_name_char)
+        && valid_ext
+        && !is_reserved_device_name(name);
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidName)
+    }
+}
+
+/// Whether `ch` can be part of a short name, besides spaces, which the names this
+/// makes never have.
+fn is_short_name_char(ch: char) -> bool {
+    ch.is_ascii_graphic() && !INVALID_SHORT_NAME_CHARS.contains(&ch)
+}
+
+fn is_reserved_device_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+
+    RESERVED_DEVICE_NAMES
+        .iter()
+        .any(|device| stem.eq_ignore_ascii_case(device))
+}
+
+/// The characters of a long name, with unpaired surrogates decoded as `policy` says,
+/// or `None` if the entry goes by its short name instead.
+pub(crate) fn long_name_chars(
+    long_name: &[u16],
+    policy: InvalidUtf16Policy,
+) -> Option<LongNameChars<'_>> {
+    if policy == InvalidUtf16Policy::ShortName && is_invalid_utf16(long_name) {
+        return None;
+    }
+
+    Some(LongNameChars {
+        units: core::char::decode_utf16(long_name.iter().copied()),
+        policy,
+        escape: None,
+    })
+}
+
+/// Whether `long_name` has unpaired surrogates.
+pub(crate) fn is_invalid_utf16(long_name: &[u16]) -> bool {
+    core::char::decode_utf16(long_name.iter().copied()).any(|ch| ch.is_err())
+}
+
+pub(crate) struct LongNameChars<'a> {
+    units: DecodeUtf16<Copied<slice::Iter<'a, u16>>>,
+    policy: InvalidUtf16Policy,
+    /// The unpaired surrogate being percent-encoded, and how many characters of its
+    /// `%uXXXX` have been given.
+    escape: Option<(u16, u32)>,
+}
+
+impl Iterator for LongNameChars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if let Some((unit, given)) = self.escape {
+            let ch = match given {
+                1 => 'u',
+                _ => {
+                    let digit = (unit >> (12 - 4 * (given - 2))) & 0xF;
+                    core::char::from_digit(u32::from(digit), 16)?.to_ascii_uppercase()
+                }
+            };
+
+            self.escape = Some((unit, given + 1)).filter(|&(_, given)| given < 6);
+            return Some(ch);
+        }
+
+        match (self.units.next()?, self.policy) {
+            (Ok(ch), _) => Some(ch),
+            // NOTE: long names with unpaired surrogates aren't decoded at all when
+            // entries go by their short names
+            (Err(_), InvalidUtf16Policy::Replace | InvalidUtf16Policy::ShortName) => {
+                Some(core::char::REPLACEMENT_CHARACTER)
+            }
+            (Err(err), InvalidUtf16Policy::PercentEncode) => {
+                self.escape = Some((err.unpaired_surrogate(), 1));
+                Some('%')
+            }
+        }
+    }
+}
+
+/// What the boot sector's label field holds when a volume has no label.
+pub(crate) const NO_LABEL: [u8; 11] = *b"NO NAME    ";
+
+/// The bytes of a volume label entry's name for `label`, upper-cased and padded with
+/// spaces, refusing labels that are too long, start with a space, or have characters
+/// short names can't.
+pub(crate) fn volume_label_bytes(label: &str) -> Result<[u8; 11]> {
+    let mut bytes = [b' '; 11];
+
+    if label.len() > bytes.len() || label.starts_with(' ') {
+        return Err(Error::InvalidName);
+    }
+
+    for (byte, ch) in bytes.iter_mut().zip(label.chars()) {
+        if ch != ' ' && !is_short_name_char(ch) {
+            return Err(Error::InvalidName);
+        }
+
+        *byte = ch.to_ascii_uppercase() as u8;
+    }
+
+    Ok(bytes)
+}
+
+/// The short name a new entry is based on, which gets a numeric tail (`~1` and so on)
+/// when the long name doesn't fit in 8.3.
+pub(crate) struct ShortNameBasis {
+    base: Vec<u8>,
+    ext: Vec<u8>,
+    /// Whether nothing was lost or truncated in making the short name.
+    lossless: bool,
+    /// Whether the short name is the long name as it stands, so that no long file name
+    /// entries are needed.
+    exact: bool,
+}
+
+impl ShortNameBasis {
+    pub fn new(long_name: &str) -> Self {
+        let (stem, ext) = match long_name.rfind('.') {
+            Some(index) if index > 0 => (&long_name[..index], &long_name[(index + 1)..]),
+            _ => (long_name, ""),
+        };
+
+        let mut lossless = true;
+        let mut exact = true;
+
+        let mut base = Self::convert(stem, 8, &mut lossless, &mut exact);
+        let ext = Self::convert(ext, 3, &mut lossless, &mut exact);
+
+        if base.is_empty() {
+            base.push(b'_');
+            lossless = false;
+        }
+
+        Self {
+            base,
+            ext,
+            lossless,
+            exact: lossless && exact,
+        }
+    }
+
+    fn convert(part: &str, max_len: usize, lossless: &mut bool, exact: &mut bool) -> Vec<u8> {
+        let mut result = Vec::with_capacity(max_len);
+
+        for ch in part.chars() {
+            let byte = match ch {
+                ' ' | '.' => {
+                    *lossless = false;
+                    continue;
+                }
+                'a'..='z' => {
+                    *exact = false;
+                    ch.to_ascii_uppercase() as u8
+                }
+                _ if is_short_name_char(ch) => ch as u8,
+                _ => {
+                    *lossless = false;
+                    b'_'
+                }
+            };
+
+            if result.len() == max_len {
+                *lossless = false;
+                break;
+            }
+
+            result.push(byte);
+        }
+
+        result
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// Gives up on storing the name as its short name alone, which makes a long name
+    /// necessary.
+    pub fn inexact(mut self) -> Self {
+        self.exact = false;
+        self
+    }
+
+    /// The candidate short names, as the 11 padded bytes, in the order they should be
+    /// tried. The name without a tail is only a candidate when nothing was lost, and
+    /// is the only one when it's exact, as there's no long name to fall back on.
+    pub fn candidates(&self) -> impl Iterator<Item = [u8; 11]> + '_ {
+        let first_tail = if self.lossless { 0 } else { 1 };
+        let end_tail = if self.exact { 1 } else { 1_000_000 };
+
+        (first_tail..end_tail).map(move |tail| self.with_tail(tail))
+    }
+
+    fn with_tail(&self, tail: u32) -> [u8; 11] {
+        let mut short_name = [b' '; 11];
+
+        let mut digits = [0u8; 7];
+        let mut digits_len = 0;
+
+        if tail > 0 {
+            let mut remaining = tail;
+
+            while remaining > 0 {
+                digits[digits_len] = b'0' + (remaining % 10) as u8;
+                digits_len += 1;
+                remaining /= 10;
+            }
+
+            digits[digits_len] = b'~';
+            digits_len += 1;
+            digits[..digits_len].reverse();
+        }
+
+        let base_len = core::cmp::min(self.base.len(), 8 - digits_len);
+        short_name[..base_len].copy_from_slice(&self.base[..base_len]);
+        short_name[base_len..(base_len + digits_len)].copy_from_slice(&digits[..digits_len]);
+        short_name[8..(8 + self.ext.len())].copy_from_slice(&self.ext);
+
+        short_name
+    }
+}
+
+/// Builds the long file name entries for `name`, in the order they're stored, which
+/// is last part of the name first.
+pub(crate) fn long_name_entries(name: &str, checksum: u8) -> Vec<[u8; DirectoryEntry::SIZE]> {
+    // The byte offsets of the 13 characters held by each entry
+    const CHAR_OFFSETS: [usize; LongNameAssembler::CHARS_PER_ENTRY] =
+        [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+    let chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceiling(LongNameAssembler::CHARS_PER_ENTRY);
+
+    (0..count)
+        .rev()
+        .map(|index| {
+            let mut entry = [0u8; DirectoryEntry::SIZE];
+
+            entry[LongFileNameEntry::RANGE_ORDER][0] = if index == count - 1 {
+                (index as u8 + 1) | LongNameAssembler::LAST_ENTRY_FLAG
+            } else {
+                index as u8 + 1
+            };
+            entry[LongFileNameEntry::RANGE_ATTR][0] = 0x0F;
+            entry[LongFileNameEntry::RANGE_CHECKSUM][0] = checksum;
+
+            for (slot, offset) in CHAR_OFFSETS.iter().enumerate() {
+                let position = index * LongNameAssembler::CHARS_PER_ENTRY + slot;
+
+                // The name is terminated with a zero if there's room, then padded
+                let value = match position.cmp(&chars.len()) {
+                    core::cmp::Ordering::Less => chars[position],
+                    core::cmp::Ordering::Equal => 0x0000,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+
+                entry[*offset..(*offset + 2)].copy_from_slice(&value.to_le_bytes());
+            }
+
+            entry
+        })
+        .collect()
+}
+
+/// Formats a short name as `NAME.EXT`, honouring the lower-case flags Windows NT
+/// stores in the reserved byte.
+pub(crate) fn format_short_name(entry: &StandardDirectoryEntry) -> String {
+    short_name_chars(entry).collect()
+}
+
+/// The characters of `format_short_name`, without building a string of them.
+pub(crate) fn short_name_chars<'e>(
+    entry: &'e StandardDirectoryEntry,
+) -> impl Iterator<Item = char> + 'e {
+    const LOWER_CASE_NAME: u8 = 0x08;
+    const LOWER_CASE_EXT: u8 = 0x10;
+
+    let case_flags = entry.case_flags();
+    let ext = trim_padding(entry.ext());
+
+    let name = trim_padding(entry.name())
+        .iter()
+        .enumerate()
+        .map(move |(index, byte)| {
+            // 0x05 stands in for a leading 0xE5, which would otherwise mark the entry
+            // as free
+            let byte = if index == 0 && *byte == 0x05 {
+                0xE5
+            } else {
+                *byte
+            };
+            short_name_char(byte, case_flags & LOWER_CASE_NAME != 0)
+        });
+
+    let dot = if ext.is_empty() { None } else { Some('.') };
+    let ext = ext
+        .iter()
+        .map(move |byte| short_name_char(*byte, case_flags & LOWER_CASE_EXT != 0));
+
+    name.chain(dot).chain(ext)
+}
+
+fn trim_padding(bytes: &[u8]) -> &[u8] {
+    let len = bytes
+        .iter()
+        .rposition(|byte| *byte != b' ')
+        .map_or(0, |index| index + 1);
+
+    &bytes[..len]
+}
+
+fn short_name_char(byte: u8, lower_case: bool) -> char {
+    // TODO: bytes above 0x7F are in the volume's OEM code page, which we don't know, so
+    // they're treated as Latin-1
+    let ch = char::from(byte);
+
+    if lower_case {
+        ch.to_ascii_lowercase()
+    } else {
+        ch
+    }
+}
+
+/// Collects the long file name entries that precede a standard entry, and hands back
+/// the assembled name if the set is complete and belongs to that entry.
+pub(crate) struct LongNameAssembler {
+    /// Fragments arrive last first, so the name is built backwards from the end.
+    chars: [u16; Self::CAPACITY],
+    start: usize,
+    next_order: u8,
+    checksum: u8,
+}
+
+impl Default for LongNameAssembler {
+    fn default() -> Self {
+        Self {
+            chars: [0; Self::CAPACITY],
+            start: Self::CAPACITY,
+            next_order: 0,
+            checksum: 0,
+        }
+    }
+}
+
+impl LongNameAssembler {
+    pub(crate) const LAST_ENTRY_FLAG: u8 = 0x40;
+    pub(crate) const ORDER_MASK: u8 = 0x1F;
+    const CHARS_PER_ENTRY: usize = 13;
+    const CAPACITY: usize = 20 * Self::CHARS_PER_ENTRY;
+
+    pub fn push(&mut self, entry: &LongFileNameEntry) {
+        let order = entry.order() & Self::ORDER_MASK;
+
+        if entry.order() & Self::LAST_ENTRY_FLAG != 0 {
+            // The physically first entry holds the end of the name and starts a new set
+            self.start = Self::CAPACITY;
+            self.checksum = entry.checksum();
+        } else if order != self.next_order || entry.checksum() != self.checksum {
+            self.reset();
+            return;
+        }
+
+        if order == 0 {
+            self.reset();
+            return;
+        }
+
+        let mut fragment = [0u16; Self::CHARS_PER_ENTRY];
+        let mut fragment_len = 0;
+
+        for c in entry.chars().take(Self::CHARS_PER_ENTRY) {
+            fragment[fragment_len] = c;
+            fragment_len += 1;
+        }
+
+        if fragment_len > self.start {
+            self.reset();
+            return;
+        }
+
+        self.start -= fragment_len;
+        self.chars[self.start..(self.start + fragment_len)]
+            .copy_from_slice(&fragment[..fragment_len]);
+        self.next_order = order - 1;
+    }
+
+    /// Hands back the name as UTF-16 if it belongs to `entry`, borrowed rather than
+    /// allocated, so it's only valid until the next `push`.
+    pub fn take(&mut self, entry: &StandardDirectoryEntry) -> Option<&[u16]> {
+        let start = self.start;
+        let complete = start < Self::CAPACITY && self.next_order == 0;
+        let matches = short_name_checksum(entry.short_name()) == self.checksum;
+
+        self.reset();
+
+        if complete && matches {
+            Some(&self.chars[start..])
+        } else {
+            None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.start = Self::CAPACITY;
+        self.next_order = 0;
+    }
+}
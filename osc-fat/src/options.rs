@@ -0,0 +1,367 @@
+use crate::names::{name_key, names_equal};
+use crate::support::FatReadPolicy;
+use crate::{FatDateTime, Metadata, TimeZonePolicy};
+use alloc::string::String;
+use core::fmt;
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MountOptions {
+    /// Refuse every mutating operation with `Error::WriteProtected`, even when the
+    /// underlying device is writable.
+    pub read_only: bool,
+
+    /// When a sector of the first FAT can't be read, read the same sector from the
+    /// other FAT copies instead, as hardware FAT drivers do on marginal media.
+    pub fat_mirror_fallback: bool,
+
+    /// When the copies of the FAT disagree about an entry, go with what most of them
+    /// say, or without a majority, with the copy whose entry leads somewhere a chain
+    /// can, rather than always with the first copy. Every copy is read for every
+    /// entry, so this is for reading damaged media. Disagreements are logged with the
+    /// `tracing` feature.
+    pub fat_majority_vote: bool,
+
+    /// How much the filesystem reads per device call into the buffers it allocates
+    /// itself.
+    pub read_granularity: ReadGranularity,
+
+    /// The zone the timestamps in the filesystem are assumed to be in, which is used
+    /// when stamping entries with the system clock.
+    pub time_zone: TimeZonePolicy,
+
+    /// Makes changes to the filesystem depend on nothing but the changes themselves,
+    /// for reproducible builds.
+    pub deterministic: Option<Deterministic>,
+
+    /// Bounds on how much work following what's on the volume can take, for images
+    /// from sources that can't be trusted.
+    pub limits: Limits,
+
+    /// How names are compared when looking entries up and checking that new ones don't
+    /// clash with those already there.
+    pub name_matching: NameMatching,
+
+    /// How long names that aren't valid UTF-16 are turned into names.
+    pub invalid_utf16: InvalidUtf16Policy,
+
+    /// Whether anything after a directory's end marker is read.
+    pub end_of_directory: EndOfDirectoryPolicy,
+
+    /// Tells the device when clusters are freed, by deleting or truncating files, with
+    /// `BlockDevice::discard`, so flash storage can erase them ahead of time.
+    pub discard: bool,
+
+    /// Where the search for a free cluster to allocate starts.
+    pub allocation_policy: AllocationPolicy,
+
+    /// How much the filesystem's caches can hold.
+    pub cache: CacheConfig,
+
+    /// Runs `FATFileSystem::quick_check` when the volume is opened, so its report can
+    /// be had from `FATFileSystem::quick_check_report`, e.g. to warn users before
+    /// serving a volume that wasn't cleanly unmounted.
+    pub quick_check: bool,
+}
+
+impl MountOptions {
+    /// Options for images that can't be trusted, such as ones uploaded to a service.
+    ///
+    /// Mounted with these, nothing on the image can make the filesystem panic or loop
+    /// forever: a boot sector describing an impossible layout, chains leading out of
+    /// the data region, and volumes claiming to be bigger than their device all fail
+    /// with `Error::Corrupt`, and the limits fail with `Error::LimitExceeded`. There's
+    /// no chain length limit here, as no chain is ever followed for more clusters than
+    /// the volume has, so chains looping back on themselves fail with
+    /// `Error::LimitExceeded(Limit::ChainLength)` once they get that far. Nothing is
+    /// ever written, and the crate has no unsafe code outside of reading the local time
+    /// zone with the `std` feature. The parsing behind all of this is exercised by the
+    /// fuzz target in `fuzz/`.
+    pub fn hardened() -> Self {
+        Self {
+            read_only: true,
+            limits: Limits {
+                max_depth: Some(64),
+                max_directory_entries: Some(65536),
+                max_chain_length: None,
+                max_long_name_length: Some(255),
+            },
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn fat_read_policy(&self) -> FatReadPolicy {
+        FatReadPolicy {
+            mirror_fallback: self.fat_mirror_fallback,
+            majority_vote: self.fat_majority_vote,
+        }
+    }
+}
+
+/// With these, making the same changes to copies of the same image gives byte-identical
+/// results. Changes take what they'd otherwise get from the clock or the system from
+/// here, and clusters are allocated lowest first, so the layout doesn't depend on the
+/// order things were freed in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Deterministic {
+    /// The time every entry is stamped with.
+    pub timestamp: FatDateTime,
+
+    /// Written as the volume serial number ahead of the first change.
+    pub volume_serial: u32,
+}
+
+impl Default for Deterministic {
+    fn default() -> Self {
+        Self {
+            timestamp: FatDateTime::EPOCH,
+            volume_serial: 0,
+        }
+    }
+}
+
+/// Bounds on what the filesystem follows, each of which fails the operation that hits
+/// it with `Error::LimitExceeded` naming it. There are none by default, though chains
+/// are never followed for more clusters than the volume has, which stops ones that
+/// loop back on themselves.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// How many directories deep paths and walks of the tree can go.
+    pub max_depth: Option<u32>,
+    /// How many entries, in use or not, are read from a directory.
+    pub max_directory_entries: Option<u32>,
+    /// How many clusters a chain can have.
+    pub max_chain_length: Option<u32>,
+    /// How many UTF-16 code units a long file name can have.
+    pub max_long_name_length: Option<u32>,
+}
+
+/// How much each of the filesystem's caches can hold, which bounds the memory they
+/// take. A cache given nothing to hold is turned off. How full they are, and how
+/// often they're of use, is given by `FATFileSystem::cache_stats`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The bytes of device blocks held by the sector cache, which holds the blocks
+    /// outside the FAT, such as those of directories and files. Reads are kept within
+    /// this, so as not to push out everything else, making it the most that's read
+    /// per device call.
+    pub sector_cache_bytes: usize,
+    /// The bytes of device blocks held for the FAT, kept apart from the sector cache so
+    /// that reading files doesn't push out the FAT.
+    pub fat_cache_bytes: usize,
+    /// How many directory entries that paths have been looked up through are held.
+    /// Writing anything to the volume empties it.
+    pub dir_cache_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            sector_cache_bytes: 128 * 1024,
+            fat_cache_bytes: 64 * 1024,
+            dir_cache_entries: 256,
+        }
+    }
+}
+
+/// One of the `Limits`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    DirectoryEntries,
+    ChainLength,
+    LongNameLength,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Depth => write!(f, "directory depth"),
+            Self::DirectoryEntries => write!(f, "directory entry count"),
+            Self::ChainLength => write!(f, "cluster chain length"),
+            Self::LongNameLength => write!(f, "long name length"),
+        }
+    }
+}
+
+/// How a name given to the filesystem is matched against the names of entries.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum NameMatching {
+    /// Ignoring case, as Windows does, against both of an entry's names. Entries keep
+    /// the case they were created with.
+    #[default]
+    CaseInsensitive,
+    /// Exactly, against an entry's long name or, if it has none, its short name, for
+    /// volumes carrying files from systems where names differing only in case are
+    /// different files. Short names are still kept apart ignoring case, so such names
+    /// get long file name entries where they'd otherwise share a short name.
+    CaseSensitive,
+}
+
+impl NameMatching {
+    /// Whether `a` and `b` are the same name.
+    pub fn matches(self, a: &str, b: &str) -> bool {
+        match self {
+            Self::CaseInsensitive => names_equal(a, b),
+            Self::CaseSensitive => a == b,
+        }
+    }
+
+    /// Like `matches`, with the first name as characters, such as those of a long name
+    /// still in its entries.
+    pub(crate) fn matches_chars<I>(self, a: I, b: &str) -> bool
+    where
+        I: Iterator<Item = char>,
+    {
+        match self {
+            Self::CaseInsensitive => a
+                .flat_map(char::to_uppercase)
+                .eq(b.chars().flat_map(char::to_uppercase)),
+            Self::CaseSensitive => a.eq(b.chars()),
+        }
+    }
+
+    /// Whether `item` is the entry `name` refers to. The volume label is never matched.
+    pub(crate) fn is_called(self, item: &Metadata, name: &str) -> bool {
+        !item.attributes.is_volume_id()
+            && self
+                .lookup_names(item)
+                .any(|item_name| self.matches(item_name, name))
+    }
+
+    /// The names `item` can be looked up by, which leaves out the short name when case
+    /// matters, as the one made for a long name is in upper case whatever its case.
+    pub(crate) fn lookup_names(self, item: &Metadata) -> impl Iterator<Item = &str> {
+        let short_name = match self {
+            Self::CaseInsensitive => Some(item.short_name.as_str()),
+            Self::CaseSensitive => None,
+        };
+
+        core::iter::once(item.name.as_str()).chain(short_name)
+    }
+
+    /// What `matches` compares, for looking names up in maps.
+    pub(crate) fn key(self, name: &str) -> String {
+        match self {
+            Self::CaseInsensitive => name_key(name),
+            Self::CaseSensitive => String::from(name),
+        }
+    }
+}
+
+/// How the unpaired surrogates that can be in long names, which aren't valid UTF-16,
+/// are decoded. `Metadata::raw_long_name` has such names as they're stored either way.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidUtf16Policy {
+    /// As U+FFFD, which is what's shown for them elsewhere, but makes names differing
+    /// only in those surrogates the same name, of which only the first can be looked up.
+    #[default]
+    Replace,
+    /// As `%u` and four hex digits, e.g. `%uD800`, which looks them up too, so that
+    /// every name can be reached by path.
+    PercentEncode,
+    /// By the entry's short name instead, which every entry has and is always valid,
+    /// so the name can be given to hosts that only take valid Unicode, such as FUSE.
+    /// The long name is kept in `Metadata::raw_long_name`.
+    ShortName,
+}
+
+/// What's made of a directory's entries after its end marker, an entry starting with a
+/// 0 byte, which the spec has as free along with every entry after it.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum EndOfDirectoryPolicy {
+    /// Nothing after the marker is read, as the spec has it.
+    #[default]
+    Stop,
+    /// The rest of the cluster the marker is in (or of FAT12/16's root directory) is
+    /// read too, skipping free entries, for entries left behind by writers that end
+    /// directories early without clearing what's after, e.g. to recover files from an
+    /// image. The entries found are still free as far as writing goes, so they can be
+    /// looked up and read, but not changed, and new entries can be written over them.
+    ScanCluster,
+}
+
+/// Where the search for a free cluster starts. Either way the search goes on to the
+/// end of the volume and then round from its start, and `Deterministic` allocation
+/// always starts from the lowest free cluster.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum AllocationPolicy {
+    /// From the start of the volume when it's mounted, and then from after the
+    /// cluster last allocated, so that low clusters are reused first.
+    #[default]
+    NextFit,
+    /// From where allocation left off when the volume was last synced, as recorded
+    /// in the FSInfo sector, so that writes are spread across the whole volume rather
+    /// than concentrated in its low clusters, evening out the wear on flash media.
+    /// FAT12/16 volumes have no FSInfo sector, so on those the search starts after the
+    /// highest cluster in use instead.
+    Rotating,
+}
+
+/// The size of the buffers used for reads, which bounds how much is read in one device
+/// call. Reads never go beyond the end of the cluster being read (or the FAT), so
+/// anything over a cluster only helps FAT lookups.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ReadGranularity {
+    /// A sector (or a device block, if that's larger) at a time.
+    Sector,
+    /// Whole clusters.
+    #[default]
+    Cluster,
+    Sectors(u16),
+}
+
+/// How `open_file_with_options` opens a file. Files are always readable.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct OpenOptions {
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+
+    /// Opens for writing, with every write going to the end of the file wherever the
+    /// position is. The last cluster of the file is remembered, so a series of
+    /// appends never walks the chain more than once.
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Empties the file on opening, which needs `write` or `append` too.
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Creates the file if it doesn't exist, which needs `write` or `append` too.
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    pub(crate) fn is_writing(&self) -> bool {
+        self.write || self.append
+    }
+
+    pub(crate) fn is_appending(&self) -> bool {
+        self.append
+    }
+
+    pub(crate) fn is_truncating(&self) -> bool {
+        self.truncate
+    }
+
+    pub(crate) fn is_creating(&self) -> bool {
+        self.create
+    }
+}
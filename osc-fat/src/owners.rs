@@ -0,0 +1,142 @@
+use crate::error::Result;
+use crate::{Cluster, DirectorySelector, FATFileSystem, FATGeometry, FatEntry, RootDirectory};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Which file or directory each cluster of a volume belongs to, as it was when the
+/// index was built, for finding out what a bad sector has damaged.
+#[derive(Debug, Clone)]
+pub struct ClusterOwners {
+    geo: FATGeometry,
+    /// The sectors of the FAT12/16 root directory, which isn't in a cluster.
+    root_region: Option<Range<u64>>,
+    paths: Vec<String>,
+    /// Runs of contiguous clusters, as the first cluster, the number of clusters and
+    /// the index of the owner's path, sorted by first cluster.
+    extents: Vec<(Cluster, u32, usize)>,
+}
+
+impl ClusterOwners {
+    /// The path of the file or directory `cluster` belongs to, or `None` if it's free
+    /// or lost. A cluster that's in more than one chain is given one of its owners.
+    pub fn owner(&self, cluster: Cluster) -> Option<&str> {
+        let index = self
+            .extents
+            .partition_point(|&(first_cluster, _, _)| first_cluster <= cluster);
+
+        let (first_cluster, cluster_count, path_index) =
+            *self.extents.get(index.checked_sub(1)?)?;
+
+        if cluster - first_cluster < cluster_count {
+            Some(&self.paths[path_index])
+        } else {
+            None
+        }
+    }
+
+    /// The path of the file or directory holding `sector`, numbered from the start of
+    /// the volume, or `None` if it's in an unused cluster or the volume's own
+    /// structures, such as the boot sector or the FAT.
+    pub fn owner_of_sector(&self, sector: u64) -> Option<&str> {
+        if matches!(self.root_region, Some(ref root_region) if root_region.contains(&sector)) {
+            return Some("/");
+        }
+
+        let data_sector = sector.checked_sub(self.geo.first_data_sector())?;
+        let cluster = data_sector / u64::from(self.geo.cluster_size_sectors()) + 2;
+
+        if cluster > u64::from(Cluster::MAX) {
+            return None;
+        }
+
+        self.owner(cluster as Cluster)
+    }
+
+    /// The clusters of every file and directory, with their paths.
+    pub fn iter(&self) -> impl Iterator<Item = (Range<Cluster>, &str)> {
+        self.extents
+            .iter()
+            .map(move |&(first_cluster, cluster_count, path_index)| {
+                (
+                    first_cluster..(first_cluster + cluster_count),
+                    self.paths[path_index].as_str(),
+                )
+            })
+    }
+}
+
+impl FATFileSystem {
+    /// Builds an index of which file or directory owns each cluster, by following the
+    /// chain of everything on the volume.
+    pub fn cluster_owners(&self) -> Result<ClusterOwners> {
+        let mut owners = ClusterOwners {
+            geo: self.geo,
+            root_region: None,
+            paths: Vec::new(),
+            extents: Vec::new(),
+        };
+
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+
+        match self.root {
+            RootDirectory::Chain(cluster) => {
+                self.add_owner(&mut owners, &mut buffer, "/", cluster)?;
+            }
+            RootDirectory::Region {
+                first_sector,
+                sector_count,
+            } => {
+                owners.root_region = Some(first_sector..(first_sector + u64::from(sector_count)));
+            }
+        }
+
+        self.walk_tree(DirectorySelector::Root, |path, item| {
+            self.add_owner(&mut owners, &mut buffer, path, item.first_cluster)
+        })?;
+
+        owners.extents.sort_unstable();
+
+        Ok(owners)
+    }
+
+    /// Records the runs of contiguous clusters in the chain starting at
+    /// `first_cluster` as belonging to `path`.
+    fn add_owner(
+        &self,
+        owners: &mut ClusterOwners,
+        buffer: &mut [u8],
+        path: &str,
+        first_cluster: Cluster,
+    ) -> Result<()> {
+        let path_index = owners.paths.len();
+        let extent_count = owners.extents.len();
+
+        let mut fat = self.fat_reader(buffer);
+        let mut cluster = first_cluster;
+        let mut cluster_count = 0;
+
+        // NOTE: a corrupt chain that loops back on itself is only followed for as many
+        // clusters as the volume has
+        while self.is_data_cluster(cluster) && cluster_count < self.geo.cluster_count {
+            match owners.extents[extent_count..].last_mut() {
+                Some((first, count, _)) if *first + *count == cluster => *count += 1,
+                _ => owners.extents.push((cluster, 1, path_index)),
+            }
+
+            cluster_count += 1;
+
+            cluster = match FatEntry::from_value(fat.read(cluster)?, self.geo.variant) {
+                FatEntry::Next(next) => next,
+                _ => break,
+            };
+        }
+
+        if cluster_count > 0 {
+            owners.paths.push(String::from(path));
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::math::DivCeiling;
-use crate::support::{ByteRange, DataStructure};
+use crate::support::{ByteRange, DataStructure, DataStructureMut};
 use crate::*;
 
 pub const BIOS_PARAMETER_BLOCK_SIZE: usize = 512;
@@ -10,22 +10,22 @@ pub struct CommonBiosParameterBlock<'a>(&'a [u8]);
 impl<'a> CommonBiosParameterBlock<'a> {
     pub const SIZE: usize = 36;
 
-    const RANGE_JUMP: ByteRange = 0..3;
-    const RANGE_OEM: ByteRange = 3..11;
-    const RANGE_BYTES_PER_SECTOR: ByteRange = 11..13;
-    const RANGE_SECTORS_PER_CLUSTER: ByteRange = 13..14;
-    const RANGE_RESERVED_SECTOR_COUNT: ByteRange = 14..16;
-    const RANGE_NUM_FATS: ByteRange = 16..17;
+    pub(crate) const RANGE_JUMP: ByteRange = 0..3;
+    pub(crate) const RANGE_OEM: ByteRange = 3..11;
+    pub(crate) const RANGE_BYTES_PER_SECTOR: ByteRange = 11..13;
+    pub(crate) const RANGE_SECTORS_PER_CLUSTER: ByteRange = 13..14;
+    pub(crate) const RANGE_RESERVED_SECTOR_COUNT: ByteRange = 14..16;
+    pub(crate) const RANGE_NUM_FATS: ByteRange = 16..17;
     // NOTE: zero for FAT32
-    const RANGE_ROOT_ENTRY_COUNT: ByteRange = 17..19;
-    const RANGE_TOTAL_SECTORS_16: ByteRange = 19..21;
-    const RANGE_MEDIA: ByteRange = 21..22;
+    pub(crate) const RANGE_ROOT_ENTRY_COUNT: ByteRange = 17..19;
+    pub(crate) const RANGE_TOTAL_SECTORS_16: ByteRange = 19..21;
+    pub(crate) const RANGE_MEDIA: ByteRange = 21..22;
     // NOTE: zero for FAT32
-    const RANGE_SECTORS_PER_FAT_16: ByteRange = 22..24;
-    const RANGE_SECTORS_PER_TRACK: ByteRange = 24..26;
-    const RANGE_NUM_HEADS: ByteRange = 26..28;
-    const RANGE_HIDDEN_SECTORS: ByteRange = 28..32;
-    const RANGE_TOTAL_SECTORS_32: ByteRange = 32..36;
+    pub(crate) const RANGE_SECTORS_PER_FAT_16: ByteRange = 22..24;
+    pub(crate) const RANGE_SECTORS_PER_TRACK: ByteRange = 24..26;
+    pub(crate) const RANGE_NUM_HEADS: ByteRange = 26..28;
+    pub(crate) const RANGE_HIDDEN_SECTORS: ByteRange = 28..32;
+    pub(crate) const RANGE_TOTAL_SECTORS_32: ByteRange = 32..36;
 
     pub fn oem(&self) -> &[u8] {
         self.0.range(Self::RANGE_OEM)
@@ -81,14 +81,49 @@ pub struct ExtendedBiosParameterBlock<'a>(&'a [u8]);
 
 #[allow(dead_code)]
 impl<'a> ExtendedBiosParameterBlock<'a> {
-    const RANGE_DRIVE_NUM: ByteRange = 36..37;
-    const RANGE_RESV1: ByteRange = 37..38;
-    const RANGE_BOOT_SIG: ByteRange = 38..39;
-    const RANGE_VOL_ID: ByteRange = 39..43;
-    const RANGE_VOL_LAB: ByteRange = 43..54;
-    const RANGE_FS_TYPE: ByteRange = 54..62;
-    const RANGE_BOOT: ByteRange = 62..510;
-    const RANGE_SIG_WORD: ByteRange = 510..512;
+    pub(crate) const RANGE_DRIVE_NUM: ByteRange = 36..37;
+    pub(crate) const RANGE_RESV1: ByteRange = 37..38;
+    pub(crate) const RANGE_BOOT_SIG: ByteRange = 38..39;
+    pub(crate) const RANGE_VOL_ID: ByteRange = 39..43;
+    pub(crate) const RANGE_VOL_LAB: ByteRange = 43..54;
+    pub(crate) const RANGE_FS_TYPE: ByteRange = 54..62;
+    pub(crate) const RANGE_BOOT: ByteRange = 62..510;
+    pub(crate) const RANGE_SIG_WORD: ByteRange = 510..512;
+
+    pub fn drive_number(&self) -> u8 {
+        self.0.u8(Self::RANGE_DRIVE_NUM)
+    }
+
+    /// 0x29 if the volume id, label and filesystem type follow, or 0x28 if only the
+    /// volume id does. Anything else means they're part of the boot code.
+    pub fn boot_signature(&self) -> u8 {
+        self.0.u8(Self::RANGE_BOOT_SIG)
+    }
+
+    /// Whether the boot signature is 0x29, which says the volume id, label and
+    /// filesystem type are all there.
+    pub fn has_extended_boot_signature(&self) -> bool {
+        self.boot_signature() == 0x29
+    }
+
+    /// Whether the sector ends in the 0xAA55 signature word that marks it as a boot
+    /// sector.
+    pub fn has_valid_signature(&self) -> bool {
+        self.0.u16(Self::RANGE_SIG_WORD) == 0xAA55
+    }
+
+    pub fn volume_id(&self) -> u32 {
+        self.0.u32(Self::RANGE_VOL_ID)
+    }
+
+    pub fn volume_label(&self) -> &'a [u8] {
+        &self.0[Self::RANGE_VOL_LAB]
+    }
+
+    /// Informational only, e.g. "FAT16   ", which mustn't be used to tell the variant.
+    pub fn fs_type(&self) -> &'a [u8] {
+        &self.0[Self::RANGE_FS_TYPE]
+    }
 }
 
 impl<'a> From<&'a [u8]> for ExtendedBiosParameterBlock<'a> {
@@ -101,29 +136,75 @@ pub struct ExtendedFat32BiosParameterBlock<'a>(&'a [u8]);
 
 #[allow(dead_code)]
 impl<'a> ExtendedFat32BiosParameterBlock<'a> {
-    const RANGE_SECTORS_PER_FAT_32: ByteRange = 36..40;
-    const RANGE_EXT_FLAGS: ByteRange = 40..42;
-    const RANGE_FS_VER: ByteRange = 42..44;
-    const RANGE_ROOT_CLUSTER: ByteRange = 44..48;
-    const RANGE_FS_INFO_SECTOR: ByteRange = 48..50;
-    const RANGE_BACKUP_BOOT_SECTOR: ByteRange = 50..52;
-    const RANGE_RESERVED: ByteRange = 52..64;
-    const RANGE_DRIVE_NUM: ByteRange = 64..65;
-    const RANGE_RESERVED1: ByteRange = 65..66;
-    const RANGE_BOOT_SIG: ByteRange = 66..67;
-    const RANGE_VOL_ID: ByteRange = 67..71;
-    const RANGE_VOL_LAB: ByteRange = 71..82;
-    const RANGE_FS_TYPE: ByteRange = 82..90;
-    const RANGE_BOOT: ByteRange = 90..510;
-    const RANGE_SIG_WORD: ByteRange = 510..512;
+    pub(crate) const RANGE_SECTORS_PER_FAT_32: ByteRange = 36..40;
+    pub(crate) const RANGE_EXT_FLAGS: ByteRange = 40..42;
+    pub(crate) const RANGE_FS_VER: ByteRange = 42..44;
+    pub(crate) const RANGE_ROOT_CLUSTER: ByteRange = 44..48;
+    pub(crate) const RANGE_FS_INFO_SECTOR: ByteRange = 48..50;
+    pub(crate) const RANGE_BACKUP_BOOT_SECTOR: ByteRange = 50..52;
+    pub(crate) const RANGE_RESERVED: ByteRange = 52..64;
+    pub(crate) const RANGE_DRIVE_NUM: ByteRange = 64..65;
+    pub(crate) const RANGE_RESERVED1: ByteRange = 65..66;
+    pub(crate) const RANGE_BOOT_SIG: ByteRange = 66..67;
+    pub(crate) const RANGE_VOL_ID: ByteRange = 67..71;
+    pub(crate) const RANGE_VOL_LAB: ByteRange = 71..82;
+    pub(crate) const RANGE_FS_TYPE: ByteRange = 82..90;
+    pub(crate) const RANGE_BOOT: ByteRange = 90..510;
+    pub(crate) const RANGE_SIG_WORD: ByteRange = 510..512;
 
     pub fn sectors_per_fat_32(&self) -> u32 {
         self.0.u32(Self::RANGE_SECTORS_PER_FAT_32)
     }
 
+    pub fn ext_flags(&self) -> u16 {
+        self.0.u16(Self::RANGE_EXT_FLAGS)
+    }
+
     pub fn root_cluster(&self) -> u32 {
         self.0.u32(Self::RANGE_ROOT_CLUSTER)
     }
+
+    pub fn fs_info_sector(&self) -> u16 {
+        self.0.u16(Self::RANGE_FS_INFO_SECTOR)
+    }
+
+    pub fn backup_boot_sector(&self) -> u16 {
+        self.0.u16(Self::RANGE_BACKUP_BOOT_SECTOR)
+    }
+
+    pub fn drive_number(&self) -> u8 {
+        self.0.u8(Self::RANGE_DRIVE_NUM)
+    }
+
+    /// Like `ExtendedBiosParameterBlock::boot_signature`.
+    pub fn boot_signature(&self) -> u8 {
+        self.0.u8(Self::RANGE_BOOT_SIG)
+    }
+
+    /// Whether the boot signature is 0x29, which says the volume id, label and
+    /// filesystem type are all there.
+    pub fn has_extended_boot_signature(&self) -> bool {
+        self.boot_signature() == 0x29
+    }
+
+    /// Whether the sector ends in the 0xAA55 signature word that marks it as a boot
+    /// sector.
+    pub fn has_valid_signature(&self) -> bool {
+        self.0.u16(Self::RANGE_SIG_WORD) == 0xAA55
+    }
+
+    pub fn volume_id(&self) -> u32 {
+        self.0.u32(Self::RANGE_VOL_ID)
+    }
+
+    pub fn volume_label(&self) -> &'a [u8] {
+        &self.0[Self::RANGE_VOL_LAB]
+    }
+
+    /// Informational only, e.g. "FAT32   ", which mustn't be used to tell the variant.
+    pub fn fs_type(&self) -> &'a [u8] {
+        &self.0[Self::RANGE_FS_TYPE]
+    }
 }
 
 impl<'a> From<&'a [u8]> for ExtendedFat32BiosParameterBlock<'a> {
@@ -132,6 +213,61 @@ impl<'a> From<&'a [u8]> for ExtendedFat32BiosParameterBlock<'a> {
     }
 }
 
+/// The FAT32 FSInfo sector, which caches the free cluster count and a hint for where
+/// to start looking for free clusters. Either may be 0xFFFFFFFF, meaning unknown.
+pub struct FileSystemInfo<'a>(&'a mut [u8]);
+
+#[allow(dead_code)]
+impl<'a> FileSystemInfo<'a> {
+    pub(crate) const RANGE_LEAD_SIG: ByteRange = 0..4;
+    pub(crate) const RANGE_STRUCT_SIG: ByteRange = 484..488;
+    pub(crate) const RANGE_FREE_COUNT: ByteRange = 488..492;
+    pub(crate) const RANGE_NEXT_FREE: ByteRange = 492..496;
+    pub(crate) const RANGE_TRAIL_SIG: ByteRange = 508..512;
+
+    pub(crate) const LEAD_SIG: u32 = 0x41615252;
+    pub(crate) const STRUCT_SIG: u32 = 0x61417272;
+    pub(crate) const TRAIL_SIG: u32 = 0xAA550000;
+
+    /// What the free cluster count or next free cluster is when it isn't known.
+    pub(crate) const UNKNOWN: u32 = 0xFFFFFFFF;
+
+    pub fn is_valid(&self) -> bool {
+        self.0.u32(Self::RANGE_LEAD_SIG) == Self::LEAD_SIG
+            && self.0.u32(Self::RANGE_STRUCT_SIG) == Self::STRUCT_SIG
+            && self.0.u32(Self::RANGE_TRAIL_SIG) == Self::TRAIL_SIG
+    }
+
+    /// Writes the signatures that make the sector a valid FSInfo sector.
+    pub fn initialize(&mut self) {
+        self.0.set_u32(Self::RANGE_LEAD_SIG, Self::LEAD_SIG);
+        self.0.set_u32(Self::RANGE_STRUCT_SIG, Self::STRUCT_SIG);
+        self.0.set_u32(Self::RANGE_TRAIL_SIG, Self::TRAIL_SIG);
+    }
+
+    pub fn free_count(&self) -> u32 {
+        self.0.u32(Self::RANGE_FREE_COUNT)
+    }
+
+    pub fn next_free(&self) -> u32 {
+        self.0.u32(Self::RANGE_NEXT_FREE)
+    }
+
+    pub fn set_free_count(&mut self, free_count: u32) {
+        self.0.set_u32(Self::RANGE_FREE_COUNT, free_count);
+    }
+
+    pub fn set_next_free(&mut self, next_free: u32) {
+        self.0.set_u32(Self::RANGE_NEXT_FREE, next_free);
+    }
+}
+
+impl<'a> From<&'a mut [u8]> for FileSystemInfo<'a> {
+    fn from(other: &'a mut [u8]) -> Self {
+        Self(other)
+    }
+}
+
 pub fn root_dir_sector_count(root_entry_count: u32, bytes_per_sector: u16) -> u32 {
     let root_entry_bytes = root_entry_count * (DirectoryEntry::SIZE as u32);
     root_entry_bytes.div_ceiling(u32::from(bytes_per_sector))
@@ -171,13 +307,17 @@ pub struct FileAllocationTable32<'a>(&'a [u8]);
 
 impl<'a> FileAllocationTable32<'a> {
     pub fn get_entry(&self, entry_byte_offset: u32) -> FileAllocationTable32Result {
+        self.get_value(entry_byte_offset).into()
+    }
+
+    pub fn get_value(&self, entry_byte_offset: u32) -> u32 {
         let start = entry_byte_offset as usize;
         let end = start + 4;
 
         // Need to mask off the top 4 bits, according to the spec
         // only 28-bits are used, and the others must be ignored
         // on read, and left alone on write
-        (self.0.u32(start..end) & 0x0FFFFFFF).into()
+        self.0.u32(start..end) & 0x0FFFFFFF
     }
 }
 
@@ -0,0 +1,29 @@
+/// How far a long-running operation has got, in items (clusters, files, ...)
+/// and in bytes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub items_done: u64,
+    pub items_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+pub trait ProgressSink {
+    fn report(&mut self, progress: &Progress);
+}
+
+impl<F> ProgressSink for F
+where
+    F: FnMut(&Progress),
+{
+    fn report(&mut self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// A sink for callers that aren't interested in progress.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn report(&mut self, _progress: &Progress) {}
+}
@@ -0,0 +1,172 @@
+use crate::error::{Error, Result};
+use crate::prim::{
+    CommonBiosParameterBlock, ExtendedBiosParameterBlock, ExtendedFat32BiosParameterBlock,
+    FileSystemInfo,
+};
+use crate::support::{read_sector, DataStructure};
+use crate::{DirectorySelector, FATFileSystem, Variant};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuickCheckIssue {
+    /// The boot sector doesn't end in the 0xAA55 signature word, which some systems
+    /// need to see before they'll mount the volume.
+    InvalidSignature,
+    /// FAT entries 0 and 1, which no cluster uses, don't hold the media descriptor and
+    /// end of chain mark they should in a copy of the FAT, which suggests it isn't a
+    /// FAT at all, or has been overwritten.
+    ReservedFatEntries { fat_index: u8 },
+    /// The volume wasn't cleanly unmounted, as the flag in FAT entry 1 says, so
+    /// changes to it may have been left half done. FAT12 has no such flag.
+    Dirty,
+    /// FAT32's FSInfo sector doesn't have its signatures, so its free cluster count
+    /// can't be trusted.
+    InvalidFsInfo,
+    /// Reading the root directory failed.
+    UnreadableRoot(Error),
+}
+
+impl fmt::Display for QuickCheckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSignature => write!(f, "the boot sector has no 0xAA55 signature"),
+            Self::ReservedFatEntries { fat_index } => {
+                write!(f, "FAT {} has unexpected reserved entries", fat_index)
+            }
+            Self::Dirty => write!(f, "the volume wasn't cleanly unmounted"),
+            Self::InvalidFsInfo => write!(f, "the FSInfo sector is invalid"),
+            Self::UnreadableRoot(err) => write!(f, "the root directory can't be read: {}", err),
+        }
+    }
+}
+
+/// What `FATFileSystem::quick_check` found.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct QuickCheckReport {
+    pub issues: Vec<QuickCheckIssue>,
+}
+
+impl QuickCheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl FATFileSystem {
+    /// Checks the volume's bookkeeping, which takes a few reads however big the volume
+    /// is: the boot sector's signature, the reserved FAT entries, the clean shutdown
+    /// flag, FAT32's FSInfo sector, and that the root directory can be read. A clean
+    /// report doesn't mean there's nothing wrong, only `check` can say that, but one
+    /// that isn't means what's served from the volume may not be right.
+    pub fn quick_check(&self) -> Result<QuickCheckReport> {
+        let mut report = QuickCheckReport::default();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+        read_sector(
+            &mut **self.device.borrow_mut(),
+            self.geo.sector_size_bytes,
+            0,
+            &mut sector,
+        )?;
+
+        let has_valid_signature = match self.variant {
+            Variant::Fat32 => {
+                ExtendedFat32BiosParameterBlock::from(&sector[..]).has_valid_signature()
+            }
+            _ => ExtendedBiosParameterBlock::from(&sector[..]).has_valid_signature(),
+        };
+
+        if !has_valid_signature {
+            report.issues.push(QuickCheckIssue::InvalidSignature);
+        }
+
+        let media = u32::from(sector.u8(CommonBiosParameterBlock::RANGE_MEDIA));
+        let mut dirty = false;
+
+        for fat_index in self.geo.written_fats() {
+            read_sector(
+                &mut **self.device.borrow_mut(),
+                self.geo.sector_size_bytes,
+                self.geo.first_fat_sector
+                    + u64::from(fat_index) * u64::from(self.geo.sectors_per_fat),
+                &mut sector,
+            )?;
+
+            // NOTE: entry 1's top bits are flags, the clean shutdown one among them,
+            // which FAT12 doesn't have
+            let (entry_0, entry_1, media_entry, end_of_chain, flags, clean_flag) =
+                match self.variant {
+                    Variant::Fat12 => (
+                        u32::from(sector[0]) | (u32::from(sector[1] & 0x0F) << 8),
+                        (u32::from(sector[1]) >> 4) | (u32::from(sector[2]) << 4),
+                        0xF00 | media,
+                        0xFF8,
+                        0,
+                        0,
+                    ),
+                    Variant::Fat16 => (
+                        u32::from(sector.u16(0..2)),
+                        u32::from(sector.u16(2..4)),
+                        0xFF00 | media,
+                        0xFFF8,
+                        0xC000,
+                        0x8000,
+                    ),
+                    Variant::Fat32 => (
+                        sector.u32(0..4) & 0x0FFFFFFF,
+                        sector.u32(4..8) & 0x0FFFFFFF,
+                        0x0FFFFF00 | media,
+                        0x0FFFFFF8,
+                        0x0C000000,
+                        0x08000000,
+                    ),
+                };
+
+            if entry_0 != media_entry || entry_1 | flags < end_of_chain {
+                report
+                    .issues
+                    .push(QuickCheckIssue::ReservedFatEntries { fat_index });
+            }
+
+            if fat_index == self.geo.written_fats().start && entry_1 & clean_flag != clean_flag {
+                dirty = true;
+            }
+        }
+
+        // NOTE: changes made since opening mark the volume as dirty themselves
+        if dirty && !self.dirty.get() {
+            report.issues.push(QuickCheckIssue::Dirty);
+        }
+
+        if let Some(fs_info_sector) = self.fs_info_sector {
+            read_sector(
+                &mut **self.device.borrow_mut(),
+                self.geo.sector_size_bytes,
+                fs_info_sector,
+                &mut sector,
+            )?;
+
+            if !FileSystemInfo::from(&mut sector[..]).is_valid() {
+                report.issues.push(QuickCheckIssue::InvalidFsInfo);
+            }
+        }
+
+        let root = self
+            .walk_directory_owned(DirectorySelector::Root)
+            .and_then(|walker| walker.enumerate_entry_views(|_| {}));
+
+        if let Err(err) = root {
+            report.issues.push(QuickCheckIssue::UnreadableRoot(err));
+        }
+
+        Ok(report)
+    }
+
+    /// What `quick_check` found when the filesystem was opened, if
+    /// `MountOptions::quick_check` had it run then.
+    pub fn quick_check_report(&self) -> Option<&QuickCheckReport> {
+        self.quick_check_report.as_ref()
+    }
+}
@@ -0,0 +1,214 @@
+use crate::error::{Error, Result};
+use crate::locate::{split_path, EntryLocation, LocatedEntry};
+use crate::support::DataStructureMut;
+use crate::{DirectoryEntry, DirectorySelector, FATFileSystem, StandardDirectoryEntry};
+use alloc::vec::Vec;
+
+impl FATFileSystem {
+    /// Removes the file at `path` and frees its clusters, then flushes the device.
+    pub fn remove_file(&self, path: &str) -> Result<()> {
+        let (parent, name) = self.split_parent(path)?;
+        let (located, long_name_locations) = self.locate_with_long_name(parent, name)?;
+
+        if located.metadata.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        self.remove_entry(&located, &long_name_locations)?;
+
+        if self.is_data_cluster(located.metadata.first_cluster) {
+            self.free_chain(located.metadata.first_cluster)?;
+        }
+
+        self.flush()
+    }
+
+    /// Removes the directory at `path`, which mustn't have anything in it but "." and
+    /// "..", then flushes the device.
+    pub fn remove_dir(&self, path: &str) -> Result<()> {
+        let (parent, name) = self.split_parent(path)?;
+        let (located, long_name_locations) = self.locate_with_long_name(parent, name)?;
+
+        if !located.metadata.is_directory() {
+            return Err(Error::NotADirectory);
+        }
+
+        let directory = DirectorySelector::from_cluster(located.metadata.first_cluster);
+
+        if self
+            .list_directory(directory)?
+            .iter()
+            .any(|item| !item.is_dot_entry() && !item.attributes.is_volume_id())
+        {
+            return Err(Error::DirectoryNotEmpty);
+        }
+
+        self.remove_entry(&located, &long_name_locations)?;
+        self.free_chain(located.metadata.first_cluster)?;
+
+        // NOTE: the directory's clusters may be given to another one later
+        self.directory_changed(directory);
+
+        self.flush()
+    }
+
+    /// Moves the entry at `from` to `to`, which can be in another directory, keeping
+    /// its clusters, attributes and timestamps. Nothing may exist at `to` yet, unless
+    /// it's the same entry, as when only the case of a name changes. A directory can't
+    /// be moved into itself or one of its subdirectories.
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let (from_parent, from_name) = self.split_parent(from)?;
+        let (source, long_name_locations) = self.locate_with_long_name(from_parent, from_name)?;
+
+        let (to_parent_path, to_name) = split_path(to)?;
+        let (to_parent, _) = self.split_parent(to)?;
+
+        if source.metadata.is_directory() {
+            self.check_not_within(source.metadata.first_cluster, to_parent_path)?;
+        }
+
+        let same_entry = match self.locate_in_directory(to_parent, to_name) {
+            Ok(existing) if existing.location == source.location => true,
+            Ok(_) => return Err(Error::AlreadyExists),
+            Err(Error::NotFound) => false,
+            Err(err) => return Err(err),
+        };
+
+        self.mark_dirty()?;
+
+        let old_entry = self.read_entry(source.location)?;
+
+        // NOTE: the new entry starts out without the clusters, and only gets them once
+        // it's complete, so an interruption leaves an empty file at worst. A directory
+        // can't be without a cluster, so one that's interrupted is found twice.
+        let first_cluster = if source.metadata.is_directory() {
+            source.metadata.first_cluster
+        } else {
+            0
+        };
+
+        let created = if same_entry {
+            // NOTE: the entry's in the way of its new name, so it goes first, and is put
+            // back if the new one can't be made
+            let old_long_name_entries = long_name_locations
+                .iter()
+                .map(|&location| self.read_entry(location))
+                .collect::<Result<Vec<_>>>()?;
+
+            self.remove_entry(&source, &long_name_locations)?;
+
+            match self.create_entry(
+                to_parent,
+                to_name,
+                source.metadata.attributes,
+                first_cluster,
+            ) {
+                Ok(created) => created,
+                Err(err) => {
+                    for (location, entry) in long_name_locations
+                        .iter()
+                        .zip(old_long_name_entries.iter())
+                        .chain(core::iter::once((&source.location, &old_entry)))
+                    {
+                        self.update_entry(*location, |bytes| bytes.copy_from_slice(entry))?;
+                    }
+
+                    return Err(err);
+                }
+            }
+        } else {
+            self.create_entry(
+                to_parent,
+                to_name,
+                source.metadata.attributes,
+                first_cluster,
+            )?
+        };
+
+        // NOTE: everything after the name is carried over but the case flags, which
+        // belong to the new name
+        self.update_entry(created.location, |entry| {
+            entry[StandardDirectoryEntry::RANGE_ATTR.start] =
+                old_entry[StandardDirectoryEntry::RANGE_ATTR.start];
+            entry[StandardDirectoryEntry::RANGE_CREATION_TIME_DECISECS.start..].copy_from_slice(
+                &old_entry[StandardDirectoryEntry::RANGE_CREATION_TIME_DECISECS.start..],
+            );
+        })?;
+
+        if !same_entry {
+            self.remove_entry(&source, &long_name_locations)?;
+        }
+
+        if source.metadata.is_directory() && from_parent != to_parent {
+            self.set_dot_dot(source.metadata.first_cluster, to_parent)?;
+        }
+
+        self.flush()
+    }
+
+    /// Fails with `Error::MoveIntoItself` if the directory starting at `cluster` is
+    /// the one at `path` or one of the directories leading to it.
+    fn check_not_within(&self, cluster: u32, path: &str) -> Result<()> {
+        let mut ancestor = path;
+
+        loop {
+            if self.lookup(ancestor)?.first_cluster == cluster {
+                return Err(Error::MoveIntoItself);
+            }
+
+            match split_path(ancestor) {
+                Ok((parent_path, _)) => ancestor = parent_path,
+                Err(Error::RootDirectory) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Marks an entry and the long file name entries ahead of it as deleted, leaving
+    /// its clusters as they are.
+    fn remove_entry(
+        &self,
+        located: &LocatedEntry,
+        long_name_locations: &[EntryLocation],
+    ) -> Result<()> {
+        self.check_located(located)?;
+        self.mark_dirty()?;
+
+        // NOTE: the standard entry goes first, so an interruption leaves orphaned long
+        // name entries at worst, which are ignored
+        self.update_entry(located.location, |entry| entry[0] = 0xE5)?;
+
+        for &location in long_name_locations {
+            self.update_entry(location, |entry| entry[0] = 0xE5)?;
+        }
+
+        Ok(())
+    }
+
+    /// Points the ".." entry of the directory starting at `cluster` at `parent`.
+    fn set_dot_dot(&self, cluster: u32, parent: DirectorySelector) -> Result<()> {
+        let parent_cluster = match parent {
+            DirectorySelector::Root => 0,
+            DirectorySelector::Cluster(parent_cluster) => parent_cluster,
+        };
+
+        let location = EntryLocation {
+            directory: DirectorySelector::Cluster(cluster),
+            index: 1,
+            sector: self.geo.first_data_sector
+                + u64::from(cluster - 2) * u64::from(self.geo.cluster_size_sectors),
+            offset: DirectoryEntry::SIZE,
+        };
+
+        self.update_entry(location, |mut entry| {
+            entry.set_u16(
+                StandardDirectoryEntry::RANGE_FIRST_CLUSTER_HIGH,
+                (parent_cluster >> 16) as u16,
+            );
+            entry.set_u16(
+                StandardDirectoryEntry::RANGE_FIRST_CLUSTER_LOW,
+                parent_cluster as u16,
+            );
+        })
+    }
+}
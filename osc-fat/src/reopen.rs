@@ -0,0 +1,291 @@
+use crate::volume::{boot_sector_label, boot_sector_serial};
+use crate::{read_layout, BlockCache, Error, FATFileSystem, FATGeometry, Layout};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::{Cell, RefCell};
+use osc_block_storage::{BlockDevice, BlockDeviceError};
+
+/// Gives the filesystem its device again after it's gone away, e.g. by opening the
+/// same disk once it's been plugged back in.
+pub trait ReopenHook {
+    /// Opens the device again, or returns `None` if it isn't back yet.
+    fn reopen(&mut self) -> Option<Box<dyn BlockDevice>>;
+}
+
+impl<F> ReopenHook for F
+where
+    F: FnMut() -> Option<Box<dyn BlockDevice>>,
+{
+    fn reopen(&mut self) -> Option<Box<dyn BlockDevice>> {
+        self()
+    }
+}
+
+/// What tells the volume apart from others: its layout, serial number and the label
+/// in its boot sector, on blocks of the same size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct VolumeIdentity {
+    block_size: u16,
+    geometry: FATGeometry,
+    serial: Option<u32>,
+    label: Option<[u8; 11]>,
+}
+
+impl VolumeIdentity {
+    fn read(device: &mut dyn BlockDevice) -> Result<Self, Error> {
+        let geometry = FATGeometry::read(device)?;
+
+        let mut boot_sector = [0u8; 512];
+        device.read_blocks(0, &mut boot_sector)?;
+
+        let mut identity = Self {
+            block_size: device.block_size(),
+            geometry,
+            serial: None,
+            label: None,
+        };
+
+        identity.update(&boot_sector);
+        Ok(identity)
+    }
+
+    /// Takes the serial number and label from `boot_sector`, which has been written.
+    fn update(&mut self, boot_sector: &[u8]) {
+        self.serial = boot_sector_serial(self.geometry.variant, boot_sector);
+        self.label = boot_sector_label(self.geometry.variant, boot_sector);
+    }
+}
+
+/// The device beneath the caches, shared between the filesystem and the
+/// `ReopeningBlockDevice` it reads and writes through, along with the identity of the
+/// volume it ought to hold.
+pub(crate) struct RawDevice {
+    device: RefCell<Box<dyn BlockDevice>>,
+    identity: Cell<VolumeIdentity>,
+    hook: RefCell<Option<Box<dyn ReopenHook>>>,
+    reopen_count: Cell<u64>,
+}
+
+impl RawDevice {
+    pub(crate) fn new(mut device: Box<dyn BlockDevice>) -> Result<Rc<Self>, Error> {
+        Ok(Rc::new(Self {
+            identity: Cell::new(VolumeIdentity::read(&mut *device)?),
+            device: RefCell::new(device),
+            hook: RefCell::new(None),
+            reopen_count: Cell::new(0),
+        }))
+    }
+
+    /// How many times the device has been reopened.
+    pub(crate) fn reopen_count(&self) -> u64 {
+        self.reopen_count.get()
+    }
+}
+
+/// Sits beneath the caches, and when the device goes away, asks the reopen hook for
+/// it again and repeats what failed on the device it gives back, as long as that holds
+/// the same volume. The caches are emptied, as the volume could have been changed
+/// while it was away.
+pub(crate) struct ReopeningBlockDevice {
+    raw: Rc<RawDevice>,
+    caches: [Rc<BlockCache>; 2],
+    write_generation: Rc<Cell<u64>>,
+    written_since_flush: bool,
+    /// Whether the device went away before what was written to it was flushed, which
+    /// the next flush reports, as it might not have reached the device.
+    writes_lost: bool,
+}
+
+impl ReopeningBlockDevice {
+    pub(crate) fn new(
+        raw: Rc<RawDevice>,
+        caches: [Rc<BlockCache>; 2],
+        write_generation: Rc<Cell<u64>>,
+    ) -> Self {
+        Self {
+            raw,
+            caches,
+            write_generation,
+            written_since_flush: false,
+            writes_lost: false,
+        }
+    }
+
+    /// Runs `transfer` on the device, and again on the one the hook gives back if the
+    /// device has gone away.
+    fn transfer<T, F>(&mut self, mut transfer: F) -> Result<T, BlockDeviceError>
+    where
+        F: FnMut(&mut dyn BlockDevice) -> Result<T, BlockDeviceError>,
+    {
+        let result = transfer(&mut **self.raw.device.borrow_mut());
+
+        match result {
+            Err(BlockDeviceError::Gone) if self.reopen() => {
+                transfer(&mut **self.raw.device.borrow_mut())
+            }
+            result => result,
+        }
+    }
+
+    fn reopen(&mut self) -> bool {
+        let device = self
+            .raw
+            .hook
+            .borrow_mut()
+            .as_mut()
+            .and_then(|hook| hook.reopen());
+
+        let mut device = match device {
+            Some(device) => device,
+            None => return false,
+        };
+
+        // NOTE: another disk, or this one reformatted, mustn't be written to as if it
+        // were this volume
+        match VolumeIdentity::read(&mut *device) {
+            Ok(identity) if identity == self.raw.identity.get() => {}
+            _ => return false,
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!("device reopened");
+
+        *self.raw.device.borrow_mut() = device;
+        self.raw.reopen_count.set(self.raw.reopen_count.get() + 1);
+        self.writes_lost |= self.written_since_flush;
+
+        for cache in &self.caches {
+            cache.clear();
+        }
+
+        self.write_generation.set(self.write_generation.get() + 1);
+        true
+    }
+}
+
+impl BlockDevice for ReopeningBlockDevice {
+    fn block_size(&self) -> u16 {
+        self.raw.device.borrow().block_size()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.raw.device.borrow().is_read_only()
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        self.raw.device.borrow().block_count()
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        self.transfer(|device| device.read_blocks(start_block, destination))
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let written = self.transfer(|device| device.write_blocks(start_block, source))?;
+        self.written_since_flush = true;
+
+        let block_size = usize::from(self.block_size());
+
+        // NOTE: the serial number and label can be changed, after which it's the new
+        // ones the device has to have
+        if start_block == 0 && written as usize * block_size >= 512 {
+            let mut identity = self.raw.identity.get();
+            identity.update(source);
+            self.raw.identity.set(identity);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockDeviceError> {
+        self.transfer(|device| device.flush())?;
+        self.written_since_flush = false;
+
+        if self.writes_lost {
+            self.writes_lost = false;
+            return Err(BlockDeviceError::Io);
+        }
+
+        Ok(())
+    }
+
+    fn prefetch(&mut self, start_block: u64, block_count: u64) {
+        self.raw
+            .device
+            .borrow_mut()
+            .prefetch(start_block, block_count)
+    }
+
+    fn discard(&mut self, start_block: u64, block_count: u64) -> Result<(), BlockDeviceError> {
+        self.transfer(|device| device.discard(start_block, block_count))
+    }
+}
+
+impl FATFileSystem {
+    /// Sets what's asked for the device again when it goes away, e.g. when a USB
+    /// stick is pulled out. Whatever failed is repeated on the device it gives back, if
+    /// that holds the same volume, so a daemon serving the filesystem can carry on
+    /// once the media returns. Until then, everything fails with `Error::DeviceGone`.
+    pub fn set_reopen_hook(&mut self, hook: Box<dyn ReopenHook>) {
+        *self.raw_device.hook.borrow_mut() = Some(hook);
+    }
+
+    /// Whether the device still holds the volume the filesystem was opened on, as told
+    /// by the layout, serial number and label in its boot sector, which is read from
+    /// the device rather than the caches. Changes made through the filesystem are
+    /// accounted for. Anything else means the device has been pointed at another
+    /// volume, and nothing cached of it can be trusted.
+    pub fn verify_identity(&self) -> Result<bool, Error> {
+        let identity = VolumeIdentity::read(&mut **self.raw_device.device.borrow_mut())?;
+        Ok(identity == self.raw_device.identity.get())
+    }
+
+    /// Picks up changes made to the volume other than through the filesystem, e.g. by
+    /// another process with the image open, without opening it again. Everything
+    /// cached of the volume is dropped, and the boot sector is read again, as is the
+    /// FSInfo sector when it's next needed, so lookups, walkers and reads see what's on
+    /// the device from then on. Nothing is written, and if the filesystem has made
+    /// changes of its own since it was last synced, the next sync records FAT32's free
+    /// cluster count as unknown, as what it had counted can't be relied on any more.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        self.flush()?;
+
+        self.sector_cache.clear();
+        self.fat_cache.clear();
+        self.device.invalidate();
+        self.directory_generations.borrow_mut().bump_all();
+
+        let mut device = self.device.borrow_mut();
+        let Layout {
+            geometry,
+            root,
+            fs_info_sector,
+        } = read_layout(&mut **device)?;
+        let identity = VolumeIdentity::read(&mut **device)?;
+        drop(device);
+
+        // NOTE: the volume may even have been formatted again, in which case it's the
+        // new one the device has to hold if it goes away
+        self.raw_device.identity.set(identity);
+
+        self.variant = geometry.variant;
+        self.geo = geometry;
+        self.root = root;
+        self.fs_info_sector = fs_info_sector;
+
+        // NOTE: the volume's still marked as not cleanly unmounted if it was dirty, for
+        // the next sync to mark it clean again
+        self.free_cluster_count.set(None);
+        self.next_free_cluster.set(None);
+
+        if self.options.quick_check {
+            self.quick_check_report = Some(self.quick_check()?);
+        }
+
+        Ok(())
+    }
+}
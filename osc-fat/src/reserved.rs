@@ -0,0 +1,147 @@
+use crate::error::{Error, Result};
+use crate::support::{read_sector, write_sector};
+use crate::{FATFileSystem, Variant};
+use alloc::vec;
+use core::cmp;
+use core::ops::Range;
+
+/// How many sectors FAT32's boot record takes up, both at the start of the volume and
+/// at its backup: the boot sector, the FSInfo sector and a sector of boot code.
+const FAT32_BOOT_RECORD_SECTORS: u64 = 3;
+
+impl FATFileSystem {
+    /// The reserved sectors between the boot sector and the first FAT that the
+    /// filesystem has no use for, which some platforms keep firmware or boot loaders
+    /// in. On FAT32 it starts after the boot record and its backup, along with the
+    /// FSInfo sector, wherever the boot sector puts them. The range is empty when the
+    /// volume has no reserved sectors to spare.
+    pub fn reserved_payload_sectors(&self) -> Result<Range<u64>> {
+        let first_fat_sector = self.geo.first_fat_sector;
+
+        let first_sector = match self.variant {
+            Variant::Fat12 | Variant::Fat16 => 1,
+            Variant::Fat32 => {
+                let mut boot_sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+                read_sector(
+                    &mut **self.device.borrow_mut(),
+                    self.geo.sector_size_bytes,
+                    0,
+                    &mut boot_sector,
+                )?;
+
+                let after_backup = self
+                    .backup_boot_sector(&boot_sector)
+                    .map_or(0, |backup_sector| backup_sector + FAT32_BOOT_RECORD_SECTORS);
+
+                let after_fs_info = self.fs_info_sector.map_or(0, |sector| sector + 1);
+
+                cmp::max(
+                    FAT32_BOOT_RECORD_SECTORS,
+                    cmp::max(after_backup, after_fs_info),
+                )
+            }
+        };
+
+        Ok(cmp::min(first_sector, first_fat_sector)..first_fat_sector)
+    }
+
+    /// Reads from the reserved sectors the filesystem has no use for, starting
+    /// `offset` bytes into them (see `reserved_payload_sectors`), filling
+    /// `destination`. Reading past their end fails with `Error::OutOfRange`.
+    pub fn read_reserved_payload(&self, offset: u64, destination: &mut [u8]) -> Result<()> {
+        let sectors = self.reserved_payload_range(offset, destination.len())?;
+        let sector_size = usize::from(self.geo.sector_size_bytes);
+
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; sector_size];
+        let mut done = 0;
+
+        for sector_index in sectors {
+            read_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                sector_index,
+                &mut sector,
+            )?;
+
+            let start = if done == 0 {
+                (offset % sector_size as u64) as usize
+            } else {
+                0
+            };
+
+            let len = cmp::min(sector_size - start, destination.len() - done);
+            destination[done..(done + len)].copy_from_slice(&sector[start..(start + len)]);
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `source` to the reserved sectors the filesystem has no use for, starting
+    /// `offset` bytes into them (see `reserved_payload_sectors`). Nothing else on the
+    /// volume is touched, and writing past their end fails with `Error::OutOfRange`
+    /// without writing anything.
+    pub fn write_reserved_payload(&self, offset: u64, source: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Err(Error::WriteProtected);
+        }
+
+        let sectors = self.reserved_payload_range(offset, source.len())?;
+        let sector_size = usize::from(self.geo.sector_size_bytes);
+
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; sector_size];
+        let mut done = 0;
+
+        for sector_index in sectors {
+            let start = if done == 0 {
+                (offset % sector_size as u64) as usize
+            } else {
+                0
+            };
+
+            let len = cmp::min(sector_size - start, source.len() - done);
+
+            // NOTE: only sectors that are partly written need what's already there
+            if len < sector_size {
+                read_sector(
+                    &mut **device,
+                    self.geo.sector_size_bytes,
+                    sector_index,
+                    &mut sector,
+                )?;
+            }
+
+            sector[start..(start + len)].copy_from_slice(&source[done..(done + len)]);
+            write_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                sector_index,
+                &sector,
+            )?;
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// The sectors `len` bytes at `offset` into the reserved payload take up.
+    fn reserved_payload_range(&self, offset: u64, len: usize) -> Result<Range<u64>> {
+        let payload = self.reserved_payload_sectors()?;
+        let sector_size = u64::from(self.geo.sector_size_bytes);
+        let payload_bytes = (payload.end - payload.start) * sector_size;
+
+        let end = offset
+            .checked_add(len as u64)
+            .filter(|&end| end <= payload_bytes)
+            .ok_or(Error::OutOfRange)?;
+
+        if len == 0 {
+            return Ok(payload.start..payload.start);
+        }
+
+        Ok((payload.start + offset / sector_size)..(payload.start + (end - 1) / sector_size + 1))
+    }
+}
@@ -0,0 +1,314 @@
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::names::LongNameAssembler;
+use crate::{
+    Cluster, DirectoryEntriesIterator, DirectoryEntry, DirectorySelector, EndOfDirectoryPolicy,
+    FATFileSystem, FATGeometry, FatEntry, InvalidUtf16Policy, Limit, Limits, Metadata,
+    NameMatching, RootDirectory,
+};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use osc_block_storage::BlockDevice;
+
+/// An immutable copy of what a filesystem knows about its volume's layout: its
+/// geometry, where its root directory is, and its whole FAT, at 4 bytes a cluster.
+/// It's `Send` and `Sync`, so it can be shared between threads in an `Arc`, each
+/// reading files and directories through a device of its own (e.g. another handle to
+/// the same image) while the filesystem goes on being written to.
+///
+/// Chains are followed as they were when the snapshot was taken, so what's read
+/// through it is only sure to be what was there then until the filesystem frees a
+/// cluster that's read, which it may then reuse. Take a new snapshot after writing.
+#[derive(Debug)]
+pub struct VolumeSnapshot {
+    geo: FATGeometry,
+    root: RootDirectory,
+    limits: Limits,
+    name_matching: NameMatching,
+    invalid_utf16: InvalidUtf16Policy,
+    end_of_directory: EndOfDirectoryPolicy,
+    /// The FAT values of the data clusters, from cluster 2.
+    fat: Vec<u32>,
+}
+
+impl FATFileSystem {
+    /// Takes a snapshot of the volume's layout, reading all of the FAT, to be shared
+    /// by readers on other threads.
+    pub fn snapshot(&self) -> Result<Arc<VolumeSnapshot>> {
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut fat = self.fat_reader(&mut buffer);
+
+        let fat = (2..(self.geo.cluster_count + 2))
+            .map(|cluster| fat.read(cluster))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Arc::new(VolumeSnapshot {
+            geo: self.geo,
+            root: self.root,
+            limits: self.options.limits,
+            name_matching: self.options.name_matching,
+            invalid_utf16: self.options.invalid_utf16,
+            end_of_directory: self.options.end_of_directory,
+            fat,
+        }))
+    }
+}
+
+impl VolumeSnapshot {
+    pub fn geometry(&self) -> FATGeometry {
+        self.geo
+    }
+
+    /// What the FAT said about `cluster`, or `None` if it isn't a data cluster.
+    pub fn fat_entry(&self, cluster: Cluster) -> Option<FatEntry> {
+        if !self.geo.is_data_cluster(cluster) {
+            return None;
+        }
+
+        let value = self.fat[(cluster - 2) as usize];
+        Some(FatEntry::from_value(value, self.geo.variant()))
+    }
+
+    /// Like `FATFileSystem::list_directory`, reading through `device`.
+    pub fn list_directory(
+        &self,
+        device: &mut dyn BlockDevice,
+        directory: DirectorySelector,
+    ) -> Result<Vec<Metadata>> {
+        let (contents, cluster_size) = match (directory, self.root) {
+            (DirectorySelector::Cluster(first_cluster), _)
+            | (DirectorySelector::Root, RootDirectory::Chain(first_cluster)) => {
+                let clusters = self.chain(first_cluster)?;
+                let mut contents = vec![0u8; clusters.len() * self.cluster_size_bytes()];
+
+                for (cluster, contents) in clusters
+                    .into_iter()
+                    .zip(contents.chunks_exact_mut(self.cluster_size_bytes()))
+                {
+                    self.read_bytes(device, self.cluster_byte_offset(cluster), contents)?;
+                }
+
+                (contents, self.cluster_size_bytes())
+            }
+
+            (
+                DirectorySelector::Root,
+                RootDirectory::Region {
+                    first_sector,
+                    sector_count,
+                },
+            ) => {
+                let sector_size_bytes = u64::from(self.geo.sector_size_bytes());
+                let mut contents = vec![0u8; sector_count as usize * sector_size_bytes as usize];
+                self.read_bytes(device, first_sector * sector_size_bytes, &mut contents)?;
+
+                let region_size = contents.len();
+                (contents, region_size)
+            }
+        };
+
+        if let Some(max) = self.limits.max_directory_entries {
+            if contents.len() / DirectoryEntry::SIZE > max as usize {
+                return Err(Error::LimitExceeded(Limit::DirectoryEntries));
+            }
+        }
+
+        let mut result = Vec::new();
+        let mut long_name = LongNameAssembler::default();
+
+        // NOTE: entries are gone through a cluster at a time, as the end marker only
+        // ends the directory at the end of its cluster when scanning past it
+        for cluster in contents.chunks(cluster_size.max(1)) {
+            let entries = DirectoryEntriesIterator {
+                entries: cluster.chunks_exact(DirectoryEntry::SIZE),
+                past_end: self.end_of_directory == EndOfDirectoryPolicy::ScanCluster,
+            };
+
+            for entry in entries {
+                match entry {
+                    DirectoryEntry::LongFileName(entry) => long_name.push(&entry),
+                    DirectoryEntry::Standard(entry) => {
+                        let long_name = long_name.take(&entry);
+
+                        if let (Some(name), Some(max)) =
+                            (long_name, self.limits.max_long_name_length)
+                        {
+                            if name.len() > max as usize {
+                                return Err(Error::LimitExceeded(Limit::LongNameLength));
+                            }
+                        }
+
+                        result.push(Metadata::new(&entry, long_name, self.invalid_utf16));
+                    }
+                }
+            }
+
+            if cluster
+                .chunks_exact(DirectoryEntry::SIZE)
+                .any(|entry| entry[0] == 0x00)
+            {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Like `FATFileSystem::lookup`, reading through `device`.
+    pub fn lookup(&self, device: &mut dyn BlockDevice, path: &str) -> Result<Metadata> {
+        let mut current = Metadata::root();
+
+        for (depth, component) in path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .enumerate()
+        {
+            if matches!(self.limits.max_depth, Some(max) if depth + 1 > max as usize) {
+                return Err(Error::LimitExceeded(Limit::Depth));
+            }
+
+            if !current.is_directory() {
+                return Err(Error::NotADirectory);
+            }
+
+            current = self
+                .list_directory(
+                    device,
+                    DirectorySelector::from_cluster(current.first_cluster),
+                )?
+                .into_iter()
+                .find(|item| self.name_matching.is_called(item, component))
+                .ok_or(Error::NotFound)?;
+        }
+
+        Ok(current)
+    }
+
+    /// Reads from the file `item` at `offset` into `destination` through `device`,
+    /// returning how many bytes were read, which is only less than asked for at the
+    /// end of the file.
+    pub fn read_at(
+        &self,
+        device: &mut dyn BlockDevice,
+        item: &Metadata,
+        offset: u64,
+        destination: &mut [u8],
+    ) -> Result<usize> {
+        if item.is_directory() {
+            return Err(Error::IsADirectory);
+        }
+
+        let size = u64::from(item.size);
+
+        if offset >= size || destination.is_empty() {
+            return Ok(0);
+        }
+
+        let length = core::cmp::min(destination.len() as u64, size - offset) as usize;
+        let cluster_size_bytes = self.cluster_size_bytes();
+
+        let clusters = self.chain(item.first_cluster)?;
+        let first_index = (offset / cluster_size_bytes as u64) as usize;
+        let mut offset_in_cluster = (offset % cluster_size_bytes as u64) as usize;
+        let mut done = 0;
+
+        for &cluster in clusters.iter().skip(first_index) {
+            let count = core::cmp::min(cluster_size_bytes - offset_in_cluster, length - done);
+            let byte_offset = self.cluster_byte_offset(cluster) + offset_in_cluster as u64;
+
+            self.read_bytes(device, byte_offset, &mut destination[done..(done + count)])?;
+
+            done += count;
+            offset_in_cluster = 0;
+
+            if done == length {
+                return Ok(done);
+            }
+        }
+
+        // The chain is shorter than the file's size says it should be
+        Err(Error::Corrupt)
+    }
+
+    /// The clusters of the chain starting at `first_cluster`, which is empty for an
+    /// empty file.
+    fn chain(&self, first_cluster: Cluster) -> Result<Vec<Cluster>> {
+        let max_chain_length = core::cmp::min(
+            self.limits.max_chain_length.unwrap_or(u32::MAX),
+            self.geo.cluster_count(),
+        );
+
+        let mut clusters = Vec::new();
+        let mut cluster = first_cluster;
+
+        if first_cluster == 0 {
+            return Ok(clusters);
+        }
+
+        loop {
+            if !self.geo.is_data_cluster(cluster) {
+                return Err(Error::Corrupt);
+            }
+
+            if clusters.len() as u32 == max_chain_length {
+                return Err(Error::LimitExceeded(Limit::ChainLength));
+            }
+
+            clusters.push(cluster);
+
+            cluster = match self.fat_entry(cluster) {
+                Some(FatEntry::Next(next)) => next,
+                Some(FatEntry::Bad) => return Err(Error::BadCluster),
+                _ => return Ok(clusters),
+            };
+        }
+    }
+
+    fn cluster_size_bytes(&self) -> usize {
+        usize::from(self.geo.cluster_size_sectors()) * usize::from(self.geo.sector_size_bytes())
+    }
+
+    fn cluster_byte_offset(&self, cluster: Cluster) -> u64 {
+        let sector = self.geo.first_data_sector()
+            + u64::from(cluster - 2) * u64::from(self.geo.cluster_size_sectors());
+
+        sector * u64::from(self.geo.sector_size_bytes())
+    }
+
+    /// Fills `destination` from `byte_offset` into the volume, reading the device's
+    /// blocks straight into it when it lines up with them.
+    fn read_bytes(
+        &self,
+        device: &mut dyn BlockDevice,
+        byte_offset: u64,
+        destination: &mut [u8],
+    ) -> Result<()> {
+        let block_size_bytes = u64::from(device.block_size());
+        let end_byte = byte_offset + destination.len() as u64;
+
+        let first_block = byte_offset / block_size_bytes;
+        let block_count = end_byte.div_ceiling(block_size_bytes) - first_block;
+
+        // NOTE: the device ending before the volume does means the volume claims to be
+        // bigger than it is
+        if byte_offset.is_multiple_of(block_size_bytes) && end_byte.is_multiple_of(block_size_bytes)
+        {
+            if device.read_blocks(first_block, destination)? < block_count {
+                return Err(Error::Corrupt);
+            }
+
+            return Ok(());
+        }
+
+        let mut blocks = vec![0u8; (block_count * block_size_bytes) as usize];
+
+        if device.read_blocks(first_block, &mut blocks)? < block_count {
+            return Err(Error::Corrupt);
+        }
+
+        let start = (byte_offset - first_block * block_size_bytes) as usize;
+        destination.copy_from_slice(&blocks[start..(start + destination.len())]);
+        Ok(())
+    }
+}
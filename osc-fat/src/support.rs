@@ -1,12 +1,30 @@
 use core::convert::{AsRef, TryInto};
 use core::ops::Range;
 
+mod buffer_pool;
+pub(crate) use buffer_pool::*;
+
+mod chain_index;
+pub(crate) use chain_index::*;
+
 mod cluster_walker;
 pub(crate) use cluster_walker::*;
 
+mod fat_table;
+pub(crate) use fat_table::*;
+
+mod file_reader;
+pub(crate) use file_reader::*;
+
 mod read_buffer;
 pub(crate) use read_buffer::*;
 
+mod sector_io;
+pub(crate) use sector_io::*;
+
+mod shared_device;
+pub(crate) use shared_device::*;
+
 pub(crate) type ByteRange = Range<usize>;
 
 pub(crate) trait DataStructure {
@@ -35,3 +53,24 @@ where
         &self.as_ref()[range]
     }
 }
+
+pub(crate) trait DataStructureMut {
+    fn range_mut(&mut self, range: ByteRange) -> &mut [u8];
+
+    fn set_u16(&mut self, range: ByteRange, value: u16) {
+        self.range_mut(range).copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn set_u32(&mut self, range: ByteRange, value: u32) {
+        self.range_mut(range).copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+impl<T> DataStructureMut for T
+where
+    T: AsMut<[u8]>,
+{
+    fn range_mut(&mut self, range: ByteRange) -> &mut [u8] {
+        &mut self.as_mut()[range]
+    }
+}
@@ -0,0 +1,55 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ops::{Deref, DerefMut};
+
+/// Buffers for readers that own theirs rather than borrowing one from the caller, which
+/// go back to the pool when the reader is dropped so that nested walks only allocate
+/// the first time they reach a given depth.
+#[derive(Clone, Default)]
+pub(crate) struct BufferPool(Rc<RefCell<Vec<Vec<u8>>>>);
+
+impl BufferPool {
+    /// Buffers beyond this many are freed rather than kept.
+    const MAX_POOLED_BUFFERS: usize = 8;
+
+    /// Takes a buffer of `size` bytes, whose contents are unspecified.
+    pub fn take(&self, size: usize) -> PooledBuffer {
+        let mut buffer = self.0.borrow_mut().pop().unwrap_or_default();
+        buffer.resize(size, 0);
+
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+}
+
+pub(crate) struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let mut buffers = self.pool.0.borrow_mut();
+
+        if buffers.len() < BufferPool::MAX_POOLED_BUFFERS {
+            buffers.push(core::mem::take(&mut self.buffer));
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::error::{Error, Result};
+use crate::prim::FileAllocationTable32Result;
+use crate::support::{raise_at_fat_entry, read_fat_entry, FatReadPolicy, ReadBuffer};
+use crate::{Cluster, FATGeometry, Limit};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A sparse map from a file's logical cluster numbers to the clusters holding them,
+/// filled in as the chain is followed, so that seeking doesn't have to walk the
+/// chain from the start every time.
+pub(crate) struct ClusterChainIndex {
+    /// Holds the cluster for every STRIDE-th logical cluster, as far as has been walked.
+    checkpoints: Vec<Cluster>,
+    /// The most recently resolved position, which makes sequential access cheap.
+    cursor: (u32, Cluster),
+    max_length: u32,
+}
+
+impl ClusterChainIndex {
+    const STRIDE: u32 = 64;
+
+    pub fn new(first_cluster: Cluster, max_length: u32) -> Self {
+        Self {
+            checkpoints: vec![first_cluster],
+            cursor: (0, first_cluster),
+            max_length,
+        }
+    }
+
+    /// Finds the cluster holding logical cluster `logical`, or `None` if the chain
+    /// ends before then.
+    pub fn resolve(
+        &mut self,
+        buffer: &mut ReadBuffer,
+        geo: &FATGeometry,
+        fat_read_policy: FatReadPolicy,
+        logical: u32,
+    ) -> Result<Option<Cluster>> {
+        let checkpoint_index =
+            core::cmp::min(logical / Self::STRIDE, self.checkpoints.len() as u32 - 1);
+
+        let (mut position, mut cluster) =
+            if self.cursor.0 <= logical && self.cursor.0 >= checkpoint_index * Self::STRIDE {
+                self.cursor
+            } else {
+                (
+                    checkpoint_index * Self::STRIDE,
+                    self.checkpoints[checkpoint_index as usize],
+                )
+            };
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(logical, from = position, "chain walk");
+
+        while position < logical {
+            cluster = match self.step(buffer, geo, fat_read_policy, position, cluster)? {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+
+            position += 1;
+        }
+
+        self.cursor = (position, cluster);
+
+        Ok(Some(cluster))
+    }
+
+    /// Finds the last cluster in the chain, and its logical cluster number.
+    pub fn last(
+        &mut self,
+        buffer: &mut ReadBuffer,
+        geo: &FATGeometry,
+        fat_read_policy: FatReadPolicy,
+    ) -> Result<(u32, Cluster)> {
+        let checkpoint_index = self.checkpoints.len() as u32 - 1;
+
+        let (mut position, mut cluster) = if self.cursor.0 >= checkpoint_index * Self::STRIDE {
+            self.cursor
+        } else {
+            (
+                checkpoint_index * Self::STRIDE,
+                self.checkpoints[checkpoint_index as usize],
+            )
+        };
+
+        while let Some(next) = self.step(buffer, geo, fat_read_policy, position, cluster)? {
+            cluster = next;
+            position += 1;
+        }
+
+        self.cursor = (position, cluster);
+
+        Ok((position, cluster))
+    }
+
+    /// Follows the chain on from logical cluster `position`, recording a checkpoint if
+    /// the next one is due one.
+    fn step(
+        &mut self,
+        buffer: &mut ReadBuffer,
+        geo: &FATGeometry,
+        fat_read_policy: FatReadPolicy,
+        position: u32,
+        cluster: Cluster,
+    ) -> Result<Option<Cluster>> {
+        let next = match read_fat_entry(buffer, geo, fat_read_policy, cluster)? {
+            FileAllocationTable32Result::NextClusterIndex(next) if geo.is_data_cluster(next) => {
+                next
+            }
+            FileAllocationTable32Result::NextClusterIndex(_) => {
+                return Err(raise_at_fat_entry(buffer, geo, Error::Corrupt, cluster))
+            }
+            FileAllocationTable32Result::EndOfChain => return Ok(None),
+            FileAllocationTable32Result::BadCluster => {
+                return Err(raise_at_fat_entry(buffer, geo, Error::BadCluster, cluster))
+            }
+        };
+
+        let next_position = position + 1;
+
+        // NOTE: positions count from 0, so the next cluster makes the chain one longer
+        if next_position >= self.max_length {
+            return Err(Error::LimitExceeded(Limit::ChainLength));
+        }
+
+        if next_position.is_multiple_of(Self::STRIDE)
+            && (next_position / Self::STRIDE) as usize == self.checkpoints.len()
+        {
+            self.checkpoints.push(next);
+        }
+
+        Ok(Some(next))
+    }
+}
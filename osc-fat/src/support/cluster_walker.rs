@@ -1,26 +1,138 @@
-use crate::prim::{FileAllocationTable32, FileAllocationTable32Result};
-use crate::support::ReadBuffer;
-use crate::FATGeometry;
+use crate::error::{Error, Result};
+use crate::prim::FileAllocationTable32Result;
+use crate::support::{raise_at_fat_entry, read_fat_entry, FatReadPolicy, ReadBuffer};
+use crate::{CancelToken, ErrorContext, FATGeometry, Limit, NoteContext};
+
+/// What the walker is currently stepping through the sectors of.
+#[derive(Debug, Copy, Clone)]
+enum Extent {
+    Cluster(u32),
+    /// A fixed run of sectors outside the data region with no chain to follow, which
+    /// is how the FAT12/16 root directory is stored.
+    Region {
+        first_sector: u64,
+        sector_count: u32,
+    },
+}
 
 pub(crate) struct ClusterWalker<'a> {
     buffer: ReadBuffer<'a>,
-    cluster_index: u32,
-    cluster_sector_index: u8,
+    extent: Extent,
+    extent_sector_index: u32,
+    /// Where the walker was opened, which `rewind` goes back to.
+    start: (Extent, u32),
     geo: FATGeometry,
+    cancel_token: Option<CancelToken>,
+    fat_read_policy: FatReadPolicy,
+    /// How many clusters of the chain have been walked, and how many can be.
+    chain_length: u32,
+    max_chain_length: u32,
 }
 
 impl<'a> ClusterWalker<'a> {
-    pub fn open(buffer: ReadBuffer<'a>, cluster_index: u32, geo: FATGeometry) -> Option<Self> {
+    pub fn open(buffer: ReadBuffer<'a>, cluster_index: u32, geo: FATGeometry) -> Result<Self> {
+        Self::open_at(buffer, cluster_index, 0, geo)
+    }
+
+    /// Opens the walker part way through a cluster.
+    pub fn open_at(
+        buffer: ReadBuffer<'a>,
+        cluster_index: u32,
+        cluster_sector_index: u8,
+        geo: FATGeometry,
+    ) -> Result<Self> {
+        Self::open_extent(
+            buffer,
+            Extent::Cluster(cluster_index),
+            u32::from(cluster_sector_index),
+            geo,
+        )
+    }
+
+    /// Opens the walker over a fixed run of sectors, after which there's no next cluster.
+    pub fn open_region(
+        buffer: ReadBuffer<'a>,
+        first_sector: u64,
+        sector_count: u32,
+        geo: FATGeometry,
+    ) -> Result<Self> {
+        let extent = Extent::Region {
+            first_sector,
+            sector_count,
+        };
+
+        Self::open_extent(buffer, extent, 0, geo)
+    }
+
+    fn open_extent(
+        buffer: ReadBuffer<'a>,
+        extent: Extent,
+        extent_sector_index: u32,
+        geo: FATGeometry,
+    ) -> Result<Self> {
+        if let Extent::Cluster(cluster_index) = extent {
+            if !geo.is_data_cluster(cluster_index) {
+                let context = ErrorContext::at_cluster(cluster_index);
+                return Err(buffer.device().raise(Error::Corrupt, context));
+            }
+        }
+
         let mut result = Self {
             buffer,
-            cluster_index,
-            cluster_sector_index: 0,
+            extent,
+            extent_sector_index,
+            start: (extent, extent_sector_index),
             geo,
+            cancel_token: None,
+            fat_read_policy: FatReadPolicy::default(),
+            chain_length: 1,
+            max_chain_length: u32::MAX,
         };
 
-        result.ensure_sector();
+        result.ensure_sector()?;
+
+        Ok(result)
+    }
+
+    pub fn set_fat_read_policy(&mut self, fat_read_policy: FatReadPolicy) {
+        self.fat_read_policy = fat_read_policy;
+    }
+
+    pub fn set_max_chain_length(&mut self, max_chain_length: u32) {
+        self.max_chain_length = max_chain_length;
+    }
+
+    pub fn set_cancel_token(&mut self, cancel_token: CancelToken) {
+        self.cancel_token = Some(cancel_token);
+    }
+
+    /// Goes back to where the walker was opened.
+    pub fn rewind(&mut self) -> Result<()> {
+        let (extent, extent_sector_index) = self.start;
 
-        Some(result)
+        self.extent = extent;
+        self.extent_sector_index = extent_sector_index;
+        self.chain_length = 1;
+        self.ensure_sector()
+    }
+
+    /// A copy of the walker at the same position, reading through `buffer`.
+    pub fn clone_with_buffer<'b>(&self, buffer: &'b mut [u8]) -> Result<ClusterWalker<'b>> {
+        let mut result = ClusterWalker {
+            buffer: self.buffer.clone_with_buffer(buffer)?,
+            extent: self.extent,
+            extent_sector_index: self.extent_sector_index,
+            start: self.start,
+            geo: self.geo,
+            cancel_token: self.cancel_token.clone(),
+            fat_read_policy: self.fat_read_policy,
+            chain_length: self.chain_length,
+            max_chain_length: self.max_chain_length,
+        };
+
+        result.ensure_sector()?;
+
+        Ok(result)
     }
 
     pub fn current_sector(&self) -> &[u8] {
@@ -29,52 +141,117 @@ impl<'a> ClusterWalker<'a> {
             .unwrap_or_else(|| unreachable!())
     }
 
-    pub fn next_sector(&mut self) -> bool {
-        match self.cluster_sector_index + 1 {
-            n if n == self.geo.cluster_size_sectors => false,
+    pub fn next_sector(&mut self) -> Result<bool> {
+        self.check_cancelled()?;
+
+        match self.extent_sector_index + 1 {
+            n if n == self.extent_sector_count() => Ok(false),
             n => {
-                self.cluster_sector_index = n;
-                self.ensure_sector();
-                true
+                self.extent_sector_index = n;
+                self.ensure_sector()?;
+                Ok(true)
             }
         }
     }
 
-    pub fn next_cluster(mut self) -> Option<Self> {
-        let fat_byte_offset = u64::from(self.cluster_index) * 4;
+    pub fn next_cluster(mut self) -> Result<Option<Self>> {
+        if self.advance_cluster()? {
+            Ok(Some(self))
+        } else {
+            Ok(None)
+        }
+    }
 
-        let fat_sector =
-            self.geo.first_fat_sector + (fat_byte_offset / u64::from(self.geo.sector_size_bytes));
+    /// Like `next_cluster`, but staying on the last cluster at the end of the chain.
+    pub fn advance_cluster(&mut self) -> Result<bool> {
+        self.check_cancelled()?;
 
-        // Sector size bytes has a maximum value of 4096 so 'as' is safe here
-        let ent_offset = (fat_byte_offset % u64::from(self.geo.sector_size_bytes)) as u32;
+        let cluster_index = match self.extent {
+            Extent::Cluster(cluster_index) => cluster_index,
+            Extent::Region { .. } => return Ok(false),
+        };
 
-        let fat_sector_data = self.buffer.get_sector(fat_sector);
+        let entry = read_fat_entry(
+            &mut self.buffer,
+            &self.geo,
+            self.fat_read_policy,
+            cluster_index,
+        )?;
 
-        match FileAllocationTable32::from(fat_sector_data).get_entry(ent_offset) {
+        match entry {
             FileAllocationTable32Result::NextClusterIndex(next_cluster_index) => {
-                self.cluster_index = next_cluster_index;
-                self.ensure_sector();
-                Some(self)
+                #[cfg(feature = "tracing")]
+                tracing::trace!(from = cluster_index, to = next_cluster_index, "chain step");
+
+                if !self.geo.is_data_cluster(next_cluster_index) {
+                    return Err(raise_at_fat_entry(
+                        &self.buffer,
+                        &self.geo,
+                        Error::Corrupt,
+                        cluster_index,
+                    ));
+                }
+
+                self.chain_length += 1;
+
+                if self.chain_length > self.max_chain_length {
+                    return Err(Error::LimitExceeded(Limit::ChainLength));
+                }
+
+                self.extent = Extent::Cluster(next_cluster_index);
+                self.extent_sector_index = 0;
+                self.ensure_sector()?;
+                Ok(true)
             }
-            FileAllocationTable32Result::EndOfChain => None,
-            FileAllocationTable32Result::BadCluster => unimplemented!(),
+            FileAllocationTable32Result::EndOfChain => Ok(false),
+            FileAllocationTable32Result::BadCluster => Err(raise_at_fat_entry(
+                &self.buffer,
+                &self.geo,
+                Error::BadCluster,
+                cluster_index,
+            )),
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.cancel_token {
+            Some(ref cancel_token) => cancel_token.check(),
+            None => Ok(()),
         }
     }
 
-    fn absolute_sector_index(&self) -> u64 {
-        let absolute_start_sector_index = u64::from(self.cluster_index - 2)
-            * u64::from(self.geo.cluster_size_sectors)
-            + self.geo.first_data_sector;
+    fn extent_sector_count(&self) -> u32 {
+        match self.extent {
+            Extent::Cluster(_) => u32::from(self.geo.cluster_size_sectors),
+            Extent::Region { sector_count, .. } => sector_count,
+        }
+    }
 
-        let absolute_sector_index =
-            absolute_start_sector_index + u64::from(self.cluster_sector_index);
+    pub fn absolute_sector_index(&self) -> u64 {
+        let absolute_start_sector_index = match self.extent {
+            Extent::Cluster(cluster_index) => {
+                u64::from(cluster_index - 2) * u64::from(self.geo.cluster_size_sectors)
+                    + self.geo.first_data_sector
+            }
+            Extent::Region { first_sector, .. } => first_sector,
+        };
 
-        absolute_sector_index
+        absolute_start_sector_index + u64::from(self.extent_sector_index)
     }
 
-    fn ensure_sector(&mut self) {
-        // TODO: this should be fallible
-        self.buffer.ensure_sector(self.absolute_sector_index());
+    fn ensure_sector(&mut self) -> Result<()> {
+        // NOTE: the rest of the extent comes along too if the buffer has room
+        let remaining_sectors = self.extent_sector_count() - self.extent_sector_index;
+
+        let result = self
+            .buffer
+            .ensure_sectors(self.absolute_sector_index(), u64::from(remaining_sectors));
+
+        match self.extent {
+            Extent::Cluster(cluster_index) => result.note_context(self.buffer.device(), || {
+                ErrorContext::at_cluster(cluster_index)
+            }),
+            Extent::Region { .. } => result,
+        }
     }
 }
@@ -0,0 +1,396 @@
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::prim::{FileAllocationTable32, FileAllocationTable32Result};
+use crate::support::{read_sector, write_sector, ReadBuffer};
+use crate::{Cluster, ErrorContext, FATGeometry, NoteContext, Variant};
+use alloc::vec;
+use alloc::vec::Vec;
+use osc_block_storage::BlockDevice;
+
+/// How the copies of the FAT are read, as the `MountOptions` ask.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct FatReadPolicy {
+    /// Sectors of the first copy that can't be read are read from another.
+    pub mirror_fallback: bool,
+    /// Every copy is read, and where they disagree, what most of them say is used.
+    pub majority_vote: bool,
+}
+
+/// Looks up the FAT entry for `cluster`, i.e. what follows it in its chain.
+pub(crate) fn read_fat_entry(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    fat_read_policy: FatReadPolicy,
+    cluster: Cluster,
+) -> Result<FileAllocationTable32Result> {
+    Ok(read_fat_value(buffer, geo, fat_read_policy, cluster)?.into())
+}
+
+/// Like `read_fat_entry`, but gives the value itself, with the FAT12/16 reserved values
+/// widened to their FAT32 equivalents, and 0 for a free cluster.
+pub(crate) fn read_fat_value(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    fat_read_policy: FatReadPolicy,
+    cluster: Cluster,
+) -> Result<u32> {
+    let first_fat = geo.active_fat.unwrap_or(0);
+    let value = read_fat_copy_value(
+        buffer,
+        geo,
+        fat_read_policy.mirror_fallback,
+        first_fat,
+        cluster,
+    )
+    .note_context(buffer.device(), || ErrorContext::at_cluster(cluster));
+
+    // NOTE: with mirroring off, the other copies are stale rather than damaged
+    if !fat_read_policy.majority_vote || geo.active_fat.is_some() || geo.fat_count < 2 {
+        return value;
+    }
+
+    vote_on_fat_value(buffer, geo, cluster, value)
+}
+
+/// Records that `error` was found in the FAT entry for `cluster`, returning it.
+pub(crate) fn raise_at_fat_entry(
+    buffer: &ReadBuffer,
+    geo: &FATGeometry,
+    error: Error,
+    cluster: Cluster,
+) -> Error {
+    let context = ErrorContext::at_cluster(cluster).with_sector(fat_entry_sector(geo, cluster));
+    buffer.device().raise(error, context)
+}
+
+/// The absolute sector of the FAT holding the entry for `cluster`, or its first byte
+/// on FAT12, where entries can be split across sectors.
+pub(crate) fn fat_entry_sector(geo: &FATGeometry, cluster: Cluster) -> u64 {
+    let fat_byte_offset = match geo.variant {
+        Variant::Fat32 => u64::from(cluster) * 4,
+        Variant::Fat16 => u64::from(cluster) * 2,
+        Variant::Fat12 => u64::from(cluster) + u64::from(cluster) / 2,
+    };
+
+    geo.first_sector_of_fat(geo.active_fat.unwrap_or(0))
+        + fat_byte_offset / u64::from(geo.sector_size_bytes)
+}
+
+/// Reads the entry for `cluster` from every copy of the FAT, and picks what most of
+/// them say, or without a majority, the first value that leads somewhere a chain can
+/// go: its end, or a data cluster that's in use according to the same copy. Copies
+/// that can't be read don't count, and `first_value`, read as the first copy is
+/// normally, is used when none of them can.
+fn vote_on_fat_value(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    cluster: Cluster,
+    first_value: Result<u32>,
+) -> Result<u32> {
+    let values: Vec<(u8, u32)> = (0..geo.fat_count)
+        .filter_map(|fat_index| {
+            read_fat_copy_value(buffer, geo, false, fat_index, cluster)
+                .ok()
+                .map(|value| (fat_index, value))
+        })
+        .collect();
+
+    let (_, candidate) = match values.first() {
+        Some(&first) => first,
+        None => return first_value,
+    };
+
+    if values.iter().all(|&(_, value)| value == candidate) {
+        return Ok(candidate);
+    }
+
+    let majority = values.iter().map(|&(_, value)| value).find(|&value| {
+        values.iter().filter(|&&(_, other)| other == value).count() * 2 > values.len()
+    });
+
+    let chosen = match majority {
+        Some(value) => value,
+        None => values
+            .iter()
+            .find(|&&(fat_index, value)| leads_somewhere(buffer, geo, fat_index, cluster, value))
+            .map_or(candidate, |&(_, value)| value),
+    };
+
+    #[cfg(feature = "tracing")]
+    tracing::warn!(cluster, ?values, chosen, "FAT copies disagree");
+
+    Ok(chosen)
+}
+
+/// Whether `value`, read from the given copy of the FAT for `cluster`, is the end of a
+/// chain or another data cluster that's in use.
+fn leads_somewhere(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    fat_index: u8,
+    cluster: Cluster,
+    value: u32,
+) -> bool {
+    match FileAllocationTable32Result::from(value) {
+        FileAllocationTable32Result::EndOfChain => true,
+        FileAllocationTable32Result::NextClusterIndex(next)
+            if next != cluster && geo.is_data_cluster(next) =>
+        {
+            match read_fat_copy_value(buffer, geo, false, fat_index, next) {
+                Ok(next_value) => !matches!(
+                    FileAllocationTable32Result::from(next_value),
+                    FileAllocationTable32Result::NextClusterIndex(0)
+                        | FileAllocationTable32Result::BadCluster
+                ),
+                Err(_) => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Reads the entry for `cluster` from one copy of the FAT, falling back to the others
+/// for sectors that can't be read if `mirror_fallback`.
+fn read_fat_copy_value(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    mirror_fallback: bool,
+    fat_index: u8,
+    cluster: Cluster,
+) -> Result<u32> {
+    let copy = FatCopy {
+        fat_index,
+        mirror_fallback,
+    };
+
+    let entry = match geo.variant {
+        Variant::Fat32 => {
+            let fat_byte_offset = u64::from(cluster) * 4;
+            let (fat_sector, ent_offset) = load_fat_byte(buffer, geo, copy, fat_byte_offset)?;
+
+            let fat_sector_data = buffer
+                .get_loaded_sector(fat_sector)
+                .unwrap_or_else(|| unreachable!());
+
+            FileAllocationTable32::from(fat_sector_data).get_value(ent_offset)
+        }
+
+        Variant::Fat16 => {
+            let fat_byte_offset = u64::from(cluster) * 2;
+            let entry = read_fat_u16(buffer, geo, copy, fat_byte_offset)?;
+
+            // Widen the reserved values to their FAT32 equivalents
+            match u32::from(entry) {
+                entry if entry >= 0xFFF7 => entry | 0x0FFF0000,
+                entry => entry,
+            }
+        }
+
+        Variant::Fat12 => {
+            // Entries are a byte and a half, packed in pairs
+            let fat_byte_offset = u64::from(cluster) + u64::from(cluster) / 2;
+            let pair = read_fat_u16(buffer, geo, copy, fat_byte_offset)?;
+
+            let entry = if cluster & 1 == 0 {
+                pair & 0x0FFF
+            } else {
+                pair >> 4
+            };
+
+            match u32::from(entry) {
+                entry if entry >= 0xFF7 => entry | 0x0FFFF000,
+                entry => entry,
+            }
+        }
+    };
+
+    Ok(entry)
+}
+
+/// Sets the FAT entry for `cluster` in every copy of the FAT in use. `value` is as
+/// `read_fat_value` gives it, and is narrowed for FAT12/16; for FAT32 the top four
+/// bits of the entry are left as they are.
+pub(crate) fn write_fat_value(
+    device: &mut dyn BlockDevice,
+    geo: &FATGeometry,
+    cluster: Cluster,
+    value: u32,
+) -> Result<()> {
+    let (fat_byte_offset, len) = match geo.variant {
+        Variant::Fat32 => (u64::from(cluster) * 4, 4),
+        Variant::Fat16 => (u64::from(cluster) * 2, 2),
+        Variant::Fat12 => (u64::from(cluster) + u64::from(cluster) / 2, 2),
+    };
+
+    for fat_index in geo.written_fats() {
+        update_fat_bytes(device, geo, fat_index, fat_byte_offset, len, |bytes| {
+            match geo.variant {
+                Variant::Fat32 => {
+                    let old = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    let new = (old & 0xF0000000) | (value & 0x0FFFFFFF);
+                    bytes.copy_from_slice(&new.to_le_bytes());
+                }
+
+                Variant::Fat16 => bytes.copy_from_slice(&(value as u16).to_le_bytes()),
+
+                Variant::Fat12 => {
+                    let old = u16::from_le_bytes([bytes[0], bytes[1]]);
+                    let value = (value & 0x0FFF) as u16;
+
+                    // The other half of the pair belongs to the neighbouring cluster
+                    let new = if cluster & 1 == 0 {
+                        (old & 0xF000) | value
+                    } else {
+                        (old & 0x000F) | (value << 4)
+                    };
+
+                    bytes.copy_from_slice(&new.to_le_bytes());
+                }
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Reads, updates and writes back `len` bytes at the given offset into a copy of the
+/// FAT, which may be split across two sectors.
+fn update_fat_bytes<F>(
+    device: &mut dyn BlockDevice,
+    geo: &FATGeometry,
+    fat_index: u8,
+    fat_byte_offset: u64,
+    len: usize,
+    update: F,
+) -> Result<()>
+where
+    F: FnOnce(&mut [u8]),
+{
+    let sector_size_bytes = u64::from(geo.sector_size_bytes);
+
+    let first_sector = geo.first_fat_sector
+        + u64::from(fat_index) * u64::from(geo.sectors_per_fat)
+        + fat_byte_offset / sector_size_bytes;
+
+    let offset = (fat_byte_offset % sector_size_bytes) as usize;
+    let sector_count = (offset + len).div_ceiling(geo.sector_size_bytes.into());
+
+    let mut sectors = vec![0u8; sector_count * usize::from(geo.sector_size_bytes)];
+
+    for (index, sector) in sectors
+        .chunks_exact_mut(usize::from(geo.sector_size_bytes))
+        .enumerate()
+    {
+        read_sector(
+            device,
+            geo.sector_size_bytes,
+            first_sector + index as u64,
+            sector,
+        )?;
+    }
+
+    update(&mut sectors[offset..(offset + len)]);
+
+    for (index, sector) in sectors
+        .chunks_exact(usize::from(geo.sector_size_bytes))
+        .enumerate()
+    {
+        write_sector(
+            device,
+            geo.sector_size_bytes,
+            first_sector + index as u64,
+            sector,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Reads the two bytes at the given offset into the FAT, which for FAT12 may be split
+/// across two sectors.
+fn read_fat_u16(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    copy: FatCopy,
+    fat_byte_offset: u64,
+) -> Result<u16> {
+    let mut bytes = [0u8; 2];
+
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        let (fat_sector, offset) =
+            load_fat_byte(buffer, geo, copy, fat_byte_offset + index as u64)?;
+
+        let fat_sector_data = buffer
+            .get_loaded_sector(fat_sector)
+            .unwrap_or_else(|| unreachable!());
+
+        *byte = fat_sector_data[offset as usize];
+    }
+
+    Ok(u16::from_le_bytes(bytes))
+}
+
+/// Which copy of the FAT is read, and whether the others are read from where it can't
+/// be.
+#[derive(Copy, Clone)]
+struct FatCopy {
+    fat_index: u8,
+    mirror_fallback: bool,
+}
+
+/// Loads the sector of the FAT holding the given byte, returning the absolute index
+/// of the sector that was loaded and the offset of the byte within it.
+fn load_fat_byte(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    copy: FatCopy,
+    fat_byte_offset: u64,
+) -> Result<(u64, u32)> {
+    let fat_sector = load_fat_sector(
+        buffer,
+        geo,
+        copy,
+        fat_byte_offset / u64::from(geo.sector_size_bytes),
+    )?;
+
+    // Sector size bytes has a maximum value of 4096 so 'as' is safe here
+    let offset = (fat_byte_offset % u64::from(geo.sector_size_bytes)) as u32;
+
+    Ok((fat_sector, offset))
+}
+
+/// Loads the given sector of a copy of the FAT, falling back to the other FAT copies if
+/// that's enabled, they're mirrored, and the copy can't be read, and returns the
+/// absolute index of the sector that was loaded.
+fn load_fat_sector(
+    buffer: &mut ReadBuffer,
+    geo: &FATGeometry,
+    copy: FatCopy,
+    fat_relative_sector_index: u64,
+) -> Result<u64> {
+    let fat_sector_index =
+        |fat_index: u8| geo.first_sector_of_fat(fat_index) + fat_relative_sector_index;
+
+    let primary_sector_index = fat_sector_index(copy.fat_index);
+    let remaining_sectors = u64::from(geo.sectors_per_fat) - fat_relative_sector_index;
+
+    let err = match buffer.ensure_sectors(primary_sector_index, remaining_sectors) {
+        Ok(()) => return Ok(primary_sector_index),
+        Err(err) => err,
+    };
+
+    if copy.mirror_fallback && geo.active_fat.is_none() {
+        for fat_index in (0..geo.fat_count).filter(|&fat_index| fat_index != copy.fat_index) {
+            let mirror_sector_index = fat_sector_index(fat_index);
+
+            if buffer
+                .ensure_sectors(mirror_sector_index, remaining_sectors)
+                .is_ok()
+            {
+                return Ok(mirror_sector_index);
+            }
+        }
+    }
+
+    Err(err)
+}
@@ -0,0 +1,79 @@
+use crate::error::Result;
+use crate::support::ClusterWalker;
+
+/// Reads a file's contents sequentially, stopping at its recorded size even though
+/// its last cluster usually extends beyond it.
+pub(crate) struct FileReader<'a> {
+    cluster_walker: Option<ClusterWalker<'a>>,
+    sector_offset: usize,
+    remaining_bytes: u32,
+}
+
+impl<'a> FileReader<'a> {
+    pub fn new(cluster_walker: Option<ClusterWalker<'a>>, size: u32) -> Self {
+        Self::starting_at(cluster_walker, 0, size)
+    }
+
+    /// Reads from `sector_offset` into the walker's current sector, with `remaining_bytes`
+    /// left before the end of the file.
+    pub fn starting_at(
+        cluster_walker: Option<ClusterWalker<'a>>,
+        sector_offset: usize,
+        remaining_bytes: u32,
+    ) -> Self {
+        Self {
+            cluster_walker,
+            sector_offset,
+            remaining_bytes,
+        }
+    }
+
+    /// Fills as much of `destination` as possible, returning the number of bytes read,
+    /// which is only short at the end of the file (or of its cluster chain).
+    pub fn read(&mut self, destination: &mut [u8]) -> Result<usize> {
+        let mut bytes_read = 0;
+
+        while bytes_read < destination.len() && self.remaining_bytes > 0 {
+            let sector = match self.cluster_walker {
+                Some(ref cluster_walker) => cluster_walker.current_sector(),
+                None => break,
+            };
+
+            if self.sector_offset == sector.len() {
+                self.advance()?;
+                continue;
+            }
+
+            let count = core::cmp::min(
+                core::cmp::min(
+                    sector.len() - self.sector_offset,
+                    destination.len() - bytes_read,
+                ),
+                self.remaining_bytes as usize,
+            );
+
+            destination[bytes_read..(bytes_read + count)]
+                .copy_from_slice(&sector[self.sector_offset..(self.sector_offset + count)]);
+
+            bytes_read += count;
+            self.sector_offset += count;
+            self.remaining_bytes -= count as u32;
+        }
+
+        Ok(bytes_read)
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        let mut cluster_walker = self.cluster_walker.take().unwrap_or_else(|| unreachable!());
+
+        self.sector_offset = 0;
+
+        if cluster_walker.next_sector()? {
+            self.cluster_walker = Some(cluster_walker);
+        } else {
+            self.cluster_walker = cluster_walker.next_cluster()?;
+        }
+
+        Ok(())
+    }
+}
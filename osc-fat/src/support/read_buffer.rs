@@ -1,32 +1,106 @@
-use alloc::boxed::Box;
-use alloc::rc::Rc;
-use core::{cell::RefCell, ops::Range};
-use osc_block_storage::BlockDevice;
+use crate::error::{Error, Result};
+use crate::math::DivCeiling;
+use crate::support::{PooledBuffer, SharedDevice};
+use crate::ErrorContext;
+use core::ops::{Deref, DerefMut, Range};
+
+/// Where a `ReadBuffer` reads into, either a buffer the caller lent it or one of its
+/// own from the filesystem's pool.
+enum Storage<'a> {
+    Borrowed(&'a mut [u8]),
+    Pooled(PooledBuffer),
+}
+
+impl Deref for Storage<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Storage::Borrowed(buffer) => buffer,
+            Storage::Pooled(buffer) => buffer,
+        }
+    }
+}
+
+impl DerefMut for Storage<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Storage::Borrowed(buffer) => buffer,
+            Storage::Pooled(buffer) => buffer,
+        }
+    }
+}
 
 pub(crate) struct ReadBuffer<'a> {
-    device: Rc<RefCell<Box<dyn BlockDevice>>>,
-    buffer: &'a mut [u8],
+    device: SharedDevice,
+    buffer: Storage<'a>,
     sector_size_bytes: u16,
     loaded_sectors: Option<Range<u64>>,
+    /// The device's write generation when the loaded sectors were read.
+    loaded_generation: u64,
 }
 
 impl<'a> ReadBuffer<'a> {
-    pub fn new(
-        device: Rc<RefCell<Box<dyn BlockDevice>>>,
-        buffer: &'a mut [u8],
-        sector_size_bytes: u16,
-    ) -> Self {
+    pub fn new(device: SharedDevice, buffer: &'a mut [u8], sector_size_bytes: u16) -> Self {
+        Self::with_storage(device, Storage::Borrowed(buffer), sector_size_bytes)
+    }
+
+    fn with_storage(device: SharedDevice, buffer: Storage<'a>, sector_size_bytes: u16) -> Self {
         Self {
             device,
             buffer,
             sector_size_bytes,
             loaded_sectors: None,
+            loaded_generation: 0,
+        }
+    }
+}
+
+impl ReadBuffer<'static> {
+    pub fn pooled(device: SharedDevice, buffer: PooledBuffer, sector_size_bytes: u16) -> Self {
+        Self::with_storage(device, Storage::Pooled(buffer), sector_size_bytes)
+    }
+}
+
+impl<'a> ReadBuffer<'a> {
+    /// A copy of the buffer in `buffer`, holding as many of the loaded sectors as fit,
+    /// so the copy doesn't have to read them again.
+    pub fn clone_with_buffer<'b>(&self, buffer: &'b mut [u8]) -> Result<ReadBuffer<'b>> {
+        let block_size_bytes = self.device.borrow_mut().block_size();
+
+        if buffer.len() < usize::from(core::cmp::max(self.sector_size_bytes, block_size_bytes)) {
+            return Err(Error::BufferTooSmall);
         }
+
+        let sector_size_bytes = u64::from(self.sector_size_bytes);
+
+        let loaded_sectors = self.loaded_sectors.clone().and_then(|loaded_sectors| {
+            let fitting_sectors = buffer.len() as u64 / sector_size_bytes;
+            let end = core::cmp::min(loaded_sectors.end, loaded_sectors.start + fitting_sectors);
+
+            match loaded_sectors.start..end {
+                sectors if sectors.is_empty() => None,
+                sectors => Some(sectors),
+            }
+        });
+
+        if let Some(ref loaded_sectors) = loaded_sectors {
+            let byte_count =
+                ((loaded_sectors.end - loaded_sectors.start) * sector_size_bytes) as usize;
+            buffer[..byte_count].copy_from_slice(&self.buffer[..byte_count]);
+        }
+
+        Ok(ReadBuffer {
+            device: self.device.clone(),
+            buffer: Storage::Borrowed(buffer),
+            sector_size_bytes: self.sector_size_bytes,
+            loaded_sectors,
+            loaded_generation: self.loaded_generation,
+        })
     }
 
-    pub fn get_sector(&mut self, sector_index: u64) -> &[u8] {
-        let sector_range = self.ensure_sector_prime(sector_index);
-        &self.buffer[sector_range]
+    pub fn device(&self) -> &SharedDevice {
+        &self.device
     }
 
     pub fn get_loaded_sector(&self, sector_index: u64) -> Option<&[u8]> {
@@ -41,17 +115,30 @@ impl<'a> ReadBuffer<'a> {
         }
     }
 
-    pub fn ensure_sector(&mut self, sector_index: u64) {
-        self.ensure_sector_prime(sector_index);
+    /// Ensures the given sector is loaded, and if it has to be read, reads up to
+    /// `sector_count` sectors from there in the same device call as far as the buffer
+    /// allows, so that callers about to visit them don't need a call each. Sectors
+    /// loaded before the device was last written to are read again, as the write may
+    /// have been to them.
+    pub fn ensure_sectors(&mut self, sector_index: u64, sector_count: u64) -> Result<()> {
+        self.ensure_sector_prime(sector_index, sector_count)?;
+        Ok(())
     }
 
-    fn ensure_sector_prime(&mut self, sector_index: u64) -> Range<usize> {
+    fn ensure_sector_prime(
+        &mut self,
+        sector_index: u64,
+        sector_count: u64,
+    ) -> Result<Range<usize>> {
         match self.loaded_sectors {
-            Some(ref loaded_sectors) if loaded_sectors.contains(&sector_index) => {
-                return self.sector_range(loaded_sectors, sector_index);
+            Some(ref loaded_sectors)
+                if loaded_sectors.contains(&sector_index)
+                    && self.loaded_generation == self.device.write_generation() =>
+            {
+                return Ok(self.sector_range(loaded_sectors, sector_index));
             }
             Some(_) | None => {
-                return self.read_block_for_sector(sector_index);
+                return self.read_block_for_sector(sector_index, sector_count);
             }
         }
     }
@@ -69,28 +156,53 @@ impl<'a> ReadBuffer<'a> {
         byte_start..byte_end
     }
 
-    fn read_block_for_sector(&mut self, desired_sector_index: u64) -> Range<usize> {
+    fn read_block_for_sector(
+        &mut self,
+        desired_sector_index: u64,
+        sector_count: u64,
+    ) -> Result<Range<usize>> {
         let mut device = self.device.borrow_mut();
+        let generation = self.device.write_generation();
 
         let sector_size_bytes = u64::from(self.sector_size_bytes);
         let block_size_bytes = u64::from(device.block_size());
 
-        // Read the block containing the desired sector
+        // Whatever was loaded is about to be overwritten, and may be left
+        // partially overwritten if the read fails
+        self.loaded_sectors = None;
+
+        // Read the block containing the desired sector, and as many of the blocks
+        // holding the sectors after it as were asked for and will fit
         let block_index = (desired_sector_index * sector_size_bytes) / block_size_bytes;
-        let blocks_read = device.read_blocks(block_index, self.buffer);
+        let wanted_end_byte = (desired_sector_index + sector_count.max(1)) * sector_size_bytes;
+        let wanted_blocks =
+            (wanted_end_byte - block_index * block_size_bytes).div_ceiling(block_size_bytes);
+        let buffer_blocks = self.buffer.len() as u64 / block_size_bytes;
+        let read_bytes = (wanted_blocks.min(buffer_blocks) * block_size_bytes) as usize;
+
+        let blocks_read = device
+            .read_blocks(block_index, &mut self.buffer[..read_bytes])
+            .map_err(|err| {
+                let context = ErrorContext::at_sector(desired_sector_index);
+                self.device.raise(err.into(), context)
+            })?;
         let sectors_read = (blocks_read * block_size_bytes) / sector_size_bytes;
 
-        // TODO: this means the sector doesn't exist on disk, we need
-        // an error handling strategy for things like that
-        assert_ne!(0, sectors_read);
-
         let first_sector = (block_index * block_size_bytes) / sector_size_bytes;
         let last_sector = first_sector + sectors_read;
 
+        // NOTE: the device ending before the sector means the volume claims to be
+        // bigger than it is
+        if !(first_sector..last_sector).contains(&desired_sector_index) {
+            let context = ErrorContext::at_sector(desired_sector_index);
+            return Err(self.device.raise(Error::Corrupt, context));
+        }
+
         let loaded_sectors = first_sector..last_sector;
         let sector_range = self.sector_range(&loaded_sectors, desired_sector_index);
 
         self.loaded_sectors = Some(loaded_sectors);
-        sector_range
+        self.loaded_generation = generation;
+        Ok(sector_range)
     }
 }
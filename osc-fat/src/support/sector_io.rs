@@ -0,0 +1,72 @@
+use crate::error::Result;
+use alloc::vec;
+use alloc::vec::Vec;
+use osc_block_storage::{BlockDevice, BlockDeviceError};
+
+/// Reads a single sector, which may be smaller than (and so share a block with other
+/// sectors) or larger than the device's blocks.
+pub(crate) fn read_sector(
+    device: &mut dyn BlockDevice,
+    sector_size_bytes: u16,
+    sector_index: u64,
+    destination: &mut [u8],
+) -> Result<()> {
+    let (block_index, block_offset, mut block_buffer) =
+        block_buffer_for(device, sector_size_bytes, sector_index);
+
+    if device.read_blocks(block_index, &mut block_buffer)? == 0 {
+        return Err(BlockDeviceError::Io.into());
+    }
+
+    let sector_size_bytes = usize::from(sector_size_bytes);
+    destination[..sector_size_bytes]
+        .copy_from_slice(&block_buffer[block_offset..(block_offset + sector_size_bytes)]);
+
+    Ok(())
+}
+
+/// Writes a single sector, reading in the rest of its block first when sectors are
+/// smaller than blocks.
+pub(crate) fn write_sector(
+    device: &mut dyn BlockDevice,
+    sector_size_bytes: u16,
+    sector_index: u64,
+    source: &[u8],
+) -> Result<()> {
+    let (block_index, block_offset, mut block_buffer) =
+        block_buffer_for(device, sector_size_bytes, sector_index);
+
+    let sector_size_bytes = usize::from(sector_size_bytes);
+
+    if block_buffer.len() > sector_size_bytes
+        && device.read_blocks(block_index, &mut block_buffer)? == 0
+    {
+        return Err(BlockDeviceError::Io.into());
+    }
+
+    block_buffer[block_offset..(block_offset + sector_size_bytes)]
+        .copy_from_slice(&source[..sector_size_bytes]);
+
+    if device.write_blocks(block_index, &block_buffer)? == 0 {
+        return Err(BlockDeviceError::Io.into());
+    }
+
+    Ok(())
+}
+
+fn block_buffer_for(
+    device: &dyn BlockDevice,
+    sector_size_bytes: u16,
+    sector_index: u64,
+) -> (u64, usize, Vec<u8>) {
+    let sector_size_bytes = u64::from(sector_size_bytes);
+    let block_size_bytes = u64::from(device.block_size());
+
+    let byte_offset = sector_index * sector_size_bytes;
+    let block_index = byte_offset / block_size_bytes;
+    let block_offset = (byte_offset % block_size_bytes) as usize;
+
+    let buffer_size = core::cmp::max(sector_size_bytes, block_size_bytes) as usize;
+
+    (block_index, block_offset, vec![0u8; buffer_size])
+}
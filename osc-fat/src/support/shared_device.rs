@@ -0,0 +1,65 @@
+use crate::context::{has_location, ErrorContext, LastError};
+use crate::error::Error;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::{Cell, RefCell, RefMut};
+use osc_block_storage::BlockDevice;
+
+/// The device, shared between the filesystem and everything reading through it, along
+/// with a count of the writes made to it so that readers holding sectors in their own
+/// buffers can tell when those might be out of date, and where the last error
+/// happened.
+#[derive(Clone)]
+pub(crate) struct SharedDevice {
+    device: Rc<RefCell<Box<dyn BlockDevice>>>,
+    write_generation: Rc<Cell<u64>>,
+    last_error: Rc<RefCell<LastError>>,
+}
+
+impl SharedDevice {
+    /// Shares `device`, which must bump `write_generation` whenever it's written to.
+    pub fn new(device: Box<dyn BlockDevice>, write_generation: Rc<Cell<u64>>) -> Self {
+        Self {
+            device: Rc::new(RefCell::new(device)),
+            write_generation,
+            last_error: Rc::new(RefCell::new(LastError::default())),
+        }
+    }
+
+    // NOTE: the device is only ever borrowed for the duration of a call to it, never
+    // across a callback, so any number of readers can be interleaved
+    pub fn borrow_mut(&self) -> RefMut<'_, Box<dyn BlockDevice>> {
+        self.device.borrow_mut()
+    }
+
+    pub fn write_generation(&self) -> u64 {
+        self.write_generation.get()
+    }
+
+    /// Has everything that was read through the device read again, as if it had been
+    /// written to.
+    pub fn invalidate(&self) {
+        self.write_generation.set(self.write_generation.get() + 1);
+    }
+
+    /// Records where `error` happened as it's raised, returning it.
+    pub fn raise(&self, error: Error, context: ErrorContext) -> Error {
+        self.last_error.borrow_mut().raise(error, context);
+        error
+    }
+
+    /// Adds to where `error` happened as it's passed up.
+    pub fn note_error<F>(&self, error: Error, context: F)
+    where
+        F: FnOnce() -> ErrorContext,
+    {
+        if has_location(error) {
+            self.last_error.borrow_mut().note(error, context());
+        }
+    }
+
+    /// Takes where `error` happened, if it's the last error recorded.
+    pub fn take_error_context(&self, error: Error) -> ErrorContext {
+        self.last_error.borrow_mut().take(error)
+    }
+}
@@ -0,0 +1,217 @@
+use crate::error::{Error, Result};
+use crate::prim::FileSystemInfo;
+use crate::support::{read_sector, write_sector, DataStructure, DataStructureMut};
+use crate::{FATFileSystem, Variant};
+use alloc::vec;
+
+impl FATFileSystem {
+    /// Asks the device to commit everything written so far to stable storage.
+    pub fn flush(&self) -> Result<()> {
+        self.device.borrow_mut().flush()?;
+        Ok(())
+    }
+
+    /// Brings the on-disk metadata up to date and flushes the device: the FSInfo
+    /// sector gets the current free cluster details and the volume is marked clean,
+    /// after which it's safe to remove the media. A volume that wasn't cleanly
+    /// unmounted when it was mounted stays marked dirty until `check` has repaired it.
+    pub fn sync(&self) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        if self.dirty.get() {
+            self.update_fs_info()?;
+
+            if self.clean_at_mount.get() {
+                self.set_clean_shutdown(true)?;
+            }
+
+            self.dirty.set(false);
+        }
+
+        self.flush()
+    }
+
+    /// Marks the volume as not cleanly unmounted ahead of its first modification, so
+    /// that a check can be forced if the media is removed before `sync`.
+    pub(crate) fn mark_dirty(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::WriteProtected);
+        }
+
+        if !self.dirty.get() {
+            if let Some(deterministic) = self.options.deterministic {
+                self.write_volume_serial(deterministic.volume_serial)?;
+            }
+
+            // NOTE: from here on the count is kept up to date as clusters are allocated
+            // and freed, starting from what the FSInfo sector records, if that's possible
+            // and the volume was cleanly unmounted, as otherwise it may be stale
+            if self.free_cluster_count.get().is_none() && self.clean_at_mount.get() {
+                let free_cluster_count = self
+                    .read_fs_info()?
+                    .map(|(free_count, _)| free_count)
+                    .filter(|&free_count| free_count <= self.geo.cluster_count);
+
+                self.free_cluster_count.set(free_cluster_count);
+            }
+
+            self.set_clean_shutdown(false)?;
+            self.dirty.set(true);
+        }
+
+        Ok(())
+    }
+
+    /// The free cluster count and next free cluster that FAT32's FSInfo sector records,
+    /// either of which can be `FileSystemInfo::UNKNOWN`, or `None` if there isn't a
+    /// valid FSInfo sector.
+    pub(crate) fn read_fs_info(&self) -> Result<Option<(u32, u32)>> {
+        let sector_index = match self.fs_info_sector {
+            Some(sector_index) => sector_index,
+            None => return Ok(None),
+        };
+
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+        read_sector(
+            &mut **self.device.borrow_mut(),
+            self.geo.sector_size_bytes,
+            sector_index,
+            &mut sector,
+        )?;
+
+        let fs_info = FileSystemInfo::from(&mut sector[..]);
+
+        Ok(fs_info
+            .is_valid()
+            .then(|| (fs_info.free_count(), fs_info.next_free())))
+    }
+
+    /// Records the free cluster count, and the next free cluster once something's been
+    /// allocated, in FAT32's FSInfo sector.
+    fn update_fs_info(&self) -> Result<()> {
+        self.rewrite_fs_info(|fs_info| {
+            fs_info.set_free_count(
+                self.free_cluster_count
+                    .get()
+                    .unwrap_or(FileSystemInfo::UNKNOWN),
+            );
+
+            // NOTE: the next free cluster recorded is left as a hint for the next
+            // mount until there's a better one
+            if let Some(next_free_cluster) = self.next_free_cluster.get() {
+                fs_info.set_next_free(next_free_cluster);
+            }
+        })
+    }
+
+    /// Rewrites FAT32's FSInfo sector through `update`, as long as it's valid.
+    pub(crate) fn rewrite_fs_info<F>(&self, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut FileSystemInfo<'_>),
+    {
+        let sector_index = match self.fs_info_sector {
+            Some(sector_index) => sector_index,
+            None => return Ok(()),
+        };
+
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+        read_sector(
+            &mut **device,
+            self.geo.sector_size_bytes,
+            sector_index,
+            &mut sector,
+        )?;
+
+        let mut fs_info = FileSystemInfo::from(&mut sector[..]);
+
+        if !fs_info.is_valid() {
+            // NOTE: not ours to repair
+            return Ok(());
+        }
+
+        update(&mut fs_info);
+
+        write_sector(
+            &mut **device,
+            self.geo.sector_size_bytes,
+            sector_index,
+            &sector,
+        )
+    }
+
+    /// Whether the "clean shutdown" flag is set in the first copy of the FAT that's
+    /// written to, which it always is for FAT12, as that has no such flag.
+    pub(crate) fn read_clean_shutdown(&self) -> Result<bool> {
+        let (range, mask) = match self.variant {
+            Variant::Fat12 => return Ok(true),
+            Variant::Fat16 => (2..4, 0x8000),
+            Variant::Fat32 => (4..8, 0x08000000),
+        };
+
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+        read_sector(
+            &mut **self.device.borrow_mut(),
+            self.geo.sector_size_bytes,
+            self.geo.first_fat_sector
+                + u64::from(self.geo.written_fats().start) * u64::from(self.geo.sectors_per_fat),
+            &mut sector,
+        )?;
+
+        let entry = match self.variant {
+            Variant::Fat16 => u32::from(sector.u16(range)),
+            _ => sector.u32(range),
+        };
+
+        Ok(entry & mask != 0)
+    }
+
+    /// Sets or clears the "clean shutdown" flag kept in the FAT entry for cluster 1,
+    /// in every copy of the FAT.
+    fn set_clean_shutdown(&self, clean: bool) -> Result<()> {
+        let (range, mask) = match self.variant {
+            // NOTE: FAT12 has no such flag
+            Variant::Fat12 => return Ok(()),
+            Variant::Fat16 => (2..4, 0x8000),
+            Variant::Fat32 => (4..8, 0x08000000),
+        };
+
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+        for fat_index in self.geo.written_fats() {
+            let sector_index = self.geo.first_fat_sector
+                + u64::from(fat_index) * u64::from(self.geo.sectors_per_fat);
+
+            read_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                sector_index,
+                &mut sector,
+            )?;
+
+            let entry = match self.variant {
+                Variant::Fat16 => u32::from(sector.u16(range.clone())),
+                _ => sector.u32(range.clone()),
+            };
+
+            let entry = if clean { entry | mask } else { entry & !mask };
+
+            match self.variant {
+                Variant::Fat16 => sector.set_u16(range.clone(), entry as u16),
+                _ => sector.set_u32(range.clone(), entry),
+            }
+
+            write_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                sector_index,
+                &sector,
+            )?;
+        }
+
+        Ok(())
+    }
+}
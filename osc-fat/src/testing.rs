@@ -0,0 +1,92 @@
+use crate::{FATFileSystem, FormatOptions, Variant};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp;
+use core::ops::Range;
+use osc_block_storage::{BlockDevice, BlockDeviceError};
+
+/// A device held in memory for tests to make volumes on. Clones share their bytes, so
+/// a volume can be opened again from a clone of the device it was made on.
+#[derive(Clone)]
+pub(crate) struct MemoryDevice {
+    bytes: Rc<RefCell<Vec<u8>>>,
+    block_size: u16,
+}
+
+impl MemoryDevice {
+    /// A device of `block_count` zeroed blocks of `block_size` bytes.
+    pub(crate) fn new(block_size: u16, block_count: u64) -> Self {
+        Self {
+            bytes: Rc::new(RefCell::new(vec![
+                0u8;
+                usize::from(block_size)
+                    * block_count as usize
+            ])),
+            block_size,
+        }
+    }
+
+    /// The bytes of as many whole blocks of `len` bytes from `start_block` as the
+    /// device has.
+    fn range(&self, start_block: u64, len: usize) -> Range<usize> {
+        let block_size = usize::from(self.block_size);
+        let block_count = (self.bytes.borrow().len() / block_size) as u64;
+        let start = cmp::min(start_block, block_count);
+        let blocks = cmp::min(block_count - start, (len / block_size) as u64);
+
+        let start = start as usize * block_size;
+        start..(start + blocks as usize * block_size)
+    }
+}
+
+impl BlockDevice for MemoryDevice {
+    fn block_size(&self) -> u16 {
+        self.block_size
+    }
+
+    fn read_blocks(
+        &mut self,
+        start_block: u64,
+        destination: &mut [u8],
+    ) -> Result<u64, BlockDeviceError> {
+        let range = self.range(start_block, destination.len());
+        let len = range.len();
+
+        destination[..len].copy_from_slice(&self.bytes.borrow()[range]);
+        Ok((len / usize::from(self.block_size)) as u64)
+    }
+
+    fn block_count(&self) -> Option<u64> {
+        Some((self.bytes.borrow().len() / usize::from(self.block_size)) as u64)
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    fn write_blocks(&mut self, start_block: u64, source: &[u8]) -> Result<u64, BlockDeviceError> {
+        let range = self.range(start_block, source.len());
+        let len = range.len();
+
+        self.bytes.borrow_mut()[range].copy_from_slice(&source[..len]);
+        Ok((len / usize::from(self.block_size)) as u64)
+    }
+}
+
+/// Formats `size_bytes` of memory as `variant`, with 512-byte blocks and a sector per
+/// cluster, so that a directory fills its first cluster after only 16 entries.
+pub(crate) fn format_volume(variant: Variant, size_bytes: u64) -> (MemoryDevice, FATFileSystem) {
+    let device = MemoryDevice::new(512, size_bytes / 512);
+    let options = FormatOptions {
+        variant: Some(variant),
+        cluster_size_sectors: Some(1),
+        ..FormatOptions::default()
+    };
+
+    let fs = FATFileSystem::format(Box::new(device.clone()), &options).unwrap();
+
+    (device, fs)
+}
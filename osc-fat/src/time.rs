@@ -0,0 +1,395 @@
+use crate::FATFileSystem;
+use alloc::boxed::Box;
+use core::fmt;
+use core::str::FromStr;
+
+/// A timestamp as stored in a directory entry: local time with no zone, to a resolution of
+/// two seconds, or of 10ms for creation times.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FatDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millisecond: u16,
+}
+
+impl FatDateTime {
+    /// Decodes the packed date (years since 1980, month, day) and time (hours, minutes,
+    /// seconds / 2) fields of a directory entry.
+    pub fn from_raw(date: u16, time: u16) -> Self {
+        Self {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0x0F) as u8,
+            day: (date & 0x1F) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3F) as u8,
+            second: ((time & 0x1F) * 2) as u8,
+            millisecond: 0,
+        }
+    }
+
+    /// Like `from_raw`, with the extra 10ms units (up to 1.99s) that accompany the
+    /// creation time.
+    pub fn from_raw_with_centiseconds(date: u16, time: u16, centiseconds: u8) -> Self {
+        let mut result = Self::from_raw(date, time);
+        let centiseconds = centiseconds.min(199);
+
+        result.second += centiseconds / 100;
+        result.millisecond = u16::from(centiseconds % 100) * 10;
+        result
+    }
+
+    /// The earliest timestamp a directory entry can hold.
+    pub const EPOCH: Self = Self {
+        year: 1980,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        millisecond: 0,
+    };
+
+    /// The latest timestamp a directory entry can hold.
+    pub const MAX: Self = Self {
+        year: 2107,
+        month: 12,
+        day: 31,
+        hour: 23,
+        minute: 59,
+        second: 58,
+        millisecond: 0,
+    };
+
+    /// Builds a timestamp from seconds since the Unix epoch, taken as UTC and rounded
+    /// down to two seconds. Returns `None` outside the years FAT can represent
+    /// (1980 to 2107).
+    pub fn from_unix_seconds(seconds: i64) -> Option<Self> {
+        let days = seconds.div_euclid(86400);
+        let second_of_day = seconds.rem_euclid(86400);
+
+        // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        if !(1980..=2107).contains(&year) {
+            return None;
+        }
+
+        Some(Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (second_of_day / 3600) as u8,
+            minute: (second_of_day / 60 % 60) as u8,
+            second: (second_of_day % 60 / 2 * 2) as u8,
+            millisecond: 0,
+        })
+    }
+
+    /// Encodes the timestamp as the packed date and time fields of a directory entry,
+    /// the reverse of `from_raw`.
+    pub fn to_raw(&self) -> (u16, u16) {
+        let date = ((self.year.saturating_sub(1980)) << 9)
+            | (u16::from(self.month) << 5)
+            | u16::from(self.day);
+        let time = (u16::from(self.hour) << 11)
+            | (u16::from(self.minute) << 5)
+            | u16::from(self.second / 2);
+
+        (date, time)
+    }
+
+    /// Like `to_raw`, with the 10ms units that go in the creation time's extra byte.
+    pub fn to_raw_with_centiseconds(&self) -> (u16, u16, u8) {
+        let (date, time) = self.to_raw();
+        let centiseconds = (self.second % 2) * 100 + (self.millisecond.min(999) / 10) as u8;
+
+        (date, time, centiseconds)
+    }
+
+    /// Seconds since the Unix epoch, taking the timestamp to be UTC since FAT doesn't
+    /// record a zone, and leaving out the milliseconds. Returns `None` for unset or
+    /// nonsensical dates.
+    pub fn to_unix_seconds(&self) -> Option<i64> {
+        if !(1..=12).contains(&self.month) || !(1..=31).contains(&self.day) {
+            return None;
+        }
+
+        if self.hour > 23 || self.minute > 59 || self.second > 59 {
+            return None;
+        }
+
+        // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = i64::from(self.month);
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        Some(
+            days * 86400
+                + i64::from(self.hour) * 3600
+                + i64::from(self.minute) * 60
+                + i64::from(self.second),
+        )
+    }
+
+    /// Like `to_unix_seconds`, but takes the timestamp to be in the given zone.
+    pub fn to_unix_seconds_in(&self, time_zone: TimeZonePolicy) -> Option<i64> {
+        let local_seconds = self.to_unix_seconds()?;
+        Some(local_seconds - i64::from(time_zone.offset_for_local(local_seconds)))
+    }
+
+    /// Like `from_unix_seconds`, but gives the time in the given zone.
+    pub fn from_unix_seconds_in(seconds: i64, time_zone: TimeZonePolicy) -> Option<Self> {
+        Self::from_unix_seconds(seconds + i64::from(time_zone.offset_at(seconds)))
+    }
+
+    /// The timestamp along with its offset from UTC, taking it to be in the given zone.
+    pub fn in_zone(&self, time_zone: TimeZonePolicy) -> ZonedDateTime {
+        ZonedDateTime {
+            date_time: *self,
+            offset: self
+                .to_unix_seconds()
+                .map(|local_seconds| time_zone.offset_for_local(local_seconds)),
+        }
+    }
+}
+
+impl fmt::Display for FatDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+
+        if self.millisecond != 0 {
+            write!(f, ".{:03}", self.millisecond)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A timestamp and its offset from UTC in seconds, displayed in ISO 8601 form, such as
+/// `2024-05-01T12:30:00+01:00`, or `2024-05-01T11:30:00Z` in UTC. Nonsensical dates
+/// have no offset, and are displayed without one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ZonedDateTime {
+    pub date_time: FatDateTime,
+    pub offset: Option<i32>,
+}
+
+impl fmt::Display for ZonedDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.date_time)?;
+
+        match self.offset {
+            Some(0) => write!(f, "Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let minutes = offset.unsigned_abs() / 60;
+
+                write!(f, "{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// FAT timestamps are wall clock time with no zone, so converting them to or from an
+/// instant needs the zone they were written in to be assumed.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TimeZonePolicy {
+    #[default]
+    Utc,
+    /// Seconds east of UTC.
+    FixedOffset(i32),
+    /// The system's local zone, including any daylight saving in effect at the time.
+    #[cfg(feature = "std")]
+    Local,
+}
+
+impl TimeZonePolicy {
+    /// The offset from UTC in seconds at the given instant.
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    pub fn offset_at(&self, unix_seconds: i64) -> i32 {
+        match self {
+            Self::Utc => 0,
+            Self::FixedOffset(offset) => *offset,
+            #[cfg(feature = "std")]
+            Self::Local => local_offset_at(unix_seconds),
+        }
+    }
+
+    /// The offset from UTC in seconds for a wall clock time, given as if it were UTC.
+    fn offset_for_local(&self, local_seconds: i64) -> i32 {
+        // NOTE: guess using the wall clock time itself, then check the guess; only times
+        // close to a daylight saving change end up with a different offset
+        let guess = self.offset_at(local_seconds);
+        self.offset_at(local_seconds - i64::from(guess))
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+#[allow(unsafe_code)]
+fn local_offset_at(unix_seconds: i64) -> i32 {
+    use std::convert::TryFrom;
+
+    let time = match libc::time_t::try_from(unix_seconds) {
+        Ok(time) => time,
+        Err(_) => return 0,
+    };
+
+    // SAFETY: localtime_r only writes to the tm it's given
+    let mut tm: libc::tm = unsafe { core::mem::zeroed() };
+
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+
+    tm.tm_gmtoff as i32
+}
+
+// TODO: other platforms, until then local time is taken to be UTC
+#[cfg(all(feature = "std", not(unix)))]
+fn local_offset_at(_unix_seconds: i64) -> i32 {
+    0
+}
+
+/// Parses `utc`, `local` (with the `std` feature), or an offset like `+01:00`, `-0530`
+/// or `+09`.
+impl FromStr for TimeZonePolicy {
+    type Err = ParseTimeZonePolicyError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "utc" | "UTC" => return Ok(Self::Utc),
+            #[cfg(feature = "std")]
+            "local" => return Ok(Self::Local),
+            _ => {}
+        }
+
+        let (sign, digits) = match value.as_bytes().first() {
+            Some(b'+') => (1, &value[1..]),
+            Some(b'-') => (-1, &value[1..]),
+            _ => return Err(ParseTimeZonePolicyError),
+        };
+
+        let (hours, minutes) = match (digits.len(), digits.find(':')) {
+            (2, None) => (digits, "0"),
+            (4, None) => (&digits[..2], &digits[2..]),
+            (5, Some(2)) => (&digits[..2], &digits[3..]),
+            _ => return Err(ParseTimeZonePolicyError),
+        };
+
+        let hours: u8 = hours.parse().map_err(|_| ParseTimeZonePolicyError)?;
+        let minutes: u8 = minutes.parse().map_err(|_| ParseTimeZonePolicyError)?;
+
+        if hours > 23 || minutes > 59 {
+            return Err(ParseTimeZonePolicyError);
+        }
+
+        Ok(Self::FixedOffset(
+            sign * (i32::from(hours) * 3600 + i32::from(minutes) * 60),
+        ))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseTimeZonePolicyError;
+
+impl fmt::Display for ParseTimeZonePolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected utc, local or an offset like +01:00")
+    }
+}
+
+/// Where the filesystem gets "now" from when it stamps entries it creates or modifies.
+pub trait TimeProvider {
+    fn now(&self) -> FatDateTime;
+}
+
+/// Always gives the same time, for targets without a clock or for reproducible images.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FixedTime(pub FatDateTime);
+
+impl Default for FixedTime {
+    fn default() -> Self {
+        Self(FatDateTime::EPOCH)
+    }
+}
+
+impl TimeProvider for FixedTime {
+    fn now(&self) -> FatDateTime {
+        self.0
+    }
+}
+
+/// The system clock, as wall clock time in the given zone.
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock(pub TimeZonePolicy);
+
+#[cfg(feature = "std")]
+impl TimeProvider for SystemClock {
+    fn now(&self) -> FatDateTime {
+        use std::convert::TryFrom;
+        use std::time::SystemTime;
+
+        // NOTE: a clock outside what FAT can represent gets the earliest timestamp
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .and_then(|since_epoch| i64::try_from(since_epoch.as_secs()).ok())
+            .and_then(|seconds| FatDateTime::from_unix_seconds_in(seconds, self.0))
+            .unwrap_or(FatDateTime::EPOCH)
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn default_time_provider(time_zone: TimeZonePolicy) -> Box<dyn TimeProvider> {
+    Box::new(SystemClock(time_zone))
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn default_time_provider(_time_zone: TimeZonePolicy) -> Box<dyn TimeProvider> {
+    Box::new(FixedTime::default())
+}
+
+impl FATFileSystem {
+    /// The zone timestamps are assumed to be in, from the mount options.
+    pub fn time_zone(&self) -> TimeZonePolicy {
+        self.options.time_zone
+    }
+
+    /// Replaces the source of timestamps for entries the filesystem creates or modifies,
+    /// which is the system clock with the `std` feature and `FixedTime` otherwise.
+    pub fn set_time_provider(&mut self, time_provider: Box<dyn TimeProvider>) {
+        self.time_provider = time_provider;
+    }
+
+    pub(crate) fn now(&self) -> FatDateTime {
+        self.time_provider.now()
+    }
+}
@@ -0,0 +1,189 @@
+use crate::error::Result;
+use crate::{Cluster, DirectorySelector, FATFileSystem, Metadata};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The characters a tree is drawn with.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TreeStyle {
+    /// Box-drawing characters, as `tree` draws with.
+    #[default]
+    Unicode,
+    /// Only ASCII, for terminals and logs that can't show anything else.
+    Ascii,
+}
+
+impl TreeStyle {
+    /// What comes before an entry that's followed by others in its directory, and
+    /// before the last, and what comes before the entries beneath those.
+    fn branches(self) -> [&'static str; 4] {
+        match self {
+            Self::Unicode => ["├── ", "└── ", "│   ", "    "],
+            Self::Ascii => ["|-- ", "`-- ", "|   ", "    "],
+        }
+    }
+}
+
+/// How `FATFileSystem::render_tree` draws a tree.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TreeOptions {
+    pub style: TreeStyle,
+    /// Shows the size of each file in a column before its name.
+    pub sizes: bool,
+    /// Shows the read-only, hidden, system and archive attributes of each entry in a
+    /// column before its name, as `rhsa` with `-` for those it doesn't have.
+    pub attributes: bool,
+    /// Only draws this many levels of the tree.
+    pub max_depth: Option<usize>,
+}
+
+/// A directory and everything beneath it, drawn as a tree when displayed. Directories
+/// that can't be read are drawn with the error instead of what's in them.
+pub struct RenderedTree<'a> {
+    fs: &'a FATFileSystem,
+    directory: DirectorySelector,
+    options: TreeOptions,
+}
+
+impl FATFileSystem {
+    /// Draws `directory` and everything beneath it as a tree, one entry per line,
+    /// headed by "/" and the volume label for the root, or "." for any other directory.
+    /// The volume is read as the tree is displayed.
+    pub fn render_tree(
+        &self,
+        directory: DirectorySelector,
+        options: TreeOptions,
+    ) -> RenderedTree<'_> {
+        RenderedTree {
+            fs: self,
+            directory,
+            options,
+        }
+    }
+}
+
+impl fmt::Display for RenderedTree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let items = self.fs.list_directory(self.directory);
+        let label = match (&items, self.directory) {
+            (Ok(items), DirectorySelector::Root) => items
+                .iter()
+                .find(|item| item.attributes.is_volume_id())
+                .map(|label| label.short_name.as_str()),
+            _ => None,
+        };
+
+        match (self.directory, label) {
+            (DirectorySelector::Root, Some(label)) => writeln!(f, "/ ({})", label)?,
+            (DirectorySelector::Root, None) => writeln!(f, "/")?,
+            (DirectorySelector::Cluster(_), _) => writeln!(f, ".")?,
+        }
+
+        let mut ancestors = match self.directory {
+            DirectorySelector::Root => vec![0],
+            DirectorySelector::Cluster(cluster) => vec![cluster],
+        };
+
+        self.render_directory(f, items, &mut String::new(), &mut ancestors)
+    }
+}
+
+impl RenderedTree<'_> {
+    fn render_directory(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        items: Result<Vec<Metadata>>,
+        prefix: &mut String,
+        ancestors: &mut Vec<Cluster>,
+    ) -> fmt::Result {
+        let [branch, last_branch, trunk, no_trunk] = self.options.style.branches();
+
+        let items = match items {
+            Ok(items) => items,
+            Err(err) => return writeln!(f, "{}{}[{}]", prefix, last_branch, err),
+        };
+
+        let items = items
+            .into_iter()
+            .filter(|item| !item.is_dot_entry() && !item.attributes.is_volume_id())
+            .collect::<Vec<_>>();
+
+        for (index, item) in items.iter().enumerate() {
+            let last = index + 1 == items.len();
+
+            write!(f, "{}{}", prefix, if last { last_branch } else { branch })?;
+            self.render_columns(f, item)?;
+
+            if !item.is_directory() {
+                writeln!(f, "{}", item.name)?;
+                continue;
+            }
+
+            writeln!(f, "{}/", item.name)?;
+
+            // NOTE: a directory that's its own ancestor is part of a loop, which
+            // would otherwise never end
+            if matches!(self.options.max_depth, Some(max_depth) if ancestors.len() >= max_depth)
+                || ancestors.contains(&item.first_cluster)
+            {
+                continue;
+            }
+
+            let children = self
+                .fs
+                .list_directory(DirectorySelector::from_cluster(item.first_cluster));
+
+            let parent_len = prefix.len();
+            prefix.push_str(if last { no_trunk } else { trunk });
+            ancestors.push(item.first_cluster);
+
+            self.render_directory(f, children, prefix, ancestors)?;
+
+            ancestors.pop();
+            prefix.truncate(parent_len);
+        }
+
+        Ok(())
+    }
+
+    fn render_columns(&self, f: &mut fmt::Formatter<'_>, item: &Metadata) -> fmt::Result {
+        let TreeOptions {
+            sizes, attributes, ..
+        } = self.options;
+
+        if !sizes && !attributes {
+            return Ok(());
+        }
+
+        write!(f, "[")?;
+
+        if attributes {
+            let flags = [
+                (item.attributes.is_read_only(), 'r'),
+                (item.attributes.is_hidden(), 'h'),
+                (item.attributes.is_system(), 's'),
+                (item.attributes.is_archive(), 'a'),
+            ];
+
+            for (set, flag) in flags.iter() {
+                write!(f, "{}", if *set { *flag } else { '-' })?;
+            }
+        }
+
+        if attributes && sizes {
+            write!(f, " ")?;
+        }
+
+        if sizes {
+            if item.is_directory() {
+                write!(f, "{:>10}", "")?;
+            } else {
+                write!(f, "{:>10}", item.size)?;
+            }
+        }
+
+        write!(f, "] ")
+    }
+}
@@ -0,0 +1,128 @@
+use crate::error::Result;
+use crate::{DirectorySelector, FATFileSystem, Metadata, RootDirectory};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub file_count: u64,
+    /// Including, for a directory in the root, the directory itself.
+    pub directory_count: u64,
+    /// The sizes of the files added up.
+    pub bytes: u64,
+    /// The bytes of the clusters the files and directories have, which is what they
+    /// take up of the volume.
+    pub allocated_bytes: u64,
+}
+
+impl Usage {
+    fn add_item(&mut self, item: &Metadata, allocated_bytes: u64) {
+        if item.is_directory() {
+            self.directory_count += 1;
+        } else {
+            self.file_count += 1;
+            self.bytes += u64::from(item.size);
+        }
+
+        self.allocated_bytes += allocated_bytes;
+    }
+
+    fn add(&mut self, other: &Usage) {
+        self.file_count += other.file_count;
+        self.directory_count += other.directory_count;
+        self.bytes += other.bytes;
+        self.allocated_bytes += other.allocated_bytes;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryUsage {
+    pub name: String,
+    /// The directory and everything beneath it.
+    pub usage: Usage,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct UsageReport {
+    /// The files in the root directory itself, and on FAT32 the root's own clusters.
+    pub root: Usage,
+    /// Each directory in the root, in the order `walk_tree` visits them.
+    pub directories: Vec<DirectoryUsage>,
+    /// The files on the volume by their extension, lower-cased, where files without
+    /// one are under "". Only filled in if asked for.
+    pub extensions: BTreeMap<String, Usage>,
+}
+
+impl UsageReport {
+    /// What everything on the volume takes up.
+    pub fn total(&self) -> Usage {
+        let mut total = self.root;
+
+        for directory in &self.directories {
+            total.add(&directory.usage);
+        }
+
+        total
+    }
+}
+
+impl FATFileSystem {
+    /// Adds up what each directory in the root takes up, along with everything beneath
+    /// it, and what the files in the root itself take up, as `du` would, e.g. to see
+    /// where a volume's space has gone. With `by_extension`, also adds up what the files
+    /// take up by their extension.
+    pub fn usage(&self, by_extension: bool) -> Result<UsageReport> {
+        let mut report = UsageReport::default();
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let cluster_size = self.cluster_size_bytes() as u64;
+
+        if let RootDirectory::Chain(root) = self.root {
+            let (cluster_count, _) = self.measure_chain(&mut buffer, root)?;
+            report.root.allocated_bytes += u64::from(cluster_count) * cluster_size;
+        }
+
+        self.walk_tree(DirectorySelector::Root, |path, item| {
+            let (cluster_count, _) = self.measure_chain(&mut buffer, item.first_cluster)?;
+            let allocated_bytes = u64::from(cluster_count) * cluster_size;
+
+            // NOTE: what's beneath a directory is visited straight after it
+            let in_root = !path[1..].contains('/');
+
+            if in_root && item.is_directory() {
+                report.directories.push(DirectoryUsage {
+                    name: item.name.clone(),
+                    usage: Usage::default(),
+                });
+            }
+
+            let usage = match report.directories.last_mut() {
+                Some(directory) if !in_root || item.is_directory() => &mut directory.usage,
+                _ => &mut report.root,
+            };
+
+            usage.add_item(item, allocated_bytes);
+
+            if by_extension && !item.is_directory() {
+                report
+                    .extensions
+                    .entry(extension(&item.name))
+                    .or_default()
+                    .add_item(item, allocated_bytes);
+            }
+
+            Ok(())
+        })?;
+
+        Ok(report)
+    }
+}
+
+/// The lower-cased extension of `name`, or "" if it hasn't got one.
+fn extension(name: &str) -> String {
+    match name.rfind('.') {
+        Some(index) if index > 0 => name[(index + 1)..].to_lowercase(),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,288 @@
+use crate::allocator::FatReader;
+use crate::error::{Error, Result};
+use crate::prim::first_sector_of_cluster;
+use crate::support::ReadBuffer;
+use crate::{
+    CancelToken, Cluster, DirectorySelector, FATFileSystem, FatEntry, Progress, ProgressSink,
+    RootDirectory,
+};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A file or directory that couldn't be read in full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreadableItem {
+    pub path: String,
+    pub is_directory: bool,
+    /// The sectors of the item that couldn't be read, numbered from the start of the
+    /// volume.
+    pub sectors: Vec<u64>,
+    /// What stopped the rest of the item being read, such as the FAT being unreadable
+    /// where its chain continues, or a directory failing to list.
+    pub error: Option<Error>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct VerifyReport {
+    pub files: u64,
+    pub directories: u64,
+    pub clusters_read: u64,
+    /// Every item with sectors that couldn't be read, in the order `walk_tree` visits
+    /// them. What's beneath an unreadable directory isn't verified.
+    pub unreadable: Vec<UnreadableItem>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.unreadable.is_empty()
+    }
+}
+
+struct Verifier<'a> {
+    fs: &'a FATFileSystem,
+    buffer: ReadBuffer<'a>,
+    fat: FatReader<'a>,
+    report: VerifyReport,
+    status: Progress,
+    progress: &'a mut dyn ProgressSink,
+    cancel_token: &'a CancelToken,
+}
+
+impl FATFileSystem {
+    /// Reads every sector of every file and directory on the volume, reporting those
+    /// that can't be read rather than failing, to find out what a failing device has
+    /// lost. Progress is reported in clusters after every cluster.
+    pub fn verify(
+        &self,
+        progress: &mut dyn ProgressSink,
+        cancel_token: &CancelToken,
+    ) -> Result<VerifyReport> {
+        let mut buffer = vec![0u8; self.buffer_requirements().recommended];
+        let mut fat_buffer = vec![0u8; self.buffer_requirements().recommended];
+
+        let items_total = self.count_allocated_clusters(&mut fat_buffer)?;
+        let cluster_size_bytes = self.cluster_size_bytes() as u64;
+
+        let mut verifier = Verifier {
+            fs: self,
+            buffer: ReadBuffer::new(self.device.clone(), &mut buffer, self.geo.sector_size_bytes),
+            fat: self.fat_reader(&mut fat_buffer),
+            report: VerifyReport::default(),
+            status: Progress {
+                items_done: 0,
+                items_total,
+                bytes_done: 0,
+                bytes_total: items_total * cluster_size_bytes,
+            },
+            progress,
+            cancel_token,
+        };
+
+        verifier.progress.report(&verifier.status);
+
+        let readable = match self.root {
+            RootDirectory::Chain(cluster) => verifier.verify_chain("/", true, cluster)?,
+            RootDirectory::Region {
+                first_sector,
+                sector_count,
+            } => {
+                let mut sectors = Vec::new();
+                verifier.read_sectors(first_sector, u64::from(sector_count), &mut sectors)?;
+                verifier.record("/", true, sectors, None)
+            }
+        };
+
+        if readable {
+            verifier.verify_directory(DirectorySelector::Root, &mut String::new(), 0)?;
+        }
+
+        Ok(verifier.report)
+    }
+
+    /// Counts the clusters the FAT has in use, or gives every cluster if it can't be
+    /// read, in which case verifying finds out what's unreadable.
+    fn count_allocated_clusters(&self, fat_buffer: &mut [u8]) -> Result<u64> {
+        let mut fat = self.fat_reader(fat_buffer);
+        let mut count = 0;
+
+        for cluster in 2..(self.geo.cluster_count + 2) {
+            match fat.read(cluster) {
+                Ok(value) => match FatEntry::from_value(value, self.geo.variant) {
+                    FatEntry::Free | FatEntry::Bad => {}
+                    _ => count += 1,
+                },
+                Err(err) if is_read_failure(err) => return Ok(self.geo.cluster_count.into()),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+impl<'a> Verifier<'a> {
+    fn verify_directory(
+        &mut self,
+        directory: DirectorySelector,
+        path: &mut String,
+        depth: usize,
+    ) -> Result<()> {
+        self.fs.check_depth(depth)?;
+
+        let items = match self.fs.list_directory(directory) {
+            Ok(items) => items,
+            Err(err) if is_read_failure(err) => {
+                let path = if path.is_empty() { "/" } else { path.as_str() };
+                self.record(path, true, Vec::new(), Some(err));
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+
+        for item in items {
+            if item.is_dot_entry() || item.attributes.is_volume_id() {
+                continue;
+            }
+
+            let parent_len = path.len();
+            path.push('/');
+            path.push_str(&item.name);
+
+            let readable = self.verify_chain(path, item.is_directory(), item.first_cluster)?;
+
+            if item.is_directory() {
+                self.report.directories += 1;
+
+                if readable {
+                    self.verify_directory(
+                        DirectorySelector::from_cluster(item.first_cluster),
+                        path,
+                        depth + 1,
+                    )?;
+                }
+            } else {
+                self.report.files += 1;
+            }
+
+            path.truncate(parent_len);
+        }
+
+        Ok(())
+    }
+
+    /// Reads every cluster in the chain starting at `first_cluster`, returning whether
+    /// all of them could be.
+    fn verify_chain(
+        &mut self,
+        path: &str,
+        is_directory: bool,
+        first_cluster: Cluster,
+    ) -> Result<bool> {
+        let sectors_per_cluster = self.fs.geo.cluster_size_sectors;
+        let max_chain_length = self.fs.max_chain_length();
+
+        let mut sectors = Vec::new();
+        let mut error = None;
+        let mut cluster = first_cluster;
+        let mut cluster_count = 0;
+
+        while self.fs.is_data_cluster(cluster) && cluster_count < max_chain_length {
+            self.cancel_token.check()?;
+
+            let first_sector = first_sector_of_cluster(
+                cluster,
+                sectors_per_cluster,
+                self.fs.geo.first_data_sector as u32,
+            );
+
+            self.read_sectors(
+                first_sector.into(),
+                sectors_per_cluster.into(),
+                &mut sectors,
+            )?;
+
+            cluster_count += 1;
+            self.report.clusters_read += 1;
+            self.status.items_done += 1;
+            self.status.bytes_done += self.fs.cluster_size_bytes() as u64;
+            self.progress.report(&self.status);
+
+            cluster = match self.fat.read(cluster) {
+                Ok(value) => match FatEntry::from_value(value, self.fs.geo.variant) {
+                    FatEntry::Next(next) => next,
+                    _ => break,
+                },
+                Err(err) if is_read_failure(err) => {
+                    error = Some(err);
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+        }
+
+        Ok(self.record(path, is_directory, sectors, error))
+    }
+
+    /// Reads `sector_count` sectors from `first_sector`, adding those that can't be
+    /// read to `unreadable`.
+    fn read_sectors(
+        &mut self,
+        first_sector: u64,
+        sector_count: u64,
+        unreadable: &mut Vec<u64>,
+    ) -> Result<()> {
+        let end_sector = first_sector + sector_count;
+        let mut one_at_a_time = false;
+
+        for sector in first_sector..end_sector {
+            let mut result = match one_at_a_time {
+                false => self.buffer.ensure_sectors(sector, end_sector - sector),
+                true => self.buffer.ensure_sectors(sector, 1),
+            };
+
+            // NOTE: a read of several sectors fails as a whole, so once one has, the
+            // rest are read on their own to find out which of them can't be
+            if !one_at_a_time && matches!(result, Err(err) if is_read_failure(err)) {
+                one_at_a_time = true;
+                result = self.buffer.ensure_sectors(sector, 1);
+            }
+
+            match result {
+                Ok(()) => {}
+                Err(err) if is_read_failure(err) => unreadable.push(sector),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Notes the item if it couldn't be read in full, returning whether it could.
+    fn record(
+        &mut self,
+        path: &str,
+        is_directory: bool,
+        sectors: Vec<u64>,
+        error: Option<Error>,
+    ) -> bool {
+        if sectors.is_empty() && error.is_none() {
+            return true;
+        }
+
+        self.report.unreadable.push(UnreadableItem {
+            path: String::from(path),
+            is_directory,
+            sectors,
+            error,
+        });
+
+        false
+    }
+}
+
+/// Whether `err` is from the device failing to give back what was asked for, which
+/// is `Error::Corrupt` when it ends before the volume does.
+fn is_read_failure(err: Error) -> bool {
+    matches!(err, Error::Device(_) | Error::Corrupt)
+}
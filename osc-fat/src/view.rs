@@ -0,0 +1,175 @@
+use crate::error::{Error, Result};
+use crate::names::{long_name_chars, short_name_chars, LongNameAssembler};
+use crate::{
+    DirectoryEntry, DirectoryWalker, InvalidUtf16Policy, Limit, Metadata, NameMatching,
+    StandardDirectoryEntry,
+};
+
+/// A borrowed view of a directory entry along with its long file name, if it has one.
+/// Both point into buffers owned by the walk (the loaded sector and the long name
+/// being assembled), so a view only lives for the duration of the callback it's
+/// passed to, and nothing is copied or allocated to produce it.
+pub struct DirectoryEntryView<'a> {
+    entry: StandardDirectoryEntry<'a>,
+    long_name: Option<&'a [u16]>,
+    invalid_utf16: InvalidUtf16Policy,
+}
+
+impl<'a> DirectoryEntryView<'a> {
+    pub fn entry(&self) -> &StandardDirectoryEntry<'a> {
+        &self.entry
+    }
+
+    /// The long file name exactly as stored.
+    pub fn long_name_utf16(&self) -> Option<&'a [u16]> {
+        self.long_name
+    }
+
+    /// Whether this is the entry `name` refers to, as `matching` matches names, which
+    /// is found out without allocating. The volume label is never matched.
+    pub fn is_called(&self, name: &str, matching: NameMatching) -> bool {
+        if self.entry.is_volume_id() {
+            return false;
+        }
+
+        let short_name_matches = || matching.matches_chars(short_name_chars(&self.entry), name);
+
+        match self.long_name_chars() {
+            Some(long_name) => {
+                matching.matches_chars(long_name, name)
+                    || (matching == NameMatching::CaseInsensitive && short_name_matches())
+            }
+            None => short_name_matches(),
+        }
+    }
+
+    /// The long file name decoded, with invalid UTF-16 decoded as
+    /// `MountOptions::invalid_utf16` says, which can have the entry go by its short
+    /// name instead.
+    pub fn long_name_chars(&self) -> Option<impl Iterator<Item = char> + 'a> {
+        let invalid_utf16 = self.invalid_utf16;
+
+        self.long_name
+            .and_then(move |long_name| long_name_chars(long_name, invalid_utf16))
+    }
+
+    /// The entry's `Metadata`, which is built from the view.
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(&self.entry, self.long_name, self.invalid_utf16)
+    }
+}
+
+impl<'a> DirectoryWalker<'a> {
+    /// Like `enumerate_occupied_entries`, but with long file names assembled, and
+    /// without allocating.
+    pub fn enumerate_entry_views<F>(self, func: F) -> Result<()>
+    where
+        F: FnMut(&DirectoryEntryView<'_>),
+    {
+        self.enumerate_entry_views_where(|_| true, func)
+    }
+
+    /// Like `enumerate_entry_views`, skipping the entries `admits` turns down before
+    /// their long names are checked or handed over.
+    pub(crate) fn enumerate_entry_views_where<A, F>(self, admits: A, mut func: F) -> Result<()>
+    where
+        A: FnMut(&StandardDirectoryEntry<'_>) -> bool,
+        F: FnMut(&DirectoryEntryView<'_>),
+    {
+        self.visit_entry_views(admits, |view| {
+            func(view);
+            true
+        })
+    }
+
+    /// Finds the first entry called `name`, as `matching` matches names, building its
+    /// `Metadata` and no other entry's.
+    pub(crate) fn find_entry(self, name: &str, matching: NameMatching) -> Result<Option<Metadata>> {
+        let mut found = None;
+
+        self.visit_entry_views(
+            |_| true,
+            |view| {
+                if view.is_called(name, matching) {
+                    found = Some(view.metadata());
+                }
+
+                found.is_none()
+            },
+        )?;
+
+        Ok(found)
+    }
+
+    /// Finds the entry at `index` among the directory's files and subdirectories, in
+    /// the order they're stored, leaving out "." and ".." and the volume label. Only its
+    /// long name is assembled, and only its `Metadata` built.
+    pub(crate) fn entry_at(self, index: u32) -> Result<Option<Metadata>> {
+        let mut seen = 0;
+        let mut found = None;
+
+        self.visit_entry_views(
+            |entry| {
+                if entry.is_volume_id() || entry.name().starts_with(b".") {
+                    return false;
+                }
+
+                seen += 1;
+                seen == index + 1
+            },
+            |view| {
+                found = Some(view.metadata());
+                false
+            },
+        )?;
+
+        Ok(found)
+    }
+
+    /// Goes through the entries `admits` lets through until `visit` says to stop by
+    /// giving back false.
+    fn visit_entry_views<A, F>(self, mut admits: A, mut visit: F) -> Result<()>
+    where
+        A: FnMut(&StandardDirectoryEntry<'_>) -> bool,
+        F: FnMut(&DirectoryEntryView<'_>) -> bool,
+    {
+        let mut long_name = LongNameAssembler::default();
+        let invalid_utf16 = self.invalid_utf16;
+        let mut walker = self;
+
+        loop {
+            for entry in walker.occupied_entries() {
+                match entry {
+                    DirectoryEntry::LongFileName(entry) => long_name.push(&entry),
+                    DirectoryEntry::Standard(entry) if !admits(&entry) => long_name.reset(),
+                    DirectoryEntry::Standard(entry) => {
+                        let long_name = long_name.take(&entry);
+
+                        if let (Some(name), Some(max)) =
+                            (long_name, walker.limits.max_long_name_length)
+                        {
+                            if name.len() > max as usize {
+                                return Err(Error::LimitExceeded(Limit::LongNameLength));
+                            }
+                        }
+
+                        let view = DirectoryEntryView {
+                            entry,
+                            long_name,
+                            invalid_utf16,
+                        };
+
+                        if !visit(&view) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+
+            match walker.next()? {
+                Some(next_walker) => walker = next_walker,
+                None => return Ok(()),
+            }
+        }
+    }
+}
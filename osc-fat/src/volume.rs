@@ -0,0 +1,293 @@
+use crate::error::Result;
+use crate::locate::EntryLocation;
+use crate::modify::stamp_modified;
+use crate::names::{volume_label_bytes, NO_LABEL};
+use crate::prim::{ExtendedBiosParameterBlock, ExtendedFat32BiosParameterBlock};
+use crate::support::{read_sector, write_sector, DataStructure, DataStructureMut};
+use crate::{Attributes, DirectoryEntry, DirectorySelector, FATFileSystem, Variant};
+use alloc::string::String;
+use alloc::vec;
+
+impl FATFileSystem {
+    /// How much of the device is past the end of the volume, if the device knows its
+    /// size. There's always some on devices bigger than the volume can be (see
+    /// `FATGeometry::total_sectors`), where it can't be used without another partition.
+    pub fn unused_device_bytes(&self) -> Option<u64> {
+        let device = self.device.borrow_mut();
+        let device_bytes = device
+            .block_count()?
+            .saturating_mul(u64::from(device.block_size()));
+
+        Some(device_bytes.saturating_sub(self.geo.size_bytes()))
+    }
+
+    /// The volume label, as the root directory's volume label entry has it, which is
+    /// what Windows shows, or `None` if there isn't one.
+    pub fn label(&self) -> Result<Option<String>> {
+        Ok(self
+            .locate_volume_label()?
+            .map(|(_, label)| String::from(label.trim_end())))
+    }
+
+    /// Sets the volume label, both in the boot sector (and FAT32's backup boot sector)
+    /// and in the root directory's volume label entry, which is added if there isn't
+    /// one. Labels are upper-cased, as Windows does, and can be up to 11 letters,
+    /// digits, spaces (but not leading ones) and the punctuation short names allow. The
+    /// empty label removes the label.
+    pub fn set_label(&self, label: &str) -> Result<()> {
+        let label_bytes = match label {
+            "" => NO_LABEL,
+            label => volume_label_bytes(label)?,
+        };
+
+        self.mark_dirty()?;
+
+        let label_range = match self.variant {
+            Variant::Fat32 => ExtendedFat32BiosParameterBlock::RANGE_VOL_LAB,
+            _ => ExtendedBiosParameterBlock::RANGE_VOL_LAB,
+        };
+
+        self.update_boot_sectors(|sector| {
+            sector[label_range.clone()].copy_from_slice(&label_bytes);
+        })?;
+
+        match (self.locate_volume_label()?, label) {
+            (Some((location, _)), "") => self.update_entry(location, |entry| entry[0] = 0xE5),
+            (Some((location, _)), _) => {
+                let now = self.now();
+
+                self.update_entry(location, |entry| {
+                    entry[..11].copy_from_slice(&label_bytes);
+                    stamp_modified(entry, now);
+                })
+            }
+            (None, "") => Ok(()),
+            (None, _) => {
+                let scan = self.scan_for_entries(DirectorySelector::Root, 1)?;
+                let location = self.allocate_entries(DirectorySelector::Root, scan, 1)?[0];
+                let entry = self.new_standard_entry(&label_bytes, Attributes::VOLUME_ID);
+
+                self.update_entry(location, |bytes| bytes.copy_from_slice(&entry))
+            }
+        }
+    }
+
+    /// Finds the root directory's volume label entry, giving the label as it's stored,
+    /// with the 8.3 name's parts run together.
+    fn locate_volume_label(&self) -> Result<Option<(EntryLocation, String)>> {
+        let mut walker = self.walk_directory_owned(DirectorySelector::Root)?;
+
+        loop {
+            let sector = walker.current_sector_index();
+
+            for (index, bytes) in walker
+                .current_sector()
+                .chunks_exact(DirectoryEntry::SIZE)
+                .enumerate()
+            {
+                match bytes[0] {
+                    0x00 => return Ok(None),
+                    0xE5 => continue,
+                    _ => {}
+                }
+
+                if let DirectoryEntry::Standard(entry) = DirectoryEntry::from(bytes) {
+                    if entry.is_volume_id() {
+                        let label = String::from_utf8_lossy(entry.short_name()).into_owned();
+                        return Ok(Some((walker.entry_location(sector, index), label)));
+                    }
+                }
+            }
+
+            match walker.next()? {
+                Some(next_walker) => walker = next_walker,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// The volume serial number from the boot sector, or `None` if the boot sector
+    /// doesn't have one, lacking the extended boot signature.
+    pub fn volume_serial(&self) -> Result<Option<u32>> {
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+        read_sector(
+            &mut **self.device.borrow_mut(),
+            self.geo.sector_size_bytes,
+            0,
+            &mut sector,
+        )?;
+
+        Ok(boot_sector_serial(self.variant, &sector))
+    }
+
+    /// Changes the volume serial number, in the boot sector and FAT32's backup boot
+    /// sector, to `serial`, or to one from the entropy source (see
+    /// `set_entropy_source`) if it's `None`, which is what a copy of an image needs
+    /// to be told apart from the original. Returns the serial number written, or
+    /// `None` if the boot sector has nowhere to put one.
+    pub fn set_volume_serial(&self, serial: Option<u32>) -> Result<Option<u32>> {
+        self.mark_dirty()?;
+
+        let serial = serial.unwrap_or_else(|| self.new_volume_serial());
+
+        Ok(self.write_volume_serial(serial)?.then_some(serial))
+    }
+
+    /// Writes `serial` as the volume serial number in the boot sector, and in the
+    /// backup boot sector on FAT32, returning whether there was somewhere to write it.
+    pub(crate) fn write_volume_serial(&self, serial: u32) -> Result<bool> {
+        let serial_range = match self.variant {
+            Variant::Fat32 => ExtendedFat32BiosParameterBlock::RANGE_VOL_ID,
+            _ => ExtendedBiosParameterBlock::RANGE_VOL_ID,
+        };
+
+        self.update_boot_sectors(|mut sector| sector.set_u32(serial_range.clone(), serial))
+    }
+
+    /// Rewrites the boot sector, and the backup boot sector on FAT32, through `update`,
+    /// as long as they have the extended boot signature that says the serial number
+    /// and label are there, returning whether they did.
+    fn update_boot_sectors<F>(&self, update: F) -> Result<bool>
+    where
+        F: Fn(&mut [u8]),
+    {
+        let boot_signature_range = match self.variant {
+            Variant::Fat32 => ExtendedFat32BiosParameterBlock::RANGE_BOOT_SIG,
+            _ => ExtendedBiosParameterBlock::RANGE_BOOT_SIG,
+        };
+
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+        read_sector(&mut **device, self.geo.sector_size_bytes, 0, &mut sector)?;
+
+        // NOTE: without the extended boot signature the serial number's and label's
+        // bytes belong to the boot code
+        if !matches!(sector.u8(boot_signature_range), 0x28 | 0x29) {
+            return Ok(false);
+        }
+
+        let backup_sector = self.backup_boot_sector(&sector);
+
+        update(&mut sector);
+        write_sector(&mut **device, self.geo.sector_size_bytes, 0, &sector)?;
+
+        if let Some(backup_sector) = backup_sector {
+            read_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                backup_sector,
+                &mut sector,
+            )?;
+            update(&mut sector);
+            write_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                backup_sector,
+                &sector,
+            )?;
+        }
+
+        Ok(true)
+    }
+
+    /// Whether FAT32's backup boot sector is the same as the boot sector, or `None` if
+    /// the volume doesn't have one.
+    pub fn backup_boot_sector_matches(&self) -> Result<Option<bool>> {
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+        read_sector(&mut **device, self.geo.sector_size_bytes, 0, &mut sector)?;
+
+        let backup_sector = match self.backup_boot_sector(&sector) {
+            Some(backup_sector) => backup_sector,
+            None => return Ok(None),
+        };
+
+        let mut backup = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+        read_sector(
+            &mut **device,
+            self.geo.sector_size_bytes,
+            backup_sector,
+            &mut backup,
+        )?;
+
+        Ok(Some(sector == backup))
+    }
+
+    /// Rewrites FAT32's backup boot sector as a copy of the boot sector, for after
+    /// something has changed the boot sector alone. Volumes without a backup boot
+    /// sector are left as they are.
+    pub fn sync_backup_boot_sector(&self) -> Result<()> {
+        self.mark_dirty()?;
+
+        let mut device = self.device.borrow_mut();
+        let mut sector = vec![0u8; usize::from(self.geo.sector_size_bytes)];
+
+        read_sector(&mut **device, self.geo.sector_size_bytes, 0, &mut sector)?;
+
+        if let Some(backup_sector) = self.backup_boot_sector(&sector) {
+            write_sector(
+                &mut **device,
+                self.geo.sector_size_bytes,
+                backup_sector,
+                &sector,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Where FAT32's backup boot sector is, as `boot_sector` has it, or `None` if
+    /// there isn't one.
+    pub(crate) fn backup_boot_sector(&self, boot_sector: &[u8]) -> Option<u64> {
+        if self.variant != Variant::Fat32 {
+            return None;
+        }
+
+        // NOTE: a backup that isn't among the reserved sectors would be somewhere
+        // else's sector, such as the FAT's
+        match ExtendedFat32BiosParameterBlock::from(boot_sector).backup_boot_sector() {
+            0 | 0xFFFF => None,
+            backup_sector if u64::from(backup_sector) >= self.geo.first_fat_sector => None,
+            backup_sector => Some(u64::from(backup_sector)),
+        }
+    }
+}
+
+/// The volume serial number in `boot_sector`, if it has the extended boot signature.
+pub(crate) fn boot_sector_serial(variant: Variant, boot_sector: &[u8]) -> Option<u32> {
+    let (boot_signature, serial) = match variant {
+        Variant::Fat32 => {
+            let bpb = ExtendedFat32BiosParameterBlock::from(boot_sector);
+            (bpb.boot_signature(), bpb.volume_id())
+        }
+        _ => {
+            let bpb = ExtendedBiosParameterBlock::from(boot_sector);
+            (bpb.boot_signature(), bpb.volume_id())
+        }
+    };
+
+    matches!(boot_signature, 0x28 | 0x29).then_some(serial)
+}
+
+/// The volume label in `boot_sector`, as it's stored, if it has the extended boot
+/// signature that says there's one there.
+pub(crate) fn boot_sector_label(variant: Variant, boot_sector: &[u8]) -> Option<[u8; 11]> {
+    let (has_label, stored_label) = match variant {
+        Variant::Fat32 => {
+            let bpb = ExtendedFat32BiosParameterBlock::from(boot_sector);
+            (bpb.has_extended_boot_signature(), bpb.volume_label())
+        }
+        _ => {
+            let bpb = ExtendedBiosParameterBlock::from(boot_sector);
+            (bpb.has_extended_boot_signature(), bpb.volume_label())
+        }
+    };
+
+    has_label.then(|| {
+        let mut label = [0u8; 11];
+        label.copy_from_slice(stored_label);
+        label
+    })
+}